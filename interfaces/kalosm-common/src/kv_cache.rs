@@ -33,6 +33,35 @@ impl KvCache {
         self.cache.reset()
     }
 
+    /// Remove every entry after the first `len` entries from the cache.
+    pub fn truncate(&mut self, len: usize) -> candle_core::Result<()> {
+        if len >= self.cache.current_seq_len() {
+            return Ok(());
+        }
+        let kept = match (self.cache.k()?, self.cache.v()?) {
+            (Some(k), Some(v)) if len > 0 => Some((
+                k.narrow(self.concat_dim, 0, len)?.contiguous()?,
+                v.narrow(self.concat_dim, 0, len)?.contiguous()?,
+            )),
+            _ => None,
+        };
+        // Reset the cache and copy back the entries we keep. The cache keeps its capacity
+        self.cache.reset();
+        if let Some((k, v)) = kept {
+            self.cache.append(&k, &v)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the cache into new memory. Unlike [`Clone::clone`], appending to the copy never writes to the memory of the original cache.
+    pub fn deep_clone(&self) -> candle_core::Result<Self> {
+        let mut cache = Self::new(self.concat_dim, self.max_seq_len);
+        if let (Some(k), Some(v)) = (self.cache.k()?, self.cache.v()?) {
+            cache.append(&k, &v)?;
+        }
+        Ok(cache)
+    }
+
     /// Append a new key/value pair to the cache.
     pub fn append(&mut self, k: &Tensor, v: &Tensor) -> candle_core::Result<(Tensor, Tensor)> {
         let k = k.contiguous()?;
@@ -66,3 +95,51 @@ impl KvCache {
         self.cache.append(&k, &v)
     }
 }
+
+#[test]
+fn truncate_keeps_prefix() {
+    use candle_core::Device;
+
+    let mut cache = KvCache::new(2, 64);
+    let states = Tensor::arange(0f32, 10., &Device::Cpu)
+        .unwrap()
+        .reshape((1, 1, 10, 1))
+        .unwrap();
+    cache.append(&states, &states).unwrap();
+    cache.truncate(4).unwrap();
+
+    let new_state = Tensor::new(&[[[[42f32]]]], &Device::Cpu).unwrap();
+    let (k, v) = cache.append(&new_state, &new_state).unwrap();
+    assert_eq!(
+        k.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        vec![0., 1., 2., 3., 42.]
+    );
+    assert_eq!(v.dims(), &[1, 1, 5, 1]);
+}
+
+#[test]
+fn deep_clone_does_not_share_memory() {
+    use candle_core::Device;
+
+    let mut cache = KvCache::new(2, 64);
+    let states = Tensor::arange(0f32, 4., &Device::Cpu)
+        .unwrap()
+        .reshape((1, 1, 4, 1))
+        .unwrap();
+    cache.append(&states, &states).unwrap();
+    let mut copy = cache.deep_clone().unwrap();
+
+    let new_state = Tensor::new(&[[[[42f32]]]], &Device::Cpu).unwrap();
+    copy.append(&new_state, &new_state).unwrap();
+    let other_state = Tensor::new(&[[[[7f32]]]], &Device::Cpu).unwrap();
+    let (k, _) = cache.append(&other_state, &other_state).unwrap();
+    assert_eq!(
+        k.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        vec![0., 1., 2., 3., 7.]
+    );
+    let (k, _) = copy.append(&other_state, &other_state).unwrap();
+    assert_eq!(
+        k.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        vec![0., 1., 2., 3., 42., 7.]
+    );
+}
@@ -49,6 +49,53 @@ impl MaskCache {
             on_true: mask.on_true,
         })
     }
+
+    /// Get the mask for a batch of sequences with different lengths and offsets. The queries of each sequence are right padded to the longest sequence, and the keys (cached and new tokens) are right padded to the longest total length. The mask hides both the padding and future tokens.
+    ///
+    /// The mask has the shape `(batch, 1, max_seq_len, max(seqlen_offset + seq_len))`.
+    pub fn get_batch_mask(
+        &self,
+        seq_lens: &[usize],
+        seqlen_offsets: &[usize],
+        device: &Device,
+    ) -> Result<AttentionMask> {
+        if seq_lens.len() != seqlen_offsets.len() {
+            candle_core::bail!(
+                "expected one offset per sequence, found {} sequences and {} offsets",
+                seq_lens.len(),
+                seqlen_offsets.len()
+            );
+        }
+        // A batch of one doesn't need any padding
+        if let ([seq_len], [seqlen_offset]) = (seq_lens, seqlen_offsets) {
+            return self.get_mask(*seq_len, *seqlen_offset, device);
+        }
+
+        let batch_size = seq_lens.len();
+        let max_seq_len = seq_lens.iter().copied().max().unwrap_or_default();
+        let kv_len = seq_lens
+            .iter()
+            .zip(seqlen_offsets)
+            .map(|(seq_len, offset)| seq_len + offset)
+            .max()
+            .unwrap_or_default();
+        let mask: Vec<_> = seq_lens
+            .iter()
+            .zip(seqlen_offsets)
+            .flat_map(|(&seq_len, &offset)| {
+                (0..max_seq_len).flat_map(move |i| {
+                    // Each token can see the cached tokens, and the new tokens before it
+                    (0..kv_len).map(move |j| u8::from(j >= offset + seq_len || j > offset + i))
+                })
+            })
+            .collect();
+        let mask = Tensor::from_vec(mask, (batch_size, 1, max_seq_len, kv_len), device)?;
+
+        Ok(AttentionMask {
+            mask,
+            on_true: OnceCell::new(),
+        })
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -74,3 +121,25 @@ impl AttentionMask {
         Ok(())
     }
 }
+
+#[test]
+fn batch_mask_hides_padding() {
+    let cache = MaskCache::default();
+    let mask = cache
+        .get_batch_mask(&[2, 1], &[0, 2], &Device::Cpu)
+        .unwrap()
+        .mask
+        .squeeze(1)
+        .unwrap()
+        .to_vec3::<u8>()
+        .unwrap();
+    assert_eq!(
+        mask,
+        vec![
+            // The first sequence has two new tokens and no cache
+            vec![vec![0, 1, 1], vec![0, 0, 1]],
+            // The second sequence has one new token after two cached tokens. The second row is padding
+            vec![vec![0, 0, 0], vec![0, 0, 0]],
+        ]
+    );
+}
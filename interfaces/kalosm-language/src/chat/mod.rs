@@ -0,0 +1,2802 @@
+//! A chat interface that builds on top of [`kalosm_language_model::Model`]
+
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    task::{Context, Poll},
+};
+
+use anyhow::Result;
+use futures_util::{Future, Stream, StreamExt};
+pub use kalosm_language_model::{ChatHistoryItem, MessageType};
+use kalosm_language_model::{ChatMarkers, ChatTemplate};
+use kalosm_language_model::{GenerationParameters, Model, ModelExt, SyncModel, SyncModelExt};
+use kalosm_language_model::{ModelFeedback, Session, ToolChatMessage, ToolDefinition};
+use kalosm_sample::{ArcParser, CreateParserState, ParserExt, SendCreateParserState};
+use kalosm_streams::text_stream::ChannelTextStream;
+use llm_samplers::types::Sampler;
+use tokio::sync::{mpsc::unbounded_channel, oneshot};
+
+mod participant;
+pub use participant::*;
+mod remote;
+use remote::RemoteChatSession;
+mod transcript;
+pub use transcript::ChatTranscript;
+use transcript::{session_to_bytes, transcript_session};
+mod tree;
+pub use tree::*;
+
+type ResponseConstraintGenerator =
+    Arc<Mutex<Box<dyn FnMut(&[ChatHistoryItem]) -> ArcParser<()> + Send + Sync>>>;
+
+const DEFAULT_SYSTEM_PROMPT: &str = "Always assist with care, respect, and truth. Respond with utmost utility yet securely. Avoid harmful, unethical, prejudiced, or negative content. Ensure replies promote fairness and positivity.";
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation the user sends you. Keep every fact, name, decision and open question that later messages may depend on. Respond with only the summary.";
+
+/// The maximum number of tokens in a summary created by [`ContextPolicy::Summarize`].
+const MAX_SUMMARY_TOKENS: u32 = 256;
+
+/// How a [`Chat`] keeps the conversation within the context window of the model. The policy is applied before each new message is added to the chat.
+///
+/// > **Note**: Context policies only apply to models that expose a [`ChatTemplate`] or [`ChatMarkers`]. Models that generate chat responses natively manage their own context.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ContextPolicy {
+    /// Don't manage the context. If the conversation grows past the context length of the model, the model decides how to handle the overflow.
+    #[default]
+    Unbounded,
+    /// Drop the oldest turns of the conversation (keeping the system prompt) until the history and the new message fit in `max_tokens`.
+    DropOldest {
+        /// The maximum number of tokens the history and the new message may use.
+        max_tokens: usize,
+    },
+    /// Keep the first `keep_first` tokens of the session (usually the system prompt) and a sliding window of the most recent `window` tokens. The first tokens act as an attention sink, so the cached state for them is kept as is and only the window is recomputed when it slides.
+    ///
+    /// Unlike the other policies, this only drops tokens from the session. The chat history keeps every message, so once the window slides the history no longer matches what the model sees:
+    /// - [`Chat::history`] and saved transcripts still contain the messages that slid out of the window
+    /// - Anything that renders the history again ([`Chat::edit_message`], [`Chat::regenerate_last`], [`Chat::checkout`] or a template that changes earlier messages) feeds the whole history to the model again. The window slides again before the next response
+    ///
+    /// The session of the model must support [`Session::truncate`].
+    SlidingWindow {
+        /// The number of tokens at the start of the session that are always kept.
+        keep_first: usize,
+        /// The number of recent tokens that are kept after the first tokens.
+        window: usize,
+    },
+    /// Summarize older turns of the conversation with the same model once the history and the new message grow past `max_tokens`. The summary is added to the history as a system message after the system prompt.
+    Summarize {
+        /// The maximum number of tokens the history and the new message may use before older turns are summarized.
+        max_tokens: usize,
+        /// The number of recent messages that are kept as is.
+        keep_recent: usize,
+    },
+}
+
+/// A simple helper function for prompting the user for input.
+pub fn prompt_input(prompt: impl Display) -> Result<String> {
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    input.pop();
+    Ok(input)
+}
+
+/// How a chat session renders the chat history into a prompt.
+enum ChatFormat {
+    /// Wrap each message in the chat markers of the model.
+    Markers(ChatMarkers),
+    /// Render the whole history with the chat template of the model and the tools the model can call.
+    Template {
+        template: ChatTemplate,
+        tools: Vec<ToolDefinition>,
+    },
+}
+
+impl ChatFormat {
+    /// Render the history into a prompt. If `add_generation_prompt` is true, the text that starts an assistant message is added to the end.
+    fn render(&self, history: &[ChatHistoryItem], add_generation_prompt: bool) -> Result<String> {
+        let history: Vec<_> = history.iter().map(prefix_speaker_name).collect();
+        match self {
+            ChatFormat::Markers(markers) => {
+                let mut prompt: String = history
+                    .iter()
+                    .map(|item| render_with_markers(markers, item))
+                    .collect();
+                if add_generation_prompt {
+                    prompt += markers.assistant_marker;
+                }
+                Ok(prompt)
+            }
+            // Templates often index into the messages, so they can't render an empty chat
+            ChatFormat::Template { .. } if history.is_empty() && !add_generation_prompt => {
+                Ok(String::new())
+            }
+            ChatFormat::Template { template, tools } => {
+                let messages: Vec<_> = history.into_iter().map(ToolChatMessage::from).collect();
+                template.render_with_tools(&messages, tools, add_generation_prompt)
+            }
+        }
+    }
+
+    /// Render a single message. This is used to estimate how many tokens a message takes up in the prompt.
+    fn render_message(&self, item: &ChatHistoryItem) -> String {
+        let item = &prefix_speaker_name(item);
+        match self {
+            ChatFormat::Markers(markers) => render_with_markers(markers, item),
+            // Some templates reject a chat that doesn't start with a user message, so fall back to the contents of the message
+            ChatFormat::Template { template, .. } => template
+                .render(std::slice::from_ref(item), false)
+                .unwrap_or_else(|_| item.contents().to_string()),
+        }
+    }
+
+    /// The text that ends an assistant message.
+    fn end_assistant_marker(&self) -> &str {
+        match self {
+            ChatFormat::Markers(markers) => markers.end_assistant_marker,
+            ChatFormat::Template { template, .. } => template.end_assistant_marker(),
+        }
+    }
+}
+
+/// Render a message with chat markers.
+fn render_with_markers(markers: &ChatMarkers, item: &ChatHistoryItem) -> String {
+    let (start, end) = match item.ty() {
+        MessageType::SystemPrompt => (
+            markers.system_prompt_marker,
+            markers.end_system_prompt_marker,
+        ),
+        MessageType::UserMessage => (markers.user_marker, markers.end_user_marker),
+        MessageType::ModelAnswer => (markers.assistant_marker, markers.end_assistant_marker),
+    };
+    format!("{start}{}{end}", item.contents())
+}
+
+/// Write the name of the participant who wrote a message at the start of the message. The name is written into the text of the message because most chat formats only know the role of a message.
+fn prefix_speaker_name(item: &ChatHistoryItem) -> ChatHistoryItem {
+    match item.name() {
+        Some(name) => ChatHistoryItem::new(item.ty(), format!("{name}: {}", item.contents())),
+        None => item.clone(),
+    }
+}
+
+/// The history of a chat session.
+struct ChatSession<Model: SyncModel> {
+    logits_scratch: Vec<f32>,
+    format: ChatFormat,
+    tree: Arc<RwLock<ChatTree>>,
+    session: Model::Session,
+    /// If true, a snapshot of the session is stored in the chat tree after each answer
+    session_snapshots: bool,
+    unfed_text: String,
+    bot_constraints: Option<ResponseConstraintGenerator>,
+    sampler: Arc<Mutex<dyn Sampler + Send + Sync>>,
+    context_policy: ContextPolicy,
+    system_prompt: Option<String>,
+    generation_parameters: GenerationParameters,
+    speakers: Speakers,
+}
+
+/// The most session snapshots kept in the chat tree. Older snapshots are evicted first.
+const MAX_SESSION_SNAPSHOTS: usize = 8;
+
+impl<Model: SyncModel> ChatSession<Model>
+where
+    Model::Session: Send + 'static,
+{
+    #[allow(clippy::too_many_arguments)]
+    /// Creates a new chat history.
+    ///
+    /// If a session is passed in, only the part of the session that matches the start of the rendered history is kept.
+    fn new(
+        model: &mut Model,
+        format: ChatFormat,
+        system_prompt: Option<String>,
+        generation_parameters: GenerationParameters,
+        bot_constraints: Option<ResponseConstraintGenerator>,
+        sampler: Option<Arc<Mutex<dyn Sampler + Send + Sync>>>,
+        context_policy: ContextPolicy,
+        session: Option<Model::Session>,
+        session_snapshots: bool,
+        speakers: Speakers,
+        initial_history: Vec<ChatHistoryItem>,
+        shared_tree: Arc<RwLock<ChatTree>>,
+    ) -> Result<Self> {
+        let history = with_system_prompt(speakers.describe(system_prompt.clone()), initial_history);
+        let session = match session {
+            Some(session) => session,
+            None => model.new_session()?,
+        };
+        // Build the sampler from the generation parameters with the model's tokenizer so logit biases keyed by text are included
+        let sampler = match sampler {
+            Some(sampler) => sampler,
+            None => Arc::new(Mutex::new(
+                generation_parameters
+                    .clone()
+                    .sampler_with_tokenizer(&model.tokenizer())?,
+            )),
+        };
+
+        let mut myself = Self {
+            logits_scratch: Vec::new(),
+            format,
+            session,
+            session_snapshots,
+            unfed_text: String::new(),
+            tree: shared_tree,
+            bot_constraints,
+            sampler,
+            context_policy,
+            system_prompt,
+            generation_parameters,
+            speakers,
+        };
+        myself.rewind(history, false, model)?;
+
+        Ok(myself)
+    }
+
+    /// Get the current chat history.
+    fn history(&self) -> Vec<ChatHistoryItem> {
+        self.tree.read().unwrap().current_history()
+    }
+
+    /// Make the given history the current branch of the chat tree.
+    fn set_history(&mut self, history: &[ChatHistoryItem]) {
+        self.tree.write().unwrap().set_history(history);
+    }
+
+    /// Save the history, settings and session of the chat.
+    fn transcript(&self) -> Result<ChatTranscript> {
+        Ok(ChatTranscript {
+            history: self.history(),
+            system_prompt: self.system_prompt.clone(),
+            generation_parameters: self.generation_parameters.clone(),
+            session: Some(session_to_bytes(&self.session)?),
+        })
+    }
+
+    /// Apply a change to the history and generate a response if the model should respond to it.
+    fn take_turn(&mut self, turn: Turn, model: &mut Model, response: ResponseSender) -> Result<()> {
+        match turn {
+            Turn::AddMessage(message) => self.add_message(message, model, response),
+            Turn::RegenerateLast => self.regenerate_last(model, response),
+            Turn::EditMessage { index, text } => self.edit_message(index, text, model, response),
+            Turn::Speak(name) => self.speak(name, model, response),
+        }
+    }
+
+    /// Adds a message to the history and generates a response if the model should respond to it.
+    fn add_message(
+        &mut self,
+        message: ChatHistoryItem,
+        model: &mut Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        self.manage_context(std::slice::from_ref(&message), model)?;
+        let mut history = self.history();
+        history.push(message.clone());
+        let responder = self.speakers.next_responder(&history);
+        let rendered_prompt = self.add_messages(vec![message], responder.is_some(), model)?;
+        match responder {
+            Some(responder) => self.respond(rendered_prompt, responder, model, response),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes every answer after the last user message and generates a new response. If a participant wrote the first answer, the same participant responds again.
+    fn regenerate_last(&mut self, model: &mut Model, response: ResponseSender) -> Result<()> {
+        let history = self.history();
+        let new_history = without_last_answer(&history)?;
+        let last_speaker = history[new_history.len()..]
+            .first()
+            .and_then(|answer| answer.name())
+            .and_then(|name| self.speakers.responder_named(name).ok());
+        let Some(responder) = last_speaker.or_else(|| self.speakers.next_responder(&new_history))
+        else {
+            self.rewind(new_history, false, model)?;
+            return Ok(());
+        };
+        let rendered_prompt = self.rewind(new_history, true, model)?;
+        self.respond(rendered_prompt, responder, model, response)
+    }
+
+    /// Replaces the text of the message at `index` and removes every message after it. If the message is a user message, a new response is generated.
+    fn edit_message(
+        &mut self,
+        index: usize,
+        text: String,
+        model: &mut Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        let history = with_edited_message(&self.history(), index, text)?;
+        let responder = match history[index].ty() {
+            MessageType::UserMessage => self.speakers.next_responder(&history),
+            _ => None,
+        };
+        let rendered_prompt = self.rewind(history, responder.is_some(), model)?;
+        match responder {
+            Some(responder) => self.respond(rendered_prompt, responder, model, response),
+            None => Ok(()),
+        }
+    }
+
+    /// Generates a message without adding a message first. If a name is given, the model speaks as the participant with that name. Otherwise the orchestrator picks the speaker.
+    fn speak(
+        &mut self,
+        name: Option<String>,
+        model: &mut Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        let history = self.history();
+        let responder = match name {
+            Some(name) => self.speakers.responder_named(&name)?,
+            None => match self.speakers.next_responder(&history) {
+                Some(responder) => responder,
+                None => return Ok(()),
+            },
+        };
+        self.manage_context(&[], model)?;
+        let rendered_prompt = self.add_messages(Vec::new(), true, model)?;
+        self.respond(rendered_prompt, responder, model, response)
+    }
+
+    /// Generate a response to the queued prompt and add it to the history. `rendered_prompt` is the rendered history with the generation prompt.
+    fn respond(
+        &mut self,
+        mut rendered_prompt: String,
+        responder: Responder,
+        model: &mut Model,
+        mut response: ResponseSender,
+    ) -> Result<()> {
+        let participant = self.speakers.participant(responder);
+        let speaker = participant.map(|participant| participant.name().to_string());
+        let bot_constraints = participant
+            .and_then(|participant| participant.constraints.clone())
+            .or_else(|| self.bot_constraints.clone());
+        if let Some(name) = &speaker {
+            // The model writes the message after the name of the participant it speaks as
+            let prefix = format!("{name}: ");
+            rendered_prompt += &prefix;
+            self.unfed_text += &prefix;
+            response.set_speaker(name.clone());
+        }
+
+        let mut bot_response = String::new();
+        let mut cancelled = false;
+        let prompt = std::mem::take(&mut self.unfed_text);
+        let end_assistant_marker = self.format.end_assistant_marker().to_string();
+
+        let mut on_token = |tok: String| {
+            let tok = tok
+                .strip_suffix(&end_assistant_marker)
+                .unwrap_or(&tok)
+                .to_string();
+            bot_response += &tok;
+            // Send the new token to the stream
+            cancelled = !response.send(tok);
+            if cancelled {
+                ModelFeedback::Stop
+            } else {
+                ModelFeedback::Continue
+            }
+        };
+
+        let result = match bot_constraints {
+            Some(constraints) => {
+                let mut constraints = constraints.lock().unwrap();
+                let constraints = constraints(&self.history());
+                let state = constraints.create_parser_state();
+                model.generate_structured(
+                    &mut self.session,
+                    &prompt,
+                    constraints,
+                    state,
+                    self.sampler.clone(),
+                    |tok| match on_token(tok) {
+                        ModelFeedback::Continue => Ok(()),
+                        ModelFeedback::Stop => Err(anyhow::anyhow!("Generation was cancelled")),
+                    },
+                    Some(4),
+                )
+            }
+            None => model.stream_text_with_sampler(
+                &mut self.session,
+                &prompt,
+                None,
+                Some(&end_assistant_marker),
+                self.sampler.clone(),
+                |tok| Ok(on_token(tok)),
+            ),
+        };
+
+        let mut answer = ChatHistoryItem::new(MessageType::ModelAnswer, bot_response);
+        if let Some(name) = speaker {
+            answer = answer.with_name(name);
+        }
+        if cancelled {
+            // Generation can stop before the last tokens are fed to the session, so rewind the session to the text it was fed
+            let mut history = self.history();
+            history.push(answer);
+            self.rewind(history, false, model)?;
+            self.snapshot();
+            return Ok(());
+        }
+        result?;
+
+        self.finish_answer(rendered_prompt, answer, model)
+    }
+
+    /// Replace the history and rewind the session to the longest part of it that matches the start of the rendered history. The rest of the rendered history is queued to be fed to the session. If `add_generation_prompt` is true, the text that starts an assistant message is queued after the history.
+    ///
+    /// Returns the rendered prompt for the whole history.
+    fn rewind(
+        &mut self,
+        history: Vec<ChatHistoryItem>,
+        add_generation_prompt: bool,
+        model: &mut Model,
+    ) -> Result<String> {
+        let rendered = self.format.render(&history, add_generation_prompt)?;
+        let tokenizer = model.tokenizer();
+        let tokens = self.session.tokens();
+        let decode = |len: usize| {
+            tokenizer
+                .decode(&tokens[..len], false)
+                .map_err(|e| anyhow::anyhow!(e))
+        };
+
+        // Find the most tokens whose text is the start of the rendered history. Tokens that end partway through a character decode to a replacement character, so it is ignored here to keep the search monotonic
+        let is_start = |text: &str| rendered.starts_with(text.trim_end_matches('\u{FFFD}'));
+        let (mut low, mut high) = (0, tokens.len());
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if is_start(&decode(mid)?) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        // Drop the tokens of a character that was only partly fed
+        let mut fed_text = decode(low)?;
+        while !rendered.starts_with(&fed_text) {
+            low -= 1;
+            fed_text = decode(low)?;
+        }
+        if low < tokens.len() && self.session.truncate(low).is_err() {
+            // The session can't be rewound, so the whole history has to be fed to a new session
+            self.session = model.new_session()?;
+            fed_text.clear();
+        }
+
+        self.unfed_text = rendered[fed_text.len()..].to_string();
+        self.set_history(&history);
+        Ok(rendered)
+    }
+
+    /// Add messages to the end of the history and queue the text they add to the prompt. If `add_generation_prompt` is true, the text that starts an assistant message is queued after the messages.
+    ///
+    /// Returns the rendered prompt for the whole history.
+    fn add_messages(
+        &mut self,
+        messages: Vec<ChatHistoryItem>,
+        add_generation_prompt: bool,
+        model: &mut Model,
+    ) -> Result<String> {
+        let history = self.history();
+        let rendered_history = self.format.render(&history, false)?;
+        let mut new_history = history;
+        new_history.extend(messages);
+        let rendered = self.format.render(&new_history, add_generation_prompt)?;
+        match rendered.strip_prefix(&rendered_history) {
+            Some(new_text) => self.unfed_text += new_text,
+            None => {
+                // Some templates render earlier messages differently once new messages are added, so the session has to start over
+                self.session = model.new_session()?;
+                self.unfed_text = rendered.clone();
+            }
+        }
+        self.set_history(&new_history);
+        Ok(rendered)
+    }
+
+    /// Add the answer the model generated after `rendered_prompt` to the history.
+    fn finish_answer(
+        &mut self,
+        rendered_prompt: String,
+        answer: ChatHistoryItem,
+        model: &mut Model,
+    ) -> Result<()> {
+        let mut new_history = self.history();
+        new_history.push(answer);
+        let rendered = self.format.render(&new_history, false)?;
+        let generated = rendered_prompt + new_history.last().unwrap().contents();
+        match rendered.strip_prefix(&generated) {
+            // Generation stops before the end of the answer is fed to the session, so queue the rest of the rendered answer
+            Some(rest) => {
+                let end_assistant_marker = self.format.end_assistant_marker();
+                let tokens = self.session.tokens();
+                // The end of the answer is usually a single token, so decoding the last few tokens is enough to check if it was fed
+                let last_text = model
+                    .tokenizer()
+                    .decode(&tokens[tokens.len().saturating_sub(4)..], false)
+                    .map_err(|e| anyhow::anyhow!(e))?;
+                let rest = match last_text.ends_with(end_assistant_marker) {
+                    true => rest.strip_prefix(end_assistant_marker).unwrap_or(rest),
+                    false => rest,
+                };
+                self.unfed_text = rest.to_string();
+            }
+            // The template changes the answer when it is rendered, so the session has to start over
+            None => {
+                self.session = model.new_session()?;
+                self.unfed_text = rendered;
+            }
+        }
+        self.set_history(&new_history);
+        self.snapshot();
+
+        Ok(())
+    }
+
+    /// Store a snapshot of the session in the chat tree for the current message if snapshots are enabled.
+    fn snapshot(&mut self) {
+        if !self.session_snapshots {
+            return;
+        }
+        let mut tree = self.tree.write().unwrap();
+        let Some(current) = tree.current() else {
+            return;
+        };
+        match self.session.try_clone() {
+            Ok(session) => tree.set_snapshot(current, session, MAX_SESSION_SNAPSHOTS),
+            Err(err) => tracing::warn!("Failed to snapshot the session: {err}"),
+        }
+    }
+
+    /// Switch the chat history to the branch of the chat tree that ends at `node`.
+    fn checkout(&mut self, node: ChatNodeId, model: &mut Model) -> Result<()> {
+        let (path, history, shared) = {
+            let tree = self.tree.read().unwrap();
+            let path = tree
+                .path(node)
+                .ok_or_else(|| anyhow::anyhow!("The message is not in the chat tree"))?;
+            let history = tree.history(node).unwrap_or_default();
+            let current_path = tree
+                .current()
+                .and_then(|current| tree.path(current))
+                .unwrap_or_default();
+            let shared = path
+                .iter()
+                .zip(&current_path)
+                .take_while(|(a, b)| a == b)
+                .count();
+            (path, history, shared)
+        };
+
+        // Start from the latest snapshot on the branch if it covers more of the branch than the current session
+        if self.session_snapshots {
+            let tree = self.tree.read().unwrap();
+            let snapshot = path
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(index, id)| Some((index, tree.snapshot::<Model::Session>(*id)?)));
+            if let Some((index, snapshot)) = snapshot {
+                if index >= shared {
+                    self.session = snapshot.lock().unwrap().try_clone()?;
+                }
+            }
+        }
+
+        self.rewind(history, false, model)?;
+        Ok(())
+    }
+
+    /// Apply the context policy before new messages are added and the model responds.
+    fn manage_context(
+        &mut self,
+        new_messages: &[ChatHistoryItem],
+        model: &mut Model,
+    ) -> Result<()> {
+        let (max_tokens, keep_recent) = match self.context_policy {
+            ContextPolicy::Unbounded => return Ok(()),
+            ContextPolicy::SlidingWindow { keep_first, window } => {
+                return self.slide_window(new_messages, model, keep_first, window)
+            }
+            ContextPolicy::DropOldest { max_tokens } => (max_tokens, None),
+            ContextPolicy::Summarize {
+                max_tokens,
+                keep_recent,
+            } => (max_tokens, Some(keep_recent)),
+        };
+
+        let tokenizer = model.tokenizer();
+        let count_tokens = |text: &str| -> Result<usize> {
+            Ok(tokenizer
+                .encode(text, false)
+                .map_err(|e| anyhow::anyhow!(e))?
+                .len())
+        };
+        let new_message_tokens = match new_messages.is_empty() {
+            true => 0,
+            false => count_tokens(&self.format.render(new_messages, true)?)?,
+        };
+        let history = self.history();
+        let token_counts = history
+            .iter()
+            .map(|item| count_tokens(&self.format.render_message(item)))
+            .collect::<Result<Vec<_>>>()?;
+        if token_counts.iter().sum::<usize>() + new_message_tokens <= max_tokens {
+            return Ok(());
+        }
+
+        // The system prompt is always kept
+        let first = usize::from(
+            history
+                .first()
+                .filter(|item| item.ty() == MessageType::SystemPrompt)
+                .is_some(),
+        );
+        let new_history = match keep_recent {
+            None => {
+                let dropped = oldest_turns_to_drop(
+                    &history,
+                    &token_counts,
+                    max_tokens.saturating_sub(new_message_tokens),
+                );
+                let mut new_history = history;
+                new_history.drain(dropped);
+                new_history
+            }
+            Some(keep_recent) => {
+                let summarize_end = history.len().saturating_sub(keep_recent).max(first);
+                if summarize_end == first {
+                    return Ok(());
+                }
+                let summary = self.summarize(&history[first..summarize_end], model)?;
+                let mut new_history = history[..first].to_vec();
+                new_history.push(ChatHistoryItem::new(
+                    MessageType::SystemPrompt,
+                    format!("Summary of the earlier conversation: {summary}"),
+                ));
+                new_history.extend_from_slice(&history[summarize_end..]);
+                new_history
+            }
+        };
+
+        // The cached session no longer matches the history, so start a new session with the new history
+        self.session = model.new_session()?;
+        self.unfed_text = self.format.render(&new_history, false)?;
+        self.set_history(&new_history);
+
+        Ok(())
+    }
+
+    /// Drop the oldest tokens after the first `keep_first` tokens of the session so the new messages fit in the window.
+    fn slide_window(
+        &mut self,
+        new_messages: &[ChatHistoryItem],
+        model: &mut Model,
+        keep_first: usize,
+        window: usize,
+    ) -> Result<()> {
+        let mut prompt = self.unfed_text.clone();
+        if !new_messages.is_empty() {
+            prompt += &self.format.render(new_messages, true)?;
+        }
+        let prompt_tokens = model
+            .tokenizer()
+            .encode(prompt, false)
+            .map_err(|e| anyhow::anyhow!(e))?
+            .len();
+        let tokens = self.session.tokens();
+        let overflow = (tokens.len() + prompt_tokens).saturating_sub(keep_first + window);
+        if overflow == 0 || tokens.len() <= keep_first {
+            return Ok(());
+        }
+
+        // Keep the cached state for the first tokens and recompute the rest of the window
+        let window_start = (keep_first + overflow).min(tokens.len());
+        let window_tokens = tokens[window_start..].to_vec();
+        self.session.truncate(keep_first)?;
+        if !window_tokens.is_empty() {
+            model.feed_tokens(&mut self.session, &window_tokens, &mut self.logits_scratch)?;
+        }
+
+        Ok(())
+    }
+
+    /// Summarize part of the conversation with the model in a new session.
+    fn summarize(&self, messages: &[ChatHistoryItem], model: &mut Model) -> Result<String> {
+        let transcript: String = messages
+            .iter()
+            .map(|item| {
+                let role = match item.ty() {
+                    MessageType::SystemPrompt => "System",
+                    MessageType::UserMessage => "User",
+                    MessageType::ModelAnswer => "Assistant",
+                };
+                format!("{}: {}\n", item.name().unwrap_or(role), item.contents())
+            })
+            .collect();
+        let prompt = self.format.render(
+            &[
+                ChatHistoryItem::new(MessageType::SystemPrompt, SUMMARY_PROMPT),
+                ChatHistoryItem::new(MessageType::UserMessage, transcript),
+            ],
+            true,
+        )?;
+
+        let mut session = model.new_session()?;
+        let mut summary = String::new();
+        model.stream_text_with_sampler(
+            &mut session,
+            &prompt,
+            Some(MAX_SUMMARY_TOKENS),
+            Some(self.format.end_assistant_marker()),
+            self.sampler.clone(),
+            |tok| {
+                summary += &tok;
+                Ok(kalosm_language_model::ModelFeedback::Continue)
+            },
+        )?;
+
+        Ok(summary.trim().to_string())
+    }
+}
+
+/// Add a system prompt to the start of the history if it doesn't already start with one.
+fn with_system_prompt(
+    system_prompt: Option<String>,
+    initial_history: Vec<ChatHistoryItem>,
+) -> Vec<ChatHistoryItem> {
+    let mut history = Vec::new();
+    if initial_history
+        .first()
+        .filter(|item| item.ty() == MessageType::SystemPrompt)
+        .is_none()
+    {
+        let system_prompt = system_prompt.unwrap_or(DEFAULT_SYSTEM_PROMPT.into());
+        history.push(ChatHistoryItem::new(
+            MessageType::SystemPrompt,
+            system_prompt,
+        ));
+    }
+    history.extend(initial_history);
+    history
+}
+
+/// Remove every message after the last user message so it can be answered again.
+fn without_last_answer(history: &[ChatHistoryItem]) -> Result<Vec<ChatHistoryItem>> {
+    let last_user_message = history
+        .iter()
+        .rposition(|item| item.ty() == MessageType::UserMessage)
+        .ok_or_else(|| anyhow::anyhow!("There is no user message to respond to"))?;
+    Ok(history[..=last_user_message].to_vec())
+}
+
+/// Replace the text of the message at `index` and remove every message after it.
+fn with_edited_message(
+    history: &[ChatHistoryItem],
+    index: usize,
+    text: String,
+) -> Result<Vec<ChatHistoryItem>> {
+    let Some(item) = history.get(index) else {
+        anyhow::bail!(
+            "Message {index} is out of bounds for a chat with {} messages",
+            history.len()
+        );
+    };
+    let mut edited = ChatHistoryItem::new(item.ty(), text);
+    if let Some(name) = item.name() {
+        edited = edited.with_name(name);
+    }
+    let mut new_history = history[..index].to_vec();
+    new_history.push(edited);
+    Ok(new_history)
+}
+
+/// Find the oldest turns to drop from the history so the remaining messages fit in `max_tokens`. The system prompt is never dropped, and answers are dropped with the message they answer.
+fn oldest_turns_to_drop(
+    history: &[ChatHistoryItem],
+    token_counts: &[usize],
+    max_tokens: usize,
+) -> std::ops::Range<usize> {
+    let first = usize::from(
+        history
+            .first()
+            .filter(|item| item.ty() == MessageType::SystemPrompt)
+            .is_some(),
+    );
+    let mut total: usize = token_counts.iter().sum();
+    let mut end = first;
+    while end < history.len() && total > max_tokens {
+        total -= token_counts[end];
+        end += 1;
+    }
+    // Don't leave an answer without the message before it
+    while end < history.len() && history[end].ty() == MessageType::ModelAnswer {
+        end += 1;
+    }
+    first..end
+}
+
+/// A builder for [`Chat`].
+pub struct ChatBuilder<M: Model> {
+    model: M,
+    chat_markers: Option<ChatMarkers>,
+    chat_template: Option<ChatTemplate>,
+    session: Option<<M::SyncModel as kalosm_language_model::SyncModel>::Session>,
+    system_prompt: Option<String>,
+    /// A custom sampler. If this is `None`, the sampler is created from the generation parameters
+    sampler: Option<Arc<Mutex<dyn Sampler + Send + Sync>>>,
+    generation_parameters: GenerationParameters,
+    bot_constraints: Option<ResponseConstraintGenerator>,
+    initial_history: Vec<ChatHistoryItem>,
+    context_policy: ContextPolicy,
+    session_snapshots: bool,
+    tools: Vec<ToolDefinition>,
+    participants: Vec<Participant>,
+    orchestrator: Box<dyn Orchestrator>,
+}
+
+impl<M: Model> ChatBuilder<M> {
+    fn new(model: M) -> ChatBuilder<M> {
+        let chat_markers = model.chat_markers();
+        let chat_template = model.chat_template();
+
+        ChatBuilder {
+            model,
+            chat_markers,
+            chat_template,
+            session: None,
+            system_prompt: None,
+            sampler: None,
+            generation_parameters: GenerationParameters::default(),
+            bot_constraints: None,
+            initial_history: Vec::new(),
+            context_policy: ContextPolicy::default(),
+            session_snapshots: false,
+            tools: Vec::new(),
+            participants: Vec::new(),
+            orchestrator: Box::new(RoundRobin),
+        }
+    }
+}
+
+impl<M: Model> ChatBuilder<M> {
+    /// Adds a system prompt to the chat. The system prompt guides the model to respond in a certain way.
+    /// If no system prompt is added, the model will use a default system prompt that instructs the model to respond in a way that is safe and respectful.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_system_prompt("The assistant will act like a pirate.")
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_system_prompt(mut self, system_prompt: impl ToString) -> Self {
+        self.system_prompt = Some(system_prompt.to_string());
+        self
+    }
+
+    /// Sets the [`Sampler`] to use for generating responses.
+    ///
+    /// > **Note**: Models that generate chat responses natively (like remote chat APIs) can't use a custom sampler. Use [`ChatBuilder::with_generation_parameters`] instead.
+    pub fn with_sampler(mut self, sampler: impl Sampler + 'static) -> Self {
+        self.sampler = Some(Arc::new(Mutex::new(sampler)));
+        self
+    }
+
+    /// Sets the [`GenerationParameters`] to use for generating responses. This also replaces the [`Sampler`] with the sampler created from the parameters.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Gpt4O::builder().build())
+    ///     .with_generation_parameters(GenerationParameters::default().with_temperature(0.2))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_generation_parameters(
+        mut self,
+        generation_parameters: GenerationParameters,
+    ) -> Self {
+        self.sampler = None;
+        self.generation_parameters = generation_parameters;
+        self
+    }
+
+    /// See [`ChatBuilder::with_constraints`]
+    #[deprecated(note = "renamed to `with_constraints`")]
+    pub fn constrain_response<Parser: SendCreateParserState + 'static>(
+        self,
+        bot_constraints: impl FnMut(&[ChatHistoryItem]) -> Parser + Send + Sync + 'static,
+    ) -> ChatBuilder<M> {
+        Self::with_constraints(self, bot_constraints)
+    }
+
+    /// Constrains the model's response to the given parser. This can be used to make the model start with a certain phrase, or to make the model respond in a certain way.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let model = Llama::new_chat().await.unwrap();
+    /// // Create constraints that parses Yes! and then stops on the end of the assistant's response
+    /// let constraints = LiteralParser::new("Yes!")
+    ///     .then(model.default_assistant_constraints().unwrap());
+    /// // Create a chat session with the model and the constraints
+    /// let mut chat = Chat::builder(model)
+    ///     .constrain_response(move |_history| constraints.clone())
+    ///     .build();
+    ///
+    /// // Chat with the user
+    /// loop {
+    ///     let mut output_stream = chat.add_message(prompt_input("\n> ").unwrap());
+    ///     output_stream.to_std_out().await.unwrap();
+    /// }
+    /// # }
+    /// ```
+    pub fn with_constraints<Parser: SendCreateParserState + 'static>(
+        self,
+        mut bot_constraints: impl FnMut(&[ChatHistoryItem]) -> Parser + Send + Sync + 'static,
+    ) -> ChatBuilder<M> {
+        ChatBuilder {
+            model: self.model,
+            chat_markers: self.chat_markers,
+            chat_template: self.chat_template,
+            session: self.session,
+            system_prompt: self.system_prompt,
+            sampler: self.sampler,
+            generation_parameters: self.generation_parameters,
+            bot_constraints: Some(Arc::new(Mutex::new(Box::new(
+                move |history: &[ChatHistoryItem]| {
+                    bot_constraints(history).map_output(|_| ()).boxed()
+                },
+            )
+                as Box<dyn FnMut(&[ChatHistoryItem]) -> ArcParser + Send + Sync>))),
+            initial_history: self.initial_history,
+            context_policy: self.context_policy,
+            session_snapshots: self.session_snapshots,
+            tools: self.tools,
+            participants: self.participants,
+            orchestrator: self.orchestrator,
+        }
+    }
+
+    /// Starts the chat instance with the given model session. This can be useful for resuming a chat session with a long context that has already been processed.
+    ///
+    /// The session is only kept if it was fed the same history the chat starts with (set with [`ChatBuilder::with_initial_history`]). Otherwise the history is fed to a new session. Use [`ChatBuilder::with_transcript`] to restore the history and the session together.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # use kalosm_llama::LlamaSession;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let model = Llama::new_chat().await.unwrap();
+    /// // Load the model session from the filesystem
+    /// let session = LlamaSession::load_from(std::path::PathBuf::from("./chat.llama")).unwrap();
+    /// // Start the chat session with the cached session
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_session(session)
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_session(
+        mut self,
+        session: <M::SyncModel as kalosm_language_model::SyncModel>::Session,
+    ) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Try to load the chat session from the given path. If the session is not found, the default session will be used.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_try_session_path("./chat.llama")
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_try_session_path(self, path: impl AsRef<std::path::Path>) -> Self {
+        let session = <M::SyncModel as kalosm_language_model::SyncModel>::Session::load_from(path);
+        if let Ok(session) = session {
+            self.with_session(session)
+        } else {
+            self
+        }
+    }
+
+    /// Restore a chat from a [`ChatTranscript`]. This sets the chat history, system prompt and generation parameters of the chat, and the session of the model if the transcript includes one. Like [`ChatBuilder::with_generation_parameters`], this replaces a custom [`Sampler`], and logit biases keyed by text are tokenized with the tokenizer of the model when the chat is built.
+    ///
+    /// When the chat is built, the session is checked against the history. If the session doesn't match the history (for example because the transcript was saved with a different model), the history is fed to a new session instead.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let transcript = ChatTranscript::load_from("./chat.kalosm").unwrap();
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_transcript(transcript)
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_transcript(mut self, transcript: ChatTranscript) -> Self {
+        let ChatTranscript {
+            history,
+            system_prompt,
+            generation_parameters,
+            session,
+        } = transcript;
+        if let Some(session) = transcript_session(session) {
+            self.session = Some(session);
+        }
+        self.system_prompt = system_prompt;
+        self.initial_history = history;
+        self.with_generation_parameters(generation_parameters)
+    }
+
+    /// Try to restore the chat from a [`ChatTranscript`] saved at the given path. If the transcript is not found, the chat starts empty.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_try_transcript_path("./chat.kalosm")
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_try_transcript_path(self, path: impl AsRef<Path>) -> Self {
+        match ChatTranscript::load_from(path) {
+            Ok(transcript) => self.with_transcript(transcript),
+            Err(_) => self,
+        }
+    }
+
+    /// Set the initial history of the chat. Each message in the original history will be added to the chat history, and the model will be fed the user messages.
+    ///
+    /// > **Note**: If the initial history doesn't start with a system prompt, the system prompt of the chat is added before it.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_initial_history(vec![
+    ///         ChatHistoryItem::new(MessageType::SystemPrompt, "The assistant will act like a pirate."),
+    ///         ChatHistoryItem::new(MessageType::UserMessage, "Hello!"),
+    ///         ChatHistoryItem::new(MessageType::ModelAnswer, "Arrr matey, how ar ya?"),
+    ///     ])
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_initial_history(mut self, initial_history: Vec<ChatHistoryItem>) -> Self {
+        self.initial_history = initial_history;
+        self
+    }
+
+    /// Sets the [`ContextPolicy`] that keeps the conversation within the context window of the model. (Defaults to [`ContextPolicy::Unbounded`])
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     // Drop the oldest messages once the conversation grows past 4096 tokens
+    ///     .with_context_policy(ContextPolicy::DropOldest { max_tokens: 4096 })
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_context_policy(mut self, context_policy: ContextPolicy) -> Self {
+        self.context_policy = context_policy;
+        self
+    }
+
+    /// Keep a snapshot of the model session after each answer in the [`ChatTree`], including answers that were cancelled. When you switch branches with [`Chat::checkout`], the chat starts from the latest snapshot on the branch instead of feeding the history of the branch to the model again. (Defaults to false)
+    ///
+    /// Each snapshot is a copy of the session, so only the 8 most recent snapshots are kept and older snapshots are evicted. The session of the model must support [`Session::try_clone`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_session_snapshots(true)
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_session_snapshots(mut self, session_snapshots: bool) -> Self {
+        self.session_snapshots = session_snapshots;
+        self
+    }
+
+    /// Describes tools to the model. The tools are passed to the [`ChatTemplate`] of the model, which lays them out in the prompt the way the model was trained on.
+    ///
+    /// The model writes tool calls in its answer in its own format, so parse the answer to run the tools and add the results as a message. Tools are ignored for models without a chat template.
+    pub fn with_tools(mut self, tools: impl IntoIterator<Item = ToolDefinition>) -> Self {
+        self.tools.extend(tools);
+        self
+    }
+
+    /// Adds a [`Participant`] to the chat. Once a chat has participants, the model speaks as the assistant participants instead of a single assistant, and the [`Orchestrator`] picks who speaks next.
+    ///
+    /// The participants are described after the system prompt of the chat, so they all share the same session of the model.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_participant(Participant::user("Alice"))
+    ///     .with_participant(
+    ///         Participant::assistant("Bob").with_system_prompt("A grumpy wizard who speaks in riddles."),
+    ///     )
+    ///     .build();
+    ///
+    /// chat.add_message_from("Alice", "Bob, where is the treasure?")
+    ///     .to_std_out()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn with_participant(mut self, participant: Participant) -> Self {
+        self.participants.push(participant);
+        self
+    }
+
+    /// Sets the [`Orchestrator`] that picks which [`Participant`] speaks next. (Defaults to [`RoundRobin`])
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_participant(Participant::assistant("Optimist"))
+    ///     .with_participant(Participant::assistant("Pessimist"))
+    ///     // Always let the optimist have the last word
+    ///     .with_orchestrator(|history: &[ChatHistoryItem], _: &[Participant]| {
+    ///         (history.last()?.name() != Some("Optimist")).then_some(0)
+    ///     })
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn with_orchestrator(mut self, orchestrator: impl Orchestrator) -> Self {
+        self.orchestrator = Box::new(orchestrator);
+        self
+    }
+
+    /// Builds a [`Chat`] instance.
+    ///
+    /// If the model exposes a [`ChatTemplate`] or [`ChatMarkers`], the chat is generated locally with the model's [`SyncModel`]. The chat template is preferred because it can lay out any chat history the model was trained on. Otherwise the chat history is sent to [`Model::stream_chat_fallible_inner`] for every response.
+    pub fn build(self) -> Chat
+    where
+        <M::SyncModel as SyncModel>::Session: Send,
+    {
+        let Self {
+            model,
+            chat_markers,
+            chat_template,
+            system_prompt,
+            sampler,
+            generation_parameters,
+            bot_constraints,
+            session,
+            initial_history,
+            context_policy,
+            session_snapshots,
+            tools,
+            participants,
+            orchestrator,
+        } = self;
+        if chat_template.is_none() && !tools.is_empty() {
+            tracing::warn!("Tools are only supported for models with a chat template. The tools will be ignored.");
+        }
+        let format = match (chat_template, chat_markers) {
+            (Some(template), _) => ChatFormat::Template { template, tools },
+            (None, Some(chat_markers)) => ChatFormat::Markers(chat_markers),
+            (None, None) => {
+                if bot_constraints.is_some() {
+                    tracing::warn!("Constraints are not supported for models without a chat template or chat markers. The constraints will be ignored.");
+                }
+                if context_policy != ContextPolicy::Unbounded {
+                    tracing::warn!("Context policies are not supported for models without a chat template or chat markers. The policy will be ignored.");
+                }
+                if !participants.is_empty() {
+                    tracing::warn!("Participants are not supported for models without a chat template or chat markers. The participants will be ignored.");
+                }
+                return Self::build_remote(
+                    model,
+                    system_prompt,
+                    generation_parameters,
+                    initial_history,
+                );
+            }
+        };
+        let speakers = Speakers::new(participants, orchestrator);
+        let (sender_tx, mut sender_rx) = unbounded_channel();
+        let shared_tree = Arc::new(RwLock::new(ChatTree::default()));
+        {
+            let shared_tree = shared_tree.clone();
+
+            tokio::spawn(async move {
+                let (tx, rx) = oneshot::channel();
+                {
+                    model
+                        .run_sync(move |model| {
+                            Box::pin(async move {
+                                let _ = tx.send(ChatSession::new(
+                                    model,
+                                    format,
+                                    system_prompt,
+                                    generation_parameters,
+                                    bot_constraints,
+                                    sampler,
+                                    context_policy,
+                                    session,
+                                    session_snapshots,
+                                    speakers,
+                                    initial_history,
+                                    shared_tree,
+                                ));
+                            })
+                        })
+                        .unwrap();
+                }
+
+                let session = match rx.await {
+                    Ok(Ok(session)) => session,
+                    Ok(Err(err)) => {
+                        tracing::error!("Error loading session: {}", err);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::error!("Error loading session");
+                        return;
+                    }
+                };
+                let chat_session = Arc::new(Mutex::new(session));
+
+                while let Some(message) = sender_rx.recv().await {
+                    match message {
+                        Message::Turn {
+                            turn,
+                            response,
+                            resolve,
+                        } => {
+                            let chat_session = chat_session.clone();
+                            model
+                                .run_sync(move |model| {
+                                    Box::pin(async move {
+                                        let mut chat_session = chat_session.lock().unwrap();
+                                        let result = chat_session.take_turn(turn, model, response);
+                                        if let Err(err) = &result {
+                                            tracing::error!("Error generating response: {}", err);
+                                        }
+                                        let _ = resolve.send(result);
+                                    })
+                                })
+                                .unwrap();
+                        }
+                        Message::SaveSession { path, resolve } => {
+                            let chat_session = chat_session.lock().unwrap();
+                            resolve.send(chat_session.session.save_to(path)).unwrap();
+                        }
+                        Message::Transcript { resolve } => {
+                            let chat_session = chat_session.lock().unwrap();
+                            let _ = resolve.send(chat_session.transcript());
+                        }
+                        Message::Checkout { node, resolve } => {
+                            let chat_session = chat_session.clone();
+                            model
+                                .run_sync(move |model| {
+                                    Box::pin(async move {
+                                        let mut chat_session = chat_session.lock().unwrap();
+                                        let _ = resolve.send(chat_session.checkout(node, model));
+                                    })
+                                })
+                                .unwrap();
+                        }
+                    }
+                }
+            });
+        }
+
+        Chat {
+            sender: sender_tx,
+            shared_tree,
+        }
+    }
+
+    /// Builds a [`Chat`] instance that generates responses with [`Model::stream_chat_fallible_inner`].
+    fn build_remote(
+        model: M,
+        system_prompt: Option<String>,
+        generation_parameters: GenerationParameters,
+        initial_history: Vec<ChatHistoryItem>,
+    ) -> Chat {
+        let (sender_tx, mut sender_rx) = unbounded_channel();
+        let shared_tree = Arc::new(RwLock::new(ChatTree::default()));
+        let mut chat_session = RemoteChatSession::new(
+            system_prompt,
+            generation_parameters,
+            initial_history,
+            shared_tree.clone(),
+        );
+
+        tokio::spawn(async move {
+            while let Some(message) = sender_rx.recv().await {
+                match message {
+                    Message::Turn {
+                        turn,
+                        response,
+                        resolve,
+                    } => {
+                        let result = chat_session.take_turn(turn, &model, response).await;
+                        if let Err(err) = &result {
+                            tracing::error!("Error generating response: {}", err);
+                        }
+                        let _ = resolve.send(result);
+                    }
+                    Message::SaveSession { resolve, .. } => {
+                        let _ = resolve.send(Err(anyhow::anyhow!(
+                            "Saving sessions is not supported for models without a chat template or chat markers"
+                        )));
+                    }
+                    Message::Transcript { resolve } => {
+                        let _ = resolve.send(Ok(chat_session.transcript()));
+                    }
+                    Message::Checkout { node, resolve } => {
+                        let _ = resolve.send(chat_session.checkout(node));
+                    }
+                }
+            }
+        });
+
+        Chat {
+            sender: sender_tx,
+            shared_tree,
+        }
+    }
+}
+
+/// A change to the chat history that the model may respond to.
+enum Turn {
+    /// Add a user message.
+    AddMessage(ChatHistoryItem),
+    /// Remove every answer after the last user message and answer again.
+    RegenerateLast,
+    /// Replace the text of a message and remove every message after it.
+    EditMessage { index: usize, text: String },
+    /// Generate a message as the participant with the given name, or as the participant the orchestrator picks.
+    Speak(Option<String>),
+}
+
+enum Message {
+    Turn {
+        turn: Turn,
+        response: ResponseSender,
+        resolve: tokio::sync::oneshot::Sender<Result<()>>,
+    },
+    SaveSession {
+        path: PathBuf,
+        resolve: tokio::sync::oneshot::Sender<Result<()>>,
+    },
+    Transcript {
+        resolve: tokio::sync::oneshot::Sender<Result<ChatTranscript>>,
+    },
+    Checkout {
+        node: ChatNodeId,
+        resolve: tokio::sync::oneshot::Sender<Result<()>>,
+    },
+}
+
+/// The sending half of a [`ChatResponseStream`].
+struct ResponseSender {
+    sender: tokio::sync::mpsc::UnboundedSender<String>,
+    cancelled: Arc<AtomicBool>,
+    speaker: Option<oneshot::Sender<String>>,
+}
+
+impl ResponseSender {
+    /// Send text to the stream. Returns false if the response was cancelled or the stream was dropped.
+    fn send(&self, text: String) -> bool {
+        !self.cancelled.load(Ordering::SeqCst) && self.sender.send(text).is_ok()
+    }
+
+    /// Tell the stream which participant is speaking.
+    fn set_speaker(&mut self, name: String) {
+        if let Some(speaker) = self.speaker.take() {
+            let _ = speaker.send(name);
+        }
+    }
+}
+
+/// Create a new response stream, the sender that feeds it and the sender that resolves the result of the turn.
+fn response_channel() -> (
+    ResponseSender,
+    oneshot::Sender<Result<()>>,
+    ChatResponseStream,
+) {
+    let (sender, receiver) = unbounded_channel();
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (speaker, speaker_rx) = oneshot::channel();
+    let (resolve, result_rx) = oneshot::channel();
+    (
+        ResponseSender {
+            sender,
+            cancelled: cancelled.clone(),
+            speaker: Some(speaker),
+        },
+        resolve,
+        ChatResponseStream {
+            stream: ChannelTextStream::from(receiver),
+            cancel: ChatCancelHandle { cancelled },
+            speaker_rx: Some(speaker_rx),
+            speaker: None,
+            result_rx,
+        },
+    )
+}
+
+/// A stream of the text the model responds with. The stream is returned by [`Chat::add_message`], [`Chat::regenerate_last`], [`Chat::edit_message`] and the methods that make [`Participant`]s speak.
+///
+/// Generation can be stopped early with [`ChatResponseStream::cancel`] or by dropping the stream. The text generated so far is kept in the chat history as the answer. If the turn fails, the stream ends early and [`ChatResponseStream::result`] returns the error.
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+/// let mut response = chat.add_message("Write a long story about a pirate.");
+/// // Stop the response after 20 tokens
+/// let mut tokens = 0;
+/// while let Some(token) = response.next().await {
+///     print!("{token}");
+///     tokens += 1;
+///     if tokens == 20 {
+///         response.cancel();
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ChatResponseStream {
+    stream: ChannelTextStream,
+    cancel: ChatCancelHandle,
+    speaker_rx: Option<oneshot::Receiver<String>>,
+    speaker: Option<String>,
+    result_rx: oneshot::Receiver<Result<()>>,
+}
+
+impl ChatResponseStream {
+    /// Get the name of the [`Participant`] the model responds as. Returns `None` if the chat doesn't have participants or the model doesn't respond.
+    ///
+    /// The speaker is picked before the response starts, so this waits for any earlier responses of the chat to finish.
+    pub async fn speaker(&mut self) -> Option<&str> {
+        if let Some(speaker_rx) = self.speaker_rx.take() {
+            self.speaker = speaker_rx.await.ok();
+        }
+        self.speaker.as_deref()
+    }
+
+    /// Stop generating the response. The text generated so far is kept in the chat history as the answer.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Get a handle that can cancel the response while the stream is being read somewhere else.
+    pub fn cancel_handle(&self) -> ChatCancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Wait for the turn to finish. Returns an error if the turn failed, for example because [`Chat::edit_message`] got an index outside of the history or [`Chat::regenerate_last`] had no message to respond to. A cancelled response is not an error.
+    ///
+    /// Any text that wasn't read from the stream yet is dropped, so read the stream to the end first if you need the text.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// let mut response = chat.edit_message(99, "There is no message at this index");
+    /// response.to_std_out().await.unwrap();
+    /// assert!(response.result().await.is_err());
+    /// # }
+    /// ```
+    pub async fn result(self) -> Result<()> {
+        self.result_rx
+            .await
+            .map_err(|_| anyhow::anyhow!("Model stopped"))?
+    }
+}
+
+impl Stream for ChatResponseStream {
+    type Item = String;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.poll_next_unpin(cx)
+    }
+}
+
+/// A handle that cancels a [`ChatResponseStream`].
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+/// let mut response = chat.add_message("Write a long story about a pirate.");
+/// let cancel = response.cancel_handle();
+/// // Stop the response after one second
+/// tokio::spawn(async move {
+///     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+///     cancel.cancel();
+/// });
+/// response.to_std_out().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ChatCancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ChatCancelHandle {
+    /// Stop generating the response.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Check if the response was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// [`Chat`] is a chat interface that builds on top of [`kalosm_language_model::Model`]. It makes it easy to create a chat session with streaming responses, and constraints.
+///
+/// Let's start with a simple chat application:
+///
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// // Before you create a chat session, you need a model. Llama::new_chat will create a good default chat model.
+/// let model = Llama::new_chat().await.unwrap();
+/// // Then you can build a chat session that uses that model
+/// let mut chat = Chat::builder(model)
+///     // The builder exposes methods for settings like the system prompt and constraints the bot response must follow
+///     .with_system_prompt("The assistant will act like a pirate")
+///     // Once you are done setting up the chat session, you can build it
+///     .build();
+///
+/// loop {
+///     // To use the chat session, you need to add messages to it
+///     let mut response_stream = chat.add_message(prompt_input("\n> ").unwrap());
+///     // And then display the response stream to the user
+///     response_stream.to_std_out().await.unwrap();
+/// }
+/// # }
+/// ```
+///
+/// If you run the application, you may notice that it takes more time for the assistant to start responding to long prompts.
+/// The LLM needs to read and transform the prompt into a format it understands before it can start generating a response.
+/// Kalosm stores that state in a chat session, which can be saved and loaded from the filesystem to make loading existing chat sessions faster.
+///
+/// You can save and load chats from the filesystem using the [`Self::transcript`] and [`ChatBuilder::with_try_transcript_path`] methods. A [`ChatTranscript`] includes the chat history and settings along with the session, so the restored chat picks up where it left off:
+///
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// // First, create a model to chat with
+/// let model = Llama::new_chat().await.unwrap();
+/// // Then try to load the chat from the filesystem
+/// let save_path = std::path::PathBuf::from("./chat.kalosm");
+/// let mut chat = Chat::builder(model)
+///     // You can try to load the chat from the filesystem with the `with_try_transcript_path` method
+///     .with_try_transcript_path(&save_path)
+///     .build();
+///
+/// // Then you can add messages to the chat session as usual
+/// let mut response_stream = chat.add_message(prompt_input("\n> ").unwrap());
+/// // And then display the response stream to the user
+/// response_stream.to_std_out().await.unwrap();
+///
+/// // After you are done, you can save the chat to the filesystem
+/// chat.transcript().await.unwrap().save_to(&save_path).unwrap();
+/// # }
+/// ```
+///
+/// LLMs are powerful because of their generality, but sometimes you need more control over the output. For example, you might want the assistant to start with a certain phrase, or to follow a certain format.
+///
+/// In kalosm, you can use constraints to guide the model's response. Constraints are a way to specify the format of the output. When generating with constraints, the model will always respond with the specified format.
+///
+///
+/// Let's create a chat application that uses constraints to guide the assistant's response to always start with "Yes!":
+///
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let model = Llama::new_chat().await.unwrap();
+/// // Create constraints that parses Yes! and then stops on the end of the assistant's response
+/// let constraints = LiteralParser::new("Yes!")
+///     .then(model.default_assistant_constraints().unwrap());
+/// // Create a chat session with the model and the constraints
+/// let mut chat = Chat::builder(model)
+///     .constrain_response(move |_history| constraints.clone())
+///     .build();
+///
+/// // Chat with the user
+/// loop {
+///     let mut output_stream = chat.add_message(prompt_input("\n> ").unwrap());
+///     output_stream.to_std_out().await.unwrap();
+/// }
+/// # }
+/// ```
+pub struct Chat {
+    sender: tokio::sync::mpsc::UnboundedSender<Message>,
+    shared_tree: Arc<RwLock<ChatTree>>,
+}
+
+impl Chat {
+    /// Creates a new builder for a chat session.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// // Before you create a chat session, you need to create a model. Llama::new_chat will create a good default chat model.
+    /// let model = Llama::new_chat().await.unwrap();
+    /// // If you don't need to customize the chat session, you can use the `new` method to create a chat session with the default settings
+    /// let mut chat = Chat::builder(model)
+    ///     // The builder exposes methods for settings like the system prompt and constraints the bot response must follow
+    ///     .with_system_prompt("The assistant will act like a pirate")
+    ///     // Once you are done setting up the chat session, you can build it
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn builder<M: Model>(model: M) -> ChatBuilder<M>
+    where
+        <M::SyncModel as SyncModel>::Session: Send,
+    {
+        ChatBuilder::new(model)
+    }
+
+    /// Create a new chat session with the default settings.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// // Before you create a chat session, you need to create a model. Llama::new_chat will create a good default chat model.
+    /// let model = Llama::new_chat().await.unwrap();
+    /// // If you don't need to customize the chat session, you can use the `new` method to create a chat session with the default settings
+    /// let mut chat = Chat::new(model);
+    /// # }
+    /// ```
+    pub fn new<M: Model>(model: M) -> Chat
+    where
+        <M::SyncModel as SyncModel>::Session: Send,
+    {
+        Self::builder(model).build()
+    }
+
+    /// Adds a user message to the chat session and streams the bot response. The response can be stopped early with [`ChatResponseStream::cancel`].
+    ///
+    /// If the chat has [`Participant`]s, the [`Orchestrator`] picks who responds. If it doesn't pick an assistant participant, the stream ends without any text.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// let prompt = prompt_input("\n> ").unwrap();
+    ///
+    /// // You can add the user message to the chat session with the `add_message` method
+    /// let mut response_stream = chat.add_message(prompt);
+    /// // And then stream the result to std out
+    /// response_stream.to_std_out().await.unwrap();
+    /// # }
+    /// ```
+    pub fn add_message(&mut self, message: impl ToString) -> ChatResponseStream {
+        let message = message.to_string();
+        let message = message.trim().to_string();
+        self.take_turn(Turn::AddMessage(ChatHistoryItem::new(
+            MessageType::UserMessage,
+            message,
+        )))
+    }
+
+    /// Adds a user message written by the [`Participant`] with the given name and streams the response of the participant the [`Orchestrator`] picks.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_participant(Participant::user("Alice"))
+    ///     .with_participant(Participant::user("Bob"))
+    ///     .with_participant(Participant::assistant("Moderator"))
+    ///     .build();
+    ///
+    /// chat.add_message_from("Alice", "I think cats are better than dogs.")
+    ///     .to_std_out()
+    ///     .await
+    ///     .unwrap();
+    /// chat.add_message_from("Bob", "Dogs are clearly better!")
+    ///     .to_std_out()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn add_message_from(
+        &mut self,
+        name: impl ToString,
+        message: impl ToString,
+    ) -> ChatResponseStream {
+        let message = message.to_string();
+        let message = message.trim().to_string();
+        self.take_turn(Turn::AddMessage(
+            ChatHistoryItem::new(MessageType::UserMessage, message).with_name(name.to_string()),
+        ))
+    }
+
+    /// Streams a new message from the assistant [`Participant`] with the given name without adding a user message first.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_participant(Participant::assistant("Writer"))
+    ///     .with_participant(Participant::assistant("Critic"))
+    ///     .build();
+    ///
+    /// chat.add_message("Write a haiku about the sea.")
+    ///     .to_std_out()
+    ///     .await
+    ///     .unwrap();
+    /// // Ask the critic for feedback, even if the orchestrator would pick someone else
+    /// chat.respond_as("Critic").to_std_out().await.unwrap();
+    /// # }
+    /// ```
+    pub fn respond_as(&mut self, name: impl ToString) -> ChatResponseStream {
+        self.take_turn(Turn::Speak(Some(name.to_string())))
+    }
+
+    /// Streams a new message from the [`Participant`] the [`Orchestrator`] picks without adding a user message first. Use [`ChatResponseStream::speaker`] to find out who is speaking.
+    ///
+    /// If the chat doesn't have participants, the model continues the chat with another assistant message.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_system_prompt("Debate whether pineapple belongs on pizza.")
+    ///     .with_participant(Participant::assistant("Alice").with_system_prompt("Loves pineapple."))
+    ///     .with_participant(Participant::assistant("Bob").with_system_prompt("Hates pineapple."))
+    ///     .build();
+    ///
+    /// for _ in 0..6 {
+    ///     let mut response = chat.next_turn();
+    ///     print!("\n{}: ", response.speaker().await.unwrap_or_default());
+    ///     response.to_std_out().await.unwrap();
+    /// }
+    /// # }
+    /// ```
+    pub fn next_turn(&mut self) -> ChatResponseStream {
+        self.take_turn(Turn::Speak(None))
+    }
+
+    /// Removes every answer after the last user message from the chat and streams a new response. The session of the model is rewound to the end of the user message, so the rest of the chat doesn't need to be fed to the model again.
+    ///
+    /// If several [`Participant`]s answered the message, all of their answers are removed and the participant who answered first responds again.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// chat.add_message("Tell me a joke.").to_std_out().await.unwrap();
+    /// // Try again with a different joke
+    /// chat.regenerate_last().to_std_out().await.unwrap();
+    /// # }
+    /// ```
+    pub fn regenerate_last(&mut self) -> ChatResponseStream {
+        self.take_turn(Turn::RegenerateLast)
+    }
+
+    /// Replaces the text of the message at `index` in the [`Chat::history`] and removes every message after it. If the edited message is a user message, the model responds to it and the response is streamed. Otherwise the stream ends without any text.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// chat.add_message("What is the capital of France?")
+    ///     .to_std_out()
+    ///     .await
+    ///     .unwrap();
+    /// // The first message is the system prompt, so the user message is at index 1
+    /// chat.edit_message(1, "What is the capital of Germany?")
+    ///     .to_std_out()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn edit_message(&mut self, index: usize, text: impl ToString) -> ChatResponseStream {
+        let text = text.to_string();
+        let text = text.trim().to_string();
+        self.take_turn(Turn::EditMessage { index, text })
+    }
+
+    /// Send a turn to the chat session and return the stream of the response.
+    fn take_turn(&mut self, turn: Turn) -> ChatResponseStream {
+        let (response, resolve, stream) = response_channel();
+        let _ = self.sender.send(Message::Turn {
+            turn,
+            response,
+            resolve,
+        });
+        stream
+    }
+
+    /// Saves the session to the given path.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// let save_path = std::path::PathBuf::from("./chat.llama");
+    /// chat.save_session(&save_path).await.unwrap();
+    /// # }
+    /// ```
+    pub fn save_session(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> impl Future<Output = Result<()>> {
+        let (tx, rx) = oneshot::channel();
+        let result = self.sender.send(Message::SaveSession {
+            path: path.as_ref().to_path_buf(),
+            resolve: tx,
+        });
+        async move {
+            result.map_err(|_| anyhow::anyhow!("Model stopped"))?;
+            rx.await.map_err(|_| anyhow::anyhow!("Model stopped"))?
+        }
+    }
+
+    /// Get a [`ChatTranscript`] with the current branch of the history, the settings and the model session of the chat. The transcript can be saved with [`ChatTranscript::save_to`] and restored with [`ChatBuilder::with_transcript`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// chat.add_message("Hello, world!").to_std_out().await.unwrap();
+    /// let transcript = chat.transcript().await.unwrap();
+    /// transcript.save_to("./chat.kalosm").unwrap();
+    /// # }
+    /// ```
+    pub fn transcript(&self) -> impl Future<Output = Result<ChatTranscript>> {
+        let (tx, rx) = oneshot::channel();
+        let result = self.sender.send(Message::Transcript { resolve: tx });
+        async move {
+            result.map_err(|_| anyhow::anyhow!("Model stopped"))?;
+            rx.await.map_err(|_| anyhow::anyhow!("Model stopped"))?
+        }
+    }
+
+    /// Get the tree of every branch the chat has had. The current history is the path to [`ChatTree::current`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// chat.add_message("Tell me a joke.").to_std_out().await.unwrap();
+    /// chat.regenerate_last().to_std_out().await.unwrap();
+    /// // The tree has a branch for each joke
+    /// let tree = chat.tree();
+    /// for leaf in tree.leaves() {
+    ///     println!("{:?}", tree.history(leaf).unwrap());
+    /// }
+    /// # }
+    /// ```
+    pub fn tree(&self) -> ChatTree {
+        self.shared_tree.read().unwrap().clone()
+    }
+
+    /// Switch the chat history to the branch of the [`ChatTree`] that ends at `node`. New messages continue from that branch, so adding a message after switching to an earlier message forks the chat.
+    ///
+    /// If session snapshots are enabled with [`ChatBuilder::with_session_snapshots`], the chat starts from the latest snapshot on the branch. Otherwise the session is rewound to the part of the history shared with the current branch and the rest of the branch is fed to the model with the next message.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+    ///     .with_session_snapshots(true)
+    ///     .build();
+    /// chat.add_message("Tell me a joke.").to_std_out().await.unwrap();
+    /// let first_joke = chat.tree().current().unwrap();
+    /// chat.regenerate_last().to_std_out().await.unwrap();
+    ///
+    /// // Go back to the first joke and continue the chat from there
+    /// chat.checkout(first_joke).await.unwrap();
+    /// chat.add_message("Explain the joke.").to_std_out().await.unwrap();
+    /// # }
+    /// ```
+    pub fn checkout(&mut self, node: ChatNodeId) -> impl Future<Output = Result<()>> {
+        let (tx, rx) = oneshot::channel();
+        let result = self.sender.send(Message::Checkout { node, resolve: tx });
+        async move {
+            result.map_err(|_| anyhow::anyhow!("Model stopped"))?;
+            rx.await.map_err(|_| anyhow::anyhow!("Model stopped"))?
+        }
+    }
+
+    /// Get the current chat history.
+    ///
+    /// With [`ContextPolicy::SlidingWindow`], this includes messages that slid out of the window of the model.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+    /// // Add a message to the chat history
+    /// chat.add_message("Hello, world!").to_std_out().await.unwrap();
+    /// // Get the chat history
+    /// let history = chat.history();
+    /// println!("{:?}", history);
+    /// # }
+    /// ```
+    pub fn history(&self) -> Vec<ChatHistoryItem> {
+        self.shared_tree.read().unwrap().current_history()
+    }
+}
+
+#[test]
+fn drop_oldest_turns_keeps_system_prompt() {
+    let history = vec![
+        ChatHistoryItem::new(MessageType::SystemPrompt, "system"),
+        ChatHistoryItem::new(MessageType::UserMessage, "first question"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "first answer"),
+        ChatHistoryItem::new(MessageType::UserMessage, "second question"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "second answer"),
+    ];
+    let token_counts = [10, 5, 5, 5, 5];
+
+    // Everything fits
+    assert_eq!(oldest_turns_to_drop(&history, &token_counts, 30), 1..1);
+    // Dropping the first question is enough, but the answer is dropped with it
+    assert_eq!(oldest_turns_to_drop(&history, &token_counts, 25), 1..3);
+    // Even if nothing fits, the system prompt is kept
+    assert_eq!(oldest_turns_to_drop(&history, &token_counts, 0), 1..5);
+}
+
+#[test]
+fn system_prompt_is_only_added_when_missing() {
+    let restored = vec![
+        ChatHistoryItem::new(MessageType::SystemPrompt, "restored"),
+        ChatHistoryItem::new(MessageType::UserMessage, "question"),
+    ];
+    assert_eq!(
+        with_system_prompt(Some("system".to_string()), restored.clone()),
+        restored
+    );
+
+    let history = with_system_prompt(Some("system".to_string()), restored[1..].to_vec());
+    assert_eq!(
+        history,
+        vec![
+            ChatHistoryItem::new(MessageType::SystemPrompt, "system"),
+            ChatHistoryItem::new(MessageType::UserMessage, "question"),
+        ]
+    );
+}
+
+#[test]
+fn regenerating_and_editing_rewinds_history() {
+    let history = vec![
+        ChatHistoryItem::new(MessageType::SystemPrompt, "system"),
+        ChatHistoryItem::new(MessageType::UserMessage, "first question"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "first answer"),
+        ChatHistoryItem::new(MessageType::UserMessage, "second question"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "second answer"),
+    ];
+
+    // Regenerating drops the last answer
+    assert_eq!(without_last_answer(&history).unwrap(), history[..4]);
+    // Regenerating without a message to answer fails
+    assert!(without_last_answer(&history[..1]).is_err());
+
+    // Editing replaces the message and drops everything after it
+    let edited = with_edited_message(&history, 1, "edited question".to_string()).unwrap();
+    assert_eq!(
+        edited,
+        vec![
+            ChatHistoryItem::new(MessageType::SystemPrompt, "system"),
+            ChatHistoryItem::new(MessageType::UserMessage, "edited question"),
+        ]
+    );
+    assert!(with_edited_message(&history, 5, "out of bounds".to_string()).is_err());
+}
+
+#[test]
+fn named_messages_keep_their_speaker() {
+    let history = vec![
+        ChatHistoryItem::new(MessageType::SystemPrompt, "system"),
+        ChatHistoryItem::new(MessageType::UserMessage, "question").with_name("Alice"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "first answer").with_name("Bob"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "second answer").with_name("Carol"),
+    ];
+
+    // The name is written into the rendered message
+    let rendered = prefix_speaker_name(&history[1]);
+    assert_eq!(rendered.contents(), "Alice: question");
+    assert_eq!(rendered.name(), None);
+    assert_eq!(prefix_speaker_name(&history[0]), history[0]);
+
+    // Regenerating drops the answers of every participant who answered the last question
+    assert_eq!(without_last_answer(&history).unwrap(), history[..2]);
+    // Editing keeps the speaker of the message
+    let edited = with_edited_message(&history, 1, "edited question".to_string()).unwrap();
+    assert_eq!(edited[1].name(), Some("Alice"));
+}
+
+/// A model that only records the tokens it is fed. Each ASCII character is a token, and other characters are split into byte tokens.
+#[cfg(test)]
+struct FakeModel {
+    tokenizer: Arc<tokenizers::Tokenizer>,
+    /// The tokens the model generates next
+    script: Mutex<std::collections::VecDeque<u32>>,
+}
+
+#[cfg(test)]
+impl FakeModel {
+    fn new() -> Self {
+        let vocab = (0..=255u8)
+            .map(|byte| format!("<0x{byte:02X}>"))
+            .chain(
+                (b' '..=b'~')
+                    .chain([b'\n'])
+                    .map(|c| (c as char).to_string()),
+            )
+            .enumerate()
+            .map(|(id, token)| (token, serde_json::Value::from(id)))
+            .collect::<serde_json::Map<_, _>>();
+        let json = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": {
+                "type": "Sequence",
+                "decoders": [{ "type": "ByteFallback" }, { "type": "Fuse" }]
+            },
+            "model": { "type": "BPE", "vocab": vocab, "merges": [], "byte_fallback": true }
+        });
+        Self {
+            tokenizer: Arc::new(json.to_string().parse().unwrap()),
+            script: Default::default(),
+        }
+    }
+
+    /// Queue the text the model generates next. Once the queued tokens run out, the model generates the stop token.
+    fn generate_next(&self, text: &str) {
+        let encoding = self.tokenizer.encode(text, false).unwrap();
+        self.script.lock().unwrap().extend(encoding.get_ids());
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug, Default)]
+struct FakeSession {
+    tokens: Vec<u32>,
+}
+
+#[cfg(test)]
+impl Session for FakeSession {
+    fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let bytes: Vec<u8> = self
+            .tokens
+            .iter()
+            .flat_map(|token| token.to_le_bytes())
+            .collect();
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        if bytes.len() % 4 != 0 {
+            anyhow::bail!("The session is corrupted");
+        }
+        let tokens = bytes
+            .chunks_exact(4)
+            .map(|token| u32::from_le_bytes(token.try_into().unwrap()))
+            .collect();
+        Ok(Self { tokens })
+    }
+
+    fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<()> {
+        self.tokens.truncate(len);
+        Ok(())
+    }
+
+    fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            tokens: self.tokens.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+impl SyncModel for FakeModel {
+    type Session = FakeSession;
+
+    fn new_session(&self) -> Result<Self::Session> {
+        Ok(FakeSession::default())
+    }
+
+    fn feed_text(
+        &self,
+        session: &mut FakeSession,
+        prompt: &str,
+        into: &mut Vec<f32>,
+    ) -> Result<()> {
+        let encoding = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(anyhow::Error::msg)?;
+        self.feed_tokens(session, encoding.get_ids(), into)
+    }
+
+    fn feed_tokens(
+        &self,
+        session: &mut FakeSession,
+        tokens: &[u32],
+        into: &mut Vec<f32>,
+    ) -> Result<()> {
+        session.tokens.extend_from_slice(tokens);
+        into.clear();
+        into.resize(self.tokenizer.get_vocab_size(true), 0.);
+        let next = self.script.lock().unwrap().pop_front();
+        into[next.unwrap_or(self.stop_token()?) as usize] = 100.;
+        Ok(())
+    }
+
+    fn stop_token(&self) -> Result<u32> {
+        Ok(0)
+    }
+
+    fn tokenizer(&self) -> Arc<tokenizers::Tokenizer> {
+        self.tokenizer.clone()
+    }
+}
+
+/// Start a chat session without a system prompt or participants with the fake model.
+#[cfg(test)]
+fn fake_chat_session(
+    model: &mut FakeModel,
+    format: ChatFormat,
+    session: Option<FakeSession>,
+    history: Vec<ChatHistoryItem>,
+) -> ChatSession<FakeModel> {
+    ChatSession::new(
+        model,
+        format,
+        None,
+        GenerationParameters::default(),
+        None,
+        None,
+        ContextPolicy::default(),
+        session,
+        false,
+        Speakers::new(Vec::new(), Box::new(RoundRobin)),
+        history,
+        Default::default(),
+    )
+    .unwrap()
+}
+
+/// A [`Model`] that runs chats with a [`FakeModel`] and [`FAKE_CHAT_MARKERS`], so tests can go through the [`Chat`] API.
+#[cfg(test)]
+struct FakeChatModel(Arc<Mutex<FakeModel>>);
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Model for FakeChatModel {
+    type TextStream = ChannelTextStream;
+    type SyncModel = FakeModel;
+
+    fn tokenizer(&self) -> Arc<tokenizers::Tokenizer> {
+        self.0.lock().unwrap().tokenizer()
+    }
+
+    fn run_sync_raw(
+        &self,
+        f: Box<
+            dyn for<'a> FnOnce(
+                    &'a mut Self::SyncModel,
+                )
+                    -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + 'a>>
+                + Send,
+        >,
+    ) -> Result<()> {
+        use futures_util::FutureExt;
+
+        // The chat doesn't wait on anything while it uses the model, so the future is ready the first time it is polled
+        let mut model = self.0.lock().unwrap();
+        f(&mut model)
+            .now_or_never()
+            .ok_or_else(|| anyhow::anyhow!("The fake model can't wait on other tasks"))
+    }
+
+    async fn stream_text_inner(
+        &self,
+        _prompt: &str,
+        _parameters: GenerationParameters,
+    ) -> Result<Self::TextStream> {
+        anyhow::bail!("The fake model only runs chats")
+    }
+
+    fn chat_markers(&self) -> Option<ChatMarkers> {
+        Some(FAKE_CHAT_MARKERS)
+    }
+}
+
+/// Feed the queued text to the session followed by the generated text, like generating a response would.
+#[cfg(test)]
+fn feed_fake_chat(chat: &mut ChatSession<FakeModel>, model: &FakeModel, generated: &str) {
+    let text = std::mem::take(&mut chat.unfed_text) + generated;
+    model
+        .feed_text(&mut chat.session, &text, &mut Vec::new())
+        .unwrap();
+}
+
+/// Chat markers that wrap each message in a tag with its role.
+#[cfg(test)]
+const FAKE_CHAT_MARKERS: ChatMarkers = ChatMarkers {
+    system_prompt_marker: "<system>",
+    end_system_prompt_marker: "</system>",
+    user_marker: "<user>",
+    end_user_marker: "</user>",
+    assistant_marker: "<assistant>",
+    end_assistant_marker: "</assistant>",
+};
+
+#[test]
+fn finishing_an_answer_the_template_renders_differently_starts_over() {
+    let mut model = FakeModel::new();
+    // The template trims answers, so an answer that starts with a space is rendered differently than it was generated
+    let template = ChatTemplate::new(
+        "{% for message in messages %}<|{{ message.role }}|>{% if message.role == 'assistant' %}{{ message.content | trim }}{% else %}{{ message.content }}{% endif %}<|end|>{% endfor %}{% if add_generation_prompt %}<|assistant|>{% endif %}",
+        "",
+        "",
+    )
+    .unwrap();
+    assert_eq!(template.end_assistant_marker(), "<|end|>");
+
+    for (answer, session_kept, unfed_text) in [
+        ("answer", true, "<|end|>"),
+        (
+            " answer",
+            false,
+            "<|user|>question<|end|><|assistant|>answer<|end|>",
+        ),
+    ] {
+        let format = ChatFormat::Template {
+            template: template.clone(),
+            tools: Vec::new(),
+        };
+        let mut chat = fake_chat_session(&mut model, format, None, Vec::new());
+        let question = ChatHistoryItem::new(MessageType::UserMessage, "question");
+        let prompt = chat.add_messages(vec![question], true, &mut model).unwrap();
+        assert_eq!(prompt, "<|user|>question<|end|><|assistant|>");
+
+        feed_fake_chat(&mut chat, &model, answer);
+        let expected_tokens = if session_kept {
+            prompt.len() + answer.len()
+        } else {
+            0
+        };
+        let answer = ChatHistoryItem::new(MessageType::ModelAnswer, answer);
+        chat.finish_answer(prompt, answer, &mut model).unwrap();
+
+        assert_eq!(chat.session.tokens.len(), expected_tokens);
+        assert_eq!(chat.unfed_text, unfed_text);
+    }
+}
+
+#[test]
+fn chat_templates_render_tools() {
+    use kalosm_sample::{JsonObjectSchema, SchemaType};
+
+    let template = ChatTemplate::new(
+        "{% if tools %}{% for tool in tools %}[tool] {{ tool.function.name }}: {{ tool.function.description }}\n{% endfor %}{% endif %}{% for message in messages %}[{{ message.role }}] {{ message.content }}\n{% endfor %}",
+        "",
+        "",
+    )
+    .unwrap();
+    let history = [ChatHistoryItem::new(MessageType::UserMessage, "question")];
+
+    let format = ChatFormat::Template {
+        template: template.clone(),
+        tools: vec![ToolDefinition::new(
+            "search",
+            "Search the web",
+            SchemaType::Object(JsonObjectSchema::new([])),
+        )],
+    };
+    assert_eq!(
+        format.render(&history, false).unwrap(),
+        "[tool] search: Search the web\n[user] question\n"
+    );
+
+    let format = ChatFormat::Template {
+        template,
+        tools: Vec::new(),
+    };
+    assert_eq!(format.render(&history, false).unwrap(), "[user] question\n");
+}
+
+#[test]
+fn transcript_sessions_are_checked_against_the_history() {
+    let mut model = FakeModel::new();
+    let history = vec![
+        ChatHistoryItem::new(MessageType::UserMessage, "question"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "answer"),
+    ];
+    let rendered = "<user>question</user><assistant>answer</assistant>";
+
+    // Without a session, the whole history is fed to a new session
+    let mut chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        None,
+        history.clone(),
+    );
+    assert_eq!(chat.unfed_text, rendered);
+    feed_fake_chat(&mut chat, &model, "");
+    let transcript = chat.transcript().unwrap();
+
+    // The session saved with the same history is kept
+    let session = transcript_session::<FakeSession>(transcript.session.clone());
+    let chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        session,
+        transcript.history().to_vec(),
+    );
+    assert_eq!(chat.session.tokens.len(), rendered.len());
+    assert_eq!(chat.unfed_text, "");
+
+    // If the history doesn't match the session, only the part of the session that matches is kept
+    let mut edited = history.clone();
+    edited[1] = ChatHistoryItem::new(MessageType::ModelAnswer, "the answer");
+    let session = transcript_session::<FakeSession>(transcript.session.clone());
+    let chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        session,
+        edited,
+    );
+    let kept = "<user>question</user><assistant>";
+    assert_eq!(chat.session.tokens.len(), kept.len());
+    assert_eq!(chat.unfed_text, "the answer</assistant>");
+
+    // A session that can't be loaded is dropped and the history is fed to a new session
+    let session = transcript_session::<FakeSession>(Some(vec![1, 2, 3]));
+    assert!(session.is_none());
+    let chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        session,
+        history,
+    );
+    assert!(chat.session.tokens.is_empty());
+    assert_eq!(chat.unfed_text, rendered);
+}
+
+#[test]
+fn cancelled_answers_rewind_to_the_text_that_was_fed() {
+    let mut model = FakeModel::new();
+    let mut chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        None,
+        Vec::new(),
+    );
+    let question = ChatHistoryItem::new(MessageType::UserMessage, "question");
+    let prompt = chat
+        .add_messages(vec![question.clone()], true, &mut model)
+        .unwrap();
+    assert_eq!(prompt, "<user>question</user><assistant>");
+
+    // The answer was cancelled after the first byte of "é" was generated, so the session ends partway through a character
+    feed_fake_chat(&mut chat, &model, "caf");
+    model
+        .feed_tokens(&mut chat.session, &[0xC3], &mut Vec::new())
+        .unwrap();
+    let answer = ChatHistoryItem::new(MessageType::ModelAnswer, "caf");
+    chat.rewind(vec![question.clone(), answer.clone()], false, &mut model)
+        .unwrap();
+
+    assert_eq!(chat.session.tokens.len(), prompt.len() + "caf".len());
+    assert_eq!(chat.unfed_text, "</assistant>");
+    assert_eq!(chat.history(), vec![question, answer]);
+}
+
+#[test]
+fn edited_messages_keep_the_session_up_to_the_edit() {
+    // Pad the start of the chat by different amounts so the search for the matching tokens lands on every position around "é"
+    for padding in 0..16 {
+        let mut model = FakeModel::new();
+        let system_prompt = "x".repeat(padding);
+        let history = vec![
+            ChatHistoryItem::new(MessageType::SystemPrompt, system_prompt.clone()),
+            ChatHistoryItem::new(MessageType::UserMessage, "café"),
+            ChatHistoryItem::new(MessageType::ModelAnswer, "answer"),
+        ];
+        let mut chat = fake_chat_session(
+            &mut model,
+            ChatFormat::Markers(FAKE_CHAT_MARKERS),
+            None,
+            history.clone(),
+        );
+        feed_fake_chat(&mut chat, &model, "");
+
+        let edited = with_edited_message(&history, 1, "cafés".to_string()).unwrap();
+        let prompt = chat.rewind(edited.clone(), true, &mut model).unwrap();
+
+        // "é" is two byte tokens, and every other character is one token, so the number of tokens is the number of bytes
+        let kept = format!("<system>{system_prompt}</system><user>café");
+        assert_eq!(chat.session.tokens.len(), kept.len());
+        assert_eq!(chat.unfed_text, "s</user><assistant>");
+        assert_eq!(prompt, format!("{kept}s</user><assistant>"));
+        assert_eq!(chat.history(), edited);
+    }
+}
+
+#[test]
+fn regenerated_answers_keep_the_session_up_to_the_answer() {
+    let mut model = FakeModel::new();
+    let history = vec![
+        ChatHistoryItem::new(MessageType::UserMessage, "question"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "café"),
+    ];
+    let mut chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        None,
+        history.clone(),
+    );
+    feed_fake_chat(&mut chat, &model, "");
+
+    let prompt = chat
+        .rewind(without_last_answer(&history).unwrap(), true, &mut model)
+        .unwrap();
+    assert_eq!(prompt, "<user>question</user><assistant>");
+    assert_eq!(chat.session.tokens.len(), prompt.len());
+    assert_eq!(chat.unfed_text, "");
+
+    feed_fake_chat(&mut chat, &model, "another answer");
+    let answer = ChatHistoryItem::new(MessageType::ModelAnswer, "another answer");
+    chat.finish_answer(prompt, answer.clone(), &mut model)
+        .unwrap();
+    assert_eq!(chat.history(), vec![history[0].clone(), answer]);
+    assert_eq!(chat.unfed_text, "</assistant>");
+}
+
+#[tokio::test]
+async fn failed_turns_are_returned_from_the_response_stream() {
+    let mut model = FakeModel::new();
+    let mut chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        None,
+        Vec::new(),
+    );
+
+    for turn in [
+        Turn::EditMessage {
+            index: 99,
+            text: "out of bounds".to_string(),
+        },
+        Turn::RegenerateLast,
+    ] {
+        let (response, resolve, mut stream) = response_channel();
+        let _ = resolve.send(chat.take_turn(turn, &mut model, response));
+        assert_eq!(stream.next().await, None);
+        assert!(stream.result().await.is_err());
+    }
+}
+
+#[test]
+fn checkout_starts_from_the_latest_snapshot_on_the_branch() {
+    let mut model = FakeModel::new();
+    let mut chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        None,
+        Vec::new(),
+    );
+    chat.session_snapshots = true;
+    let question = ChatHistoryItem::new(MessageType::UserMessage, "question");
+    let prompt = chat
+        .add_messages(vec![question.clone()], true, &mut model)
+        .unwrap();
+    feed_fake_chat(&mut chat, &model, "first");
+    let first = ChatHistoryItem::new(MessageType::ModelAnswer, "first");
+    chat.finish_answer(prompt, first, &mut model).unwrap();
+    let first_node = chat.tree.read().unwrap().current().unwrap();
+
+    let prompt = chat
+        .rewind(vec![question.clone()], true, &mut model)
+        .unwrap();
+    feed_fake_chat(&mut chat, &model, "second");
+    let second = ChatHistoryItem::new(MessageType::ModelAnswer, "second");
+    chat.finish_answer(prompt, second, &mut model).unwrap();
+    let question_node = chat
+        .tree
+        .read()
+        .unwrap()
+        .node(first_node)
+        .unwrap()
+        .parent()
+        .unwrap();
+
+    // The first answer has a snapshot, so the session isn't rewound to the question
+    chat.checkout(first_node, &mut model).unwrap();
+    assert_eq!(
+        chat.session.tokens.len(),
+        "<user>question</user><assistant>first".len()
+    );
+    assert_eq!(chat.unfed_text, "</assistant>");
+
+    // The question has no snapshot, so the session is rewound
+    chat.checkout(question_node, &mut model).unwrap();
+    assert_eq!(chat.session.tokens.len(), "<user>question</user>".len());
+    assert_eq!(chat.unfed_text, "");
+    assert_eq!(chat.history(), vec![question]);
+}
+
+#[test]
+fn cancelled_answers_are_snapshotted() {
+    let mut model = FakeModel::new();
+    let mut chat = fake_chat_session(
+        &mut model,
+        ChatFormat::Markers(FAKE_CHAT_MARKERS),
+        None,
+        Vec::new(),
+    );
+    chat.session_snapshots = true;
+
+    // Dropping the stream cancels the answer after the first token
+    let (response, _, stream) = response_channel();
+    drop(stream);
+    model.generate_next("answer");
+    let question = ChatHistoryItem::new(MessageType::UserMessage, "question");
+    chat.take_turn(Turn::AddMessage(question), &mut model, response)
+        .unwrap();
+
+    let tree = chat.tree.read().unwrap();
+    let answer = tree.node(tree.current().unwrap()).unwrap();
+    assert_eq!(answer.item().contents(), "a");
+    assert!(answer.has_snapshot());
+}
+
+/// Read a response to the end and return the name of the speaker and the text.
+#[cfg(test)]
+async fn read_response(mut response: ChatResponseStream) -> Result<(Option<String>, String)> {
+    let speaker = response.speaker().await.map(ToString::to_string);
+    let mut text = String::new();
+    while let Some(token) = response.next().await {
+        text += &token;
+    }
+    response.result().await?;
+    Ok((speaker, text))
+}
+
+#[tokio::test]
+async fn participants_take_turns_through_the_chat() {
+    let model = Arc::new(Mutex::new(FakeModel::new()));
+    let mut chat = Chat::builder(FakeChatModel(model.clone()))
+        .with_participant(Participant::user("Alice"))
+        .with_participant(Participant::assistant("Bob"))
+        .with_participant(Participant::assistant("Carol"))
+        .build();
+    let generate_next = |text: &str| model.lock().unwrap().generate_next(text);
+
+    // The orchestrator skips users, so Bob answers Alice
+    generate_next("hi Alice");
+    let response = read_response(chat.add_message_from("Alice", "hello")).await;
+    assert_eq!(
+        response.unwrap(),
+        (Some("Bob".to_string()), "hi Alice".to_string())
+    );
+
+    generate_next("hey");
+    let response = read_response(chat.respond_as("Carol")).await;
+    assert_eq!(
+        response.unwrap(),
+        (Some("Carol".to_string()), "hey".to_string())
+    );
+
+    // Carol spoke last, so the turn goes back around to Bob
+    generate_next("welcome");
+    let response = read_response(chat.next_turn()).await;
+    assert_eq!(
+        response.unwrap(),
+        (Some("Bob".to_string()), "welcome".to_string())
+    );
+
+    // The model never speaks as a user
+    assert!(read_response(chat.respond_as("Alice")).await.is_err());
+    assert!(read_response(chat.respond_as("Dave")).await.is_err());
+
+    let messages = |chat: &Chat| {
+        chat.history()
+            .into_iter()
+            .filter(|item| item.ty() != MessageType::SystemPrompt)
+            .map(|item| {
+                (
+                    item.name().map(ToString::to_string),
+                    item.contents().to_string(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let message = |name: &str, text: &str| (Some(name.to_string()), text.to_string());
+    assert_eq!(
+        messages(&chat),
+        [
+            message("Alice", "hello"),
+            message("Bob", "hi Alice"),
+            message("Carol", "hey"),
+            message("Bob", "welcome"),
+        ]
+    );
+
+    // Regenerating drops every answer to Alice, and Bob answered first so Bob answers again
+    generate_next("good morning");
+    let response = read_response(chat.regenerate_last()).await;
+    assert_eq!(
+        response.unwrap(),
+        (Some("Bob".to_string()), "good morning".to_string())
+    );
+    assert_eq!(
+        messages(&chat),
+        [message("Alice", "hello"), message("Bob", "good morning")]
+    );
+}
+
+#[tokio::test]
+async fn custom_orchestrators_pick_the_speaker() {
+    let model = Arc::new(Mutex::new(FakeModel::new()));
+    let mut chat = Chat::builder(FakeChatModel(model.clone()))
+        .with_participant(Participant::user("Alice"))
+        .with_participant(Participant::assistant("Bob"))
+        .with_participant(Participant::assistant("Carol"))
+        // Carol answers messages that mention her, and nobody answers other messages
+        .with_orchestrator(|history: &[ChatHistoryItem], _: &[Participant]| {
+            let last = history.last()?;
+            last.contents().contains("Carol").then_some(2)
+        })
+        .build();
+
+    let response = read_response(chat.add_message_from("Alice", "hello")).await;
+    assert_eq!(response.unwrap(), (None, String::new()));
+    assert_eq!(chat.history().last().unwrap().name(), Some("Alice"));
+
+    model.lock().unwrap().generate_next("hi");
+    let response = read_response(chat.add_message_from("Alice", "hello Carol")).await;
+    assert_eq!(
+        response.unwrap(),
+        (Some("Carol".to_string()), "hi".to_string())
+    );
+}
+
+/// A remote model whose responses fail after the first token.
+#[cfg(test)]
+struct FailingRemoteModel;
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Model for FailingRemoteModel {
+    type TextStream = ChannelTextStream;
+    type SyncModel = kalosm_language_model::SyncModelNotSupported;
+
+    fn tokenizer(&self) -> Arc<tokenizers::Tokenizer> {
+        unreachable!("Remote chats never tokenize text")
+    }
+
+    async fn stream_text_inner(
+        &self,
+        _prompt: &str,
+        _parameters: GenerationParameters,
+    ) -> Result<Self::TextStream> {
+        anyhow::bail!("The failing model only runs chats")
+    }
+
+    async fn stream_chat_fallible_inner(
+        &self,
+        _messages: &[ChatHistoryItem],
+        _parameters: GenerationParameters,
+    ) -> Result<kalosm_language_model::FallibleTextStream> {
+        Ok(Box::pin(futures_util::stream::iter([
+            Ok("Hello".to_string()),
+            Err(anyhow::anyhow!("The connection was reset")),
+        ])))
+    }
+}
+
+#[tokio::test]
+async fn remote_responses_that_fail_midway_are_not_saved() {
+    let mut chat = Chat::builder(FailingRemoteModel).build();
+    let history = chat.history();
+    let mut response = chat.add_message("Hi");
+    let mut text = String::new();
+    while let Some(token) = response.next().await {
+        text += &token;
+    }
+    assert_eq!(text, "Hello");
+    assert!(response.result().await.is_err());
+    assert_eq!(chat.history(), history);
+}
@@ -0,0 +1,285 @@
+use std::sync::{Arc, Mutex};
+
+use kalosm_language_model::{ChatHistoryItem, MessageType};
+use kalosm_sample::{ArcParser, ParserExt, SendCreateParserState};
+
+use super::{ResponseConstraintGenerator, DEFAULT_SYSTEM_PROMPT};
+
+/// A named participant in a [`Chat`](crate::chat::Chat) with more than one speaker.
+///
+/// Assistant participants are personas the model speaks as. User participants are people who add messages with [`Chat::add_message_from`](crate::chat::Chat::add_message_from). Every participant is described in the system prompt of the chat, and every message is prefixed with the name of the participant who wrote it, so all participants share one session of the model.
+///
+/// > **Note**: Participants are only supported for models that expose a [`ChatTemplate`](kalosm_language_model::ChatTemplate) or [`ChatMarkers`](kalosm_language_model::ChatMarkers). Messages the model writes as any participant are assistant messages, so chat templates that require user and assistant messages to alternate can't render two participants speaking in a row.
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+///     .with_participant(
+///         Participant::assistant("Pirate").with_system_prompt("A pirate who loves treasure."),
+///     )
+///     .with_participant(
+///         Participant::assistant("Explorer")
+///             .with_system_prompt("A curious explorer who asks questions about the world."),
+///     )
+///     .build();
+///
+/// // Let the participants talk to each other
+/// for _ in 0..4 {
+///     let mut response = chat.next_turn();
+///     print!("{}: ", response.speaker().await.unwrap_or_default());
+///     response.to_std_out().await.unwrap();
+///     println!();
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Participant {
+    name: String,
+    system_prompt: Option<String>,
+    user: bool,
+    pub(crate) constraints: Option<ResponseConstraintGenerator>,
+}
+
+impl std::fmt::Debug for Participant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Participant")
+            .field("name", &self.name)
+            .field("system_prompt", &self.system_prompt)
+            .field("user", &self.user)
+            .finish()
+    }
+}
+
+impl Participant {
+    /// Create a persona the model speaks as.
+    pub fn assistant(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            system_prompt: None,
+            user: false,
+            constraints: None,
+        }
+    }
+
+    /// Create a person who takes part in the chat. The model never speaks as a user.
+    pub fn user(name: impl ToString) -> Self {
+        Self {
+            user: true,
+            ..Self::assistant(name)
+        }
+    }
+
+    /// Describe the participant in the system prompt of the chat. For assistants, this is the persona the model takes on when it speaks as the participant.
+    pub fn with_system_prompt(mut self, system_prompt: impl ToString) -> Self {
+        self.system_prompt = Some(system_prompt.to_string());
+        self
+    }
+
+    /// Constrain the messages the model writes as this participant. These constraints replace the constraints of the chat set with [`ChatBuilder::with_constraints`](crate::chat::ChatBuilder::with_constraints).
+    ///
+    /// The name of the participant is added before the message, so the constraints only need to parse the message itself.
+    pub fn with_constraints<Parser: SendCreateParserState + 'static>(
+        mut self,
+        mut constraints: impl FnMut(&[ChatHistoryItem]) -> Parser + Send + Sync + 'static,
+    ) -> Self {
+        self.constraints = Some(Arc::new(Mutex::new(Box::new(
+            move |history: &[ChatHistoryItem]| constraints(history).map_output(|_| ()).boxed(),
+        )
+            as Box<dyn FnMut(&[ChatHistoryItem]) -> ArcParser + Send + Sync>)));
+        self
+    }
+
+    /// Get the name of the participant.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the description of the participant in the system prompt.
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    /// Check if the participant is a user the model never speaks as.
+    pub fn is_user(&self) -> bool {
+        self.user
+    }
+}
+
+/// Who generates the next message of a chat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Responder {
+    /// The model answers as the assistant of a chat without participants.
+    Assistant,
+    /// The model speaks as the participant at this index.
+    Participant(usize),
+}
+
+/// The participants of a chat and the orchestrator that picks who speaks next.
+pub(crate) struct Speakers {
+    participants: Vec<Participant>,
+    orchestrator: Box<dyn Orchestrator>,
+}
+
+impl Speakers {
+    pub(crate) fn new(participants: Vec<Participant>, orchestrator: Box<dyn Orchestrator>) -> Self {
+        Self {
+            participants,
+            orchestrator,
+        }
+    }
+
+    /// Get the participant who responds, or `None` for the assistant of a chat without participants.
+    pub(crate) fn participant(&self, responder: Responder) -> Option<&Participant> {
+        match responder {
+            Responder::Assistant => None,
+            Responder::Participant(index) => self.participants.get(index),
+        }
+    }
+
+    /// Choose who responds to the history. Returns `None` if the model shouldn't respond.
+    pub(crate) fn next_responder(&mut self, history: &[ChatHistoryItem]) -> Option<Responder> {
+        if self.participants.is_empty() {
+            return Some(Responder::Assistant);
+        }
+        let index = self
+            .orchestrator
+            .next_speaker(history, &self.participants)?;
+        match self.participants.get(index) {
+            Some(participant) => (!participant.user).then_some(Responder::Participant(index)),
+            None => {
+                tracing::warn!("The orchestrator chose participant {index}, but the chat only has {} participants", self.participants.len());
+                None
+            }
+        }
+    }
+
+    /// Find the assistant participant with the given name.
+    pub(crate) fn responder_named(&self, name: &str) -> anyhow::Result<Responder> {
+        let index = self
+            .participants
+            .iter()
+            .position(|participant| participant.name == name)
+            .ok_or_else(|| anyhow::anyhow!("There is no participant named {name}"))?;
+        if self.participants[index].user {
+            anyhow::bail!("{name} is a user participant, so the model can't speak as {name}");
+        }
+        Ok(Responder::Participant(index))
+    }
+
+    /// Add a description of the participants to the system prompt. If the chat doesn't have participants, the system prompt is unchanged.
+    pub(crate) fn describe(&self, system_prompt: Option<String>) -> Option<String> {
+        if self.participants.is_empty() {
+            return system_prompt;
+        }
+        let system_prompt = system_prompt.as_deref().unwrap_or(DEFAULT_SYSTEM_PROMPT);
+        let mut prompt = format!("{system_prompt}\n\nThe conversation has these participants:\n");
+        for participant in &self.participants {
+            prompt += &format!("- {}", participant.name);
+            if let Some(description) = &participant.system_prompt {
+                prompt += &format!(": {description}");
+            }
+            prompt += "\n";
+        }
+        prompt += "Each message starts with the name of the participant who wrote it. Only write the message of the participant whose turn it is.";
+        Some(prompt)
+    }
+}
+
+/// Decides which participant speaks next in a [`Chat`](crate::chat::Chat) with [`Participant`]s.
+///
+/// The orchestrator is asked for the next speaker after each message added with [`Chat::add_message`](crate::chat::Chat::add_message) and each call to [`Chat::next_turn`](crate::chat::Chat::next_turn). Any closure that takes the history and the participants and returns the index of a participant is an orchestrator.
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut chat = Chat::builder(Llama::new_chat().await.unwrap())
+///     .with_participant(Participant::assistant("Writer"))
+///     .with_participant(Participant::assistant("Critic"))
+///     // The writer always answers the user, and the critic always reviews the writer
+///     .with_orchestrator(|history: &[ChatHistoryItem], _: &[Participant]| {
+///         match history.last()?.name() {
+///             Some("Writer") => Some(1),
+///             _ => Some(0),
+///         }
+///     })
+///     .build();
+/// # }
+/// ```
+pub trait Orchestrator: Send + 'static {
+    /// Choose the next speaker. Returns the index of the participant who speaks next, or `None` if the model shouldn't respond. If the index is a user participant, the model doesn't respond either.
+    fn next_speaker(
+        &mut self,
+        history: &[ChatHistoryItem],
+        participants: &[Participant],
+    ) -> Option<usize>;
+}
+
+impl<F> Orchestrator for F
+where
+    F: FnMut(&[ChatHistoryItem], &[Participant]) -> Option<usize> + Send + 'static,
+{
+    fn next_speaker(
+        &mut self,
+        history: &[ChatHistoryItem],
+        participants: &[Participant],
+    ) -> Option<usize> {
+        self(history, participants)
+    }
+}
+
+/// An [`Orchestrator`] that lets each assistant participant speak in turn. The next speaker is the assistant after the last assistant that spoke, in the order the participants were added.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoundRobin;
+
+impl Orchestrator for RoundRobin {
+    fn next_speaker(
+        &mut self,
+        history: &[ChatHistoryItem],
+        participants: &[Participant],
+    ) -> Option<usize> {
+        let last_speaker = history
+            .iter()
+            .rev()
+            .filter(|item| item.ty() == MessageType::ModelAnswer)
+            .find_map(|item| {
+                let name = item.name()?;
+                participants
+                    .iter()
+                    .position(|participant| participant.name == name)
+            });
+        let start = last_speaker.map_or(0, |index| index + 1);
+        (0..participants.len())
+            .map(|offset| (start + offset) % participants.len())
+            .find(|index| !participants[*index].user)
+    }
+}
+
+#[test]
+fn round_robin_skips_users() {
+    let participants = [
+        Participant::assistant("Alice"),
+        Participant::user("Bob"),
+        Participant::assistant("Carol"),
+    ];
+    let answer = |name: &str| ChatHistoryItem::new(MessageType::ModelAnswer, "...").with_name(name);
+    let question = ChatHistoryItem::new(MessageType::UserMessage, "...").with_name("Bob");
+
+    assert_eq!(RoundRobin.next_speaker(&[], &participants), Some(0));
+    assert_eq!(
+        RoundRobin.next_speaker(&[answer("Alice"), question.clone()], &participants),
+        Some(2)
+    );
+    assert_eq!(
+        RoundRobin.next_speaker(&[answer("Alice"), answer("Carol"), question], &participants),
+        Some(0)
+    );
+    assert_eq!(
+        RoundRobin.next_speaker(&[], &[Participant::user("Bob")]),
+        None
+    );
+}
@@ -0,0 +1,163 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use futures_util::StreamExt;
+use kalosm_language_model::{ChatHistoryItem, GenerationParameters, MessageType, Model};
+
+use super::{
+    prefix_speaker_name, with_edited_message, with_system_prompt, without_last_answer, ChatNodeId,
+    ChatTranscript, ChatTree, ResponseSender, Turn,
+};
+
+/// The history of a chat session with a model that generates chat responses natively (like a remote chat API) instead of through a [`ChatTemplate`](kalosm_language_model::ChatTemplate) or [`ChatMarkers`](kalosm_language_model::ChatMarkers).
+pub(super) struct RemoteChatSession {
+    tree: Arc<RwLock<ChatTree>>,
+    system_prompt: Option<String>,
+    generation_parameters: GenerationParameters,
+}
+
+impl RemoteChatSession {
+    /// Creates a new chat history.
+    pub(super) fn new(
+        system_prompt: Option<String>,
+        generation_parameters: GenerationParameters,
+        initial_history: Vec<ChatHistoryItem>,
+        shared_tree: Arc<RwLock<ChatTree>>,
+    ) -> Self {
+        shared_tree
+            .write()
+            .unwrap()
+            .set_history(&with_system_prompt(system_prompt.clone(), initial_history));
+
+        Self {
+            tree: shared_tree,
+            system_prompt,
+            generation_parameters,
+        }
+    }
+
+    /// Get the current chat history.
+    fn history(&self) -> Vec<ChatHistoryItem> {
+        self.tree.read().unwrap().current_history()
+    }
+
+    /// Make the given history the current branch of the chat tree.
+    fn set_history(&mut self, history: &[ChatHistoryItem]) {
+        self.tree.write().unwrap().set_history(history);
+    }
+
+    /// Switch the chat history to the branch of the chat tree that ends at `node`.
+    pub(super) fn checkout(&mut self, node: ChatNodeId) -> Result<()> {
+        let history = self
+            .tree
+            .read()
+            .unwrap()
+            .history(node)
+            .ok_or_else(|| anyhow::anyhow!("The message is not in the chat tree"))?;
+        self.set_history(&history);
+        Ok(())
+    }
+
+    /// Save the history and settings of the chat. Remote chats don't have a local session to save.
+    pub(super) fn transcript(&self) -> ChatTranscript {
+        ChatTranscript {
+            history: self.history(),
+            system_prompt: self.system_prompt.clone(),
+            generation_parameters: self.generation_parameters.clone(),
+            session: None,
+        }
+    }
+
+    /// Apply a change to the history and generate a response if the model should respond to it.
+    pub(super) async fn take_turn(
+        &mut self,
+        turn: Turn,
+        model: &impl Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        match turn {
+            Turn::AddMessage(message) => self.add_message(message, model, response).await,
+            Turn::RegenerateLast => self.regenerate_last(model, response).await,
+            Turn::EditMessage { index, text } => {
+                self.edit_message(index, text, model, response).await
+            }
+            Turn::Speak(None) => self.respond(model, response).await,
+            Turn::Speak(Some(_)) => Err(anyhow::anyhow!(
+                "Participants are not supported for models without a chat template or chat markers"
+            )),
+        }
+    }
+
+    /// Adds a message to the history and generates a response.
+    async fn add_message(
+        &mut self,
+        message: ChatHistoryItem,
+        model: &impl Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        let mut history = self.history();
+        history.push(message);
+        self.respond_to(history, model, response).await
+    }
+
+    /// Removes the last answer and generates a new response.
+    async fn regenerate_last(
+        &mut self,
+        model: &impl Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        let history = without_last_answer(&self.history())?;
+        self.respond_to(history, model, response).await
+    }
+
+    /// Replaces the text of the message at `index` and removes every message after it. If the message is a user message, a new response is generated.
+    async fn edit_message(
+        &mut self,
+        index: usize,
+        text: String,
+        model: &impl Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        let history = with_edited_message(&self.history(), index, text)?;
+        if history[index].ty() == MessageType::UserMessage {
+            self.respond_to(history, model, response).await
+        } else {
+            self.set_history(&history);
+            Ok(())
+        }
+    }
+
+    /// Generate a response to the history and add it to the history.
+    async fn respond(&mut self, model: &impl Model, response: ResponseSender) -> Result<()> {
+        self.respond_to(self.history(), model, response).await
+    }
+
+    /// Generate a response to `history` and make the history with the response the current branch. If the request fails, the current branch is left unchanged.
+    async fn respond_to(
+        &mut self,
+        mut history: Vec<ChatHistoryItem>,
+        model: &impl Model,
+        response: ResponseSender,
+    ) -> Result<()> {
+        let prompt: Vec<_> = history.iter().map(prefix_speaker_name).collect();
+        let mut stream = model
+            .stream_chat_fallible_inner(&prompt, self.generation_parameters.clone())
+            .await?;
+
+        let mut bot_response = String::new();
+        while let Some(tok) = stream.next().await {
+            // If the response fails partway through, the cut off answer isn't added to the history
+            let tok = tok?;
+            bot_response += &tok;
+            // Send the new token to the stream. If the response was cancelled, keep the partial answer
+            if !response.send(tok) {
+                break;
+            }
+        }
+
+        history.push(ChatHistoryItem::new(MessageType::ModelAnswer, bot_response));
+        self.set_history(&history);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::Result;
+use kalosm_language_model::{ChatHistoryItem, GenerationParameters, Session};
+
+/// The bytes every transcript file starts with.
+const TRANSCRIPT_MAGIC: &[u8; 8] = b"KALOSMCT";
+
+/// The version of the transcript file format. Bump this when the layout of the file changes.
+const TRANSCRIPT_VERSION: u32 = 1;
+
+/// A saved [`Chat`](crate::chat::Chat). The transcript bundles the chat history, the system prompt, the [`GenerationParameters`] and the session of the model, so a chat can be restored with [`ChatBuilder::with_transcript`](crate::chat::ChatBuilder::with_transcript) without feeding the whole history to the model again.
+///
+/// Only the current branch of the [`ChatTree`](crate::chat::ChatTree) is saved. Other branches and the session snapshots from [`ChatBuilder::with_session_snapshots`](crate::chat::ChatBuilder::with_session_snapshots) are dropped, so the restored chat starts with a tree that has a single branch.
+///
+/// > **Note**: A custom [`Sampler`](llm_samplers::types::Sampler) set with [`ChatBuilder::with_sampler`](crate::chat::ChatBuilder::with_sampler) can't be saved. The transcript includes the generation parameters the chat was built with instead. [`Participant`](crate::chat::Participant)s, tools and the [`Orchestrator`](crate::chat::Orchestrator) aren't saved either, so add them to the builder again when you restore a chat that uses them.
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+/// chat.add_message("Hello, world!").to_std_out().await.unwrap();
+/// // Save the chat to the filesystem
+/// let transcript = chat.transcript().await.unwrap();
+/// transcript.save_to("./chat.kalosm").unwrap();
+/// // And print the history that was saved
+/// for item in transcript.history() {
+///     println!("{:?}: {}", item.ty(), item.contents());
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ChatTranscript {
+    pub(super) history: Vec<ChatHistoryItem>,
+    pub(super) system_prompt: Option<String>,
+    pub(super) generation_parameters: GenerationParameters,
+    /// The saved session of the model. The session is stored after the rest of the transcript in the file
+    #[serde(skip)]
+    pub(super) session: Option<Vec<u8>>,
+}
+
+impl ChatTranscript {
+    /// Get the chat history, including the system prompt.
+    pub fn history(&self) -> &[ChatHistoryItem] {
+        &self.history
+    }
+
+    /// Get the system prompt the chat was built with.
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    /// Get the generation parameters the chat was built with.
+    pub fn generation_parameters(&self) -> &GenerationParameters {
+        &self.generation_parameters
+    }
+
+    /// Check if the transcript includes the session of the model. Chats with models that generate chat responses natively don't have a session.
+    pub fn has_session(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Save the transcript to the given path.
+    ///
+    /// The file starts with the bytes `KALOSMCT` and the version of the format as a little endian `u32`. Then comes the length of the JSON encoded history and settings as a little endian `u64`, followed by the JSON and then the saved session of the model.
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let header = serde_json::to_vec(self)?;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        file.write_all(TRANSCRIPT_MAGIC)?;
+        file.write_all(&TRANSCRIPT_VERSION.to_le_bytes())?;
+        file.write_all(&(header.len() as u64).to_le_bytes())?;
+        file.write_all(&header)?;
+        if let Some(session) = &self.session {
+            file.write_all(session)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Load a transcript saved with [`ChatTranscript::save_to`] from the given path.
+    ///
+    /// Returns an error if the file isn't a transcript or was saved with a different version of the format.
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut file = std::io::BufReader::new(file);
+        let mut magic = [0; TRANSCRIPT_MAGIC.len()];
+        file.read_exact(&mut magic)
+            .map_err(|_| anyhow::anyhow!("The file is not a chat transcript"))?;
+        if &magic != TRANSCRIPT_MAGIC {
+            anyhow::bail!("The file is not a chat transcript");
+        }
+        let mut version = [0; 4];
+        file.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != TRANSCRIPT_VERSION {
+            anyhow::bail!(
+                "The transcript was saved with version {version} of the format, but only version {TRANSCRIPT_VERSION} is supported"
+            );
+        }
+        let mut header_len = [0; 8];
+        file.read_exact(&mut header_len)?;
+        let header_len = u64::from_le_bytes(header_len);
+        // Check the length before allocating so a corrupted file can't make us allocate more than the file holds
+        let prefix_len = (TRANSCRIPT_MAGIC.len() + 4 + 8) as u64;
+        if header_len > file_len.saturating_sub(prefix_len) {
+            anyhow::bail!(
+                "The transcript is corrupted: the header is {header_len} bytes long, but the file is only {file_len} bytes long"
+            );
+        }
+        let mut header = vec![0; header_len.try_into()?];
+        file.read_exact(&mut header)?;
+        let mut transcript: Self = serde_json::from_slice(&header)?;
+        let mut session = Vec::new();
+        file.read_to_end(&mut session)?;
+        transcript.session = (!session.is_empty()).then_some(session);
+        Ok(transcript)
+    }
+}
+
+/// Save a session to bytes through a temporary file.
+pub(super) fn session_to_bytes(session: &impl Session) -> Result<Vec<u8>> {
+    let file = tempfile::NamedTempFile::new()?;
+    session.save_to(file.path())?;
+    Ok(std::fs::read(file.path())?)
+}
+
+/// Load a session from bytes through a temporary file.
+fn session_from_bytes<S: Session>(bytes: &[u8]) -> Result<S> {
+    let mut file = tempfile::NamedTempFile::new()?;
+    file.write_all(bytes)?;
+    file.flush()?;
+    S::load_from(file.path())
+}
+
+/// Load the session saved in a transcript. If the session can't be loaded, the history is fed to a new session instead, so this only warns.
+pub(super) fn transcript_session<S: Session>(bytes: Option<Vec<u8>>) -> Option<S> {
+    match session_from_bytes(&bytes?) {
+        Ok(session) => Some(session),
+        Err(err) => {
+            tracing::warn!(
+                "Failed to load the session from the transcript. The history will be fed to a new session: {err}"
+            );
+            None
+        }
+    }
+}
+
+#[test]
+fn transcript_round_trips_through_a_file() {
+    use kalosm_language_model::MessageType;
+
+    let transcript = ChatTranscript {
+        history: vec![
+            ChatHistoryItem::new(MessageType::SystemPrompt, "system"),
+            ChatHistoryItem::new(MessageType::UserMessage, "question"),
+            ChatHistoryItem::new(MessageType::ModelAnswer, "answer"),
+        ],
+        system_prompt: Some("system".to_string()),
+        generation_parameters: GenerationParameters::default()
+            .with_seed(42)
+            .with_text_bias("hello", -5.),
+        session: Some(vec![1, 2, 3]),
+    };
+    let file = tempfile::NamedTempFile::new().unwrap();
+    transcript.save_to(file.path()).unwrap();
+    let loaded = ChatTranscript::load_from(file.path()).unwrap();
+
+    assert_eq!(loaded.history(), transcript.history());
+    assert_eq!(loaded.system_prompt(), Some("system"));
+    assert_eq!(
+        loaded.generation_parameters(),
+        transcript.generation_parameters()
+    );
+    assert_eq!(loaded.session, transcript.session);
+}
+
+#[test]
+fn transcripts_with_the_wrong_header_are_rejected() {
+    let transcript = ChatTranscript {
+        history: Vec::new(),
+        system_prompt: None,
+        generation_parameters: GenerationParameters::default(),
+        session: None,
+    };
+    let file = tempfile::NamedTempFile::new().unwrap();
+    transcript.save_to(file.path()).unwrap();
+    let saved = std::fs::read(file.path()).unwrap();
+
+    let load_modified = |modify: &dyn Fn(&mut Vec<u8>)| {
+        let mut bytes = saved.clone();
+        modify(&mut bytes);
+        std::fs::write(file.path(), bytes).unwrap();
+        ChatTranscript::load_from(file.path())
+            .unwrap_err()
+            .to_string()
+    };
+    assert!(load_modified(&|bytes| bytes[0] = b'X').contains("not a chat transcript"));
+    assert!(load_modified(&|bytes| bytes.truncate(4)).contains("not a chat transcript"));
+    assert!(load_modified(&|bytes| bytes[8] = 2).contains("version 2"));
+    // A header length longer than the file is rejected before anything is allocated
+    assert!(
+        load_modified(&|bytes| bytes[12..20].copy_from_slice(&u64::MAX.to_le_bytes()))
+            .contains("corrupted")
+    );
+}
@@ -0,0 +1,265 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use kalosm_language_model::ChatHistoryItem;
+
+/// The id of a message in a [`ChatTree`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChatNodeId(usize);
+
+/// A message in a [`ChatTree`].
+#[derive(Clone, Debug)]
+pub struct ChatNode {
+    item: ChatHistoryItem,
+    parent: Option<ChatNodeId>,
+    children: Vec<ChatNodeId>,
+    snapshot: Option<SessionSnapshot>,
+}
+
+/// A copy of the model session after a message. The session type depends on the model, so it is stored as [`Any`].
+#[derive(Clone)]
+struct SessionSnapshot {
+    session: Arc<dyn Any + Send + Sync>,
+    /// When the snapshot was taken, so the oldest snapshots are evicted first
+    taken: u64,
+}
+
+impl std::fmt::Debug for SessionSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionSnapshot")
+            .field("taken", &self.taken)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChatNode {
+    /// Get the message.
+    pub fn item(&self) -> &ChatHistoryItem {
+        &self.item
+    }
+
+    /// Get the message this message follows, or `None` if the message starts the chat.
+    pub fn parent(&self) -> Option<ChatNodeId> {
+        self.parent
+    }
+
+    /// Get the messages that follow this message. Each child starts a different branch of the chat.
+    pub fn children(&self) -> &[ChatNodeId] {
+        &self.children
+    }
+
+    /// Check if a snapshot of the model session after this message is stored. See [`ChatBuilder::with_session_snapshots`](crate::chat::ChatBuilder::with_session_snapshots).
+    pub fn has_snapshot(&self) -> bool {
+        self.snapshot.is_some()
+    }
+}
+
+/// Every branch of a [`Chat`](crate::chat::Chat). Each path from a root of the tree to a node is a chat history the chat has had.
+///
+/// The chat history is a path through the tree that ends at the [`ChatTree::current`] node. When the history changes (for example with [`Chat::edit_message`](crate::chat::Chat::edit_message) or [`Chat::regenerate_last`](crate::chat::Chat::regenerate_last)), the messages that are no longer in the history stay in the tree as a separate branch. You can switch back to any branch with [`Chat::checkout`](crate::chat::Chat::checkout). Context policies that drop or summarize messages (see [`ContextPolicy`](crate::chat::ContextPolicy)) also start a new branch.
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let mut chat = Chat::new(Llama::new_chat().await.unwrap());
+/// chat.add_message("Tell me a joke.").to_std_out().await.unwrap();
+/// chat.regenerate_last().to_std_out().await.unwrap();
+///
+/// // Both jokes answer the same question, so they are siblings in the tree
+/// let tree = chat.tree();
+/// let question = tree.node(tree.current().unwrap()).unwrap().parent().unwrap();
+/// for answer in tree.node(question).unwrap().children() {
+///     println!("{}", tree.node(*answer).unwrap().item().contents());
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ChatTree {
+    nodes: Vec<ChatNode>,
+    roots: Vec<ChatNodeId>,
+    current: Option<ChatNodeId>,
+    snapshots_taken: u64,
+}
+
+impl ChatTree {
+    /// Get a node in the tree.
+    pub fn node(&self, id: ChatNodeId) -> Option<&ChatNode> {
+        self.nodes.get(id.0)
+    }
+
+    /// Get the nodes that start a chat. There is more than one root if the first message of the chat was edited.
+    pub fn roots(&self) -> &[ChatNodeId] {
+        &self.roots
+    }
+
+    /// Get the last message of the current chat history.
+    pub fn current(&self) -> Option<ChatNodeId> {
+        self.current
+    }
+
+    /// Get the nodes that end a branch of the chat.
+    pub fn leaves(&self) -> impl Iterator<Item = ChatNodeId> + '_ {
+        (0..self.nodes.len())
+            .map(ChatNodeId)
+            .filter(|id| self.nodes[id.0].children.is_empty())
+    }
+
+    /// Get the ids of the messages from the root of the tree to the given node. Returns `None` if the node is not in the tree.
+    pub fn path(&self, id: ChatNodeId) -> Option<Vec<ChatNodeId>> {
+        let mut path = vec![id];
+        let mut node = self.node(id)?;
+        while let Some(parent) = node.parent {
+            path.push(parent);
+            node = &self.nodes[parent.0];
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Get the chat history that ends at the given node. Returns `None` if the node is not in the tree.
+    pub fn history(&self, id: ChatNodeId) -> Option<Vec<ChatHistoryItem>> {
+        Some(
+            self.path(id)?
+                .into_iter()
+                .map(|id| self.nodes[id.0].item.clone())
+                .collect(),
+        )
+    }
+
+    /// Get the current chat history.
+    pub fn current_history(&self) -> Vec<ChatHistoryItem> {
+        self.current
+            .and_then(|id| self.history(id))
+            .unwrap_or_default()
+    }
+
+    /// Make the given history the current history. Messages that are already in the tree are reused, and the rest of the history is added as a new branch.
+    ///
+    /// Returns the ids of the messages in the history.
+    pub(crate) fn set_history(&mut self, history: &[ChatHistoryItem]) -> Vec<ChatNodeId> {
+        let mut path: Vec<ChatNodeId> = Vec::with_capacity(history.len());
+        for item in history {
+            let siblings = match path.last() {
+                Some(parent) => &self.nodes[parent.0].children,
+                None => &self.roots,
+            };
+            let existing = siblings
+                .iter()
+                .copied()
+                .find(|id| self.nodes[id.0].item == *item);
+            let id = match existing {
+                Some(id) => id,
+                None => self.push(item.clone(), path.last().copied()),
+            };
+            path.push(id);
+        }
+        self.current = path.last().copied();
+        path
+    }
+
+    /// Store a snapshot of the session after the message. If more than `max_snapshots` snapshots are stored, the oldest snapshots are evicted.
+    pub(crate) fn set_snapshot<S: Send + 'static>(
+        &mut self,
+        id: ChatNodeId,
+        session: S,
+        max_snapshots: usize,
+    ) {
+        let Some(node) = self.nodes.get_mut(id.0) else {
+            return;
+        };
+        node.snapshot = Some(SessionSnapshot {
+            session: Arc::new(Mutex::new(session)),
+            taken: self.snapshots_taken,
+        });
+        self.snapshots_taken += 1;
+
+        let mut snapshots: Vec<_> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| Some((node.snapshot.as_ref()?.taken, index)))
+            .collect();
+        if snapshots.len() > max_snapshots {
+            snapshots.sort_unstable();
+            let evicted = snapshots.len() - max_snapshots;
+            for (_, index) in &snapshots[..evicted] {
+                self.nodes[*index].snapshot = None;
+            }
+        }
+    }
+
+    /// Get the snapshot of the session after the message. Returns `None` if there is no snapshot or it has a different session type.
+    pub(crate) fn snapshot<S: Send + 'static>(&self, id: ChatNodeId) -> Option<&Mutex<S>> {
+        self.node(id)?.snapshot.as_ref()?.session.downcast_ref()
+    }
+
+    /// Add a new node to the tree.
+    fn push(&mut self, item: ChatHistoryItem, parent: Option<ChatNodeId>) -> ChatNodeId {
+        let id = ChatNodeId(self.nodes.len());
+        self.nodes.push(ChatNode {
+            item,
+            parent,
+            children: Vec::new(),
+            snapshot: None,
+        });
+        match parent {
+            Some(parent) => self.nodes[parent.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+}
+
+#[test]
+fn changing_history_branches_the_tree() {
+    use kalosm_language_model::MessageType;
+
+    let system = ChatHistoryItem::new(MessageType::SystemPrompt, "system");
+    let question = ChatHistoryItem::new(MessageType::UserMessage, "question");
+    let first_answer = ChatHistoryItem::new(MessageType::ModelAnswer, "first answer");
+    let second_answer = ChatHistoryItem::new(MessageType::ModelAnswer, "second answer");
+
+    let mut tree = ChatTree::default();
+    let first = tree.set_history(&[system.clone(), question.clone(), first_answer.clone()]);
+    // Regenerating the answer reuses the system prompt and question
+    let second = tree.set_history(&[system.clone(), question.clone(), second_answer.clone()]);
+    assert_eq!(first[..2], second[..2]);
+    assert_ne!(first[2], second[2]);
+    assert_eq!(
+        tree.node(second[1]).unwrap().children(),
+        [first[2], second[2]]
+    );
+    assert_eq!(tree.current(), Some(second[2]));
+    assert_eq!(tree.leaves().collect::<Vec<_>>(), [first[2], second[2]]);
+
+    // Going back to the first branch doesn't add any nodes
+    assert_eq!(tree.set_history(&tree.history(first[2]).unwrap()), first);
+    assert_eq!(tree.current_history(), [system, question, first_answer]);
+    assert_eq!(tree.path(first[2]).unwrap(), first);
+    assert_eq!(tree.roots(), [first[0]]);
+}
+
+#[test]
+fn oldest_snapshots_are_evicted() {
+    use kalosm_language_model::MessageType;
+
+    let mut tree = ChatTree::default();
+    let history: Vec<_> = (0..4)
+        .map(|index| ChatHistoryItem::new(MessageType::UserMessage, index.to_string()))
+        .collect();
+    let ids = tree.set_history(&history);
+    for (index, id) in ids.iter().enumerate() {
+        tree.set_snapshot(*id, index, 2);
+    }
+
+    let kept: Vec<_> = ids
+        .iter()
+        .map(|id| tree.node(*id).unwrap().has_snapshot())
+        .collect();
+    assert_eq!(kept, [false, false, true, true]);
+    assert_eq!(*tree.snapshot::<usize>(ids[3]).unwrap().lock().unwrap(), 3);
+    // A snapshot with a different session type is not returned
+    assert!(tree.snapshot::<String>(ids[3]).is_none());
+}
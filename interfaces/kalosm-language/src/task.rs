@@ -5,10 +5,12 @@ use futures_util::Stream;
 use kalosm_language_model::ChatMarkers;
 use kalosm_language_model::Session;
 use kalosm_language_model::StructureParserResult;
+use kalosm_language_model::{ChatHistoryItem, MessageType};
 use kalosm_language_model::{GenerationParameters, Model, ModelExt, SyncModel, SyncModelExt};
 use kalosm_sample::CreateParserState;
 use kalosm_sample::Parse;
 use kalosm_sample::Schema;
+use kalosm_sample::SchemaType;
 use kalosm_sample::SendCreateParserState;
 use kalosm_streams::text_stream::ChannelTextStream;
 use llm_samplers::types::Sampler;
@@ -129,6 +131,26 @@ impl TaskSessions {
             examples,
         }
     }
+
+    /// Get the task as a chat history that ends with the input.
+    fn chat_history(&self, input: &str) -> Vec<ChatHistoryItem> {
+        let mut history = vec![ChatHistoryItem::new(
+            MessageType::SystemPrompt,
+            self.system_prompt.clone(),
+        )];
+        for example in &self.examples {
+            history.push(ChatHistoryItem::new(
+                MessageType::UserMessage,
+                example.input.clone(),
+            ));
+            history.push(ChatHistoryItem::new(
+                MessageType::ModelAnswer,
+                example.output.clone(),
+            ));
+        }
+        history.push(ChatHistoryItem::new(MessageType::UserMessage, input));
+        history
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -145,8 +167,11 @@ pub struct NoParser;
 #[derive(Debug, Clone)]
 pub struct TaskBuilder<P = NoParser> {
     system_prompt: String,
-    sampler: Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>,
+    /// A custom sampler. If this is `None`, the sampler is created from the generation parameters
+    sampler: Option<Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>>,
+    generation_parameters: GenerationParameters,
     constraints: P,
+    schema: Option<SchemaType>,
     examples: Vec<TaskExample>,
 }
 
@@ -154,10 +179,10 @@ impl TaskBuilder {
     fn new(description: impl ToString) -> TaskBuilder {
         TaskBuilder {
             system_prompt: description.to_string(),
-            sampler: Arc::new(std::sync::Mutex::new(
-                GenerationParameters::default().sampler(),
-            )),
+            sampler: None,
+            generation_parameters: GenerationParameters::default(),
             constraints: NoParser,
+            schema: None,
             examples: Vec::new(),
         }
     }
@@ -165,8 +190,20 @@ impl TaskBuilder {
 
 impl<P: TaskBuilderReturn + Send + Sync + 'static> TaskBuilder<P> {
     /// Sets the [`Sampler`] to use for generating responses.
+    ///
+    /// > **Note**: Models that generate JSON natively (like remote chat APIs) can't use a custom sampler. Use [`TaskBuilder::with_generation_parameters`] instead.
     pub fn with_sampler(mut self, sampler: impl Sampler + 'static) -> Self {
-        self.sampler = Arc::new(std::sync::Mutex::new(sampler));
+        self.sampler = Some(Arc::new(std::sync::Mutex::new(sampler)));
+        self
+    }
+
+    /// Sets the [`GenerationParameters`] to use for generating responses. This also replaces the [`Sampler`] with the sampler created from the parameters.
+    pub fn with_generation_parameters(
+        mut self,
+        generation_parameters: GenerationParameters,
+    ) -> Self {
+        self.sampler = None;
+        self.generation_parameters = generation_parameters;
         self
     }
 
@@ -179,10 +216,18 @@ impl<P: TaskBuilderReturn + Send + Sync + 'static> TaskBuilder<P> {
             constraints,
             system_prompt: self.system_prompt,
             sampler: self.sampler,
+            generation_parameters: self.generation_parameters,
+            schema: self.schema,
             examples: self.examples,
         }
     }
 
+    /// Set the JSON schema the response follows. Models that support JSON schemas (like remote chat models) will generate a response with the schema and validate it with the constraints instead of using the constraints during generation.
+    pub fn with_schema(mut self, schema: SchemaType) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
     /// Add an example to the task.
     pub fn with_example(mut self, input: impl Into<String>, output: impl Into<String>) -> Self {
         let input = input.into();
@@ -230,6 +275,7 @@ impl TaskBuilderReturn for NoParser {
         let TaskBuilder {
             system_prompt,
             sampler,
+            generation_parameters,
             examples,
             ..
         } = task_builder;
@@ -238,6 +284,7 @@ impl TaskBuilderReturn for NoParser {
         UnstructuredRunner {
             sessions: Arc::new(sessions),
             sampler,
+            generation_parameters,
         }
     }
 }
@@ -245,7 +292,8 @@ impl TaskBuilderReturn for NoParser {
 /// A task runner for a task that does not follow constraints.
 pub struct UnstructuredRunner {
     sessions: Arc<TaskSessions>,
-    sampler: Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>,
+    sampler: Option<Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>>,
+    generation_parameters: GenerationParameters,
 }
 
 impl TaskRunner for UnstructuredRunner {
@@ -262,6 +310,7 @@ impl TaskRunner for UnstructuredRunner {
         let (tx, rx) = unbounded_channel();
 
         let sampler = self.sampler.clone();
+        let generation_parameters = self.generation_parameters.clone();
         let stop_on = stop_on.clone();
         let sessions = self.sessions.clone();
 
@@ -290,6 +339,13 @@ impl TaskRunner for UnstructuredRunner {
                         return;
                     }
                 };
+                let sampler = match resolve_sampler(sampler, generation_parameters, &*model) {
+                    Ok(sampler) => sampler,
+                    Err(err) => {
+                        tracing::error!("Failed to create sampler: {}", err);
+                        return;
+                    }
+                };
                 let on_token = |tok: String| {
                     tx.send(tok)?;
                     Ok(kalosm_language_model::ModelFeedback::Continue)
@@ -319,7 +375,9 @@ impl<P: SendCreateParserState + 'static> TaskBuilderReturn for P {
         let TaskBuilder {
             system_prompt,
             sampler,
+            generation_parameters,
             constraints,
+            schema,
             examples,
         } = task_builder;
 
@@ -342,7 +400,9 @@ impl<P: SendCreateParserState + 'static> TaskBuilderReturn for P {
         StructuredRunner {
             sessions: Arc::new(sessions),
             sampler,
+            generation_parameters,
             parser: arc_parser,
+            schema,
         }
     }
 }
@@ -350,8 +410,10 @@ impl<P: SendCreateParserState + 'static> TaskBuilderReturn for P {
 /// A task runner for a task that follows constraints.
 pub struct StructuredRunner<P> {
     sessions: Arc<TaskSessions>,
-    sampler: Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>,
+    sampler: Option<Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>>,
+    generation_parameters: GenerationParameters,
     parser: Arc<P>,
+    schema: Option<SchemaType>,
 }
 
 impl<P> TaskRunner for StructuredRunner<P>
@@ -361,10 +423,36 @@ where
     type Output = StructureParserResult<ChannelTextStream, P::Output>;
 
     fn run<M: Model>(&self, input: String, model: &M) -> Self::Output where <<M as kalosm_language_model::Model>::SyncModel as kalosm_language_model::SyncModel>::Session: Send + Sync{
+        // If the model can generate JSON natively, validate the response with the parser after it is generated
+        if model.supports_json_schema() {
+            let history = self.sessions.chat_history(&input);
+            let schema = self.schema.as_ref();
+            match model.stream_chat_with_schema_inner(
+                &history,
+                schema,
+                self.generation_parameters.clone(),
+            ) {
+                Ok(stream) => {
+                    return StructureParserResult::from_json_stream(
+                        stream,
+                        schema,
+                        self.parser.clone(),
+                    )
+                }
+                Err(err) => {
+                    let (_, rx) = unbounded_channel::<String>();
+                    let (parsed_tx, parsed_rx) = oneshot::channel();
+                    _ = parsed_tx.send(Err(err));
+                    return StructureParserResult::new(rx.into(), parsed_rx);
+                }
+            }
+        }
+
         let (tx, rx) = unbounded_channel();
         let (parsed_tx, parsed_rx) = oneshot::channel();
         let arc_parser = self.parser.clone();
         let sampler = self.sampler.clone();
+        let generation_parameters = self.generation_parameters.clone();
         let sessions = self.sessions.clone();
         let chat_markers = model.chat_markers();
 
@@ -397,6 +485,14 @@ where
                     }
                 };
 
+                let sampler = match resolve_sampler(sampler, generation_parameters, &*model) {
+                    Ok(sampler) => sampler,
+                    Err(err) => {
+                        _ = parsed_tx.send(Err(err));
+                        return;
+                    }
+                };
+
                 let state = arc_parser.create_parser_state();
                 let on_token = |tok: String| {
                     tracing::trace!("Task generated token: {}", tok);
@@ -423,6 +519,20 @@ where
     }
 }
 
+/// Use the custom sampler if there is one. Otherwise create the sampler from the generation parameters with the model's tokenizer so logit biases keyed by text are included.
+fn resolve_sampler(
+    sampler: Option<Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>>,
+    generation_parameters: GenerationParameters,
+    model: &impl SyncModel,
+) -> Result<Arc<std::sync::Mutex<dyn Sampler + Send + Sync>>> {
+    Ok(match sampler {
+        Some(sampler) => sampler,
+        None => Arc::new(std::sync::Mutex::new(
+            generation_parameters.sampler_with_tokenizer(&model.tokenizer())?,
+        )),
+    })
+}
+
 // This is essentially a manual implementation of a closure so you can name the type
 /// Something that can run a task.
 pub trait TaskRunner {
@@ -486,6 +596,7 @@ impl Task {
             P::schema()
         ))
         .with_constraints(P::new_parser())
+        .with_schema(P::schema())
     }
 }
 
@@ -1,6 +1,8 @@
+use kalosm_language_model::ToolCall;
 use kalosm_sample::{ArcParser, ParseResult};
 use kalosm_sample::{
-    CreateParserState, FloatParser, LiteralParser, ParseStatus, Parser, ParserExt,
+    CreateParserState, FloatParser, JsonObjectSchema, JsonPropertySchema, LiteralParser,
+    ParseStatus, Parser, ParserExt, SchemaType, StringSchema,
 };
 use once_cell::sync::Lazy;
 use std::fmt::Debug;
@@ -9,7 +11,7 @@ use std::sync::Arc;
 
 use crate::tool::Tool;
 
-use super::IndexParser;
+use super::{string_argument, IndexParser};
 
 #[derive(Debug)]
 struct LazyParser<T>(Box<Lazy<T>>);
@@ -225,6 +227,19 @@ impl Tool for CalculatorTool {
         format!("Evaluate a mathematical expression (made only of numbers and one of the prebuilt math functions). Available functions: sqrt, abs, exp, ln, sin, cos, tan, asin, acos, atan, atan2, sinh, cosh, tanh, asinh, acosh, atanh, floor, ceil, round, signum, pi, e\nUse tool with:\nAction: Calculator\nAction Input: the expression\nExample:\nQuestion: What is 2 + 2?\nThought: I should calculate 2 + 2.\nAction: Calculator\n{input_prompt}2 + 2\nObservation: 4\nThought: I now know that 2 + 2 is 4.\nFinal Answer: 4")
     }
 
+    fn input_schema(&self) -> Option<SchemaType> {
+        Some(SchemaType::Object(JsonObjectSchema::new([
+            JsonPropertySchema::new("expression", SchemaType::String(StringSchema::new()))
+                .with_description("The mathematical expression to evaluate")
+                .with_required(true),
+        ])))
+    }
+
+    // Any expression is passed to the evaluator, which reports invalid expressions back to the model
+    fn parse_tool_call(&self, call: &ToolCall) -> anyhow::Result<Self::Input> {
+        string_argument(call, "expression")
+    }
+
     async fn run<'a>(&'a mut self, expr: &'a Self::Input) -> String {
         match meval::eval_str(expr){
                 Ok(result) => result.to_string(),
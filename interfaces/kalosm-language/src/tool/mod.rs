@@ -4,16 +4,20 @@ mod search;
 use std::{
     any::Any,
     borrow::Cow,
+    collections::VecDeque,
     error::Error,
     pin::Pin,
     sync::{Arc, Mutex},
 };
 
 use futures_util::Future;
-use kalosm_language_model::{GenerationParameters, SyncModel, SyncModelExt};
+use kalosm_language_model::{
+    ChatHistoryItem, GenerationParameters, MessageType, Model, SyncModel, SyncModelExt, ToolCall,
+    ToolChatMessage, ToolChatResponse, ToolDefinition,
+};
 use kalosm_sample::{
-    ArcParser, CreateParserState, Either, LiteralParser, ParseResult, ParseStatus, Parser,
-    ParserExt,
+    ArcParser, CreateParserState, Either, JsonObjectSchema, JsonPropertySchema, LiteralParser,
+    ParseResult, ParseStatus, Parser, ParserExt, SchemaType, StringSchema,
 };
 pub use search::*;
 mod calculator;
@@ -40,6 +44,23 @@ pub trait Tool {
     /// A description of the tool
     fn description(&self) -> String;
 
+    /// The JSON schema of the input to the tool if the input parser parses JSON (for example a type that derives [`kalosm_sample::Schema`]).
+    ///
+    /// Models with native tool calling use the schema for the arguments of the tool. If this returns `None`, the input is passed to the model as a single `input` string and parsed with the input parser.
+    fn input_schema(&self) -> Option<SchemaType> {
+        None
+    }
+
+    /// Parse the arguments of a native tool call (see [`ToolManager::run_native_step`]) into the input of the tool.
+    ///
+    /// By default, the arguments are parsed with the input parser: as JSON if the tool has an [`Tool::input_schema`], or from the `input` string argument otherwise.
+    fn parse_tool_call(&self, call: &ToolCall) -> anyhow::Result<Self::Input> {
+        match self.input_schema() {
+            Some(_) => call.parse_arguments(&self.input_parser()),
+            None => parse_text_input(&string_argument(call, "input")?, &self.input_parser()),
+        }
+    }
+
     /// Run the tool with the given arguments
     fn run<'a>(&'a mut self, args: &'a Self::Input) -> impl Future<Output = String> + Send + 'a;
 }
@@ -72,6 +93,15 @@ impl<T: Tool + Send + Sync + 'static> DynToolExt for T {
                 let this: &T = tool.downcast_ref().unwrap();
                 this.description()
             },
+            input_schema: |tool| {
+                let this: &T = tool.downcast_ref().unwrap();
+                this.input_schema()
+            },
+            parse_tool_call: |tool, call| {
+                let this: &T = tool.downcast_ref().unwrap();
+                this.parse_tool_call(call)
+                    .map(|input| Arc::new(input) as Arc<dyn Any + Send + Sync>)
+            },
             run: |tool, args| {
                 let this: &mut T = tool.downcast_mut().unwrap();
                 let args: &<Self as Tool>::Input = args.downcast_ref().unwrap();
@@ -88,6 +118,8 @@ pub struct BoxedTool {
     name: fn(&dyn Any) -> String,
     input_prompt: fn(&dyn Any) -> String,
     description: fn(&dyn Any) -> String,
+    input_schema: fn(&dyn Any) -> Option<SchemaType>,
+    parse_tool_call: fn(&dyn Any, &ToolCall) -> anyhow::Result<Arc<dyn Any + Send + Sync>>,
     run: for<'a> fn(
         &'a mut dyn Any,
         &'a Arc<dyn Any + Send + Sync>,
@@ -115,6 +147,12 @@ impl Tool for BoxedTool {
     fn description(&self) -> String {
         (self.description)(&self.tool)
     }
+    fn input_schema(&self) -> Option<SchemaType> {
+        (self.input_schema)(&self.tool)
+    }
+    fn parse_tool_call(&self, call: &ToolCall) -> anyhow::Result<Self::Input> {
+        (self.parse_tool_call)(&self.tool, call)
+    }
     fn run<'a>(&'a mut self, args: &'a Self::Input) -> impl Future<Output = String> + Send + 'a {
         (self.run)(&mut self.tool, args)
     }
@@ -124,6 +162,9 @@ impl Tool for BoxedTool {
 #[derive(Default)]
 pub struct ToolManager {
     tools: Vec<BoxedTool>,
+    generation_parameters: GenerationParameters,
+    /// Outputs of native tool calls that haven't been returned from [`ToolManager::run_native_step`] yet
+    pending_native_outputs: VecDeque<(usize, String)>,
 }
 
 impl std::fmt::Debug for ToolManager {
@@ -154,7 +195,18 @@ pub(crate) enum Action {
 impl ToolManager {
     /// Create a new tool empty manager
     pub fn new() -> Self {
-        Self { tools: Vec::new() }
+        Self::default()
+    }
+
+    /// Set the [`GenerationParameters`] the model uses to pick an action
+    ///
+    /// Remote models that call tools natively ignore the parameters their API doesn't support and log a warning.
+    pub fn with_generation_parameters(
+        mut self,
+        generation_parameters: GenerationParameters,
+    ) -> Self {
+        self.generation_parameters = generation_parameters;
+        self
     }
 
     /// Add a tool to the manager
@@ -290,12 +342,16 @@ Question: {question}
 
         let constraints = self.any_action_constraint();
         let validator_state = constraints.create_parser_state();
+        let sampler = self
+            .generation_parameters
+            .clone()
+            .sampler_with_tokenizer(&llm.tokenizer())?;
         let result = llm.generate_structured(
             llm_session,
             prompt,
             constraints,
             validator_state,
-            Arc::new(Mutex::new(GenerationParameters::default().sampler())),
+            Arc::new(Mutex::new(sampler)),
             &mut add_token,
             Some(4),
         )?;
@@ -320,6 +376,131 @@ Question: {question}
             Action::Answer(answer) => ToolManagerStepResult::Finished(answer),
         })
     }
+
+    /// Get the native tool definitions for the tools in the manager
+    pub fn tool_definitions(&self) -> Vec<ToolDefinition> {
+        self.tools
+            .iter()
+            .map(|tool| {
+                let parameters = tool.input_schema().unwrap_or_else(|| {
+                    SchemaType::Object(JsonObjectSchema::new([JsonPropertySchema::new(
+                        "input",
+                        SchemaType::String(StringSchema::new()),
+                    )
+                    .with_required(true)]))
+                });
+                let description = match tool.input_schema() {
+                    Some(_) => tool.description(),
+                    None => format!("{}\n{}", tool.description(), tool.input_prompt()),
+                };
+                ToolDefinition::new(tool.name(), description, parameters)
+            })
+            .collect()
+    }
+
+    /// Run one step of the tool manager with a model that supports native tool calling (see [`Model::supports_tool_calls`]).
+    ///
+    /// Unlike [`ToolManager::run_step`], the conversation is kept as a list of messages. If the history is empty, the question is added as a user message. The model's tool calls and the tool results are added to the history.
+    ///
+    /// Every tool call gets a result. If the model calls a tool that doesn't exist or passes invalid arguments, the error is sent back to the model as the result so it can try again.
+    ///
+    /// If the model calls several tools at once, each call is returned as its own [`ToolManagerStepResult::Action`] step. The remaining steps are returned before the model is asked again. Calls to tools that don't exist have no tool index, so they are only passed to `add_token` and sent back to the model.
+    pub async fn run_native_step<M: Model>(
+        &mut self,
+        question: &str,
+        history: &mut Vec<ToolChatMessage>,
+        llm: &M,
+        mut add_token: impl FnMut(String) -> anyhow::Result<()>,
+    ) -> anyhow::Result<ToolManagerStepResult> {
+        if history.is_empty() {
+            // A new conversation doesn't continue the calls of the last one
+            self.pending_native_outputs.clear();
+            history.push(ChatHistoryItem::new(MessageType::UserMessage, question).into());
+        }
+
+        loop {
+            if let Some((index, output)) = self.pending_native_outputs.pop_front() {
+                return Ok(ToolManagerStepResult::Action { index, output });
+            }
+
+            let response = llm
+                .chat_with_tools_inner(
+                    history,
+                    &self.tool_definitions(),
+                    self.generation_parameters.clone(),
+                )
+                .await?;
+
+            match response {
+                ToolChatResponse::Message(answer) => {
+                    history.push(
+                        ChatHistoryItem::new(MessageType::ModelAnswer, answer.clone()).into(),
+                    );
+                    add_token(answer.clone())?;
+                    return Ok(ToolManagerStepResult::Finished(answer));
+                }
+                ToolChatResponse::ToolCalls(calls) => {
+                    let mut outputs = Vec::with_capacity(calls.len());
+                    for call in &calls {
+                        let index = self
+                            .tools
+                            .iter()
+                            .position(|tool| tool.name() == call.name());
+                        let output = match index {
+                            Some(index) => {
+                                let tool = &mut self.tools[index];
+                                let output = match tool.parse_tool_call(call) {
+                                    Ok(input) => tool.run(&input).await,
+                                    Err(err) => {
+                                        format!("Invalid arguments for {}: {err}", call.name())
+                                    }
+                                };
+                                self.pending_native_outputs
+                                    .push_back((index, output.clone()));
+                                output
+                            }
+                            None => format!("Unknown tool: {}", call.name()),
+                        };
+                        add_token(output.clone() + "\n")?;
+                        outputs.push(output);
+                    }
+
+                    // The calls are only added once every call has a result
+                    history.push(ToolChatMessage::ToolCalls(calls.clone()));
+                    for (call, output) in calls.iter().zip(outputs) {
+                        history.push(ToolChatMessage::ToolResult {
+                            id: call.id().to_string(),
+                            output,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Get a string argument of a native tool call.
+fn string_argument(call: &ToolCall, name: &str) -> anyhow::Result<String> {
+    let arguments: serde_json::Value = serde_json::from_str(call.arguments())?;
+    arguments
+        .get(name)
+        .and_then(|value| value.as_str())
+        .map(ToString::to_string)
+        .ok_or_else(|| anyhow::anyhow!("Tool arguments are missing the {name}: {arguments}"))
+}
+
+/// Parse the text input of a tool with the tool's input parser.
+fn parse_text_input<P: CreateParserState>(input: &str, parser: &P) -> anyhow::Result<P::Output> {
+    // Text parsers like OneLine finish at the end of the line
+    let input = format!("{input}\n");
+    let state = parser.create_parser_state();
+    match parser.parse(&state, input.as_bytes()) {
+        Ok(ParseStatus::Finished { result, .. }) => Ok(result),
+        Ok(ParseStatus::Incomplete { .. }) => {
+            Err(anyhow::anyhow!("Incomplete tool input: {input:?}"))
+        }
+        Err(err) => Err(anyhow::anyhow!("Invalid tool input {input:?}: {err:?}")),
+    }
 }
 
 /// The result of a step in the tool manager
@@ -553,3 +734,97 @@ impl_from_tool_tuple!(A, B, C, D, E, F, G, H, I);
 impl_from_tool_tuple!(A, B, C, D, E, F, G, H, I, J);
 impl_from_tool_tuple!(A, B, C, D, E, F, G, H, I, J, K);
 impl_from_tool_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// A model that answers a chat with tools with a fixed list of responses.
+#[cfg(test)]
+struct ScriptedToolModel {
+    responses: Mutex<Vec<ToolChatResponse>>,
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Model for ScriptedToolModel {
+    type TextStream = kalosm_streams::text_stream::ChannelTextStream;
+    type SyncModel = kalosm_language_model::SyncModelNotSupported;
+
+    // The tool manager only calls chat_with_tools_inner on models that support tool calls
+    fn tokenizer(&self) -> Arc<tokenizers::Tokenizer> {
+        unreachable!("The scripted model only answers chats with tools")
+    }
+
+    async fn stream_text_inner(
+        &self,
+        _: &str,
+        _: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        unreachable!("The scripted model only answers chats with tools")
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        true
+    }
+
+    async fn chat_with_tools_inner(
+        &self,
+        _: &[ToolChatMessage],
+        _: &[ToolDefinition],
+        _: GenerationParameters,
+    ) -> anyhow::Result<ToolChatResponse> {
+        Ok(self.responses.lock().unwrap().remove(0))
+    }
+}
+
+#[tokio::test]
+async fn native_step_answers_every_tool_call() {
+    let llm = ScriptedToolModel {
+        responses: Mutex::new(vec![
+            ToolChatResponse::ToolCalls(vec![
+                ToolCall::new("1", "Calculator", r#"{"expression": "2 + 2"}"#),
+                ToolCall::new("2", "Weather", r#"{"city": "Paris"}"#),
+                ToolCall::new("3", "Calculator", "{}"),
+                ToolCall::new("4", "Calculator", r#"{"expression": "3 * 3"}"#),
+            ]),
+            ToolChatResponse::Message("4 and 9".to_string()),
+        ]),
+    };
+    let mut tools = ToolManager::new().with_tool(CalculatorTool);
+    let mut history = Vec::new();
+
+    let mut steps = Vec::new();
+    for _ in 0..3 {
+        let ToolManagerStepResult::Action { index, output } = tools
+            .run_native_step("What is 2 + 2 and 3 * 3?", &mut history, &llm, |_| Ok(()))
+            .await
+            .unwrap()
+        else {
+            panic!("Each tool call should be its own step");
+        };
+        steps.push((index, output));
+    }
+    // The unknown tool is only reported back to the model, and the invalid arguments don't fail the step
+    assert_eq!(steps[0], (0, "4".to_string()));
+    assert_eq!(steps[1].0, 0);
+    assert!(steps[1].1.starts_with("Invalid arguments for Calculator"));
+    assert_eq!(steps[2], (0, "9".to_string()));
+
+    // Every call has a result in the history
+    assert!(matches!(&history[1], ToolChatMessage::ToolCalls(calls) if calls.len() == 4));
+    let result_ids = history[2..]
+        .iter()
+        .map(|message| match message {
+            ToolChatMessage::ToolResult { id, .. } => id.as_str(),
+            _ => panic!("Expected a tool result"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(result_ids, ["1", "2", "3", "4"]);
+    assert!(
+        matches!(&history[3], ToolChatMessage::ToolResult { output, .. } if output == "Unknown tool: Weather")
+    );
+
+    let result = tools
+        .run_native_step("What is 2 + 2 and 3 * 3?", &mut history, &llm, |_| Ok(()))
+        .await
+        .unwrap();
+    assert!(matches!(result, ToolManagerStepResult::Finished(answer) if answer == "4 and 9"));
+    assert_eq!(history.len(), 7);
+}
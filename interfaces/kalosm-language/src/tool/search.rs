@@ -1,10 +1,13 @@
-use kalosm_sample::CreateParserState;
+use kalosm_language_model::ToolCall;
+use kalosm_sample::{
+    CreateParserState, JsonObjectSchema, JsonPropertySchema, SchemaType, StringSchema,
+};
 
 use crate::context::IntoDocuments;
 use crate::context::SearchQuery;
 use crate::tool::Tool;
 
-use super::OneLine;
+use super::{string_argument, OneLine};
 
 /// A tool that can search the web
 pub struct WebSearchTool {
@@ -42,6 +45,18 @@ impl Tool for WebSearchTool {
         "Search the web for a query.\nUse tool with:\nAction: Web Search\nSearch query: the search query\nExample:\n\nQuestion: What is Floneum?\nThought: I don't remember what Floneum is. I should search the web for it.\nAction: Web Search\nAction Input: What is Floneum?\nObservation: Floneum is a visual editor for AI workflows.\nThought: I now know that Floneum is a visual editor for AI workflows.\nFinal Answer: Floneum is a visual editor for AI workflows.".to_string()
     }
 
+    fn input_schema(&self) -> Option<SchemaType> {
+        Some(SchemaType::Object(JsonObjectSchema::new([
+            JsonPropertySchema::new("query", SchemaType::String(StringSchema::new()))
+                .with_description("The search query")
+                .with_required(true),
+        ])))
+    }
+
+    fn parse_tool_call(&self, call: &ToolCall) -> anyhow::Result<Self::Input> {
+        string_argument(call, "query")
+    }
+
     async fn run<'a>(&'a mut self, query: &'a Self::Input) -> String {
         let api_key =
             std::env::var("SERPER_API_KEY").expect("SERPER_API_KEY environment variable not set");
@@ -9,7 +9,7 @@ use syn::meta::ParseNestedMeta;
 use syn::parse::{Parse, ParseStream};
 use syn::spanned::Spanned;
 use syn::{ext::IdentExt, parse_macro_input, DeriveInput, Field, Ident, LitStr};
-use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
+use syn::{DataEnum, Fields, FieldsNamed, FieldsUnnamed, LitInt, Path, TypePath, Variant};
 
 /// Derive a default JSON parser for a unit value, struct or enum.
 ///
@@ -63,6 +63,29 @@ use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
 /// assert_eq!(action, Action::Search { query: "my query".to_string() });
 /// ```
 ///
+/// Structs and enum variants with unnamed fields use the same JSON layout as serde. A newtype is parsed as the inner value and any other number of fields is parsed as an array:
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// struct Point(i32, i32);
+///
+/// let parser = Point::new_parser();
+/// let state = parser.create_parser_state();
+/// let point = parser.parse(&state, b"[1, 2]").unwrap().unwrap_finished();
+/// assert_eq!(point, Point(1, 2));
+///
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// enum Shape {
+///     Circle(u32),
+///     Line(Point, Point),
+/// }
+///
+/// let parser = Shape::new_parser();
+/// let state = parser.create_parser_state();
+/// let shape = parser.parse(&state, b"{ \"type\": \"Line\", \"data\": [[0, 0], [1, 1]] }").unwrap().unwrap_finished();
+/// assert_eq!(shape, Shape::Line(Point(0, 0), Point(1, 1)));
+/// ```
+///
 /// ## Attributes
 ///
 /// The `#[parse]` attribute modifies the default behavior of the parser. It can be used in the following forms:
@@ -99,6 +122,106 @@ use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
 /// }
 /// ```
 ///
+/// - `#[parse(items = 1..=5)]` limits the number of items in a `Vec` field (defaults to any number of items). Array fields always have exactly their length in items, so they don't accept `items`
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Clone)]
+/// struct Recipe {
+///     #[parse(items = 1..=5)]
+///     ingredients: Vec<String>,
+///     steps: [String; 3],
+/// }
+///
+/// let parser = Recipe::new_parser();
+/// let state = parser.create_parser_state();
+/// let recipe = parser
+///     .parse(&state, b"{ \"ingredients\": [\"flour\"], \"steps\": [\"mix\", \"bake\", \"eat\"] }")
+///     .unwrap()
+///     .unwrap_finished();
+/// assert_eq!(recipe.steps, ["mix", "bake", "eat"]);
+/// ```
+///
+/// - `#[parse(skip)]` never generates the field and creates it with [`Default::default`]. `#[parse(skip, default = expression)]` creates the field with the expression instead. Skipped fields are not included in the schema
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Clone, Debug)]
+/// struct Person {
+///     name: String,
+///     #[parse(skip)]
+///     friends: Vec<String>,
+///     #[parse(skip, default = "model".to_string())]
+///     source: String,
+/// }
+///
+/// let parser = Person::new_parser();
+/// let state = parser.create_parser_state();
+/// let person = parser.parse(&state, b"{ \"name\": \"John\" }").unwrap().unwrap_finished();
+/// assert!(person.friends.is_empty());
+/// assert_eq!(person.source, "model");
+/// ```
+///
+/// - `#[parse(examples = [...])]` adds example values for the field to the schema
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Clone)]
+/// struct Person {
+///     #[parse(examples = ["John", "Jane"])]
+///     name: String,
+///     #[parse(examples = [30, 42])]
+///     age: u32,
+/// }
+/// ```
+///
+/// - `#[parse(validate = function)]` rejects the field if the function returns an error once the field is complete. The function takes a reference to the value of the field and returns a `ParseResult<()>`. If the value is invalid, parsing fails with the validation error. During generation, this rejects the token that would complete the invalid value, so the model has to continue the field differently and the invalid value never appears in the output
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// fn even(value: &u32) -> ParseResult<()> {
+///     if value % 2 != 0 {
+///         bail!("The number of legs must be even");
+///     }
+///     Ok(())
+/// }
+///
+/// #[derive(Parse, Schema, Clone)]
+/// struct Animal {
+///     #[parse(validate = even)]
+///     legs: u32,
+/// }
+///
+/// let parser = Animal::new_parser();
+/// let state = parser.create_parser_state();
+/// // The field is only checked once it is complete
+/// let (state, _) = parser
+///     .parse(&state, b"{ \"legs\": 3")
+///     .unwrap()
+///     .unwrap_incomplete();
+/// assert!(parser.parse(&state, b" }").is_err());
+/// let animal = parser.parse(&state, b"4 }").unwrap().unwrap_finished();
+/// assert_eq!(animal.legs, 34);
+/// ```
+///
+/// - `#[parse(unordered)]` lets the fields of a struct be generated in any order. Fields with an `Option` type may be omitted from the object and are marked as not required in the schema
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// #[parse(unordered)]
+/// struct Person {
+///     name: String,
+///     age: u32,
+///     nickname: Option<String>,
+/// }
+///
+/// let parser = Person::new_parser();
+/// let state = parser.create_parser_state();
+/// let person = parser.parse(&state, b"{ \"age\": 30, \"name\": \"John\" }").unwrap().unwrap_finished();
+/// assert_eq!(person, Person { name: "John".to_string(), age: 30, nickname: None });
+/// ```
+///
 /// - `#[parse(tag = "tag")]` changes the name of the tag for enum variants (defaults to "type")
 ///
 /// ```rust
@@ -122,6 +245,40 @@ use syn::{DataEnum, Fields, FieldsNamed, LitInt, Path, TypePath, Variant};
 ///     Quit,
 /// }
 /// ```
+///
+/// - `#[parse(externally_tagged)]` stores the data of enum variants under the name of the variant like `{ "Search": { "query": "my query" } }`. Unit variants are parsed as the name of the variant like `"Quit"`. This matches the default enum representation in serde
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// #[parse(externally_tagged)]
+/// enum Action {
+///     Search { query: String },
+///     Quit,
+/// }
+///
+/// let parser = Action::new_parser();
+/// let state = parser.create_parser_state();
+/// let action = parser.parse(&state, b"{ \"Search\": { \"query\": \"my query\" } }").unwrap().unwrap_finished();
+/// assert_eq!(action, Action::Search { query: "my query".to_string() });
+/// ```
+///
+/// - `#[parse(untagged)]` parses the data of any variant without the name of the variant. Unit variants are parsed as `null`. If the data of multiple variants matches, the first variant is used
+///
+/// ```rust
+/// # use kalosm::language::*;
+/// #[derive(Parse, Schema, Debug, Clone, PartialEq)]
+/// #[parse(untagged)]
+/// enum Value {
+///     Number(u32),
+///     Text(String),
+/// }
+///
+/// let parser = Value::new_parser();
+/// let state = parser.create_parser_state();
+/// let value = parser.parse(&state, b"\"hello\"").unwrap().unwrap_finished();
+/// assert_eq!(value, Value::Text("hello".to_string()));
+/// ```
 #[proc_macro_derive(Parse, attributes(parse))]
 pub fn derive_parse(input: TokenStream) -> TokenStream {
     // Parse the input tokens into a syntax tree
@@ -145,16 +302,19 @@ pub fn derive_parse(input: TokenStream) -> TokenStream {
 
                 TokenStream::from(struct_parser.parser())
             }
+            syn::Fields::Unnamed(fields) => {
+                let ty = input.ident;
+                let struct_parser = match TupleStructParser::new(input.attrs, fields, ty) {
+                    Ok(parser) => parser,
+                    Err(err) => return err.to_compile_error().into(),
+                };
+
+                TokenStream::from(struct_parser.parser())
+            }
             syn::Fields::Unit => {
                 let ty = input.ident;
                 TokenStream::from(impl_unit_parser(&input.attrs, &ty, quote! { Self }))
             }
-            _ => syn::Error::new(
-                input.ident.span(),
-                "Only structs with named fields are supported",
-            )
-            .to_compile_error()
-            .into(),
         },
         syn::Data::Enum(data) => {
             let ty = input.ident;
@@ -181,12 +341,9 @@ pub fn derive_parse(input: TokenStream) -> TokenStream {
             }
             .into()
         }
-        _ => syn::Error::new(
-            input.ident.span(),
-            "Only structs and unit value enums are supported",
-        )
-        .to_compile_error()
-        .into(),
+        _ => syn::Error::new(input.ident.span(), "Only structs and enums are supported")
+            .to_compile_error()
+            .into(),
     }
 }
 
@@ -209,16 +366,19 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
 
                 TokenStream::from(struct_parser.quote_schema())
             }
+            syn::Fields::Unnamed(fields) => {
+                let ty = input.ident;
+                let struct_parser = match TupleStructParser::new(input.attrs, fields, ty) {
+                    Ok(parser) => parser,
+                    Err(err) => return err.to_compile_error().into(),
+                };
+
+                TokenStream::from(struct_parser.quote_schema())
+            }
             syn::Fields::Unit => {
                 let ty = input.ident;
                 TokenStream::from(unit_schema(&input.attrs, &ty))
             }
-            _ => syn::Error::new(
-                input.ident.span(),
-                "Only structs with named fields are supported",
-            )
-            .to_compile_error()
-            .into(),
         },
         syn::Data::Enum(data) => {
             let ty = input.ident;
@@ -245,12 +405,9 @@ pub fn derive_schema(input: TokenStream) -> TokenStream {
             }
             .into()
         }
-        _ => syn::Error::new(
-            input.ident.span(),
-            "Only structs and unit value enums are supported",
-        )
-        .to_compile_error()
-        .into(),
+        _ => syn::Error::new(input.ident.span(), "Only structs and enums are supported")
+            .to_compile_error()
+            .into(),
     }
 }
 
@@ -266,13 +423,16 @@ impl StructParser {
         let named = fields.named.into_iter().collect::<Vec<_>>();
 
         let mut name = ty.unraw().to_string();
+        let mut unordered = false;
         for attr in &attributes {
             if attr.path().is_ident("parse") {
                 attr.parse_nested_meta(|meta| {
                     if let Some(value) = parse_rename_attribute(&meta)? {
                         name = value.value();
+                    } else if meta.path.is_ident("unordered") {
+                        unordered = true;
                     } else {
-                        return Err(meta.error("expected `rename`"));
+                        return Err(meta.error("expected `rename` or `unordered`"));
                     }
                     Ok(())
                 })?;
@@ -283,7 +443,7 @@ impl StructParser {
             attributes,
             name,
             ty,
-            fields: FieldsParser::new(&named)?,
+            fields: FieldsParser::new(&named, unordered)?,
         })
     }
 
@@ -338,6 +498,65 @@ impl StructParser {
     }
 }
 
+/// A parser for a struct with unnamed fields. Newtype structs are parsed as the inner value and other tuple structs are parsed as an array
+struct TupleStructParser {
+    ty: Ident,
+    fields: TupleFieldsParser,
+}
+
+impl TupleStructParser {
+    fn new(attributes: Vec<syn::Attribute>, fields: FieldsUnnamed, ty: Ident) -> syn::Result<Self> {
+        for attr in &attributes {
+            if attr.path().is_ident("parse") {
+                attr.parse_nested_meta(|meta| {
+                    Err(meta.error("Structs with unnamed fields do not support any attributes"))
+                })?;
+            }
+        }
+
+        let unnamed = fields.unnamed.into_iter().collect::<Vec<_>>();
+
+        Ok(Self {
+            ty,
+            fields: TupleFieldsParser::new(&unnamed)?,
+        })
+    }
+
+    fn parser(&self) -> TokenStream2 {
+        let field_names = self.fields.fields.iter().map(|f| &f.binding);
+        let construct = quote! {
+            Self(
+                #(
+                    #field_names
+                ),*
+            )
+        };
+        let parser = self.fields.parser(construct);
+        let ty = &self.ty;
+
+        quote! {
+            impl kalosm_sample::Parse for #ty {
+                fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
+                    #parser
+                }
+            }
+        }
+    }
+
+    fn quote_schema(&self) -> TokenStream2 {
+        let schema = self.fields.quote_schema();
+        let ty = &self.ty;
+
+        quote! {
+            impl kalosm_sample::Schema for #ty {
+                fn schema() -> kalosm_sample::SchemaType {
+                    #schema
+                }
+            }
+        }
+    }
+}
+
 fn quote_fields(fields: Fields) -> TokenStream2 {
     match fields {
         Fields::Named(fields) => {
@@ -433,18 +652,29 @@ fn unit_parser(attrs: &[syn::Attribute], ty: &Ident) -> TokenStream2 {
     }
 }
 
+/// How the variant of an enum is stored in the JSON. The names match the enum representations in serde
+enum EnumRepresentation {
+    /// `{ "type": "Variant", "data": ... }`
+    Adjacent { tag: String, content: String },
+    /// `{ "Variant": ... }` or `"Variant"` for unit variants
+    External,
+    /// The data of the variant without the name. Unit variants are `null`
+    Untagged,
+}
+
 struct EnumParser {
     ty: Ident,
-    tag: String,
-    data: String,
+    representation: EnumRepresentation,
     variants: Vec<EnumVariant>,
 }
 
 impl EnumParser {
     fn new(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> syn::Result<Self> {
-        // Look for the tag and content attributes within the #[parse] attribute
-        let mut tag = "type".to_string();
-        let mut content = "data".to_string();
+        // Look for the tag, content, externally_tagged and untagged attributes within the #[parse] attribute
+        let mut tag = None;
+        let mut content = None;
+        let mut externally_tagged = None;
+        let mut untagged = None;
         for attr in attrs.iter() {
             if attr.path().is_ident("parse") {
                 attr.parse_nested_meta(|meta| {
@@ -452,21 +682,53 @@ impl EnumParser {
                         let value = meta
                             .value()
                             .and_then(|value| value.parse::<syn::LitStr>())?;
-                        tag = value.value();
+                        tag = Some(value.value());
                         Ok(())
                     } else if meta.path.is_ident("content") {
                         let value = meta
                             .value()
                             .and_then(|value| value.parse::<syn::LitStr>())?;
-                        content = value.value();
+                        content = Some(value.value());
+                        Ok(())
+                    } else if meta.path.is_ident("externally_tagged") {
+                        externally_tagged = Some(meta.path.span());
+                        Ok(())
+                    } else if meta.path.is_ident("untagged") {
+                        untagged = Some(meta.path.span());
                         Ok(())
                     } else {
-                        Err(meta.error("expected `tag` or `content`"))
+                        Err(meta.error(expected_attributes_error([
+                            "tag",
+                            "content",
+                            "externally_tagged",
+                            "untagged",
+                        ])))
                     }
                 })?;
             }
         }
 
+        let representation = match (externally_tagged, untagged) {
+            (None, None) => EnumRepresentation::Adjacent {
+                tag: tag.unwrap_or_else(|| "type".to_string()),
+                content: content.unwrap_or_else(|| "data".to_string()),
+            },
+            (Some(span), None) | (None, Some(span)) if tag.is_some() || content.is_some() => {
+                return Err(syn::Error::new(
+                    span,
+                    "`tag` and `content` can only be used with adjacently tagged enums",
+                ));
+            }
+            (Some(_), None) => EnumRepresentation::External,
+            (None, Some(_)) => EnumRepresentation::Untagged,
+            (Some(_), Some(span)) => {
+                return Err(syn::Error::new(
+                    span,
+                    "An enum can't be both `externally_tagged` and `untagged`",
+                ));
+            }
+        };
+
         let variants = data
             .variants
             .iter()
@@ -475,20 +737,17 @@ impl EnumParser {
 
         Ok(EnumParser {
             ty,
-            tag,
-            data: content,
+            representation,
             variants,
         })
     }
 
     fn quote_parser(&self) -> syn::Result<TokenStream2> {
-        let tag = &self.tag;
         let ty = &self.ty;
-        let content = &self.data;
         let mut parser = None;
 
         for variant in &self.variants {
-            let parse_variant = variant.quote_parser(content)?;
+            let parse_variant = variant.quote_parser(&self.representation)?;
             match &mut parser {
                 Some(current) => {
                     *current = quote! {
@@ -504,44 +763,58 @@ impl EnumParser {
             }
         }
 
-        let struct_start = format!("{{ \"{tag}\": \"");
+        let parser = match &self.representation {
+            EnumRepresentation::Adjacent { tag, .. } => {
+                let struct_start = format!("{{ \"{tag}\": \"");
+                quote! {
+                    kalosm_sample::LiteralParser::from(#struct_start)
+                        .ignore_output_then(#parser)
+                        .then_literal(r#" }"#)
+                }
+            }
+            EnumRepresentation::External | EnumRepresentation::Untagged => quote! { #parser },
+        };
 
         Ok(quote! {
             impl kalosm_sample::Parse for #ty {
                 fn new_parser() -> impl kalosm_sample::SendCreateParserState<Output = Self> {
-                    kalosm_sample::LiteralParser::from(#struct_start)
-                        .ignore_output_then(#parser)
-                        .then_literal(r#" }"#)
+                    #parser
                 }
             }
         })
     }
 
     fn quote_schema(&self) -> syn::Result<proc_macro2::TokenStream> {
-        let tag = &self.tag;
-        let content = &self.data;
         let ty = &self.ty;
 
         let variants: Vec<_> = self
             .variants
             .iter()
-            .map(|variant| {
-                let variant_name = &variant.name;
-                let variant_parser = variant.quote_schema(tag, content, variant_name)?;
-                Ok(quote! {
-                    #variant_parser
-                })
-            })
-            .collect::<syn::Result<_>>()?;
+            .map(|variant| variant.quote_schema(&self.representation))
+            .collect();
+
+        // Untagged variants may overlap, so the value only needs to match one of them
+        let schema = match &self.representation {
+            EnumRepresentation::Untagged => quote! {
+                kalosm_sample::SchemaType::AnyOf(
+                    kalosm_sample::AnyOfSchema::new([
+                        #(#variants),*
+                    ])
+                )
+            },
+            _ => quote! {
+                kalosm_sample::SchemaType::OneOf(
+                    kalosm_sample::OneOfSchema::new([
+                        #(#variants),*
+                    ])
+                )
+            },
+        };
 
         Ok(quote! {
             impl kalosm_sample::Schema for #ty {
                 fn schema() -> kalosm_sample::SchemaType {
-                    kalosm_sample::SchemaType::OneOf(
-                        kalosm_sample::OneOfSchema::new([
-                            #(#variants),*
-                        ])
-                    )
+                    #schema
                 }
             }
         })
@@ -574,21 +847,15 @@ impl EnumVariant {
 
         let parse_variant = match &variant.fields {
             syn::Fields::Named(fields) => {
-                EnumVariantType::Struct(StructEnumVariantParser::new(fields)?)
+                let fields = fields.named.iter().cloned().collect::<Vec<_>>();
+                EnumVariantType::Struct(FieldsParser::new(&fields, false)?)
             }
             syn::Fields::Unnamed(fields) => {
-                let field_vec = fields.unnamed.iter().collect::<Vec<_>>();
-                let [inner] = *field_vec else {
-                    return Err(syn::Error::new(
-                        variant.ident.span(),
-                        "Unnamed enum variants with more or less than one field are not supported",
-                    ));
-                };
-
-                EnumVariantType::Tuple(TupleEnumVariantParser::new(inner))
+                let fields = fields.unnamed.iter().cloned().collect::<Vec<_>>();
+                EnumVariantType::Tuple(TupleFieldsParser::new(&fields)?)
             }
             // If this is a unit variant, we can just parse the type
-            syn::Fields::Unit => EnumVariantType::Unit(UnitEnumVariantParser::new()),
+            syn::Fields::Unit => EnumVariantType::Unit,
         };
 
         Ok(Self {
@@ -606,191 +873,125 @@ impl EnumVariant {
         }
     }
 
-    fn quote_parser(&self, content_name: &str) -> syn::Result<TokenStream2> {
+    /// The parser for the data of the variant or `None` for unit variants
+    fn quote_data_parser(&self) -> syn::Result<Option<TokenStream2>> {
         let construct_variant = self.construct_variant();
-        match &self.ty {
-            EnumVariantType::Struct(parser) => {
-                parser.quote_parser(&self.name, content_name, construct_variant)
-            }
-            EnumVariantType::Tuple(parser) => {
-                parser.quote_parser(&self.name, content_name, construct_variant)
-            }
-            EnumVariantType::Unit(parser) => parser.quote_parser(&self.name, construct_variant),
-        }
-    }
-
-    fn quote_schema(
-        &self,
-        tag: &str,
-        content: &str,
-        variant_name: &str,
-    ) -> syn::Result<proc_macro2::TokenStream> {
-        match &self.ty {
-            EnumVariantType::Struct(parser) => parser.quote_schema(tag, content, variant_name),
-            EnumVariantType::Tuple(parser) => parser.quote_schema(tag, content, variant_name),
-            EnumVariantType::Unit(parser) => parser.quote_schema(tag, variant_name),
-        }
-    }
-}
-
-enum EnumVariantType {
-    Unit(UnitEnumVariantParser),
-    Tuple(TupleEnumVariantParser),
-    Struct(StructEnumVariantParser),
-}
-
-struct UnitEnumVariantParser {}
-
-impl UnitEnumVariantParser {
-    fn new() -> Self {
-        Self {}
-    }
-
-    fn quote_parser(
-        &self,
-        variant_name: &str,
-        construct_variant: TokenStream2,
-    ) -> syn::Result<TokenStream2> {
-        let lit_str_name = LitStr::new(&format!("{variant_name}\""), Span::call_site());
-        Ok(quote! {
-            kalosm_sample::LiteralParser::from(#lit_str_name).map_output(|_| #construct_variant)
+        Ok(match &self.ty {
+            EnumVariantType::Struct(fields) => Some(fields.parser(construct_variant)?),
+            EnumVariantType::Tuple(fields) => Some(fields.parser(construct_variant)),
+            EnumVariantType::Unit => None,
         })
     }
 
-    fn quote_schema(&self, tag: &str, variant_name: &str) -> syn::Result<proc_macro2::TokenStream> {
-        Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
-                        )
+    /// The schema for the data of the variant or `None` for unit variants
+    fn quote_data_schema(&self) -> Option<TokenStream2> {
+        match &self.ty {
+            EnumVariantType::Struct(fields) => {
+                let schema = fields.quote_schema();
+                Some(quote! {
+                    kalosm_sample::SchemaType::Object(
+                        #schema
                     )
-                    .with_required(true)
-                ])
-            )
-        })
-    }
-}
-
-struct StructEnumVariantParser {
-    fields: FieldsParser,
-}
-
-impl StructEnumVariantParser {
-    fn new(fields: &FieldsNamed) -> syn::Result<Self> {
-        let fields = fields.named.iter().cloned().collect::<Vec<_>>();
-        Ok(Self {
-            fields: FieldsParser::new(&fields)?,
-        })
+                })
+            }
+            EnumVariantType::Tuple(fields) => Some(fields.quote_schema()),
+            EnumVariantType::Unit => None,
+        }
     }
 
-    fn quote_parser(
-        &self,
-        variant_name: &str,
-        content_name: &str,
-        construct_variant: TokenStream2,
-    ) -> syn::Result<TokenStream2> {
-        let parse_name_and_data = LitStr::new(
-            &format!("{variant_name}\", \"{content_name}\": "),
-            Span::call_site(),
-        );
-        let field_parser = self.fields.parser(construct_variant)?;
-        Ok(quote! {
-            kalosm_sample::LiteralParser::from(#parse_name_and_data).ignore_output_then(#field_parser)
+    fn quote_parser(&self, representation: &EnumRepresentation) -> syn::Result<TokenStream2> {
+        let variant_name = &self.name;
+        let construct_variant = self.construct_variant();
+        let data_parser = self.quote_data_parser()?;
+        Ok(match (representation, data_parser) {
+            // The adjacently tagged enum parser parses the start of the tag before the variant
+            (EnumRepresentation::Adjacent { .. }, None) => {
+                let lit_str_name = LitStr::new(&format!("{variant_name}\""), Span::call_site());
+                quote! {
+                    kalosm_sample::LiteralParser::from(#lit_str_name).map_output(|_| #construct_variant)
+                }
+            }
+            (EnumRepresentation::Adjacent { content, .. }, Some(data_parser)) => {
+                let parse_name_and_data = LitStr::new(
+                    &format!("{variant_name}\", \"{content}\": "),
+                    Span::call_site(),
+                );
+                quote! {
+                    kalosm_sample::LiteralParser::from(#parse_name_and_data).ignore_output_then(#data_parser)
+                }
+            }
+            (EnumRepresentation::External, None) => {
+                let lit_str_name = LitStr::new(&format!("\"{variant_name}\""), Span::call_site());
+                quote! {
+                    kalosm_sample::LiteralParser::from(#lit_str_name).map_output(|_| #construct_variant)
+                }
+            }
+            (EnumRepresentation::External, Some(data_parser)) => {
+                let parse_name =
+                    LitStr::new(&format!("{{ \"{variant_name}\": "), Span::call_site());
+                quote! {
+                    kalosm_sample::LiteralParser::from(#parse_name)
+                        .ignore_output_then(#data_parser)
+                        .then_literal(r#" }"#)
+                }
+            }
+            (EnumRepresentation::Untagged, None) => quote! {
+                kalosm_sample::LiteralParser::from("null").map_output(|_| #construct_variant)
+            },
+            (EnumRepresentation::Untagged, Some(data_parser)) => data_parser,
         })
     }
 
-    fn quote_schema(
-        &self,
-        tag: &str,
-        content: &str,
-        variant_name: &str,
-    ) -> syn::Result<proc_macro2::TokenStream> {
-        let variant_parser = self.fields.quote_schema();
-        Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
-                        )
-                    )
-                    .with_required(true),
-                    kalosm_sample::JsonPropertySchema::new(
-                        #content,
-                        kalosm_sample::SchemaType::Object(
-                            #variant_parser
-                        )
-                    )
-                    .with_required(true)
-                ])
+    fn quote_schema(&self, representation: &EnumRepresentation) -> TokenStream2 {
+        let variant_name = &self.name;
+        let data_schema = self.quote_data_schema();
+        let name_schema = quote! {
+            kalosm_sample::SchemaType::Const(
+                kalosm_sample::ConstSchema::new(
+                    kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
+                )
             )
-        })
-    }
-}
-
-struct TupleEnumVariantParser {
-    field: Field,
-}
-
-impl TupleEnumVariantParser {
-    fn new(fields: &Field) -> Self {
-        Self {
-            field: fields.clone(),
+        };
+        match (representation, data_schema) {
+            (EnumRepresentation::Adjacent { tag, .. }, None) => quote! {
+                kalosm_sample::SchemaType::Object(
+                    kalosm_sample::JsonObjectSchema::new([
+                        kalosm_sample::JsonPropertySchema::new(#tag, #name_schema)
+                            .with_required(true)
+                    ])
+                )
+            },
+            (EnumRepresentation::Adjacent { tag, content }, Some(data_schema)) => quote! {
+                kalosm_sample::SchemaType::Object(
+                    kalosm_sample::JsonObjectSchema::new([
+                        kalosm_sample::JsonPropertySchema::new(#tag, #name_schema)
+                            .with_required(true),
+                        kalosm_sample::JsonPropertySchema::new(#content, #data_schema)
+                            .with_required(true)
+                    ])
+                )
+            },
+            (EnumRepresentation::External, None) => name_schema,
+            (EnumRepresentation::External, Some(data_schema)) => quote! {
+                kalosm_sample::SchemaType::Object(
+                    kalosm_sample::JsonObjectSchema::new([
+                        kalosm_sample::JsonPropertySchema::new(#variant_name, #data_schema)
+                            .with_required(true)
+                    ])
+                )
+            },
+            (EnumRepresentation::Untagged, None) => quote! {
+                kalosm_sample::SchemaType::Null
+            },
+            (EnumRepresentation::Untagged, Some(data_schema)) => data_schema,
         }
     }
+}
 
-    fn quote_parser(
-        &self,
-        variant_name: &str,
-        content: &str,
-        construct_variant: TokenStream2,
-    ) -> syn::Result<TokenStream2> {
-        let parse_name_and_data = LitStr::new(
-            &format!("{variant_name}\", \"{content}\": "),
-            Span::call_site(),
-        );
-        let ty = &self.field.ty;
-        Ok(quote! {
-            kalosm_sample::LiteralParser::from(#parse_name_and_data).ignore_output_then(<#ty as kalosm_sample::Parse>::new_parser()).map_output(|data0| #construct_variant)
-        })
-    }
-
-    fn quote_schema(
-        &self,
-        tag: &str,
-        content: &str,
-        variant_name: &str,
-    ) -> syn::Result<proc_macro2::TokenStream> {
-        let ty = &self.field.ty;
-        Ok(quote! {
-            kalosm_sample::SchemaType::Object(
-                kalosm_sample::JsonObjectSchema::new([
-                    kalosm_sample::JsonPropertySchema::new(
-                        #tag,
-                        kalosm_sample::SchemaType::Const(
-                            kalosm_sample::ConstSchema::new(
-                                kalosm_sample::SchemaLiteral::String(#variant_name.to_string())
-                            )
-                        )
-                    )
-                    .with_required(true),
-                    kalosm_sample::JsonPropertySchema::new(
-                        #content,
-                        <#ty as kalosm_sample::Schema>::schema()
-                    )
-                    .with_required(true)
-                ])
-            )
-        })
-    }
+enum EnumVariantType {
+    Unit,
+    Tuple(TupleFieldsParser),
+    Struct(FieldsParser),
 }
 
 fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> TokenStream2 {
@@ -806,7 +1007,14 @@ fn unit_enum_parser(attrs: Vec<syn::Attribute>, data: DataEnum, ty: Ident) -> To
                     unquoted = true;
                     return Ok(());
                 }
-                Err(meta.error("expected `unquoted`"))
+                // Unit enums are already parsed as the name of the variant like externally tagged enums
+                if meta.path.is_ident("externally_tagged") {
+                    return Ok(());
+                }
+                if meta.path.is_ident("untagged") {
+                    return Err(meta.error("Untagged enums need at least one variant with fields"));
+                }
+                Err(meta.error("expected `unquoted` or `externally_tagged`"))
             });
             if let Err(err) = result {
                 return err.to_compile_error();
@@ -1039,26 +1247,48 @@ fn parse_rename_attribute(meta: &ParseNestedMeta) -> syn::Result<Option<LitStr>>
 
 struct FieldsParser {
     fields: Vec<FieldParser>,
+    /// If the fields can be generated in any order. `Option` fields may be omitted in unordered mode
+    unordered: bool,
 }
 
 impl FieldsParser {
-    fn new(fields: &[Field]) -> syn::Result<Self> {
+    fn new(fields: &[Field], unordered: bool) -> syn::Result<Self> {
         Ok(Self {
             fields: fields
                 .iter()
-                .map(FieldParser::new)
+                .enumerate()
+                .map(|(index, field)| FieldParser::new(field, index))
                 .collect::<syn::Result<_>>()?,
+            unordered,
         })
     }
 
+    /// If a field must be present in the generated object
+    fn is_required(&self, field: &FieldParser) -> bool {
+        !self.unordered || !field.is_option()
+    }
+
     fn parser(&self, construct: TokenStream2) -> syn::Result<TokenStream2> {
+        // Skipped fields are never generated. They are created from their default value after the other fields are parsed
+        let (skipped, parsed): (Vec<_>, Vec<_>) = self.fields.iter().partition(|f| f.skip);
+        let construct = construct_with_defaults(&skipped, construct);
+
+        if self.unordered && !parsed.is_empty() {
+            return Ok(self.unordered_parser(&parsed, construct));
+        }
+
+        if parsed.is_empty() {
+            return Ok(quote! {
+                kalosm_sample::LiteralParser::from("{}").map_output(|_| #construct)
+            });
+        }
+
         let mut parsers = Vec::new();
-        let idents: Vec<_> = self
-            .fields
+        let idents: Vec<_> = parsed
             .iter()
-            .map(|f| format_ident!("{}_parser", f.field.ident.as_ref().unwrap().unraw()))
+            .map(|f| format_ident!("{}_parser", f.binding.unraw()))
             .collect();
-        for (i, (field, parser_ident)) in self.fields.iter().zip(idents.iter()).enumerate() {
+        for (i, (field, parser_ident)) in parsed.iter().zip(idents.iter()).enumerate() {
             let mut literal_text = String::new();
             if i == 0 {
                 literal_text.push_str("{ ");
@@ -1066,7 +1296,7 @@ impl FieldsParser {
                 literal_text.push_str(", ");
             }
             let field_name = &field.name;
-            let field_parser = &field.parser;
+            let field_parser = field.quote_parser();
             literal_text.push_str(&format!("\"{field_name}\": "));
             let literal_text = LitStr::new(&literal_text, field.field.ident.span());
 
@@ -1077,8 +1307,8 @@ impl FieldsParser {
         }
 
         let mut output_tuple = None;
-        for field in self.fields.iter() {
-            let name = field.field.ident.as_ref().unwrap();
+        for field in parsed.iter() {
+            let name = &field.binding;
             match output_tuple {
                 Some(current) => {
                     output_tuple = Some(wrap_tuple(name, current));
@@ -1117,8 +1347,47 @@ impl FieldsParser {
         })
     }
 
+    /// A parser for an object with fields in any order that tracks which properties have already been generated
+    fn unordered_parser(&self, parsed: &[&FieldParser], construct: TokenStream2) -> TokenStream2 {
+        let properties = parsed.iter().map(|field| {
+            let name = &field.name;
+            let field_parser = field.quote_parser();
+            let required = self.is_required(field);
+            quote! {
+                kalosm_sample::ObjectProperty::new(#name, #field_parser).with_required(#required)
+            }
+        });
+        let values = parsed.iter().enumerate().map(|(index, field)| {
+            let binding = &field.binding;
+            let ty = &field.field.ty;
+            if self.is_required(field) {
+                quote! {
+                    let #binding = output.get::<#ty>(#index).expect("required properties are always parsed");
+                }
+            } else {
+                quote! {
+                    let #binding = output.get::<#ty>(#index).flatten();
+                }
+            }
+        });
+
+        quote! {
+            kalosm_sample::ObjectParser::new([#(#properties),*])
+                .map_output(|output| {
+                    #(
+                        #values
+                    )*
+                    #construct
+                })
+        }
+    }
+
     fn quote_schema(&self) -> proc_macro2::TokenStream {
-        let properties = self.fields.iter().map(|field| field.quote_schema());
+        let properties = self
+            .fields
+            .iter()
+            .filter(|field| !field.skip)
+            .map(|field| field.quote_schema(self.is_required(field)));
         quote! {
             kalosm_sample::JsonObjectSchema::new(
                 vec![#(#properties),*]
@@ -1127,16 +1396,113 @@ impl FieldsParser {
     }
 }
 
+/// A parser for unnamed fields. One field is parsed as the value of the field and any other number of fields are parsed as an array
+struct TupleFieldsParser {
+    fields: Vec<FieldParser>,
+}
+
+impl TupleFieldsParser {
+    fn new(fields: &[Field]) -> syn::Result<Self> {
+        Ok(Self {
+            fields: fields
+                .iter()
+                .enumerate()
+                .map(|(index, field)| FieldParser::new(field, index))
+                .collect::<syn::Result<_>>()?,
+        })
+    }
+
+    fn parser(&self, construct: TokenStream2) -> TokenStream2 {
+        let (skipped, parsed): (Vec<_>, Vec<_>) = self.fields.iter().partition(|f| f.skip);
+        let construct = construct_with_defaults(&skipped, construct);
+
+        // A newtype is parsed as the inner value
+        if let [field] = &*parsed {
+            let binding = &field.binding;
+            let field_parser = field.quote_parser();
+            return quote! {
+                #field_parser.map_output(|#binding| #construct)
+            };
+        }
+
+        let mut join_parser: Option<TokenStream2> = None;
+        let mut output_tuple = None;
+        for field in parsed.iter() {
+            let field_parser = field.quote_parser();
+            let name = &field.binding;
+            match (&mut join_parser, output_tuple) {
+                (Some(current), Some(current_output)) => {
+                    *current = quote! {
+                        #current
+                            .then(kalosm_sample::LiteralParser::from(", ").ignore_output_then(#field_parser))
+                    };
+                    output_tuple = Some(wrap_tuple(name, current_output));
+                }
+                _ => {
+                    join_parser = Some(field_parser);
+                    output_tuple = Some(name.to_token_stream());
+                }
+            }
+        }
+
+        match (join_parser, output_tuple) {
+            (Some(join_parser), Some(output_tuple)) => quote! {
+                kalosm_sample::LiteralParser::from("[")
+                    .ignore_output_then(#join_parser)
+                    .then_literal("]")
+                    .map_output(|#output_tuple| #construct)
+            },
+            _ => quote! {
+                kalosm_sample::LiteralParser::from("[]").map_output(|_| #construct)
+            },
+        }
+    }
+
+    fn quote_schema(&self) -> proc_macro2::TokenStream {
+        let parsed = self
+            .fields
+            .iter()
+            .filter(|field| !field.skip)
+            .collect::<Vec<_>>();
+        if let [field] = &*parsed {
+            return field.parser.quote_schema();
+        }
+        let items = parsed.iter().map(|field| field.parser.quote_schema());
+        quote! {
+            kalosm_sample::SchemaType::Tuple(
+                kalosm_sample::TupleSchema::new([#(#items),*])
+            )
+        }
+    }
+}
+
 struct FieldParser {
     field: Field,
     parser: Parser,
+    /// The name of the variable the field is bound to when the value is constructed
+    binding: Ident,
     name: String,
+    skip: bool,
+    default: Option<syn::Expr>,
+    examples: Option<syn::ExprArray>,
+    validate: Option<syn::Expr>,
 }
 
 impl FieldParser {
-    fn new(field: &Field) -> syn::Result<Self> {
-        let mut field_name = field.ident.as_ref().unwrap().unraw().to_string();
+    const ATTRIBUTES: &'static [&'static str] =
+        &["rename", "skip", "default", "examples", "validate"];
+
+    fn new(field: &Field, index: usize) -> syn::Result<Self> {
+        let binding = match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("data{}", index),
+        };
+        let mut field_name = binding.unraw().to_string();
         let mut parser: Parser = syn::parse2(field.ty.to_token_stream())?;
+        let mut skip = false;
+        let mut default: Option<syn::Expr> = None;
+        let mut examples = None;
+        let mut validate = None;
 
         // Look for #[parse(rename = "name")] or #[parse(with = expr)] attributes
         for attr in field.attrs.iter() {
@@ -1145,10 +1511,22 @@ impl FieldParser {
                     if let Some(value) = parse_rename_attribute(&meta)? {
                         field_name = value.value();
                         Ok(())
+                    } else if meta.path.is_ident("skip") {
+                        skip = true;
+                        Ok(())
+                    } else if meta.path.is_ident("default") {
+                        default = Some(meta.value()?.parse()?);
+                        Ok(())
+                    } else if meta.path.is_ident("examples") {
+                        examples = Some(meta.value()?.parse()?);
+                        Ok(())
+                    } else if meta.path.is_ident("validate") {
+                        validate = Some(meta.value()?.parse()?);
+                        Ok(())
                     } else {
                         let attribute_applied = parser.apply_attribute(&meta)?;
                         if !attribute_applied {
-                            let mut possible_attributes = vec!["rename"];
+                            let mut possible_attributes = Self::ATTRIBUTES.to_vec();
                             possible_attributes.extend(parser.possible_attributes());
                             return Err(meta.error(expected_attributes_error(possible_attributes)));
                         }
@@ -1158,22 +1536,94 @@ impl FieldParser {
             }
         }
 
+        if let (Some(default), false) = (&default, skip) {
+            return Err(syn::Error::new(
+                default.span(),
+                "`default` can only be used on fields with `skip`",
+            ));
+        }
+
         Ok(Self {
             field: field.clone(),
             parser,
+            binding,
             name: field_name,
+            skip,
+            default,
+            examples,
+            validate,
         })
     }
 
-    fn quote_schema(&self) -> proc_macro2::TokenStream {
+    /// The expression that creates the value of a skipped field
+    fn quote_default(&self) -> TokenStream2 {
+        match &self.default {
+            Some(default) => default.to_token_stream(),
+            None => quote_spanned! {
+                self.field.ty.span() =>
+                ::std::default::Default::default()
+            },
+        }
+    }
+
+    fn quote_parser(&self) -> TokenStream2 {
+        let parser = &self.parser;
+        match &self.validate {
+            Some(validate) => quote_spanned! {
+                validate.span() =>
+                kalosm_sample::ParserExt::validate(#parser, #validate)
+            },
+            None => parser.to_token_stream(),
+        }
+    }
+
+    /// If the type of the field is an `Option`
+    fn is_option(&self) -> bool {
+        match &self.field.ty {
+            syn::Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "Option"),
+            _ => false,
+        }
+    }
+
+    fn quote_schema(&self, required: bool) -> proc_macro2::TokenStream {
         let schema = self.parser.quote_schema();
         let name = &self.name;
         let description = doc_comment(&self.field.attrs);
         let description = description.map(|description| quote! { .with_description(#description) });
+        let examples = self.examples.as_ref().map(|examples| {
+            let examples = examples.elems.iter();
+            quote! {
+                .with_examples([#(kalosm_sample::SchemaLiteral::from(#examples)),*])
+            }
+        });
         quote! {
             kalosm_sample::JsonPropertySchema::new(#name.to_string(), #schema)
-                .with_required(true)
+                .with_required(#required)
                 #description
+                #examples
+        }
+    }
+}
+
+/// Create the fields that are skipped from their default values before constructing the value
+fn construct_with_defaults(skipped: &[&FieldParser], construct: TokenStream2) -> TokenStream2 {
+    let skipped_defaults = skipped.iter().map(|field| {
+        let name = &field.binding;
+        let default = field.quote_default();
+        quote! {
+            let #name = #default;
+        }
+    });
+    quote! {
+        {
+            #(
+                #skipped_defaults
+            )*
+            #construct
         }
     }
 }
@@ -1228,6 +1678,7 @@ enum ParserType {
     Number(NumberParserOptions),
     Integer(NumberParserOptions),
     Boolean(BoolOptions),
+    Vec(VecParserOptions),
     Custom(proc_macro2::TokenStream),
 }
 
@@ -1244,8 +1695,15 @@ impl Parse for ParserType {
                 });
             } else if let Ok(boolean) = BoolOptions::from_path(&path) {
                 return Ok(Self::Boolean(boolean));
+            } else if let Ok(vec) = VecParserOptions::from_path(&path) {
+                return Ok(Self::Vec(vec));
             }
             Ok(Self::Custom(path.to_token_stream()))
+        } else if input.peek(syn::token::Bracket) {
+            match input.parse::<syn::Type>()? {
+                syn::Type::Array(array) => Ok(Self::Vec(VecParserOptions::from_array(&array))),
+                ty => Ok(Self::Custom(ty.to_token_stream())),
+            }
         } else {
             Ok(Self::Custom(input.parse()?))
         }
@@ -1270,6 +1728,36 @@ fn type_parses() {
         dbg!(syn::parse2::<ParserType>(quote! { f32 })).unwrap(),
         ParserType::Number(_)
     ));
+    assert!(matches!(
+        dbg!(syn::parse2::<ParserType>(quote! { Vec<String> })).unwrap(),
+        ParserType::Vec(_)
+    ));
+    assert!(matches!(
+        dbg!(syn::parse2::<ParserType>(quote! { [String; 3] })).unwrap(),
+        ParserType::Vec(VecParserOptions { len: Some(_), .. })
+    ));
+    assert!(matches!(
+        dbg!(syn::parse2::<ParserType>(quote! { [String] })).unwrap(),
+        ParserType::Custom(_)
+    ));
+}
+
+#[test]
+fn items_are_rejected_on_arrays() {
+    let field: syn::FieldsNamed = syn::parse_quote! {{
+        #[parse(items = 1..=5)]
+        steps: [String; 3]
+    }};
+    let error = FieldParser::new(&field.named[0], 0).err().unwrap();
+    assert!(error
+        .to_string()
+        .starts_with("`items` can only be used on `Vec` fields"));
+
+    let field: syn::FieldsNamed = syn::parse_quote! {{
+        #[parse(items = 1..=5)]
+        steps: Vec<String>
+    }};
+    assert!(FieldParser::new(&field.named[0], 0).is_ok());
 }
 
 #[derive(Debug)]
@@ -1303,6 +1791,7 @@ impl Parser {
                 ParserType::Number(options) => options.apply_attribute(input),
                 ParserType::Integer(options) => options.apply_attribute(input),
                 ParserType::Boolean(options) => options.apply_attribute(input),
+                ParserType::Vec(options) => options.apply_attribute(input),
                 ParserType::Custom(_) => Ok(false),
             }
         }
@@ -1316,6 +1805,7 @@ impl Parser {
                 attributes.extend(NumberParserOptions::ATTRIBUTES)
             }
             ParserType::Boolean(_) => attributes.extend(BoolOptions::ATTRIBUTES),
+            ParserType::Vec(_) => attributes.extend(VecParserOptions::ATTRIBUTES),
             _ => {}
         }
         attributes
@@ -1351,6 +1841,7 @@ impl Parser {
                     kalosm_sample::SchemaType::Boolean(#schema)
                 }
             }
+            ParserType::Vec(options) => options.quote_schema(),
             ParserType::Custom(ty) => {
                 quote_spanned! {
                     ty.span() =>
@@ -1389,6 +1880,11 @@ impl ToTokens for Parser {
                     #options
                 }
             }
+            ParserType::Vec(options) => {
+                quote! {
+                    #options
+                }
+            }
             ParserType::Custom(ty) => {
                 quote! {
                     <#ty as kalosm_sample::Parse>::new_parser()
@@ -1792,6 +2288,125 @@ impl ToTokens for BoolOptions {
     }
 }
 
+// Vecs and arrays accept these attributes:
+// - #[parse(items = 1..=5)]
+struct VecParserOptions {
+    ty: Box<syn::Type>,
+    item: Box<syn::Type>,
+    /// The length of an array type. Vecs have no fixed length
+    len: Option<Box<syn::Expr>>,
+    items: Option<syn::Expr>,
+}
+
+impl Debug for VecParserOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VecParserOptions")
+            .field("len", &self.len.as_ref().map(|len| len.to_token_stream()))
+            .field(
+                "items",
+                &self.items.as_ref().map(|items| items.to_token_stream()),
+            )
+            .finish()
+    }
+}
+
+impl VecParserOptions {
+    const ATTRIBUTES: &'static [&'static str] = &["items"];
+
+    fn apply_attribute(&mut self, input: &syn::meta::ParseNestedMeta) -> syn::Result<bool> {
+        if input.path.is_ident("items") {
+            if self.len.is_some() {
+                return Err(input.error(
+                    "`items` can only be used on `Vec` fields. Array fields always have exactly their length in items",
+                ));
+            }
+            self.items = Some(input.value()?.parse()?);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn from_path(path: &Path) -> syn::Result<Self> {
+        let vec_path = syn::parse_quote!(::std::vec::Vec);
+        let item = is_path_type(path, &vec_path)
+            .then(|| path.segments.last())
+            .flatten()
+            .and_then(|segment| match &segment.arguments {
+                syn::PathArguments::AngleBracketed(arguments) => match arguments.args.first() {
+                    Some(syn::GenericArgument::Type(item)) if arguments.args.len() == 1 => {
+                        Some(Box::new(item.clone()))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            });
+        let Some(item) = item else {
+            return Err(syn::Error::new(path.span(), "Expected a Vec type"));
+        };
+        Ok(Self {
+            ty: Box::new(syn::Type::Path(TypePath {
+                qself: None,
+                path: path.clone(),
+            })),
+            item,
+            len: None,
+            items: None,
+        })
+    }
+
+    fn from_array(array: &syn::TypeArray) -> Self {
+        Self {
+            ty: Box::new(syn::Type::Array(array.clone())),
+            item: array.elem.clone(),
+            len: Some(Box::new(array.len.clone())),
+            items: None,
+        }
+    }
+
+    fn quote_schema(&self) -> proc_macro2::TokenStream {
+        let ty = &self.ty;
+        let item = &self.item;
+        match &self.items {
+            Some(items) => quote_spanned! {
+                items.span() =>
+                kalosm_sample::SchemaType::Array(
+                    kalosm_sample::ArraySchema::new(<#item as kalosm_sample::Schema>::schema())
+                        .with_length(#items)
+                )
+            },
+            None => quote_spanned! {
+                ty.span() =>
+                <#ty as kalosm_sample::Schema>::schema()
+            },
+        }
+    }
+}
+
+impl ToTokens for VecParserOptions {
+    fn to_tokens(&self, tokens: &mut TokenStream2) {
+        let ty = &self.ty;
+        let item = &self.item;
+        let quote = match &self.items {
+            Some(items) => quote_spanned! {
+                items.span() =>
+                kalosm_sample::LiteralParser::from("[")
+                    .ignore_output_then(kalosm_sample::SeparatedParser::new(
+                        <#item as kalosm_sample::Parse>::new_parser(),
+                        kalosm_sample::LiteralParser::from(", "),
+                        #items,
+                    ))
+                    .then_literal("]")
+            },
+            None => quote_spanned! {
+                ty.span() =>
+                <#ty as kalosm_sample::Parse>::new_parser()
+            },
+        };
+        tokens.extend(quote);
+    }
+}
+
 fn is_bool(ty: &syn::Path) -> bool {
     let bool_path = syn::parse_quote!(::std::bool);
     is_path_type(ty, &bool_path)
@@ -0,0 +1,221 @@
+//! [`Parse`](crate::Parse) and [`Schema`](crate::Schema) implementations for types from other crates. Each crate is behind a cargo feature with the same name.
+
+use crate::{
+    CreateParserState, ParseStatus, Parser, RegexParser, RegexParserState, SchemaType,
+    StringSchema, TokenMask,
+};
+
+/// A parser for a JSON string that matches a pattern and is converted into a value once it is finished.
+struct FormattedStringParser<T> {
+    regex: RegexParser,
+    convert: fn(&str) -> Option<T>,
+}
+
+impl<T> FormattedStringParser<T> {
+    /// Create a parser for strings that match the pattern. The pattern must only match valid characters in a JSON string without escapes.
+    fn new(pattern: &str, convert: fn(&str) -> Option<T>) -> Self {
+        Self {
+            regex: RegexParser::new(&format!("\"{pattern}\"")).unwrap(),
+            convert,
+        }
+    }
+}
+
+impl<T> CreateParserState for FormattedStringParser<T>
+where
+    T: Clone,
+{
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.regex.create_parser_state()
+    }
+}
+
+impl<T: Clone> Parser for FormattedStringParser<T> {
+    type Output = T;
+    type PartialState = RegexParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        match self.regex.parse(state, input)? {
+            ParseStatus::Finished { result, remaining } => {
+                // The pattern only checks the format, so values like February 30th still need to be rejected here
+                let Some(result) = (self.convert)(&result[1..result.len() - 1]) else {
+                    crate::bail!("{result} is not a valid value");
+                };
+                Ok(ParseStatus::Finished { result, remaining })
+            }
+            ParseStatus::Incomplete {
+                new_state,
+                required_next,
+            } => Ok(ParseStatus::Incomplete {
+                new_state,
+                required_next,
+            }),
+        }
+    }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        self.regex.token_mask()
+    }
+}
+
+/// Create the schema for a string that matches a pattern.
+fn pattern_schema(pattern: &str) -> SchemaType {
+    SchemaType::String(StringSchema::new().with_pattern(format!("^{pattern}$")))
+}
+
+#[cfg(feature = "chrono")]
+mod chrono_impls {
+    use super::{pattern_schema, FormattedStringParser};
+    use crate::{Parse, Schema, SchemaType, SendCreateParserState};
+    use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+
+    const DATE: &str = "[0-9]{4}-(0[1-9]|1[0-2])-(0[1-9]|[12][0-9]|3[01])";
+    const TIME: &str = "([01][0-9]|2[0-3]):[0-5][0-9]:[0-5][0-9]([.][0-9]{1,9})?";
+    const OFFSET: &str = "(Z|[+-]([01][0-9]|2[0-3]):[0-5][0-9])";
+
+    /// An RFC 3339 date and time with an offset like `2024-01-31T12:30:00Z`
+    fn date_time_pattern() -> String {
+        format!("{DATE}T{TIME}{OFFSET}")
+    }
+
+    /// An RFC 3339 date and time without an offset like `2024-01-31T12:30:00`
+    fn naive_date_time_pattern() -> String {
+        format!("{DATE}T{TIME}")
+    }
+
+    impl Parse for DateTime<Utc> {
+        fn new_parser() -> impl SendCreateParserState<Output = Self> {
+            FormattedStringParser::new(&date_time_pattern(), |text| {
+                DateTime::parse_from_rfc3339(text)
+                    .ok()
+                    .map(|date_time| date_time.with_timezone(&Utc))
+            })
+        }
+    }
+
+    impl Schema for DateTime<Utc> {
+        fn schema() -> SchemaType {
+            pattern_schema(&date_time_pattern())
+        }
+    }
+
+    impl Parse for DateTime<FixedOffset> {
+        fn new_parser() -> impl SendCreateParserState<Output = Self> {
+            FormattedStringParser::new(&date_time_pattern(), |text| {
+                DateTime::parse_from_rfc3339(text).ok()
+            })
+        }
+    }
+
+    impl Schema for DateTime<FixedOffset> {
+        fn schema() -> SchemaType {
+            pattern_schema(&date_time_pattern())
+        }
+    }
+
+    impl Parse for NaiveDateTime {
+        fn new_parser() -> impl SendCreateParserState<Output = Self> {
+            FormattedStringParser::new(&naive_date_time_pattern(), |text| {
+                NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f").ok()
+            })
+        }
+    }
+
+    impl Schema for NaiveDateTime {
+        fn schema() -> SchemaType {
+            pattern_schema(&naive_date_time_pattern())
+        }
+    }
+
+    impl Parse for NaiveDate {
+        fn new_parser() -> impl SendCreateParserState<Output = Self> {
+            FormattedStringParser::new(DATE, |text| {
+                NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+            })
+        }
+    }
+
+    impl Schema for NaiveDate {
+        fn schema() -> SchemaType {
+            pattern_schema(DATE)
+        }
+    }
+
+    impl Parse for NaiveTime {
+        fn new_parser() -> impl SendCreateParserState<Output = Self> {
+            FormattedStringParser::new(TIME, |text| {
+                NaiveTime::parse_from_str(text, "%H:%M:%S%.f").ok()
+            })
+        }
+    }
+
+    impl Schema for NaiveTime {
+        fn schema() -> SchemaType {
+            pattern_schema(TIME)
+        }
+    }
+
+    #[test]
+    fn parse_chrono_types() {
+        use crate::{CreateParserState, Parser};
+
+        let parser = DateTime::<Utc>::new_parser();
+        let state = parser.create_parser_state();
+        let result = parser
+            .parse(&state, br#""2024-01-31T12:30:00.5+02:00""#)
+            .unwrap()
+            .unwrap_finished();
+        assert_eq!(result.to_rfc3339(), "2024-01-31T10:30:00.500+00:00");
+
+        let parser = NaiveDate::new_parser();
+        let state = parser.create_parser_state();
+        let result = parser
+            .parse(&state, br#""2024-02-29""#)
+            .unwrap()
+            .unwrap_finished();
+        assert_eq!(result, NaiveDate::from_ymd_opt(2024, 2, 29).unwrap());
+        // The format is valid, but the date doesn't exist
+        assert!(parser.parse(&state, br#""2023-02-29""#).is_err());
+        assert!(parser.parse(&state, br#""2023-13"#).is_err());
+    }
+}
+
+#[cfg(feature = "uuid")]
+mod uuid_impls {
+    use super::{pattern_schema, FormattedStringParser};
+    use crate::{Parse, Schema, SchemaType, SendCreateParserState};
+    use uuid::Uuid;
+
+    /// A lowercase hyphenated UUID like `67e55044-10b1-426f-9247-bb680e5fe0c8`
+    const UUID: &str = "[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}";
+
+    impl Parse for Uuid {
+        fn new_parser() -> impl SendCreateParserState<Output = Self> {
+            FormattedStringParser::new(UUID, |text| Uuid::parse_str(text).ok())
+        }
+    }
+
+    impl Schema for Uuid {
+        fn schema() -> SchemaType {
+            pattern_schema(UUID)
+        }
+    }
+
+    #[test]
+    fn parse_uuid() {
+        use crate::{CreateParserState, Parser};
+
+        let parser = Uuid::new_parser();
+        let state = parser.create_parser_state();
+        let result = parser
+            .parse(&state, br#""67e55044-10b1-426f-9247-bb680e5fe0c8""#)
+            .unwrap()
+            .unwrap_finished();
+        assert_eq!(result.to_string(), "67e55044-10b1-426f-9247-bb680e5fe0c8");
+        assert!(parser.parse(&state, br#""67E5"#).is_err());
+    }
+}
@@ -262,11 +262,8 @@ impl Parser for FloatParser {
                 _ => {
                     if state.is_after_digit() {
                         let result = value * if positive { 1.0 } else { -1.0 };
-                        if self.is_number_valid(result) {
-                            return Ok(ParseStatus::Finished {
-                                result,
-                                remaining: &input[index..],
-                            });
+                        if !self.is_number_valid(result) {
+                            crate::bail!(OutOfRangeError);
                         }
                         return Ok(ParseStatus::Finished {
                             result,
@@ -0,0 +1,851 @@
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use crate::{CreateParserState, ParseStatus, Parser};
+
+/// The maximum number of characters [`GrammarParser`] will look ahead to find the text that is required next. Grammars like `root ::= "a" root` require text forever.
+const MAX_REQUIRED_NEXT: usize = 256;
+
+/// An error that occurred while parsing a grammar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarError {
+    message: String,
+    line: usize,
+    column: usize,
+}
+
+impl std::fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid grammar at line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+/// One element of a sequence in a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Element {
+    /// A single character in one of the ranges. If the element is negated, a single character that is not in any of the ranges.
+    Char {
+        ranges: Vec<RangeInclusive<char>>,
+        negated: bool,
+    },
+    /// A reference to another rule.
+    Rule(usize),
+}
+
+impl Element {
+    fn literal(c: char) -> Self {
+        Self::Char {
+            ranges: vec![c..=c],
+            negated: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    name: String,
+    alternatives: Vec<Vec<Element>>,
+}
+
+/// A position in one of the alternatives of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Position {
+    rule: usize,
+    alternative: usize,
+    index: usize,
+}
+
+/// A parser for a context free grammar in the [GBNF](https://github.com/ggerganov/llama.cpp/blob/master/grammars/README.md) format used by llama.cpp. This lets you reuse existing grammars for formats like JSON, SQL or arithmetic to constrain generation.
+///
+/// The grammar starts at the rule named `root`. Rules may reference each other recursively, but they may not be left recursive (`expr ::= expr "+" term` must be written as `expr ::= term ("+" term)*`).
+///
+/// Like the other repeating parsers, the parser only finishes once the input cannot continue the grammar. Text that is left over after the grammar could have ended is returned as the remaining input.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = GrammarParser::new(
+///     r#"
+/// root   ::= expr ";"
+/// expr   ::= term (("+" | "-") term)*
+/// term   ::= factor (("*" | "/") factor)*
+/// factor ::= [0-9]+ | "(" expr ")"
+/// "#,
+/// )
+/// .unwrap();
+/// let state = parser.create_parser_state();
+/// let result = parser.parse(&state, b"(1+2)*3;").unwrap().unwrap_finished();
+/// assert_eq!(result, "(1+2)*3;");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarParser {
+    rules: Vec<Rule>,
+    root: usize,
+}
+
+impl GrammarParser {
+    /// Create a new parser from a grammar in the GBNF format.
+    pub fn new(grammar: &str) -> Result<Self, GrammarError> {
+        let mut builder = GrammarBuilder {
+            source: grammar,
+            position: 0,
+            rules: Vec::new(),
+            names: HashMap::new(),
+            defined: Vec::new(),
+        };
+        builder.parse_grammar()?;
+        builder.build()
+    }
+
+    fn element(&self, position: Position) -> Option<&Element> {
+        self.rules[position.rule].alternatives[position.alternative].get(position.index)
+    }
+
+    /// Expand the rule references at the top of the stack until every stack ends with a character or is empty.
+    fn expand(&self, mut stack: Vec<Position>, stacks: &mut Vec<Vec<Position>>) {
+        let Some(&top) = stack.last() else {
+            stacks.push(stack);
+            return;
+        };
+        match self.element(top) {
+            // The alternative is finished, so continue with the rule that referenced it
+            None => {
+                stack.pop();
+                self.expand(stack, stacks);
+            }
+            Some(Element::Char { .. }) => stacks.push(stack),
+            Some(Element::Rule(rule)) => {
+                // Replace the reference with the element after it. If the reference ends the alternative, drop it so recursive rules don't grow the stack
+                stack.pop();
+                let next = Position {
+                    index: top.index + 1,
+                    ..top
+                };
+                if self.element(next).is_some() {
+                    stack.push(next);
+                }
+                for alternative in 0..self.rules[*rule].alternatives.len() {
+                    let mut stack = stack.clone();
+                    stack.push(Position {
+                        rule: *rule,
+                        alternative,
+                        index: 0,
+                    });
+                    self.expand(stack, stacks);
+                }
+            }
+        }
+    }
+
+    /// Advance every stack that accepts the character.
+    fn advance(&self, stacks: &[Vec<Position>], c: char) -> Vec<Vec<Position>> {
+        let mut new_stacks = Vec::new();
+        for stack in stacks {
+            let Some(&top) = stack.last() else {
+                continue;
+            };
+            if let Some(Element::Char { ranges, negated }) = self.element(top) {
+                if ranges.iter().any(|range| range.contains(&c)) != *negated {
+                    let mut stack = stack.clone();
+                    stack.last_mut().unwrap().index += 1;
+                    self.expand(stack, &mut new_stacks);
+                }
+            }
+        }
+        new_stacks.sort();
+        new_stacks.dedup();
+        new_stacks
+    }
+
+    /// Find the text every stack requires next.
+    fn required_next(&self, stacks: &[Vec<Position>]) -> String {
+        let mut required_next = String::new();
+        let mut stacks = stacks.to_vec();
+        while required_next.len() < MAX_REQUIRED_NEXT {
+            let mut required = None;
+            for stack in &stacks {
+                let element = stack.last().and_then(|top| self.element(*top));
+                let Some(Element::Char {
+                    ranges,
+                    negated: false,
+                }) = element
+                else {
+                    return required_next;
+                };
+                let [range] = ranges.as_slice() else {
+                    return required_next;
+                };
+                if range.start() != range.end() || required.is_some_and(|c| c != *range.start()) {
+                    return required_next;
+                }
+                required = Some(*range.start());
+            }
+            let Some(c) = required else {
+                break;
+            };
+            required_next.push(c);
+            stacks = self.advance(&stacks, c);
+        }
+        required_next
+    }
+}
+
+impl CreateParserState for GrammarParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        let mut stacks = Vec::new();
+        for alternative in 0..self.rules[self.root].alternatives.len() {
+            let position = Position {
+                rule: self.root,
+                alternative,
+                index: 0,
+            };
+            self.expand(vec![position], &mut stacks);
+        }
+        stacks.sort();
+        stacks.dedup();
+        GrammarParserState {
+            stacks,
+            partial_char: Vec::new(),
+            text: String::new(),
+        }
+    }
+}
+
+impl Parser for GrammarParser {
+    type Output = String;
+    type PartialState = GrammarParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut state = state.clone();
+        let mut index = 0;
+        while index < input.len() {
+            // If the grammar is finished, stop before the next character
+            if state.is_finished() {
+                return Ok(ParseStatus::Finished {
+                    result: state.text,
+                    remaining: &input[index..],
+                });
+            }
+
+            state.partial_char.push(input[index]);
+            index += 1;
+            let Some(char_len) = utf8_len(state.partial_char[0]) else {
+                crate::bail!("invalid UTF-8 in the input");
+            };
+            if state.partial_char.len() < char_len {
+                continue;
+            }
+            let Some(c) = std::str::from_utf8(&state.partial_char)
+                .ok()
+                .and_then(|s| s.chars().next())
+            else {
+                crate::bail!("invalid UTF-8 in the input");
+            };
+            state.partial_char.clear();
+
+            let stacks = self.advance(&state.stacks, c);
+            if stacks.is_empty() {
+                // The grammar could have ended before this character, so the character is the start of the remaining input
+                if state.stacks.iter().any(Vec::is_empty) && index >= char_len {
+                    return Ok(ParseStatus::Finished {
+                        result: state.text,
+                        remaining: &input[index - char_len..],
+                    });
+                }
+                crate::bail!("unexpected character {c:?} for the grammar");
+            }
+            state.stacks = stacks;
+            state.text.push(c);
+        }
+
+        if state.is_finished() {
+            return Ok(ParseStatus::Finished {
+                result: state.text,
+                remaining: &[],
+            });
+        }
+
+        let required_next = if state.partial_char.is_empty() {
+            self.required_next(&state.stacks)
+        } else {
+            String::new()
+        };
+        Ok(ParseStatus::Incomplete {
+            new_state: state,
+            required_next: required_next.into(),
+        })
+    }
+}
+
+/// The state of a grammar parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarParserState {
+    /// Every way the grammar could continue. Each stack holds the positions of the rules that are being parsed with the innermost rule last. An empty stack means the grammar could end here.
+    stacks: Vec<Vec<Position>>,
+    /// The bytes of a character that was split between inputs.
+    partial_char: Vec<u8>,
+    text: String,
+}
+
+impl GrammarParserState {
+    fn is_finished(&self) -> bool {
+        self.partial_char.is_empty() && self.stacks.iter().all(Vec::is_empty)
+    }
+}
+
+/// Get the length of a UTF-8 character from its first byte.
+fn utf8_len(first: u8) -> Option<usize> {
+    match first {
+        0x00..=0x7F => Some(1),
+        0xC0..=0xDF => Some(2),
+        0xE0..=0xEF => Some(3),
+        0xF0..=0xF7 => Some(4),
+        _ => None,
+    }
+}
+
+/// A recursive descent parser for the GBNF format.
+struct GrammarBuilder<'a> {
+    source: &'a str,
+    position: usize,
+    rules: Vec<Rule>,
+    names: HashMap<String, usize>,
+    /// Whether each rule has been defined or only referenced so far.
+    defined: Vec<bool>,
+}
+
+impl GrammarBuilder<'_> {
+    fn error(&self, message: impl ToString) -> GrammarError {
+        let before = &self.source[..self.position];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        GrammarError {
+            message: message.to_string(),
+            line,
+            column,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.position..].chars().next()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += c.len_utf8();
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), GrammarError> {
+        if self.source[self.position..].starts_with(expected) {
+            self.position += expected.len();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{expected}`")))
+        }
+    }
+
+    /// Skip whitespace and comments. Newlines end a rule, so they are only skipped if `newlines` is true.
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => {}
+                '\r' | '\n' if newlines => {}
+                '#' => {
+                    let comment_len = self.source[self.position..]
+                        .find('\n')
+                        .unwrap_or(self.source.len() - self.position);
+                    self.position += comment_len;
+                    continue;
+                }
+                _ => break,
+            }
+            self.position += 1;
+        }
+    }
+
+    fn rule_id(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.names.get(name) {
+            return id;
+        }
+        self.add_rule(name.to_string(), Vec::new(), false)
+    }
+
+    fn add_rule(&mut self, name: String, alternatives: Vec<Vec<Element>>, defined: bool) -> usize {
+        let id = self.rules.len();
+        self.names.insert(name.clone(), id);
+        self.rules.push(Rule { name, alternatives });
+        self.defined.push(defined);
+        id
+    }
+
+    /// Add a rule for a group or repetition that doesn't have a name in the grammar.
+    fn add_generated_rule(&mut self, alternatives: Vec<Vec<Element>>) -> usize {
+        let name = format!("__generated_{}", self.rules.len());
+        self.add_rule(name, alternatives, true)
+    }
+
+    fn parse_grammar(&mut self) -> Result<(), GrammarError> {
+        self.skip_space(true);
+        while self.peek().is_some() {
+            self.parse_rule()?;
+            self.skip_space(true);
+        }
+        Ok(())
+    }
+
+    fn parse_rule(&mut self) -> Result<(), GrammarError> {
+        let name = self.parse_name()?;
+        self.skip_space(false);
+        self.expect("::=")?;
+        self.skip_space(true);
+        let alternatives = self.parse_alternatives(false)?;
+
+        let id = self.rule_id(&name);
+        if self.defined[id] {
+            return Err(self.error(format!("the rule `{name}` is defined more than once")));
+        }
+        self.rules[id].alternatives = alternatives;
+        self.defined[id] = true;
+
+        self.skip_space(false);
+        match self.peek() {
+            None | Some('\r' | '\n') => Ok(()),
+            Some(c) => Err(self.error(format!("unexpected character {c:?}"))),
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<String, GrammarError> {
+        let len = self.source[self.position..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(self.source.len() - self.position);
+        if len == 0 {
+            return Err(self.error("expected a rule name"));
+        }
+        let name = self.source[self.position..self.position + len].to_string();
+        self.position += len;
+        Ok(name)
+    }
+
+    fn parse_alternatives(&mut self, nested: bool) -> Result<Vec<Vec<Element>>, GrammarError> {
+        let mut alternatives = vec![self.parse_sequence(nested)?];
+        while self.peek() == Some('|') {
+            self.position += 1;
+            self.skip_space(true);
+            alternatives.push(self.parse_sequence(nested)?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self, nested: bool) -> Result<Vec<Element>, GrammarError> {
+        let mut sequence = Vec::new();
+        // The start of the last item in the sequence. Repetition operators apply to everything after this index
+        let mut last_item = None;
+        while let Some(c) = self.peek() {
+            match c {
+                '"' => {
+                    self.position += 1;
+                    last_item = Some(sequence.len());
+                    loop {
+                        match self.peek() {
+                            Some('"') => {
+                                self.position += 1;
+                                break;
+                            }
+                            Some(_) => sequence.push(Element::literal(self.parse_char()?)),
+                            None => return Err(self.error("unterminated string literal")),
+                        }
+                    }
+                }
+                '[' => {
+                    self.position += 1;
+                    last_item = Some(sequence.len());
+                    sequence.push(self.parse_char_class()?);
+                }
+                '.' => {
+                    self.position += 1;
+                    last_item = Some(sequence.len());
+                    sequence.push(Element::Char {
+                        ranges: Vec::new(),
+                        negated: true,
+                    });
+                }
+                '(' => {
+                    self.position += 1;
+                    self.skip_space(true);
+                    let alternatives = self.parse_alternatives(true)?;
+                    self.expect(")")?;
+                    last_item = Some(sequence.len());
+                    let rule = self.add_generated_rule(alternatives);
+                    sequence.push(Element::Rule(rule));
+                }
+                '*' | '+' | '?' | '{' => {
+                    let Some(start) = last_item.take() else {
+                        return Err(self.error(format!("expected an item before `{c}`")));
+                    };
+                    self.position += 1;
+                    let (min, max) = match c {
+                        '*' => (0, None),
+                        '+' => (1, None),
+                        '?' => (0, Some(1)),
+                        _ => self.parse_repetition_bounds()?,
+                    };
+                    let item = sequence.split_off(start);
+                    self.push_repetition(&mut sequence, item, min, max);
+                }
+                c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => {
+                    let name = self.parse_name()?;
+                    last_item = Some(sequence.len());
+                    let rule = self.rule_id(&name);
+                    sequence.push(Element::Rule(rule));
+                }
+                _ => break,
+            }
+            self.skip_space(nested);
+        }
+        Ok(sequence)
+    }
+
+    /// Parse the bounds of a repetition like `{2}`, `{2,}` or `{2,5}` after the opening brace.
+    fn parse_repetition_bounds(&mut self) -> Result<(usize, Option<usize>), GrammarError> {
+        self.skip_space(true);
+        let min = self.parse_number()?;
+        self.skip_space(true);
+        let max = if self.peek() == Some(',') {
+            self.position += 1;
+            self.skip_space(true);
+            if self.peek() == Some('}') {
+                None
+            } else {
+                Some(self.parse_number()?)
+            }
+        } else {
+            Some(min)
+        };
+        self.skip_space(true);
+        self.expect("}")?;
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("the maximum of a repetition is less than the minimum"));
+        }
+        Ok((min, max))
+    }
+
+    fn parse_number(&mut self) -> Result<usize, GrammarError> {
+        let len = self.source[self.position..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.source.len() - self.position);
+        let number = self.source[self.position..self.position + len]
+            .parse()
+            .map_err(|_| self.error("expected a number"))?;
+        self.position += len;
+        Ok(number)
+    }
+
+    /// Push an item repeated between `min` and `max` times. The repetitions are rewritten as right recursive rules.
+    fn push_repetition(
+        &mut self,
+        sequence: &mut Vec<Element>,
+        item: Vec<Element>,
+        min: usize,
+        max: Option<usize>,
+    ) {
+        for _ in 0..min {
+            sequence.extend(item.iter().cloned());
+        }
+        match max {
+            // rest ::= item rest | ""
+            None => {
+                let rule = self.add_generated_rule(Vec::new());
+                let mut repeat = item;
+                repeat.push(Element::Rule(rule));
+                self.rules[rule].alternatives = vec![repeat, Vec::new()];
+                sequence.push(Element::Rule(rule));
+            }
+            // rest ::= item (item (...)?)? | ""
+            Some(max) if max > min => {
+                let mut rest = None;
+                for _ in min..max {
+                    let mut repeat = item.clone();
+                    repeat.extend(rest.map(Element::Rule));
+                    rest = Some(self.add_generated_rule(vec![repeat, Vec::new()]));
+                }
+                sequence.extend(rest.map(Element::Rule));
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Parse a character class after the opening bracket.
+    fn parse_char_class(&mut self) -> Result<Element, GrammarError> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.position += 1;
+        }
+        let mut ranges = Vec::new();
+        loop {
+            match self.peek() {
+                Some(']') => {
+                    self.position += 1;
+                    break;
+                }
+                Some(_) => {
+                    let start = self.parse_char()?;
+                    let end = if self.source[self.position..].starts_with('-')
+                        && !self.source[self.position..].starts_with("-]")
+                    {
+                        self.position += 1;
+                        self.parse_char()?
+                    } else {
+                        start
+                    };
+                    if end < start {
+                        return Err(self.error(format!("invalid range {start:?}-{end:?}")));
+                    }
+                    ranges.push(start..=end);
+                }
+                None => return Err(self.error("unterminated character class")),
+            }
+        }
+        Ok(Element::Char { ranges, negated })
+    }
+
+    /// Parse a character in a literal or character class, handling escape sequences.
+    fn parse_char(&mut self) -> Result<char, GrammarError> {
+        let c = self
+            .next_char()
+            .ok_or_else(|| self.error("unexpected end of the grammar"))?;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let escaped = self
+            .next_char()
+            .ok_or_else(|| self.error("unexpected end of the grammar"))?;
+        let hex_len = match escaped {
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            '\\' | '"' | '[' | ']' | '-' | '^' => return Ok(escaped),
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            _ => return Err(self.error(format!("unknown escape sequence \\{escaped}"))),
+        };
+        let hex = self
+            .source
+            .get(self.position..self.position + hex_len)
+            .ok_or_else(|| self.error("expected a hex escape sequence"))?;
+        let c = u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(format!("invalid hex escape sequence {hex}")))?;
+        self.position += hex_len;
+        Ok(c)
+    }
+
+    fn build(self) -> Result<GrammarParser, GrammarError> {
+        if let Some(id) = self.defined.iter().position(|defined| !defined) {
+            return Err(self.error(format!("the rule `{}` is not defined", self.rules[id].name)));
+        }
+        let root = *self
+            .names
+            .get("root")
+            .ok_or_else(|| self.error("the grammar has no `root` rule"))?;
+        if let Some(rule) = self.left_recursive_rule() {
+            return Err(self.error(format!(
+                "the rule `{}` is left recursive",
+                self.rules[rule].name
+            )));
+        }
+        Ok(GrammarParser {
+            rules: self.rules,
+            root,
+        })
+    }
+
+    /// Find a rule that can reference itself without consuming any characters. Expanding a left recursive rule would never end.
+    fn left_recursive_rule(&self) -> Option<usize> {
+        // Find the rules that can match the empty string
+        let mut nullable = vec![false; self.rules.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (id, rule) in self.rules.iter().enumerate() {
+                if !nullable[id]
+                    && rule.alternatives.iter().any(|alternative| {
+                        alternative.iter().all(
+                            |element| matches!(element, Element::Rule(rule) if nullable[*rule]),
+                        )
+                    })
+                {
+                    nullable[id] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        // Find the rules each rule can start with
+        let starts_with = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let mut starts_with = Vec::new();
+                for alternative in &rule.alternatives {
+                    for element in alternative {
+                        let Element::Rule(rule) = element else {
+                            break;
+                        };
+                        starts_with.push(*rule);
+                        if !nullable[*rule] {
+                            break;
+                        }
+                    }
+                }
+                starts_with
+            })
+            .collect::<Vec<_>>();
+
+        // Search for a rule that can reach itself
+        (0..self.rules.len()).find(|&rule| {
+            let mut visited = vec![false; self.rules.len()];
+            let mut queue = starts_with[rule].clone();
+            while let Some(next) = queue.pop() {
+                if next == rule {
+                    return true;
+                }
+                if !std::mem::replace(&mut visited[next], true) {
+                    queue.extend(&starts_with[next]);
+                }
+            }
+            false
+        })
+    }
+}
+
+#[test]
+fn grammar_arithmetic() {
+    let parser = GrammarParser::new(
+        r#"
+# Arithmetic expressions
+root   ::= expr
+expr   ::= term (("+" | "-") term)*
+term   ::= factor (("*" | "/") factor)*
+factor ::= [0-9]+ | "(" expr ")"
+"#,
+    )
+    .unwrap();
+    let state = parser.create_parser_state();
+
+    assert_eq!(
+        parser.parse(&state, b"(1+23)*4;").unwrap(),
+        ParseStatus::Finished {
+            result: "(1+23)*4".to_string(),
+            remaining: b";"
+        }
+    );
+    assert!(parser.parse(&state, b"(1+)").is_err());
+
+    // The input can be split anywhere
+    let ParseStatus::Incomplete { new_state, .. } = parser.parse(&state, b"((1").unwrap() else {
+        panic!("expected the parser to be incomplete");
+    };
+    let ParseStatus::Incomplete { new_state, .. } = parser.parse(&new_state, b"2))").unwrap()
+    else {
+        panic!("expected the parser to be incomplete");
+    };
+    assert_eq!(
+        parser.parse(&new_state, b" ").unwrap(),
+        ParseStatus::Finished {
+            result: "((12))".to_string(),
+            remaining: b" "
+        }
+    );
+}
+
+#[test]
+fn grammar_json() {
+    let parser = GrammarParser::new(
+        r#"
+root   ::= object
+value  ::= object | array | string | number | ("true" | "false" | "null") ws
+
+object ::=
+  "{" ws (
+            string ":" ws value
+    ("," ws string ":" ws value)*
+  )? "}" ws
+
+array  ::=
+  "[" ws (
+            value
+    ("," ws value)*
+  )? "]" ws
+
+string ::=
+  "\"" (
+    [^"\\\x7F\x00-\x1F] |
+    "\\" (["\\/bfnrt] | "u" [0-9a-fA-F]{4}) # escapes
+  )* "\"" ws
+
+number ::= ("-"? ([0-9] | [1-9] [0-9]{0,15})) ("." [0-9]+)? ([eE] [-+]? [0-9] [1-9]{0,15})? ws
+
+ws ::= | " " | "\n" [ \t]{0,20}
+"#,
+    )
+    .unwrap();
+    let state = parser.create_parser_state();
+
+    let json = "{\"name\": \"caf\\u00e9 ☕\", \"tags\": [1, 2.5, true, null]}";
+    // The grammar allows trailing whitespace, so it only finishes at the first character that isn't part of the JSON
+    assert_eq!(
+        parser.parse(&state, format!("{json}.").as_bytes()).unwrap(),
+        ParseStatus::Finished {
+            result: json.to_string(),
+            remaining: b"."
+        }
+    );
+    assert!(parser.parse(&state, b"{\"name\": tre").is_err());
+
+    // Multi-byte characters can be split between inputs
+    let ParseStatus::Incomplete { new_state, .. } =
+        parser.parse(&state, &"{\"☕".as_bytes()[..3]).unwrap()
+    else {
+        panic!("expected the parser to be incomplete");
+    };
+    assert!(matches!(
+        parser.parse(&new_state, &"☕\"".as_bytes()[1..]).unwrap(),
+        ParseStatus::Incomplete { .. }
+    ));
+}
+
+#[test]
+fn grammar_required_next() {
+    let parser = GrammarParser::new(r#"root ::= "hello " ("world" | "wide web") "!""#).unwrap();
+    let state = parser.create_parser_state();
+    let ParseStatus::Incomplete { required_next, .. } = parser.parse(&state, b"hel").unwrap()
+    else {
+        panic!("expected the parser to be incomplete");
+    };
+    assert_eq!(required_next, "lo w");
+}
+
+#[test]
+fn grammar_errors() {
+    let error = GrammarParser::new("root ::= expr\nexpr ::= expr \"+\" [0-9] | [0-9]").unwrap_err();
+    assert!(error.to_string().contains("left recursive"));
+    let error = GrammarParser::new("root ::= missing").unwrap_err();
+    assert!(error.to_string().contains("not defined"));
+    let error = GrammarParser::new("root ::= [0-9]\nroot ::= \"(\" root").unwrap_err();
+    assert!(error.to_string().contains("more than once"));
+    let error = GrammarParser::new("root ::= \"a\"\n  other ::= ( \"b\"").unwrap_err();
+    assert_eq!(error.line, 2);
+}
@@ -0,0 +1,665 @@
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    ArcParser, FloatParser, IntegerParser, LiteralParser, ParserExt, RegexParser, SeparatedParser,
+    StringParser,
+};
+
+/// The number of levels of nested arrays and objects a schema that accepts any value may generate.
+const ANY_VALUE_DEPTH: usize = 3;
+
+/// An error that occurred while compiling a JSON Schema into a parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonSchemaError(String);
+
+impl JsonSchemaError {
+    fn new(message: impl ToString) -> Self {
+        Self(message.to_string())
+    }
+}
+
+impl std::fmt::Display for JsonSchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid JSON Schema: {}", self.0)
+    }
+}
+
+impl std::error::Error for JsonSchemaError {}
+
+/// Compile the text of a JSON Schema document into a parser that only accepts JSON matching the schema. This is useful when the schema is only known at runtime (for example if it is loaded from a file or received over HTTP). If the schema is known at compile time, derive [`Parse`](crate::Parse) and [`Schema`](crate::Schema) instead.
+///
+/// The parser accepts the same whitespace layout as the derived parsers: `{ "key": value, "key2": value }` and `[a, b]`. Object properties are generated in the order they are written in the schema text, and every property is generated even if it is not required.
+///
+/// The following keywords are supported:
+/// - `type` (including a list of types)
+/// - `properties` for objects
+/// - `items`, `minItems` and `maxItems` for arrays
+/// - `minLength`, `maxLength` and `pattern` for strings
+/// - `minimum`, `maximum`, `exclusiveMinimum` and `exclusiveMaximum` for numbers and integers
+/// - `enum` and `const`
+/// - `anyOf` and `oneOf`
+/// - `$ref` to other parts of the same document (for example `#/$defs/Person`), as long as the references are not recursive
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let schema = r#"{
+///     "type": "object",
+///     "properties": {
+///         "name": { "type": "string", "maxLength": 20 },
+///         "age": { "type": "integer", "minimum": 0, "maximum": 150 }
+///     }
+/// }"#;
+/// let parser = parser_from_json_schema(schema).unwrap();
+/// let state = parser.create_parser_state();
+/// let result = parser
+///     .parse(&state, br#"{ "name": "Alice", "age": 42 }"#)
+///     .unwrap()
+///     .unwrap_finished();
+/// assert_eq!(result, serde_json::json!({ "name": "Alice", "age": 42 }));
+/// ```
+pub fn parser_from_json_schema(schema: &str) -> Result<ArcParser<Value>, JsonSchemaError> {
+    let schema: SchemaValue = serde_json::from_str(schema)
+        .map_err(|err| JsonSchemaError::new(format!("the schema is not valid JSON: {err}")))?;
+    SchemaCompiler {
+        root: &schema,
+        references: Vec::new(),
+    }
+    .compile(&schema)
+}
+
+/// A JSON value that keeps object keys in the order they are written. [`serde_json::Map`] sorts its keys unless the `preserve_order` feature is enabled, which would lose the order of the properties in the schema.
+#[derive(Debug, Clone, PartialEq)]
+enum SchemaValue {
+    /// A null, boolean, number or string
+    Primitive(Value),
+    Array(Vec<SchemaValue>),
+    Object(Vec<(String, SchemaValue)>),
+}
+
+impl SchemaValue {
+    fn get(&self, key: &str) -> Option<&SchemaValue> {
+        match self {
+            SchemaValue::Object(entries) => entries
+                .iter()
+                .find_map(|(name, value)| (name == key).then_some(value)),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            SchemaValue::Primitive(value) => value.as_str(),
+            _ => None,
+        }
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            SchemaValue::Primitive(value) => value.as_u64(),
+            _ => None,
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            SchemaValue::Primitive(value) => value.as_f64(),
+            _ => None,
+        }
+    }
+
+    /// Look up a value by a JSON pointer like `/$defs/Person`.
+    fn pointer(&self, pointer: &str) -> Option<&SchemaValue> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        pointer
+            .strip_prefix('/')?
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .try_fold(self, |value, token| match value {
+                SchemaValue::Object(_) => value.get(&token),
+                SchemaValue::Array(items) => items.get(token.parse::<usize>().ok()?),
+                SchemaValue::Primitive(_) => None,
+            })
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            SchemaValue::Primitive(value) => value.clone(),
+            SchemaValue::Array(items) => Value::Array(items.iter().map(Self::to_value).collect()),
+            SchemaValue::Object(entries) => Value::Object(
+                entries
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for SchemaValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_value().fmt(f)
+    }
+}
+
+impl<'de> Deserialize<'de> for SchemaValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SchemaValueVisitor;
+
+        impl<'de> Visitor<'de> for SchemaValueVisitor {
+            type Value = SchemaValue;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON value")
+            }
+
+            fn visit_unit<E>(self) -> Result<SchemaValue, E> {
+                Ok(SchemaValue::Primitive(Value::Null))
+            }
+
+            fn visit_bool<E>(self, value: bool) -> Result<SchemaValue, E> {
+                Ok(SchemaValue::Primitive(Value::Bool(value)))
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<SchemaValue, E> {
+                Ok(SchemaValue::Primitive(Value::from(value)))
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<SchemaValue, E> {
+                Ok(SchemaValue::Primitive(Value::from(value)))
+            }
+
+            fn visit_f64<E>(self, value: f64) -> Result<SchemaValue, E> {
+                Ok(SchemaValue::Primitive(Value::from(value)))
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<SchemaValue, E> {
+                Ok(SchemaValue::Primitive(Value::from(value)))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SchemaValue, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(SchemaValue::Array(items))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<SchemaValue, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(SchemaValue::Object(entries))
+            }
+        }
+
+        deserializer.deserialize_any(SchemaValueVisitor)
+    }
+}
+
+struct SchemaCompiler<'a> {
+    root: &'a SchemaValue,
+    /// The references that are currently being compiled. This is used to detect recursive schemas
+    references: Vec<String>,
+}
+
+impl<'a> SchemaCompiler<'a> {
+    fn compile(&mut self, schema: &'a SchemaValue) -> Result<ArcParser<Value>, JsonSchemaError> {
+        match schema {
+            SchemaValue::Primitive(Value::Bool(true)) => {
+                return Ok(any_value_parser(ANY_VALUE_DEPTH))
+            }
+            SchemaValue::Primitive(Value::Bool(false)) => {
+                return Err(JsonSchemaError::new("the schema `false` never matches"))
+            }
+            SchemaValue::Object(_) => {}
+            _ => {
+                return Err(JsonSchemaError::new(format!(
+                    "expected an object or a boolean, found {schema}"
+                )))
+            }
+        }
+
+        if let Some(reference) = schema.get("$ref") {
+            return self.compile_reference(reference);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(const_parser(value.to_value()));
+        }
+        if let Some(values) = schema.get("enum") {
+            let SchemaValue::Array(values) = values else {
+                return Err(JsonSchemaError::new("`enum` must be an array"));
+            };
+            return choice(values.iter().map(|value| const_parser(value.to_value())));
+        }
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(schemas) = schema.get(keyword) {
+                let SchemaValue::Array(schemas) = schemas else {
+                    return Err(JsonSchemaError::new(format!(
+                        "`{keyword}` must be an array"
+                    )));
+                };
+                let parsers = schemas
+                    .iter()
+                    .map(|schema| self.compile(schema))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return choice(parsers);
+            }
+        }
+        for keyword in ["allOf", "not", "if"] {
+            if schema.get(keyword).is_some() {
+                return Err(JsonSchemaError::new(format!(
+                    "`{keyword}` is not supported"
+                )));
+            }
+        }
+
+        match schema.get("type") {
+            Some(SchemaValue::Array(types)) => {
+                let parsers = types
+                    .iter()
+                    .map(|ty| match ty.as_str() {
+                        Some(ty) => self.compile_type(ty, schema),
+                        None => Err(JsonSchemaError::new(format!(
+                            "expected a type name, found {ty}"
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                choice(parsers)
+            }
+            Some(ty) => match ty.as_str() {
+                Some(ty) => self.compile_type(ty, schema),
+                None => Err(JsonSchemaError::new(format!(
+                    "expected a type name, found {ty}"
+                ))),
+            },
+            // Infer the type from the other keywords
+            None if schema.get("properties").is_some() => self.compile_type("object", schema),
+            None if schema.get("items").is_some() => self.compile_type("array", schema),
+            None => Ok(any_value_parser(ANY_VALUE_DEPTH)),
+        }
+    }
+
+    fn compile_reference(
+        &mut self,
+        reference: &SchemaValue,
+    ) -> Result<ArcParser<Value>, JsonSchemaError> {
+        let reference = reference
+            .as_str()
+            .ok_or_else(|| JsonSchemaError::new("`$ref` must be a string"))?;
+        let pointer = reference.strip_prefix('#').ok_or_else(|| {
+            JsonSchemaError::new(format!(
+                "only references to the same document are supported, found {reference}"
+            ))
+        })?;
+        if self.references.iter().any(|r| r == reference) {
+            return Err(JsonSchemaError::new(format!(
+                "recursive references are not supported, found {reference}"
+            )));
+        }
+        let schema = self.root.pointer(pointer).ok_or_else(|| {
+            JsonSchemaError::new(format!("the reference {reference} does not exist"))
+        })?;
+
+        self.references.push(reference.to_string());
+        let parser = self.compile(schema);
+        self.references.pop();
+        parser
+    }
+
+    fn compile_type(
+        &mut self,
+        ty: &str,
+        schema: &'a SchemaValue,
+    ) -> Result<ArcParser<Value>, JsonSchemaError> {
+        match ty {
+            "null" => Ok(const_parser(Value::Null)),
+            "boolean" => choice([
+                const_parser(Value::Bool(true)),
+                const_parser(Value::Bool(false)),
+            ]),
+            "string" => string_parser(schema),
+            "integer" => integer_parser(schema),
+            "number" => number_parser(schema),
+            "array" => {
+                let items = match schema.get("items") {
+                    Some(items) => self.compile(items)?,
+                    None => any_value_parser(ANY_VALUE_DEPTH - 1),
+                };
+                let min = usize_keyword(schema, "minItems")?.unwrap_or(0);
+                let max = usize_keyword(schema, "maxItems")?.unwrap_or(usize::MAX);
+                Ok(array_parser(items, min..=max))
+            }
+            "object" => {
+                let properties = match schema.get("properties") {
+                    Some(SchemaValue::Object(properties)) => properties
+                        .iter()
+                        .map(|(name, schema)| Ok((name.clone(), self.compile(schema)?)))
+                        .collect::<Result<Vec<_>, JsonSchemaError>>()?,
+                    Some(properties) => {
+                        return Err(JsonSchemaError::new(format!(
+                            "`properties` must be an object, found {properties}"
+                        )))
+                    }
+                    None => Vec::new(),
+                };
+                Ok(object_parser(properties))
+            }
+            _ => Err(JsonSchemaError::new(format!("unknown type {ty}"))),
+        }
+    }
+}
+
+fn usize_keyword(schema: &SchemaValue, keyword: &str) -> Result<Option<usize>, JsonSchemaError> {
+    schema
+        .get(keyword)
+        .map(|value| {
+            value.as_u64().map(|value| value as usize).ok_or_else(|| {
+                JsonSchemaError::new(format!("`{keyword}` must be a positive integer"))
+            })
+        })
+        .transpose()
+}
+
+fn f64_keyword(schema: &SchemaValue, keyword: &str) -> Result<Option<f64>, JsonSchemaError> {
+    schema
+        .get(keyword)
+        .map(|value| {
+            value
+                .as_f64()
+                .ok_or_else(|| JsonSchemaError::new(format!("`{keyword}` must be a number")))
+        })
+        .transpose()
+}
+
+/// Parse a choice between several parsers.
+fn choice(
+    parsers: impl IntoIterator<Item = ArcParser<Value>>,
+) -> Result<ArcParser<Value>, JsonSchemaError> {
+    parsers
+        .into_iter()
+        .reduce(|first, second| first.or(second).boxed())
+        .ok_or_else(|| JsonSchemaError::new("expected at least one schema to choose from"))
+}
+
+/// Parse exactly one value.
+fn const_parser(value: Value) -> ArcParser<Value> {
+    LiteralParser::new(format_json(&value))
+        .map_output(move |_| value.clone())
+        .boxed()
+}
+
+fn string_parser(schema: &SchemaValue) -> Result<ArcParser<Value>, JsonSchemaError> {
+    if let Some(pattern) = schema.get("pattern") {
+        let pattern = pattern
+            .as_str()
+            .ok_or_else(|| JsonSchemaError::new("`pattern` must be a string"))?;
+        // JSON Schema patterns match anywhere in the string unless they are anchored
+        let (start, pattern) = match pattern.strip_prefix('^') {
+            Some(pattern) => ("", pattern),
+            None => ("[^\"]*", pattern),
+        };
+        let (pattern, end) = match pattern.strip_suffix('$') {
+            Some(pattern) if !pattern.ends_with('\\') => (pattern, ""),
+            _ => (pattern, "[^\"]*"),
+        };
+        let parser = RegexParser::new(&format!("\"{start}(?:{pattern}){end}\""))
+            .map_err(|err| JsonSchemaError::new(format!("invalid pattern {pattern}: {err}")))?;
+        return Ok(parser
+            .map_output(|string| Value::String(string[1..string.len() - 1].to_string()))
+            .boxed());
+    }
+
+    let min = usize_keyword(schema, "minLength")?.unwrap_or(0);
+    let max = usize_keyword(schema, "maxLength")?.unwrap_or(usize::MAX);
+    Ok(StringParser::new(min..=max)
+        .map_output(Value::String)
+        .boxed())
+}
+
+fn integer_parser(schema: &SchemaValue) -> Result<ArcParser<Value>, JsonSchemaError> {
+    let mut min = i64::MIN as i128;
+    let mut max = i64::MAX as i128;
+    if let Some(minimum) = f64_keyword(schema, "minimum")? {
+        min = min.max(minimum.ceil() as i128);
+    }
+    if let Some(minimum) = f64_keyword(schema, "exclusiveMinimum")? {
+        min = min.max(minimum.floor() as i128 + 1);
+    }
+    if let Some(maximum) = f64_keyword(schema, "maximum")? {
+        max = max.min(maximum.floor() as i128);
+    }
+    if let Some(maximum) = f64_keyword(schema, "exclusiveMaximum")? {
+        max = max.min(maximum.ceil() as i128 - 1);
+    }
+    if min > max {
+        return Err(JsonSchemaError::new("no integer is in the range"));
+    }
+    Ok(IntegerParser::new(min..=max)
+        .map_output(|value| Value::from(value as i64))
+        .boxed())
+}
+
+fn number_parser(schema: &SchemaValue) -> Result<ArcParser<Value>, JsonSchemaError> {
+    let mut min = f64::MIN;
+    let mut max = f64::MAX;
+    if let Some(minimum) = f64_keyword(schema, "minimum")? {
+        min = min.max(minimum);
+    }
+    // Exclusive bounds become inclusive bounds at the next representable number
+    if let Some(minimum) = f64_keyword(schema, "exclusiveMinimum")? {
+        min = min.max(minimum.next_up());
+    }
+    if let Some(maximum) = f64_keyword(schema, "maximum")? {
+        max = max.min(maximum);
+    }
+    if let Some(maximum) = f64_keyword(schema, "exclusiveMaximum")? {
+        max = max.min(maximum.next_down());
+    }
+    if min > max {
+        return Err(JsonSchemaError::new("no number is in the range"));
+    }
+    Ok(FloatParser::new(min..=max)
+        .map_output(|value| Number::from_f64(value).map_or(Value::Null, Value::Number))
+        .boxed())
+}
+
+fn array_parser(
+    items: ArcParser<Value>,
+    length: std::ops::RangeInclusive<usize>,
+) -> ArcParser<Value> {
+    LiteralParser::new("[")
+        .ignore_output_then(SeparatedParser::new(
+            items,
+            LiteralParser::new(", "),
+            length,
+        ))
+        .then_literal("]")
+        .map_output(Value::Array)
+        .boxed()
+}
+
+fn object_parser(properties: Vec<(String, ArcParser<Value>)>) -> ArcParser<Value> {
+    if properties.is_empty() {
+        return const_parser(Value::Object(Map::new()));
+    }
+    let mut parser = LiteralParser::new("{ ").map_output(|_| Map::new()).boxed();
+    for (i, (name, value)) in properties.into_iter().enumerate() {
+        let separator = if i == 0 { "" } else { ", " };
+        let key = format!("{separator}{}: ", format_json(&Value::String(name.clone())));
+        parser = parser
+            .then(LiteralParser::new(key).ignore_output_then(value))
+            .map_output(move |(mut map, value)| {
+                map.insert(name.clone(), value);
+                map
+            })
+            .boxed();
+    }
+    parser.then_literal(" }").map_output(Value::Object).boxed()
+}
+
+/// A parser for any JSON value with at most `depth` levels of nested arrays and objects.
+fn any_value_parser(depth: usize) -> ArcParser<Value> {
+    let primitives = [
+        const_parser(Value::Null),
+        const_parser(Value::Bool(true)),
+        const_parser(Value::Bool(false)),
+        StringParser::new(0..=usize::MAX)
+            .map_output(Value::String)
+            .boxed(),
+        FloatParser::new(f64::MIN..=f64::MAX)
+            .map_output(|value| Number::from_f64(value).map_or(Value::Null, Value::Number))
+            .boxed(),
+    ];
+    if depth == 0 {
+        return choice(primitives).unwrap();
+    }
+
+    let value = any_value_parser(depth - 1);
+    let array = array_parser(value.clone(), 0..=usize::MAX);
+    let entry = StringParser::new(0..=usize::MAX)
+        .then_literal(": ")
+        .then(value);
+    let object = LiteralParser::new("{ ")
+        .ignore_output_then(SeparatedParser::new(
+            entry,
+            LiteralParser::new(", "),
+            1..=usize::MAX,
+        ))
+        .then_literal(" }")
+        .map_output(|entries| Value::Object(entries.into_iter().collect()))
+        .boxed()
+        .or(const_parser(Value::Object(Map::new())))
+        .boxed();
+    choice(primitives.into_iter().chain([array, object])).unwrap()
+}
+
+/// Format a value with the same whitespace layout as the parsers.
+fn format_json(value: &Value) -> String {
+    match value {
+        Value::Array(items) => {
+            let items = items.iter().map(format_json).collect::<Vec<_>>();
+            format!("[{}]", items.join(", "))
+        }
+        Value::Object(map) if map.is_empty() => "{}".to_string(),
+        Value::Object(map) => {
+            let entries = map
+                .iter()
+                .map(|(key, value)| {
+                    format!(
+                        "{}: {}",
+                        format_json(&Value::String(key.clone())),
+                        format_json(value)
+                    )
+                })
+                .collect::<Vec<_>>();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+fn parse_with_schema(schema: &str, input: &str) -> Option<Value> {
+    use crate::{CreateParserState, ParseStatus, Parser};
+
+    let parser = parser_from_json_schema(schema).unwrap();
+    let state = parser.create_parser_state();
+    match parser.parse(&state, input.as_bytes()) {
+        Ok(ParseStatus::Finished { result, .. }) => Some(result),
+        _ => None,
+    }
+}
+
+#[test]
+fn json_schema_objects_and_arrays() {
+    use serde_json::json;
+
+    let schema = r##"{
+        "$defs": {
+            "color": { "enum": ["red", "green", "blue"] }
+        },
+        "type": "object",
+        "properties": {
+            "name": { "type": "string", "minLength": 1 },
+            "age": { "type": "integer", "minimum": 0, "exclusiveMaximum": 150 },
+            "height": { "type": ["number", "null"] },
+            "colors": { "type": "array", "items": { "$ref": "#/$defs/color" }, "maxItems": 2 },
+            "kind": { "const": "person" }
+        }
+    }"##;
+    let input = r#"{ "name": "Alice", "age": 42, "height": null, "colors": ["red", "blue"], "kind": "person" }"#;
+    assert_eq!(
+        parse_with_schema(schema, input),
+        Some(json!({
+            "name": "Alice",
+            "age": 42,
+            "height": null,
+            "colors": ["red", "blue"],
+            "kind": "person"
+        }))
+    );
+
+    // Out of range, not in the enum, too many items and properties out of the order of the schema
+    for input in [
+        r#"{ "name": "Alice", "age": 150"#,
+        r#"{ "name": "Alice", "age": 42, "height": 1.5, "colors": ["pink"#,
+        r#"{ "name": "Alice", "age": 42, "height": 1.5, "colors": ["red", "red", "#,
+        r#"{ "age": 42"#,
+    ] {
+        assert_eq!(parse_with_schema(schema, input), None);
+    }
+
+    assert!(parser_from_json_schema(r#"{ "type": "object""#).is_err());
+}
+
+#[test]
+fn json_schema_strings() {
+    use serde_json::json;
+
+    let schema = r#"{
+        "anyOf": [
+            { "type": "string", "pattern": "^[0-9]{3}-[0-9]{4}$" },
+            { "type": "boolean" }
+        ]
+    }"#;
+    assert_eq!(
+        parse_with_schema(schema, r#""555-1234""#),
+        Some(json!("555-1234"))
+    );
+    assert_eq!(parse_with_schema(schema, "true"), Some(json!(true)));
+    assert_eq!(parse_with_schema(schema, r#""555-12a"#), None);
+
+    let recursive = r##"{
+        "$defs": { "node": { "type": "array", "items": { "$ref": "#/$defs/node" } } },
+        "$ref": "#/$defs/node"
+    }"##;
+    assert!(parser_from_json_schema(recursive).is_err());
+}
+
+#[test]
+fn json_schema_number_bounds() {
+    use serde_json::json;
+
+    let schema =
+        r#"{ "type": "number", "exclusiveMinimum": 1, "maximum": 10, "exclusiveMaximum": 20 }"#;
+    assert_eq!(parse_with_schema(schema, "1.5 "), Some(json!(1.5)));
+    assert_eq!(parse_with_schema(schema, "10 "), Some(json!(10.0)));
+    // The exclusive minimum and the tighter inclusive maximum are both enforced
+    assert_eq!(parse_with_schema(schema, "1 "), None);
+    assert_eq!(parse_with_schema(schema, "-2 "), None);
+    assert_eq!(parse_with_schema(schema, "15 "), None);
+
+    let schema = r#"{ "type": "number", "minimum": 1, "exclusiveMaximum": 5 }"#;
+    assert_eq!(parse_with_schema(schema, "1 "), Some(json!(1.0)));
+    assert_eq!(parse_with_schema(schema, "4.5 "), Some(json!(4.5)));
+    assert_eq!(parse_with_schema(schema, "5 "), None);
+
+    let empty = r#"{ "type": "number", "exclusiveMinimum": 1, "exclusiveMaximum": 1 }"#;
+    assert!(parser_from_json_schema(empty).is_err());
+}
@@ -1,8 +1,10 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::Arc};
 
 use crate::bail;
 
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{
+    CreateParserState, ParseStatus, Parser, TokenMask, TokenSet, TokenStep, TokenVocabulary,
+};
 
 /// A parser for a literal.
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -102,6 +104,29 @@ impl Parser for LiteralParser {
             })
         }
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        Some(self)
+    }
+}
+
+impl TokenMask<LiteralParserOffset> for LiteralParser {
+    fn allowed_tokens(
+        &self,
+        state: &LiteralParserOffset,
+        vocabulary: &TokenVocabulary,
+    ) -> Arc<TokenSet> {
+        let literal = self.literal.as_bytes();
+        Arc::new(vocabulary.walk(state.offset, |&offset, byte| {
+            match literal.get(offset) {
+                // The literal is already finished
+                None => TokenStep::Accept,
+                Some(&expected) if expected != byte => TokenStep::Reject,
+                Some(_) if offset + 1 == literal.len() => TokenStep::Accept,
+                Some(_) => TokenStep::Continue(offset + 1),
+            }
+        }))
+    }
 }
 
 #[test]
@@ -1,6 +1,6 @@
 use std::{fmt::Debug, marker::PhantomData};
 
-use crate::{CreateParserState, ParseStatus, Parser};
+use crate::{CreateParserState, ParseStatus, Parser, TokenMask};
 
 /// A parser that maps the output of another parser.
 pub struct MapOutputParser<P: Parser, O, F = fn(<P as Parser>::Output) -> O> {
@@ -63,4 +63,8 @@ impl<P: Parser, O: Clone, F: Fn(P::Output) -> O> Parser for MapOutputParser<P, O
             }),
         }
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        self.parser.token_mask()
+    }
 }
@@ -36,9 +36,23 @@ mod stop_on;
 pub use stop_on::*;
 mod map;
 pub use map::*;
+mod validate;
+pub use validate::*;
+mod object;
+pub use object::*;
 mod regex;
 pub use regex::*;
+mod json_schema;
+pub use json_schema::*;
+mod partial_json;
+pub use partial_json::*;
+mod grammar;
+pub use grammar::*;
+mod token_mask;
+pub use token_mask::*;
 mod arc_linked_list;
+#[cfg(any(feature = "chrono", feature = "uuid"))]
+mod external;
 pub(crate) use arc_linked_list::*;
 mod schema;
 pub use schema::*;
@@ -165,6 +179,11 @@ pub trait Parser {
         state: &Self::PartialState,
         input: &'a [u8],
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>>;
+
+    /// Get the [`TokenMask`] for this parser if it can find the allowed tokens for a state directly. Constrained generation uses the mask instead of parsing the text of every token in the vocabulary.
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        None
+    }
 }
 
 impl Parser for () {
@@ -194,6 +213,10 @@ impl<P: ?Sized + Parser> Parser for &P {
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         (*self).parse(state, input)
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        (*self).token_mask()
+    }
 }
 
 impl<P: ?Sized + Parser> Parser for Box<P> {
@@ -208,6 +231,11 @@ impl<P: ?Sized + Parser> Parser for Box<P> {
         let _self: &P = self;
         _self.parse(state, input)
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        let _self: &P = self;
+        _self.token_mask()
+    }
 }
 
 impl<P: ?Sized + Parser> Parser for Arc<P> {
@@ -222,6 +250,11 @@ impl<P: ?Sized + Parser> Parser for Arc<P> {
         let _self: &P = self;
         _self.parse(state, input)
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        let _self: &P = self;
+        _self.token_mask()
+    }
 }
 
 trait AnyCreateParserState:
@@ -268,6 +301,10 @@ impl<O: Clone> Parser for ArcParser<O> {
         let _self: &dyn Parser<Output = O, PartialState = Arc<dyn Any + Send + Sync>> = &self.0;
         _self.parse(state, input)
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        self.0.token_mask()
+    }
 }
 
 /// A wrapper for a parser that implements an easily boxable version of Parser.
@@ -305,6 +342,35 @@ where
             .parse(state, input)
             .map(|result| result.map_state(|state| Arc::new(state) as Arc<dyn Any + Sync + Send>))
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        self.0.token_mask()?;
+        Some(self)
+    }
+}
+
+impl<P> TokenMask<Arc<dyn Any + Sync + Send>> for AnyParser<P>
+where
+    P: Parser,
+    P::PartialState: Send + Sync + 'static,
+{
+    fn allowed_tokens(
+        &self,
+        state: &Arc<dyn Any + Sync + Send>,
+        vocabulary: &TokenVocabulary,
+    ) -> Arc<TokenSet> {
+        match (self.0.token_mask(), state.downcast_ref::<P::PartialState>()) {
+            (Some(mask), Some(state)) => mask.allowed_tokens(state, vocabulary),
+            // The parser has no mask or the state is not of the correct type, so allow every token and let parsing reject them
+            _ => {
+                let mut allowed = TokenSet::empty(vocabulary.len());
+                for token in 0..vocabulary.len() as u32 {
+                    allowed.insert(token);
+                }
+                Arc::new(allowed)
+            }
+        }
+    }
 }
 
 impl<P: CreateParserState> CreateParserState for AnyParser<P>
@@ -417,6 +483,18 @@ pub trait ParserExt: Parser {
         }
     }
 
+    /// Reject the output of this parser if the validation function returns an error.
+    fn validate<F>(self, f: F) -> ValidateParser<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&Self::Output) -> ParseResult<()>,
+    {
+        ValidateParser {
+            parser: self,
+            validate: f,
+        }
+    }
+
     /// Get a boxed version of this parser.
     fn boxed(self) -> ArcParser<Self::Output>
     where
@@ -474,6 +552,10 @@ impl<P: Parser, F: Fn() -> P::PartialState + Clone> Parser for WithInitialState<
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         self.parser.parse(state, input)
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        self.parser.token_mask()
+    }
 }
 
 /// A parser that is lazily initialized.
@@ -520,6 +602,10 @@ impl<P: Parser, F: FnOnce() -> P> Parser for LazyParser<P, F> {
     ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
         self.get_parser().parse(state, input)
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        self.get_parser().token_mask()
+    }
 }
 
 /// A parser for a choice between two parsers.
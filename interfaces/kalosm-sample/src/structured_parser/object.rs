@@ -0,0 +1,378 @@
+use std::{any::Any, borrow::Cow, sync::Arc};
+
+use crate::{ArcParser, CreateParserState, ParseStatus, Parser, ParserExt};
+
+/// A value parsed by a property of an [`ObjectParser`].
+pub type ObjectValue = Arc<dyn Any + Send + Sync>;
+
+/// A property of an [`ObjectParser`].
+#[derive(Clone)]
+pub struct ObjectProperty {
+    name: String,
+    required: bool,
+    parser: ArcParser<ObjectValue>,
+}
+
+impl ObjectProperty {
+    /// Create a new required property with the parser for its value.
+    pub fn new<P>(name: impl ToString, parser: P) -> Self
+    where
+        P: CreateParserState + Send + Sync + 'static,
+        P::Output: Send + Sync + 'static,
+        P::PartialState: Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            required: true,
+            parser: parser
+                .map_output(|value| Arc::new(value) as ObjectValue)
+                .boxed(),
+        }
+    }
+
+    /// Set whether the property is required. Properties that are not required can be omitted from the object.
+    pub fn with_required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+}
+
+/// A parser for a JSON object with a fixed set of properties that may be in any order. Properties that are not required may be omitted.
+///
+/// The object uses the same layout as the [`Parse`](crate::Parse) implementations: `{ "key": value, "key2": value }` or `{}` if there are no properties.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = ObjectParser::new([
+///     ObjectProperty::new("name", String::new_parser()),
+///     ObjectProperty::new("age", u32::new_parser()).with_required(false),
+/// ]);
+/// let state = parser.create_parser_state();
+/// let output = parser
+///     .parse(&state, br#"{ "name": "John" }"#)
+///     .unwrap()
+///     .unwrap_finished();
+/// assert_eq!(output.get::<String>(0), Some("John".to_string()));
+/// assert_eq!(output.get::<u32>(1), None);
+/// ```
+#[derive(Clone)]
+pub struct ObjectParser {
+    properties: Arc<[ObjectProperty]>,
+}
+
+impl ObjectParser {
+    /// Create a new object parser with the properties of the object.
+    pub fn new(properties: impl IntoIterator<Item = ObjectProperty>) -> Self {
+        Self {
+            properties: properties.into_iter().collect(),
+        }
+    }
+
+    /// Check if every required property has been parsed.
+    fn required_properties_parsed(&self, values: &[Option<ObjectValue>]) -> bool {
+        self.properties
+            .iter()
+            .zip(values)
+            .all(|(property, value)| !property.required || value.is_some())
+    }
+
+    /// Get the properties that have not been parsed yet and start with a prefix.
+    fn remaining_properties<'a>(
+        &'a self,
+        values: &'a [Option<ObjectValue>],
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = (usize, &'a ObjectProperty)> + 'a {
+        self.properties
+            .iter()
+            .enumerate()
+            .filter(move |(index, property)| {
+                values[*index].is_none() && property.name.as_bytes().starts_with(prefix)
+            })
+    }
+}
+
+/// The values of the properties parsed by an [`ObjectParser`].
+#[derive(Clone)]
+pub struct ObjectParserOutput {
+    values: Vec<Option<ObjectValue>>,
+}
+
+impl ObjectParserOutput {
+    /// Get the value of the property at an index in the list of properties passed to [`ObjectParser::new`]. Returns `None` if the property was omitted or the value is not of type `T`.
+    pub fn get<T: Clone + 'static>(&self, index: usize) -> Option<T> {
+        self.values
+            .get(index)?
+            .as_ref()?
+            .downcast_ref::<T>()
+            .cloned()
+    }
+}
+
+/// The state of an [`ObjectParser`].
+#[derive(Clone)]
+pub struct ObjectParserState {
+    values: Vec<Option<ObjectValue>>,
+    position: ObjectPosition,
+}
+
+#[derive(Clone)]
+enum ObjectPosition {
+    /// Before the opening brace
+    Open,
+    /// After the opening brace
+    AfterOpen,
+    /// Before the quote that starts a key
+    KeyStart,
+    /// Inside a key
+    Key(Vec<u8>),
+    /// Inside the `: ` after a key
+    Colon { property: usize, progress: usize },
+    /// Inside the value of a property
+    Value {
+        property: usize,
+        state: Arc<dyn Any + Send + Sync>,
+    },
+    /// After a value
+    AfterValue,
+    /// After the comma that separates two properties
+    Separator,
+    /// After the space before the closing brace
+    Close,
+}
+
+impl CreateParserState for ObjectParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        ObjectParserState {
+            values: vec![None; self.properties.len()],
+            position: ObjectPosition::Open,
+        }
+    }
+}
+
+impl Parser for ObjectParser {
+    type Output = ObjectParserOutput;
+    type PartialState = ObjectParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        const COLON: &[u8] = b": ";
+
+        let mut values = state.values.clone();
+        let mut position = state.position.clone();
+        let mut index = 0;
+        while index < input.len() {
+            let byte = input[index];
+            position = match position {
+                ObjectPosition::Open => match byte {
+                    b'{' => ObjectPosition::AfterOpen,
+                    _ => crate::bail!("Expected the start of an object"),
+                },
+                ObjectPosition::AfterOpen => match byte {
+                    b' ' if !self.properties.is_empty() => ObjectPosition::KeyStart,
+                    b'}' if self.required_properties_parsed(&values) => {
+                        return Ok(ParseStatus::Finished {
+                            result: ObjectParserOutput { values },
+                            remaining: &input[index + 1..],
+                        });
+                    }
+                    _ => crate::bail!("Expected a property or the end of the object"),
+                },
+                ObjectPosition::KeyStart => match byte {
+                    b'"' => ObjectPosition::Key(Vec::new()),
+                    _ => crate::bail!("Expected the start of a key"),
+                },
+                ObjectPosition::Key(mut key) => {
+                    if byte == b'"' {
+                        match self
+                            .remaining_properties(&values, &key)
+                            .find(|(_, property)| property.name.len() == key.len())
+                        {
+                            Some((property, _)) => ObjectPosition::Colon {
+                                property,
+                                progress: 0,
+                            },
+                            None => crate::bail!("Unknown or repeated property"),
+                        }
+                    } else {
+                        key.push(byte);
+                        if self.remaining_properties(&values, &key).next().is_none() {
+                            crate::bail!("Unknown or repeated property");
+                        }
+                        ObjectPosition::Key(key)
+                    }
+                }
+                ObjectPosition::Colon { property, progress } => {
+                    if byte != COLON[progress] {
+                        crate::bail!("Expected a colon after the key");
+                    }
+                    if progress + 1 == COLON.len() {
+                        ObjectPosition::Value {
+                            property,
+                            state: self.properties[property].parser.create_parser_state(),
+                        }
+                    } else {
+                        ObjectPosition::Colon {
+                            property,
+                            progress: progress + 1,
+                        }
+                    }
+                }
+                ObjectPosition::Value { property, state } => {
+                    match self.properties[property]
+                        .parser
+                        .parse(&state, &input[index..])?
+                    {
+                        ParseStatus::Finished { result, remaining } => {
+                            values[property] = Some(result);
+                            index = input.len() - remaining.len();
+                            position = ObjectPosition::AfterValue;
+                            continue;
+                        }
+                        ParseStatus::Incomplete {
+                            new_state,
+                            required_next,
+                        } => {
+                            return Ok(ParseStatus::Incomplete {
+                                new_state: ObjectParserState {
+                                    values,
+                                    position: ObjectPosition::Value {
+                                        property,
+                                        state: new_state,
+                                    },
+                                },
+                                required_next,
+                            });
+                        }
+                    }
+                }
+                ObjectPosition::AfterValue => match byte {
+                    b',' if self.remaining_properties(&values, b"").next().is_some() => {
+                        ObjectPosition::Separator
+                    }
+                    b' ' if self.required_properties_parsed(&values) => ObjectPosition::Close,
+                    _ => crate::bail!("Expected another property or the end of the object"),
+                },
+                ObjectPosition::Separator => match byte {
+                    b' ' => ObjectPosition::KeyStart,
+                    _ => crate::bail!("Expected a space after the comma"),
+                },
+                ObjectPosition::Close => match byte {
+                    b'}' => {
+                        return Ok(ParseStatus::Finished {
+                            result: ObjectParserOutput { values },
+                            remaining: &input[index + 1..],
+                        });
+                    }
+                    _ => crate::bail!("Expected the end of the object"),
+                },
+            };
+            index += 1;
+        }
+
+        let required_next = match &position {
+            ObjectPosition::Open => Cow::Borrowed("{"),
+            ObjectPosition::AfterOpen if !self.required_properties_parsed(&values) => {
+                Cow::Borrowed(" \"")
+            }
+            ObjectPosition::KeyStart => Cow::Borrowed("\""),
+            ObjectPosition::Key(key) => {
+                let mut remaining = self.remaining_properties(&values, key);
+                match (remaining.next(), remaining.next()) {
+                    (Some((_, property)), None) => {
+                        Cow::Owned(format!("{}\": ", &property.name[key.len()..]))
+                    }
+                    _ => Cow::Borrowed(""),
+                }
+            }
+            ObjectPosition::Colon { progress, .. } => Cow::Borrowed(&": "[*progress..]),
+            ObjectPosition::AfterValue
+                if self.remaining_properties(&values, b"").next().is_none() =>
+            {
+                Cow::Borrowed(" }")
+            }
+            ObjectPosition::Separator => Cow::Borrowed(" \""),
+            ObjectPosition::Close => Cow::Borrowed("}"),
+            _ => Cow::Borrowed(""),
+        };
+
+        Ok(ParseStatus::Incomplete {
+            new_state: ObjectParserState { values, position },
+            required_next,
+        })
+    }
+}
+
+#[test]
+fn object_parser() {
+    use crate::Parse;
+
+    let parser = ObjectParser::new([
+        ObjectProperty::new("name", String::new_parser()),
+        ObjectProperty::new("age", u32::new_parser()),
+        ObjectProperty::new("nickname", String::new_parser()).with_required(false),
+    ]);
+    let state = parser.create_parser_state();
+
+    // Properties can be in any order
+    let output = parser
+        .parse(&state, br#"{ "age": 30, "name": "John" }"#)
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output.get::<String>(0), Some("John".to_string()));
+    assert_eq!(output.get::<u32>(1), Some(30));
+    assert_eq!(output.get::<String>(2), None);
+
+    let output = parser
+        .parse(
+            &state,
+            br#"{ "nickname": "Johnny", "name": "John", "age": 30 }"#,
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(output.get::<String>(2), Some("Johnny".to_string()));
+
+    // Required properties can't be omitted
+    assert!(parser.parse(&state, br#"{ "name": "John" }"#).is_err());
+    // Properties can't be repeated
+    assert!(parser
+        .parse(&state, br#"{ "name": "John", "name": "#)
+        .is_err());
+    // Unknown properties are rejected
+    assert!(parser.parse(&state, br#"{ "height"#).is_err());
+
+    // Once every property is parsed, the object must end
+    let (_, required_next) = parser
+        .parse(
+            &state,
+            br#"{ "nickname": "Johnny", "name": "John", "age": 30"#,
+        )
+        .unwrap()
+        .unwrap_incomplete();
+    assert_eq!(required_next, "");
+    let (_, required_next) = parser
+        .parse(
+            &state,
+            br#"{ "nickname": "Johnny", "age": 30, "name": "John""#,
+        )
+        .unwrap()
+        .unwrap_incomplete();
+    assert_eq!(required_next, " }");
+    let (_, required_next) = parser
+        .parse(&state, br#"{ "ni"#)
+        .unwrap()
+        .unwrap_incomplete();
+    assert_eq!(required_next, "ckname\": ");
+
+    // An object with only optional properties can be empty
+    let parser = ObjectParser::new([
+        ObjectProperty::new("nickname", String::new_parser()).with_required(false)
+    ]);
+    let state = parser.create_parser_state();
+    let output = parser.parse(&state, b"{}").unwrap().unwrap_finished();
+    assert_eq!(output.get::<String>(0), None);
+}
@@ -196,3 +196,77 @@ impl<T: Parse> Parse for Option<T> {
             .or(LiteralParser::new("null").map_output(|_| None))
     }
 }
+
+impl<T: Parse, S: std::hash::BuildHasher + Default + Clone + Send + Sync> Parse
+    for std::collections::HashMap<String, T, S>
+{
+    fn new_parser() -> impl SendCreateParserState<Output = Self> {
+        let entry = StringParser::new(0..=usize::MAX)
+            .then_literal(": ")
+            .then(T::new_parser());
+        LiteralParser::new("{ ")
+            .ignore_output_then(SeparatedParser::new(
+                entry,
+                LiteralParser::new(", "),
+                1..=usize::MAX,
+            ))
+            .then_literal(" }")
+            .map_output(|entries| entries.into_iter().collect())
+            .or(LiteralParser::new("{}").map_output(|_| Self::default()))
+    }
+}
+
+macro_rules! tuple_parser {
+    ($($ty:ident $value:ident),+) => {
+        impl<$($ty: Parse),+> Parse for ($($ty,)+) {
+            fn new_parser() -> impl SendCreateParserState<Output = Self> {
+                tuple_parser!(@then LiteralParser::new("["), (), []; $($ty $value),+)
+            }
+        }
+    };
+    // Parse the last item and close the array
+    (@then $parser:expr, $pattern:pat, [$($done:ident)*]; $ty:ident $value:ident) => {
+        $parser
+            .then($ty::new_parser())
+            .then_literal("]")
+            .map_output(|($pattern, $value)| ($($done,)* $value,))
+    };
+    // Parse the next item and the separator after it
+    (@then $parser:expr, $pattern:pat, [$($done:ident)*]; $ty:ident $value:ident, $($rest:tt)*) => {
+        tuple_parser!(
+            @then $parser.then($ty::new_parser()).then_literal(", "),
+            ($pattern, $value),
+            [$($done)* $value];
+            $($rest)*
+        )
+    };
+}
+
+tuple_parser!(A a);
+tuple_parser!(A a, B b);
+tuple_parser!(A a, B b, C c);
+tuple_parser!(A a, B b, C c, D d);
+tuple_parser!(A a, B b, C c, D d, E e);
+tuple_parser!(A a, B b, C c, D d, E e, F f);
+
+#[test]
+fn parse_maps_and_tuples() {
+    let parser = <std::collections::HashMap<String, (i32, String)>>::new_parser();
+    let state = parser.create_parser_state();
+    let result = parser
+        .parse(&state, br#"{ "a": [1, "x"], "b": [-2, "y"] }"#)
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        result,
+        [
+            ("a".to_string(), (1, "x".to_string())),
+            ("b".to_string(), (-2, "y".to_string()))
+        ]
+        .into_iter()
+        .collect()
+    );
+
+    let result = parser.parse(&state, b"{}").unwrap().unwrap_finished();
+    assert!(result.is_empty());
+}
@@ -0,0 +1,326 @@
+use serde_json::{Map, Number, Value};
+
+/// Read a snapshot of a JSON value from the start of a JSON document that is still being generated.
+///
+/// Complete values are read as is. Incomplete values are filled in as far as possible:
+/// - Strings contain the text generated so far
+/// - Numbers contain the digits generated so far
+/// - Objects contain every property with a complete key and at least part of a value
+/// - Arrays contain every item that has been started
+///
+/// Incomplete `true`, `false` and `null` literals are left out. Returns `None` if no value has been started yet or the text is not valid JSON.
+///
+/// This is useful to show the value generated by a [`Parse`](crate::Parse) parser while the model is still generating it.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let snapshot = parse_partial_json(r#"{ "name": "Jo"#);
+/// assert_eq!(snapshot, Some(serde_json::json!({ "name": "Jo" })));
+///
+/// let snapshot = parse_partial_json(r#"{ "name": "John", "friends": ["Ja"#);
+/// assert_eq!(
+///     snapshot,
+///     Some(serde_json::json!({ "name": "John", "friends": ["Ja"] }))
+/// );
+/// ```
+pub fn parse_partial_json(text: &str) -> Option<Value> {
+    let mut reader = PartialJsonReader { text, position: 0 };
+    match reader.value()? {
+        PartialValue::Complete(value) | PartialValue::Incomplete(Some(value)) => Some(value),
+        PartialValue::Incomplete(None) => None,
+    }
+}
+
+/// A value read from a JSON document that may end in the middle of the value
+enum PartialValue {
+    Complete(Value),
+    /// The text ended before the value was complete. The value contains everything that was read so far if anything
+    Incomplete(Option<Value>),
+}
+
+struct PartialJsonReader<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> PartialJsonReader<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn remaining(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\n' | b'\r' | b'\t') = self.peek() {
+            self.position += 1;
+        }
+    }
+
+    fn value(&mut self) -> Option<PartialValue> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Some(PartialValue::Incomplete(None)),
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => Some(match self.string()? {
+                (string, true) => PartialValue::Complete(Value::String(string)),
+                (string, false) => PartialValue::Incomplete(Some(Value::String(string))),
+            }),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => None,
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Value) -> Option<PartialValue> {
+        let remaining = self.remaining();
+        if remaining.starts_with(literal) {
+            self.position += literal.len();
+            Some(PartialValue::Complete(value))
+        } else if literal.starts_with(remaining) {
+            self.position = self.text.len();
+            Some(PartialValue::Incomplete(None))
+        } else {
+            None
+        }
+    }
+
+    fn number(&mut self) -> Option<PartialValue> {
+        let start = self.position;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+            self.position += 1;
+        }
+        let number = &self.text[start..self.position];
+        if self.peek().is_some() {
+            return Some(PartialValue::Complete(Value::Number(number.parse().ok()?)));
+        }
+
+        // The number may continue, so only read the digits that form a valid number so far
+        let number = number.trim_end_matches(['-', '+', '.', 'e', 'E']);
+        Some(PartialValue::Incomplete(
+            number.parse::<Number>().ok().map(Value::Number),
+        ))
+    }
+
+    /// Read a string and if the string was complete
+    fn string(&mut self) -> Option<(String, bool)> {
+        // Skip the opening quote
+        self.position += 1;
+        let mut string = String::new();
+        loop {
+            let remaining = self.remaining();
+            let Some(end) = remaining.find(['"', '\\']) else {
+                string.push_str(remaining);
+                self.position = self.text.len();
+                return Some((string, false));
+            };
+            string.push_str(&remaining[..end]);
+            self.position += end + 1;
+            if remaining.as_bytes()[end] == b'"' {
+                return Some((string, true));
+            }
+
+            let Some(escape) = self.peek() else {
+                return Some((string, false));
+            };
+            self.position += 1;
+            let character = match escape {
+                b'"' => '"',
+                b'\\' => '\\',
+                b'/' => '/',
+                b'b' => '\u{8}',
+                b'f' => '\u{c}',
+                b'n' => '\n',
+                b'r' => '\r',
+                b't' => '\t',
+                b'u' => match self.unicode_escape()? {
+                    Some(character) => character,
+                    None => return Some((string, false)),
+                },
+                _ => return None,
+            };
+            string.push(character);
+        }
+    }
+
+    /// Read the character of a `\u` escape after the `\u`. Returns `Some(None)` if the text ends before the escape is complete
+    fn unicode_escape(&mut self) -> Option<Option<char>> {
+        let Some(first) = self.hex_code_unit()? else {
+            return Some(None);
+        };
+        if !(0xD800..0xDC00).contains(&first) {
+            return char::from_u32(first as u32).map(Some);
+        }
+
+        // A high surrogate must be followed by a low surrogate
+        let remaining = self.remaining();
+        if !remaining.starts_with("\\u") {
+            return if "\\u".starts_with(remaining) {
+                Some(None)
+            } else {
+                None
+            };
+        }
+        self.position += 2;
+        let Some(second) = self.hex_code_unit()? else {
+            return Some(None);
+        };
+        char::decode_utf16([first, second]).next()?.ok().map(Some)
+    }
+
+    fn hex_code_unit(&mut self) -> Option<Option<u16>> {
+        let remaining = self.remaining();
+        let Some(hex) = remaining.get(..4) else {
+            if !remaining.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return None;
+            }
+            self.position = self.text.len();
+            return Some(None);
+        };
+        let code_unit = u16::from_str_radix(hex, 16).ok()?;
+        self.position += 4;
+        Some(Some(code_unit))
+    }
+
+    fn object(&mut self) -> Option<PartialValue> {
+        // Skip the opening brace
+        self.position += 1;
+        let mut object = Map::new();
+        let mut first = true;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Some(PartialValue::Incomplete(Some(Value::Object(object)))),
+                Some(b'}') if first => {
+                    self.position += 1;
+                    return Some(PartialValue::Complete(Value::Object(object)));
+                }
+                Some(b'"') => {}
+                Some(_) => return None,
+            }
+            first = false;
+
+            let (key, true) = self.string()? else {
+                return Some(PartialValue::Incomplete(Some(Value::Object(object))));
+            };
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Some(PartialValue::Incomplete(Some(Value::Object(object)))),
+                Some(b':') => self.position += 1,
+                Some(_) => return None,
+            }
+            match self.value()? {
+                PartialValue::Complete(value) => {
+                    object.insert(key, value);
+                }
+                PartialValue::Incomplete(value) => {
+                    if let Some(value) = value {
+                        object.insert(key, value);
+                    }
+                    return Some(PartialValue::Incomplete(Some(Value::Object(object))));
+                }
+            }
+
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Some(PartialValue::Incomplete(Some(Value::Object(object)))),
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Some(PartialValue::Complete(Value::Object(object)));
+                }
+                Some(_) => return None,
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<PartialValue> {
+        // Skip the opening bracket
+        self.position += 1;
+        let mut array = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Some(PartialValue::Complete(Value::Array(array)));
+        }
+        loop {
+            match self.value()? {
+                PartialValue::Complete(value) => array.push(value),
+                PartialValue::Incomplete(value) => {
+                    array.extend(value);
+                    return Some(PartialValue::Incomplete(Some(Value::Array(array))));
+                }
+            }
+
+            self.skip_whitespace();
+            match self.peek() {
+                None => return Some(PartialValue::Incomplete(Some(Value::Array(array)))),
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Some(PartialValue::Complete(Value::Array(array)));
+                }
+                Some(_) => return None,
+            }
+        }
+    }
+}
+
+#[test]
+fn partial_json() {
+    use serde_json::json;
+
+    let text = r#"{ "name": "John \"Jo\" Doe", "age": 42, "alive": true, "friends": [{ "name": "Jane" }], "nickname": null }"#;
+    assert_eq!(
+        parse_partial_json(text),
+        Some(serde_json::from_str::<Value>(text).unwrap())
+    );
+
+    // Every prefix of a valid document is read without errors
+    for end in 0..text.len() {
+        parse_partial_json(&text[..end]);
+    }
+
+    assert_eq!(parse_partial_json(""), None);
+    assert_eq!(parse_partial_json("{"), Some(json!({})));
+    assert_eq!(parse_partial_json(r#"{ "na"#), Some(json!({})));
+    assert_eq!(parse_partial_json(r#"{ "name": "#), Some(json!({})));
+    assert_eq!(
+        parse_partial_json(r#"{ "name": "John \"J"#),
+        Some(json!({ "name": "John \"J" }))
+    );
+    assert_eq!(
+        parse_partial_json(r#"{ "name": "John\"#),
+        Some(json!({ "name": "John" }))
+    );
+    assert_eq!(
+        parse_partial_json(r#"{ "name": "John", "age": 4"#),
+        Some(json!({ "name": "John", "age": 4 }))
+    );
+    assert_eq!(
+        parse_partial_json(r#"{ "name": "John", "alive": tr"#),
+        Some(json!({ "name": "John" }))
+    );
+    assert_eq!(parse_partial_json("[1.5, 2."), Some(json!([1.5, 2])));
+    assert_eq!(parse_partial_json("[1, [2, "), Some(json!([1, [2]])));
+    assert_eq!(
+        parse_partial_json(r#""snow ☃ 😀"#),
+        Some(json!("snow \u{2603} \u{1F600}"))
+    );
+    assert_eq!(
+        parse_partial_json(r#""\u2603 \uD83D\uDE00""#),
+        Some(json!("\u{2603} \u{1F600}"))
+    );
+    assert_eq!(parse_partial_json(r#""snow \u26"#), Some(json!("snow ")));
+    assert_eq!(parse_partial_json(r#""snow \uD83D\"#), Some(json!("snow ")));
+
+    // Text that is not JSON
+    assert_eq!(parse_partial_json("hello"), None);
+    assert_eq!(parse_partial_json(r#"{ "name" "John" }"#), None);
+}
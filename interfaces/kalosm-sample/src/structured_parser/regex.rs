@@ -1,17 +1,25 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
-use crate::{CreateParserState, Parser};
+use crate::{CreateParserState, Parser, TokenMask, TokenSet, TokenStep, TokenVocabulary};
 use regex_automata::{
     dfa::{dense, Automaton},
     util::primitives::StateID,
 };
 
+/// The maximum number of token masks a [`RegexParser`] keeps. Each mask has one bit per token in the vocabulary.
+const MAX_CACHED_TOKEN_MASKS: usize = 1024;
+
 /// A parser that uses a regex pattern to parse input.
 pub struct RegexParser {
     dfa: dense::DFA<Vec<u32>>,
     config: regex_automata::util::start::Config,
     // A cache for the required next bytes for each state
     jump_table: RwLock<HashMap<StateID, String>>,
+    // A cache for the allowed tokens for each state of the last vocabulary. The cache is bounded by MAX_CACHED_TOKEN_MASKS
+    token_masks: RwLock<HashMap<(u64, StateID), Arc<TokenSet>>>,
 }
 
 impl RegexParser {
@@ -27,6 +35,7 @@ impl RegexParser {
             dfa,
             config,
             jump_table: Default::default(),
+            token_masks: Default::default(),
         })
     }
 }
@@ -115,6 +124,43 @@ impl Parser for RegexParser {
             required_next: required_next.into(),
         })
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        Some(self)
+    }
+}
+
+impl TokenMask<RegexParserState> for RegexParser {
+    fn allowed_tokens(
+        &self,
+        state: &RegexParserState,
+        vocabulary: &TokenVocabulary,
+    ) -> Arc<TokenSet> {
+        let key = (vocabulary.id(), state.state);
+        if let Some(mask) = self.token_masks.read().unwrap().get(&key) {
+            return mask.clone();
+        }
+
+        // Walk every token through the DFA at once. This matches how parse treats each byte
+        let mask = Arc::new(vocabulary.walk(state.state, |&state, byte| {
+            let state = self.dfa.next_state(state, byte);
+            if self.dfa.is_match_state(self.dfa.next_eoi_state(state)) {
+                TokenStep::Accept
+            } else if self.dfa.is_dead_state(state) || self.dfa.is_quit_state(state) {
+                TokenStep::Reject
+            } else {
+                TokenStep::Continue(state)
+            }
+        }));
+        let mut token_masks = self.token_masks.write().unwrap();
+        // Masks for other vocabularies are rarely used again, and the cache is cleared once it is full
+        token_masks.retain(|(id, _), _| *id == vocabulary.id());
+        if token_masks.len() >= MAX_CACHED_TOKEN_MASKS {
+            token_masks.clear();
+        }
+        token_masks.insert(key, mask.clone());
+        mask
+    }
 }
 
 /// The state of a regex parser.
@@ -194,3 +240,20 @@ fn required_next_regex() {
         _ => panic!("unexpected result to be incomplete: {result:?}"),
     }
 }
+
+#[test]
+fn token_mask_cache_is_bounded() {
+    let parser = RegexParser::new("a{1100}b").unwrap();
+    let vocabulary = TokenVocabulary::new([Some("a"), Some("b")]);
+    let mut state = parser.create_parser_state();
+    for _ in 0..MAX_CACHED_TOKEN_MASKS + 10 {
+        parser.allowed_tokens(&state, &vocabulary);
+        state.state = parser.dfa.next_state(state.state, b'a');
+    }
+    assert!(parser.token_masks.read().unwrap().len() <= MAX_CACHED_TOKEN_MASKS);
+
+    // Masks for a vocabulary that is no longer used are dropped
+    let other_vocabulary = TokenVocabulary::new([Some("a")]);
+    parser.allowed_tokens(&state, &other_vocabulary);
+    assert_eq!(parser.token_masks.read().unwrap().len(), 1);
+}
@@ -62,7 +62,41 @@ impl Display for SchemaLiteral {
     }
 }
 
+impl From<String> for SchemaLiteral {
+    fn from(value: String) -> Self {
+        SchemaLiteral::String(value)
+    }
+}
+
+impl From<&str> for SchemaLiteral {
+    fn from(value: &str) -> Self {
+        SchemaLiteral::String(value.to_string())
+    }
+}
+
+impl From<bool> for SchemaLiteral {
+    fn from(value: bool) -> Self {
+        SchemaLiteral::Boolean(value)
+    }
+}
+
+macro_rules! impl_from_number_for_schema_literal {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for SchemaLiteral {
+                fn from(value: $ty) -> Self {
+                    SchemaLiteral::Number(value as f64)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_number_for_schema_literal!(f64, f32, i64, i32, i16, i8, u64, u32, u16, u8);
+
 /// The type of a schema
+///
+/// Adding a variant is a breaking change. The [`SchemaType::Tuple`] and [`SchemaType::Map`] variants were added in kalosm-sample 0.4.0, so exhaustive matches on the type need new arms when upgrading from 0.3.
 #[derive(Debug, Clone)]
 pub enum SchemaType {
     /// A string schema
@@ -77,6 +111,10 @@ pub enum SchemaType {
     Array(ArraySchema),
     /// An object schema
     Object(JsonObjectSchema),
+    /// A fixed length array schema with a different schema for each item
+    Tuple(TupleSchema),
+    /// An object schema with arbitrary keys
+    Map(MapSchema),
     /// An enum schema
     Enum(EnumSchema),
     /// A schema that matches any of the composite schemas
@@ -92,36 +130,82 @@ pub enum SchemaType {
 }
 
 impl SchemaType {
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         match self {
-            SchemaType::String(schema) => schema.display_with_description(f, description),
-            SchemaType::Number(schema) => schema.display_with_description(f, description),
-            SchemaType::Integer(schema) => schema.display_with_description(f, description),
-            SchemaType::Boolean(schema) => schema.display_with_description(f, description),
-            SchemaType::Array(schema) => schema.display_with_description(f, description),
-            SchemaType::Object(schema) => schema.display_with_description(f, description),
-            SchemaType::Enum(schema) => schema.display_with_description(f, description),
-            SchemaType::AnyOf(schema) => schema.display_with_description(f, description),
-            SchemaType::OneOf(schema) => schema.display_with_description(f, description),
-            SchemaType::Const(schema) => schema.display_with_description(f, description),
-            SchemaType::IfThen(schema) => schema.display_with_description(f, description),
-            SchemaType::Null => match description {
-                Some(description) => f.write_fmt(format_args!(
-                    "{{\n\t\"description\": \"{description}\",\n\t\"type\": \"null\"\n}}"
-                )),
-                None => f.write_str("{ \"type\": \"null\" }"),
-            },
+            SchemaType::String(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Number(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Integer(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Boolean(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Array(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Object(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Tuple(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Map(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Enum(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::AnyOf(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::OneOf(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Const(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::IfThen(schema) => schema.display_with_annotations(f, annotations),
+            SchemaType::Null => display_simple_schema(f, annotations, "\"type\": \"null\""),
         }
     }
 }
 
+/// Annotations that describe a schema without changing the values it accepts
+#[derive(Debug, Clone, Copy, Default)]
+struct SchemaAnnotations<'a> {
+    description: Option<&'a str>,
+    examples: &'a [SchemaLiteral],
+}
+
+impl SchemaAnnotations<'_> {
+    fn is_empty(&self) -> bool {
+        self.description.is_none() && self.examples.is_empty()
+    }
+
+    /// Write each annotation on a new line followed by a comma
+    fn write(&self, writer: &mut impl Write) -> std::fmt::Result {
+        if let Some(description) = self.description {
+            write!(writer, "\n\"description\": \"{description}\",")?;
+        }
+        if !self.examples.is_empty() {
+            writer.write_str("\n\"examples\": [")?;
+            for (i, example) in self.examples.iter().enumerate() {
+                if i > 0 {
+                    writer.write_str(", ")?;
+                }
+                write!(writer, "{example}")?;
+            }
+            writer.write_str("],")?;
+        }
+        Ok(())
+    }
+}
+
+/// Display a schema with a single property. The schema is written on one line if there are no annotations
+fn display_simple_schema(
+    f: &mut std::fmt::Formatter<'_>,
+    annotations: SchemaAnnotations,
+    property: &str,
+) -> std::fmt::Result {
+    if annotations.is_empty() {
+        return write!(f, "{{ {property} }}");
+    }
+    f.write_char('{')?;
+    {
+        let mut writer = IndentationWriter::new(1, f);
+        annotations.write(&mut writer)?;
+        write!(&mut writer, "\n{property}")?;
+    }
+    f.write_str("\n}")
+}
+
 impl Display for SchemaType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -141,17 +225,15 @@ impl IfThenSchema {
         }
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
-            if let Some(description) = description {
-                write!(&mut writer, "\n\"description\": \"{description}\",")?;
-            }
+            annotations.write(&mut writer)?;
             writer.write_str("\n\"if\": ")?;
             write!(&mut writer, "{}", self.if_schema)?;
             writer.write_str(",\n\"then\": ")?;
@@ -163,7 +245,7 @@ impl IfThenSchema {
 
 impl Display for IfThenSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -181,17 +263,15 @@ impl AnyOfSchema {
         }
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
-            if let Some(description) = description {
-                write!(&mut writer, "\n\"description\": \"{description}\",")?;
-            }
+            annotations.write(&mut writer)?;
             writer.write_str("\n\"anyOf\": [")?;
             if !self.any_of.is_empty() {
                 writer.with_indent(|writer| {
@@ -213,7 +293,7 @@ impl AnyOfSchema {
 
 impl Display for AnyOfSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -231,17 +311,15 @@ impl OneOfSchema {
         }
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
-            if let Some(description) = description {
-                write!(&mut writer, "\n\"description\": \"{description}\",")?;
-            }
+            annotations.write(&mut writer)?;
             writer.write_str("\n\"oneOf\": [")?;
             if !self.one_of.is_empty() {
                 writer.with_indent(|writer| {
@@ -263,7 +341,7 @@ impl OneOfSchema {
 
 impl Display for OneOfSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -281,26 +359,18 @@ impl ConstSchema {
         }
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
-        if let Some(description) = description {
-            write!(
-                f,
-                "{{\n\t\"descripiton\": \"{description}\"\n\t\"const\": {}\n}}",
-                self.value
-            )
-        } else {
-            write!(f, "{{ \"const\": {} }}", self.value)
-        }
+        display_simple_schema(f, annotations, &format!("\"const\": {}", self.value))
     }
 }
 
 impl Display for ConstSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -325,17 +395,15 @@ impl EnumSchema {
         }
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
-            if let Some(description) = description {
-                write!(&mut writer, "\n\"description\": \"{description}\",")?;
-            }
+            annotations.write(&mut writer)?;
             writer.write_str("\n\"enum\": [")?;
             {
                 for (i, variant) in self.variants.iter().enumerate() {
@@ -353,7 +421,7 @@ impl EnumSchema {
 
 impl Display for EnumSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -402,17 +470,15 @@ impl StringSchema {
         self
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
-            if let Some(description) = description {
-                write!(&mut writer, "\n\"description\": \"{description}\",")?;
-            }
+            annotations.write(&mut writer)?;
             writer.write_str("\n\"type\": \"string\"")?;
             if let Some(length) = &self.length {
                 if *length.start() > 0 {
@@ -432,7 +498,7 @@ impl StringSchema {
 
 impl Display for StringSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -474,33 +540,31 @@ impl NumberSchema {
         self
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         match &self.range {
             Some(range) => {
                 f.write_char('{')?;
                 {
                     let mut writer = IndentationWriter::new(1, f);
-                    if let Some(description) = description {
-                        write!(&mut writer, "\n\"description\": \"{description}\",")?;
-                    }
+                    annotations.write(&mut writer)?;
                     writer.write_str("\n\"type\": \"number\",")?;
                     writer.write_fmt(format_args!("\n\"minimum\": {},", range.start()))?;
                     writer.write_fmt(format_args!("\n\"maximum\": {}", range.end()))?;
                 }
                 f.write_str("\n}")
             }
-            None => f.write_str("{ \"type\": \"number\" }"),
+            None => display_simple_schema(f, annotations, "\"type\": \"number\""),
         }
     }
 }
 
 impl Display for NumberSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -557,25 +621,18 @@ impl_schema_for_integer!(u8);
 impl_schema_for_integer!(usize);
 
 impl IntegerSchema {
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
-        if let Some(description) = description {
-            write!(
-                f,
-                "{{\n\t\"description\": \"{description}\",\n\t\"type\": \"integer\"\n}}"
-            )
-        } else {
-            f.write_str("{ \"type\": \"integer\" }")
-        }
+        display_simple_schema(f, annotations, "\"type\": \"integer\"")
     }
 }
 
 impl Display for IntegerSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -591,25 +648,18 @@ fn test_integer_schema() {
 pub struct BooleanSchema;
 
 impl BooleanSchema {
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
-        if let Some(description) = description {
-            write!(
-                f,
-                "{{\n\t\"description\": \"{description}\",\n\t\"type\": \"boolean\"\n}}"
-            )
-        } else {
-            f.write_str("{ \"type\": \"boolean\" }")
-        }
+        display_simple_schema(f, annotations, "\"type\": \"boolean\"")
     }
 }
 
 impl Display for BooleanSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -657,17 +707,15 @@ impl ArraySchema {
         self
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
-            if let Some(description) = description {
-                write!(&mut writer, "\n\"description\": \"{description}\",")?;
-            }
+            annotations.write(&mut writer)?;
             writer.write_str("\n\"type\": \"array\"")?;
             writer.write_str(",\n\"items\": ")?;
             write!(&mut writer, "{}", self.items)?;
@@ -689,7 +737,7 @@ impl ArraySchema {
 
 impl Display for ArraySchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -725,6 +773,131 @@ fn test_array_schema() {
     assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"items\": {\n\t\t\"type\": \"string\"\n\t},\n\t\"unevaluatedItems\": false\n}");
 }
 
+/// A schema for a fixed length array with a different schema for each item
+#[derive(Debug, Clone)]
+pub struct TupleSchema {
+    items: Vec<SchemaType>,
+}
+
+macro_rules! impl_schema_for_tuple {
+    ($($ty:ident),+) => {
+        impl<$($ty: Schema),+> Schema for ($($ty,)+) {
+            fn schema() -> SchemaType {
+                SchemaType::Tuple(TupleSchema::new([$($ty::schema()),+]))
+            }
+        }
+    };
+}
+
+impl_schema_for_tuple!(A);
+impl_schema_for_tuple!(A, B);
+impl_schema_for_tuple!(A, B, C);
+impl_schema_for_tuple!(A, B, C, D);
+impl_schema_for_tuple!(A, B, C, D, E);
+impl_schema_for_tuple!(A, B, C, D, E, F);
+
+impl TupleSchema {
+    /// Create a new tuple schema
+    pub fn new(items: impl IntoIterator<Item = SchemaType>) -> Self {
+        Self {
+            items: items.into_iter().collect(),
+        }
+    }
+
+    fn display_with_annotations(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        annotations: SchemaAnnotations,
+    ) -> std::fmt::Result {
+        f.write_char('{')?;
+        {
+            let mut writer = IndentationWriter::new(1, f);
+            annotations.write(&mut writer)?;
+            writer.write_str("\n\"type\": \"array\"")?;
+            writer.write_str(",\n\"prefixItems\": [")?;
+            writer.with_indent(|writer| {
+                for (i, item) in self.items.iter().enumerate() {
+                    if i > 0 {
+                        writer.write_char(',')?;
+                    }
+                    write!(writer, "\n{}", item)?;
+                }
+                Ok(())
+            })?;
+            writer.write_str("\n]")?;
+            write!(&mut writer, ",\n\"minItems\": {}", self.items.len())?;
+            write!(&mut writer, ",\n\"maxItems\": {}", self.items.len())?;
+            writer.write_str(",\n\"items\": false")?;
+        }
+        f.write_str("\n}")
+    }
+}
+
+impl Display for TupleSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display_with_annotations(f, SchemaAnnotations::default())
+    }
+}
+
+#[test]
+fn test_tuple_schema() {
+    let schema = <(String, i32)>::schema();
+
+    assert_eq!(schema.to_string(), "{\n\t\"type\": \"array\",\n\t\"prefixItems\": [\n\t\t{\n\t\t\t\"type\": \"string\"\n\t\t},\n\t\t{ \"type\": \"number\" }\n\t],\n\t\"minItems\": 2,\n\t\"maxItems\": 2,\n\t\"items\": false\n}");
+}
+
+/// A schema for an object with arbitrary keys and values that all match the same schema
+#[derive(Debug, Clone)]
+pub struct MapSchema {
+    values: Box<SchemaType>,
+}
+
+impl<T: Schema, S> Schema for std::collections::HashMap<String, T, S> {
+    fn schema() -> SchemaType {
+        SchemaType::Map(MapSchema::new(T::schema()))
+    }
+}
+
+impl MapSchema {
+    /// Create a new map schema
+    pub fn new(values: SchemaType) -> Self {
+        Self {
+            values: Box::new(values),
+        }
+    }
+
+    fn display_with_annotations(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        annotations: SchemaAnnotations,
+    ) -> std::fmt::Result {
+        f.write_char('{')?;
+        {
+            let mut writer = IndentationWriter::new(1, f);
+            annotations.write(&mut writer)?;
+            writer.write_str("\n\"type\": \"object\"")?;
+            write!(&mut writer, ",\n\"additionalProperties\": {}", self.values)?;
+        }
+        f.write_str("\n}")
+    }
+}
+
+impl Display for MapSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display_with_annotations(f, SchemaAnnotations::default())
+    }
+}
+
+#[test]
+fn test_map_schema() {
+    let schema = std::collections::HashMap::<String, i32>::schema();
+
+    assert_eq!(
+        schema.to_string(),
+        "{\n\t\"type\": \"object\",\n\t\"additionalProperties\": { \"type\": \"number\" }\n}"
+    );
+}
+
 /// A schema for an object
 #[derive(Debug, Clone)]
 pub struct JsonObjectSchema {
@@ -755,18 +928,16 @@ impl JsonObjectSchema {
         self
     }
 
-    fn display_with_description(
+    fn display_with_annotations(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        description: Option<&str>,
+        annotations: SchemaAnnotations,
     ) -> std::fmt::Result {
         f.write_char('{')?;
         {
             let mut writer = IndentationWriter::new(1, f);
+            annotations.write(&mut writer)?;
             writer.write_char('\n')?;
-            if let Some(description) = description {
-                writeln!(&mut writer, "\"description\": \"{description}\",")?;
-            }
             if let Some(title) = &self.title {
                 writer.write_str("\"title\": \"")?;
                 writer.write_str(title)?;
@@ -815,7 +986,7 @@ impl JsonObjectSchema {
 
 impl Display for JsonObjectSchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.display_with_description(f, None)
+        self.display_with_annotations(f, SchemaAnnotations::default())
     }
 }
 
@@ -828,6 +999,7 @@ fn test_object_schema() {
             JsonPropertySchema {
                 name: "name".to_string(),
                 description: None,
+                examples: Vec::new(),
                 required: true,
                 ty: SchemaType::String(StringSchema {
                     length: Some(1..=10),
@@ -837,6 +1009,7 @@ fn test_object_schema() {
             JsonPropertySchema {
                 name: "age".to_string(),
                 description: None,
+                examples: Vec::new(),
                 required: true,
                 ty: SchemaType::Number(NumberSchema {
                     range: Some(0.0..=100.0),
@@ -845,6 +1018,7 @@ fn test_object_schema() {
             JsonPropertySchema {
                 name: "height".to_string(),
                 description: None,
+                examples: Vec::new(),
                 required: false,
                 ty: SchemaType::Number(NumberSchema {
                     range: Some(0.0..=500.0),
@@ -861,6 +1035,7 @@ fn test_object_schema() {
 pub struct JsonPropertySchema {
     name: String,
     description: Option<&'static str>,
+    examples: Vec<SchemaLiteral>,
     required: bool,
     ty: SchemaType,
 }
@@ -871,6 +1046,7 @@ impl JsonPropertySchema {
         Self {
             name: name.to_string(),
             description: None,
+            examples: Vec::new(),
             required: false,
             ty,
         }
@@ -882,6 +1058,12 @@ impl JsonPropertySchema {
         self
     }
 
+    /// Set example values for the property
+    pub fn with_examples(mut self, examples: impl IntoIterator<Item = SchemaLiteral>) -> Self {
+        self.examples = examples.into_iter().collect();
+        self
+    }
+
     /// Set whether the property is required
     pub fn with_required(mut self, required: bool) -> Self {
         self.required = required;
@@ -892,10 +1074,36 @@ impl JsonPropertySchema {
 impl Display for JsonPropertySchema {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("\"{}\": ", self.name))?;
-        self.ty.display_with_description(f, self.description)
+        self.ty.display_with_annotations(
+            f,
+            SchemaAnnotations {
+                description: self.description,
+                examples: &self.examples,
+            },
+        )
     }
 }
 
+#[test]
+fn test_property_examples() {
+    let property = JsonPropertySchema::new("age", SchemaType::Integer(IntegerSchema))
+        .with_description("The age of the person")
+        .with_examples([30.into(), 42.into()]);
+
+    assert_eq!(
+        property.to_string(),
+        "\"age\": {\n\t\"description\": \"The age of the person\",\n\t\"examples\": [30, 42],\n\t\"type\": \"integer\"\n}"
+    );
+
+    let property = JsonPropertySchema::new("name", SchemaType::String(StringSchema::new()))
+        .with_examples(["John".into()]);
+
+    assert_eq!(
+        property.to_string(),
+        "\"name\": {\n\t\"examples\": [\"John\"],\n\t\"type\": \"string\"\n}"
+    );
+}
+
 /// A description of the format of a type
 pub trait Schema {
     /// Get the schema for the type
@@ -1,6 +1,8 @@
 use std::sync::Arc;
 
-use crate::{CreateParserState, ParseResult, ParseStatus, Parser};
+use crate::{
+    CreateParserState, ParseResult, ParseStatus, Parser, TokenMask, TokenSet, TokenVocabulary,
+};
 
 /// State of a sequence parser.
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -115,6 +117,37 @@ impl<P1: Parser, P2: CreateParserState> Parser for SequenceParser<P1, P2> {
             }
         }
     }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        self.parser1.token_mask()?;
+        self.parser2.token_mask()?;
+        Some(self)
+    }
+}
+
+impl<P1: Parser, P2: CreateParserState>
+    TokenMask<SequenceParserState<P1::PartialState, P2::PartialState, P1::Output>>
+    for SequenceParser<P1, P2>
+{
+    fn allowed_tokens(
+        &self,
+        state: &SequenceParserState<P1::PartialState, P2::PartialState, P1::Output>,
+        vocabulary: &TokenVocabulary,
+    ) -> Arc<TokenSet> {
+        // The mask of the first parser allows any text after it finishes, so it includes tokens that continue into the second parser
+        match state {
+            SequenceParserState::FirstParser(p1) => self
+                .parser1
+                .token_mask()
+                .unwrap()
+                .allowed_tokens(p1, vocabulary),
+            SequenceParserState::SecondParser(p2, _) => self
+                .parser2
+                .token_mask()
+                .unwrap()
+                .allowed_tokens(p2, vocabulary),
+        }
+    }
 }
 
 #[test]
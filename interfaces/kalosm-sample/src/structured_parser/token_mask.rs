@@ -0,0 +1,223 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// The text of every token in a model's vocabulary. Parsers that implement [`TokenMask`] use the vocabulary to find the tokens that are allowed in a state without parsing the text of each token.
+///
+/// The tokens are stored in a trie so tokens that share a prefix are only walked once.
+#[derive(Debug)]
+pub struct TokenVocabulary {
+    /// A unique id for the vocabulary. Parsers use this to cache masks per vocabulary
+    id: u64,
+    nodes: Vec<TrieNode>,
+    len: usize,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    /// The children of the node sorted by the byte that leads to them.
+    children: Vec<(u8, usize)>,
+    /// The tokens whose text ends at this node.
+    tokens: Vec<u32>,
+}
+
+impl TokenVocabulary {
+    /// Create a vocabulary from the text of each token, indexed by token id. Tokens without text (like special tokens) are never allowed by a mask.
+    pub fn new<S: AsRef<[u8]>>(tokens: impl IntoIterator<Item = Option<S>>) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let mut nodes = vec![TrieNode::default()];
+        let mut len = 0;
+        for (token, text) in tokens.into_iter().enumerate() {
+            len += 1;
+            let Some(text) = text else {
+                continue;
+            };
+            let text = text.as_ref();
+            if text.is_empty() {
+                continue;
+            }
+            let mut node = 0;
+            for &byte in text {
+                node = match nodes[node]
+                    .children
+                    .binary_search_by_key(&byte, |(byte, _)| *byte)
+                {
+                    Ok(index) => nodes[node].children[index].1,
+                    Err(index) => {
+                        let child = nodes.len();
+                        nodes[node].children.insert(index, (byte, child));
+                        nodes.push(TrieNode::default());
+                        child
+                    }
+                };
+            }
+            nodes[node].tokens.push(token as u32);
+        }
+
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            nodes,
+            len,
+        }
+    }
+
+    /// A unique id for this vocabulary.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The number of tokens in the vocabulary.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the vocabulary is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Find the tokens that are allowed by walking the bytes of every token from a starting state.
+    pub(crate) fn walk<S: Clone>(
+        &self,
+        start: S,
+        mut step: impl FnMut(&S, u8) -> TokenStep<S>,
+    ) -> TokenSet {
+        let mut allowed = TokenSet::empty(self.len);
+        let mut queue = vec![(0, start)];
+        while let Some((node, state)) = queue.pop() {
+            for &(byte, child) in &self.nodes[node].children {
+                match step(&state, byte) {
+                    TokenStep::Reject => {}
+                    TokenStep::Accept => self.insert_all(child, &mut allowed),
+                    TokenStep::Continue(state) => {
+                        for &token in &self.nodes[child].tokens {
+                            allowed.insert(token);
+                        }
+                        queue.push((child, state));
+                    }
+                }
+            }
+        }
+        allowed
+    }
+
+    /// Insert every token under a node.
+    fn insert_all(&self, node: usize, allowed: &mut TokenSet) {
+        let mut queue = vec![node];
+        while let Some(node) = queue.pop() {
+            for &token in &self.nodes[node].tokens {
+                allowed.insert(token);
+            }
+            queue.extend(self.nodes[node].children.iter().map(|(_, child)| *child));
+        }
+    }
+}
+
+/// The result of walking one byte of a token in [`TokenVocabulary::walk`].
+pub(crate) enum TokenStep<S> {
+    /// The byte is valid and the parser continues in the new state.
+    Continue(S),
+    /// The parser finishes after the byte, so any text after it is allowed.
+    Accept,
+    /// The byte is not valid.
+    Reject,
+}
+
+/// A set of token ids.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenSet {
+    bits: Vec<u64>,
+}
+
+impl TokenSet {
+    /// Create an empty set that can hold tokens with ids less than `len`.
+    pub fn empty(len: usize) -> Self {
+        Self {
+            bits: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    /// Add a token to the set.
+    pub fn insert(&mut self, token: u32) {
+        let token = token as usize;
+        if token / 64 >= self.bits.len() {
+            self.bits.resize(token / 64 + 1, 0);
+        }
+        self.bits[token / 64] |= 1 << (token % 64);
+    }
+
+    /// Check if the set contains a token.
+    pub fn contains(&self, token: u32) -> bool {
+        let token = token as usize;
+        self.bits
+            .get(token / 64)
+            .is_some_and(|bits| bits & (1 << (token % 64)) != 0)
+    }
+
+    /// Iterate over the tokens in the set in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.bits.iter().enumerate().flat_map(|(i, &bits)| {
+            (0..64)
+                .filter(move |bit| bits & (1 << bit) != 0)
+                .map(move |bit| (i * 64 + bit) as u32)
+        })
+    }
+
+    /// The number of tokens in the set.
+    pub fn len(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|bits| bits.count_ones() as usize)
+            .sum()
+    }
+
+    /// Check if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|bits| *bits == 0)
+    }
+}
+
+/// A parser that can find the tokens that are allowed in a state directly instead of parsing the text of every token. Constrained generation uses the mask when [`Parser::token_mask`](crate::Parser::token_mask) returns one.
+///
+/// A token is allowed if its text is a valid continuation of the state, or if the parser finishes partway through the token. The mask may contain tokens that are not valid, so callers still need to parse the text of the token they choose.
+pub trait TokenMask<S> {
+    /// Get the tokens that are allowed in the state.
+    fn allowed_tokens(&self, state: &S, vocabulary: &TokenVocabulary) -> Arc<TokenSet>;
+}
+
+#[test]
+fn vocabulary_walk() {
+    use crate::{CreateParserState, LiteralParser, Parser, RegexParser};
+
+    let vocabulary = TokenVocabulary::new([
+        Some("a"),
+        Some("ab"),
+        Some("abc"),
+        Some("b"),
+        None,
+        Some("1"),
+        Some("12"),
+        Some("1a"),
+    ]);
+    assert_eq!(vocabulary.len(), 8);
+
+    let literal = LiteralParser::new("ab");
+    let mask = literal.token_mask().unwrap();
+    let allowed = mask.allowed_tokens(&literal.create_parser_state(), &vocabulary);
+    // "abc" finishes the literal partway through the token
+    assert_eq!(allowed.iter().collect::<Vec<_>>(), [0, 1, 2]);
+
+    let regex = RegexParser::new("[0-9]+a").unwrap();
+    let mask = regex.token_mask().unwrap();
+    let allowed = mask.allowed_tokens(&regex.create_parser_state(), &vocabulary);
+    assert_eq!(allowed.iter().collect::<Vec<_>>(), [5, 6, 7]);
+    let state = regex
+        .parse(&regex.create_parser_state(), b"1")
+        .unwrap()
+        .unwrap_incomplete()
+        .0;
+    let allowed = mask.allowed_tokens(&state, &vocabulary);
+    assert_eq!(allowed.iter().collect::<Vec<_>>(), [0, 1, 2, 5, 6, 7]);
+}
@@ -0,0 +1,140 @@
+use std::fmt::Debug;
+
+use crate::{CreateParserState, ParseResult, ParseStatus, Parser, TokenMask};
+
+/// A parser that rejects the output of another parser if it fails a validation function.
+///
+/// The output is only validated once the inner parser finishes. If the value is invalid, parsing fails with the validation error. During constrained generation, this rejects the token that would complete the invalid value, so the model has to continue the value differently (for example with more digits) and the rejected text is never added to the session or the output. If no other token can continue the value, generation fails.
+pub struct ValidateParser<P, F> {
+    pub(crate) parser: P,
+    pub(crate) validate: F,
+}
+
+impl<P: Debug, F> Debug for ValidateParser<P, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.parser.fmt(f)
+    }
+}
+
+impl<P: PartialEq, F: PartialEq> PartialEq for ValidateParser<P, F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.parser == other.parser && self.validate == other.validate
+    }
+}
+
+impl<P: Clone, F: Clone> Clone for ValidateParser<P, F> {
+    fn clone(&self) -> Self {
+        Self {
+            parser: self.parser.clone(),
+            validate: self.validate.clone(),
+        }
+    }
+}
+
+impl<P: CreateParserState, F: Fn(&P::Output) -> ParseResult<()>> CreateParserState
+    for ValidateParser<P, F>
+{
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        self.parser.create_parser_state()
+    }
+}
+
+impl<P: CreateParserState, F: Fn(&P::Output) -> ParseResult<()>> Parser for ValidateParser<P, F> {
+    type Output = P::Output;
+    type PartialState = P::PartialState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let status = self.parser.parse(state, input)?;
+        if let ParseStatus::Finished { result, .. } = &status {
+            (self.validate)(result)?;
+        }
+        Ok(status)
+    }
+
+    fn token_mask(&self) -> Option<&dyn TokenMask<Self::PartialState>> {
+        // The mask of the inner parser can't see the value, so it may allow the token that completes an invalid value. Constrained generation parses every sampled token again and rejects that token.
+        self.parser.token_mask()
+    }
+}
+
+#[test]
+fn validate_parser() {
+    use crate::{bail, ParserExt, U32Parser};
+
+    let parser = U32Parser::new().validate(|value| {
+        if value % 2 != 0 {
+            bail!("The value must be even");
+        }
+        Ok(())
+    });
+    let state = parser.create_parser_state();
+
+    assert_eq!(
+        parser.parse(&state, b"42 ").unwrap(),
+        ParseStatus::Finished {
+            result: 42,
+            remaining: b" "
+        }
+    );
+    assert!(parser.parse(&state, b"41 ").is_err());
+    // The value is not validated until it is finished, so it can still become valid
+    let ParseStatus::Incomplete { new_state, .. } = parser.parse(&state, b"41").unwrap() else {
+        panic!("expected the number to be incomplete");
+    };
+    assert!(parser.parse(&new_state, b" ").is_err());
+    assert_eq!(
+        parser.parse(&new_state, b"4 ").unwrap(),
+        ParseStatus::Finished {
+            result: 414,
+            remaining: b" "
+        }
+    );
+}
+
+#[test]
+fn validate_parser_rejects_literals() {
+    use crate::{bail, LiteralParser, ParserExt, StringParser};
+
+    let parser = LiteralParser::new("true")
+        .map_output(|_| true)
+        .or(LiteralParser::new("false").map_output(|_| false))
+        .validate(|value| {
+            if !value {
+                bail!("The value must be true");
+            }
+            Ok(())
+        });
+    let state = parser.create_parser_state();
+    assert!(parser.parse(&state, b"false").is_err());
+    let ParseStatus::Incomplete { new_state, .. } = parser.parse(&state, b"fals").unwrap() else {
+        panic!("expected the boolean to be incomplete");
+    };
+    assert!(parser.parse(&new_state, b"e").is_err());
+    assert_eq!(
+        parser.parse(&state, b"true").unwrap(),
+        ParseStatus::Finished {
+            result: true,
+            remaining: b""
+        }
+    );
+
+    let parser = StringParser::new(0..=10).validate(|value| {
+        if value.is_empty() {
+            bail!("The string must not be empty");
+        }
+        Ok(())
+    });
+    let state = parser.create_parser_state();
+    assert!(parser.parse(&state, b"\"\"").is_err());
+    assert_eq!(
+        parser.parse(&state, b"\"hi\"").unwrap(),
+        ParseStatus::Finished {
+            result: "hi".to_string(),
+            remaining: b""
+        }
+    );
+}
@@ -0,0 +1,23 @@
+use kalosm::language::*;
+
+#[tokio::main]
+async fn main() {
+    let llm = Llama::new().await.unwrap();
+    let prompt = "An arithmetic expression that equals 42: ";
+
+    println!("# with constraints");
+    print!("{}", prompt);
+
+    let validator = GrammarParser::new(
+        r#"
+root   ::= expr "\n"
+expr   ::= term ((" + " | " - ") term)*
+term   ::= factor ((" * " | " / ") factor)*
+factor ::= [1-9] [0-9]? | "(" expr ")"
+"#,
+    )
+    .unwrap();
+    let mut stream = llm.stream_structured_text(prompt, validator);
+
+    stream.to_std_out().await.unwrap();
+}
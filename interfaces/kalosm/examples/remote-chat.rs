@@ -0,0 +1,19 @@
+// You must set the environment variable OPENAI_API_KEY (https://platform.openai.com/account/api-keys) to run this example.
+
+use kalosm::language::*;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let mut chat = Chat::builder(Gpt4Mini::default())
+        .with_system_prompt("The assistant will act like a pirate")
+        .build();
+
+    loop {
+        chat.add_message(prompt_input("\n> ").unwrap())
+            .to_std_out()
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,15 @@
+//! This example uses a model hosted with Ollama (https://ollama.com). Start the server and pull the model with `ollama pull llama3.1` before running it.
+
+use kalosm::language::*;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let llm = Ollama::builder().with_model("llama3.1").build();
+    let prompt = "The following is a 300 word essay about why the capital of France is Paris:";
+    print!("{}", prompt);
+
+    let mut stream = llm.stream_text(prompt).with_max_length(300).await.unwrap();
+    stream.to_std_out().await.unwrap();
+}
@@ -0,0 +1,35 @@
+// You must set the environment variable OPENAI_API_KEY (https://platform.openai.com/account/api-keys) to run this example.
+
+use kalosm::language::*;
+
+#[tokio::main]
+async fn main() {
+    let llm = Gpt4Mini::default();
+
+    let question = prompt_input("Question: ").unwrap();
+
+    let mut tools = ToolManager::default().with_tool(CalculatorTool);
+    // Remote models call tools natively, so the conversation is a list of messages instead of a ReAct prompt
+    let mut history = Vec::new();
+    loop {
+        match tools
+            .run_native_step(&question, &mut history, &llm, |_| Ok(()))
+            .await
+            .unwrap()
+        {
+            ToolManagerStepResult::Finished(result) => {
+                println!("\n\nAnswer: {}", result);
+                break;
+            }
+            ToolManagerStepResult::Action {
+                index,
+                output: result,
+            } => {
+                println!("Action {index} Result: {}", result)
+            }
+            ToolManagerStepResult::Thought(thought) => {
+                println!("Thought: {}", thought);
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use crate::{ModelFeedback, SyncModel, TokenEvent, TokenOutputStream};
+use llm_samplers::prelude::*;
+use std::sync::{Arc, Mutex};
+
+/// The default number of sequences [`BatchedTextGeneration`] feeds to the model in one forward pass.
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 8;
+
+/// The state of a single text generation request. This samples tokens from the logits of the model and handles max tokens and stop sequences, but leaves feeding the model to the caller so generation can be stepped one token at a time.
+pub struct TextGenerationState {
+    text_stream: TokenOutputStream,
+    sampler: Arc<Mutex<dyn Sampler>>,
+    max_tokens: Option<u32>,
+    stop_on: Option<String>,
+    stop_on_lowercase: Option<String>,
+    // This stores a buffer of text that has been generated to check against the stop_on string. It should never be longer than the stop_on string.
+    queued_text_matching_stop_on: String,
+    tokens_generated: u32,
+    stop_token: u32,
+}
+
+impl TextGenerationState {
+    /// Create a new generation state for a prompt. Returns the state and the tokens of the prompt which should be fed to the model first.
+    pub fn new<M: SyncModel + ?Sized>(
+        model: &M,
+        prompt: &str,
+        max_tokens: Option<u32>,
+        stop_on: Option<&str>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+    ) -> anyhow::Result<(Self, Vec<u32>)> {
+        let tokens = model
+            .tokenizer()
+            .encode(prompt, false)
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let tokens = tokens.get_ids().to_vec();
+        let mut text_stream = TokenOutputStream::new(model.tokenizer());
+        for &token in &tokens {
+            text_stream.next_token(token)?;
+        }
+
+        Ok((
+            Self {
+                text_stream,
+                sampler,
+                max_tokens,
+                stop_on: stop_on.map(|s| s.to_string()),
+                stop_on_lowercase: stop_on.map(|s| s.to_lowercase()),
+                queued_text_matching_stop_on: String::new(),
+                tokens_generated: 0,
+                stop_token: model.stop_token()?,
+            },
+            tokens,
+        ))
+    }
+
+    /// Sample the next token from the logits the model produced for the last fed tokens, calling `on_token` with any new text.
+    ///
+    /// Returns the token that should be fed to the model next, or `None` if generation is finished.
+    pub fn next_token(
+        &mut self,
+        logits: &[f32],
+        mut on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<Option<u32>> {
+        let logits = Logits::try_from_iter_top_k(logits.iter().copied(), 512)?;
+        let new_token =
+            self.text_stream
+                .sample_token(&mut self.sampler, logits, self.stop_on.as_deref())?;
+        if new_token == self.stop_token {
+            tracing::trace!("Stopping on stop token");
+            return Ok(None);
+        }
+        if let Some(mut new_text) = self.text_stream.next_token(new_token)? {
+            if let Some(stop_on) = self.stop_on_lowercase.as_deref() {
+                let lowercase = new_text.to_lowercase();
+
+                // Check if the string ends with the start of the stop_on string
+                let mut before_stop_on = None;
+                let remaining_stop_on = stop_on
+                    .strip_prefix(&self.queued_text_matching_stop_on)
+                    .unwrap_or(stop_on);
+
+                // If the remaining stop_on string is empty, we have found a match
+                if remaining_stop_on.is_empty() {
+                    return Ok(None);
+                }
+
+                for (i, _) in lowercase.char_indices() {
+                    let end_of_new_text = &lowercase[i..];
+                    if end_of_new_text.is_empty() {
+                        break;
+                    }
+
+                    // Check if we have matched all of the stop_on string
+                    if end_of_new_text.starts_with(remaining_stop_on) {
+                        self.queued_text_matching_stop_on += end_of_new_text;
+                        return Ok(None);
+                    }
+
+                    // Check if the string ends with the start of the stop_on string
+                    if remaining_stop_on.starts_with(end_of_new_text) {
+                        before_stop_on = Some(lowercase[..i].to_string());
+                        self.queued_text_matching_stop_on += end_of_new_text;
+                        break;
+                    }
+                }
+
+                match before_stop_on {
+                    Some(before_stop_on) => {
+                        if let ModelFeedback::Stop = on_token(before_stop_on)? {
+                            return Ok(None);
+                        }
+                    }
+                    None => {
+                        new_text =
+                            std::mem::take(&mut self.queued_text_matching_stop_on) + &new_text;
+                        if let ModelFeedback::Stop = on_token(new_text)? {
+                            return Ok(None);
+                        }
+                    }
+                }
+            } else if let ModelFeedback::Stop = on_token(new_text)? {
+                return Ok(None);
+            }
+        }
+        self.tokens_generated += 1;
+        if let Some(max_tokens) = self.max_tokens {
+            if self.tokens_generated >= max_tokens {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(new_token))
+    }
+
+    /// Like [`TextGenerationState::next_token`], but calls `on_token` with a [`TokenEvent`] for every sampled token that includes the log probability of the token and the `top_logprobs` most likely alternatives.
+    pub fn next_token_with_logprobs(
+        &mut self,
+        logits: &[f32],
+        top_logprobs: usize,
+        mut on_token: impl FnMut(TokenEvent) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<Option<u32>> {
+        let tokens_before = self.text_stream.tokens().len();
+        let mut text = String::new();
+        let new_token = self.next_token(logits, |new_text| {
+            text += &new_text;
+            Ok(ModelFeedback::Continue)
+        })?;
+        // The stop token is never added to the output
+        if self.text_stream.tokens().len() == tokens_before {
+            return Ok(new_token);
+        }
+        let token = *self.text_stream.tokens().last().unwrap();
+        let event = TokenEvent::sampled(
+            text,
+            token,
+            logits,
+            Some(top_logprobs),
+            self.text_stream.tokenizer(),
+        );
+        match on_token(event)? {
+            ModelFeedback::Continue => Ok(new_token),
+            ModelFeedback::Stop => Ok(None),
+        }
+    }
+
+    /// Sample tokens from the logits of [`SyncModel::feed_tokens_speculative`], accepting draft tokens for as long as they match the sampled tokens.
+    ///
+    /// Returns the token that should be fed to the model next (or `None` if generation is finished) and the number of draft tokens that must be rejected.
+    pub fn next_token_speculative(
+        &mut self,
+        logits: &[Vec<f32>],
+        draft_tokens: &[u32],
+        mut on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<(Option<u32>, usize)> {
+        self.speculate(logits, draft_tokens, |state, logits| {
+            state.next_token(logits, &mut on_token)
+        })
+    }
+
+    /// Like [`TextGenerationState::next_token_speculative`], but calls `on_token` with a [`TokenEvent`] for every sampled token. See [`TextGenerationState::next_token_with_logprobs`].
+    pub fn next_token_speculative_with_logprobs(
+        &mut self,
+        logits: &[Vec<f32>],
+        draft_tokens: &[u32],
+        top_logprobs: usize,
+        mut on_token: impl FnMut(TokenEvent) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<(Option<u32>, usize)> {
+        self.speculate(logits, draft_tokens, |state, logits| {
+            state.next_token_with_logprobs(logits, top_logprobs, &mut on_token)
+        })
+    }
+
+    fn speculate(
+        &mut self,
+        logits: &[Vec<f32>],
+        draft_tokens: &[u32],
+        mut next_token: impl FnMut(&mut Self, &[f32]) -> anyhow::Result<Option<u32>>,
+    ) -> anyhow::Result<(Option<u32>, usize)> {
+        for (i, logits) in logits.iter().enumerate() {
+            let new_token = next_token(self, logits)?;
+            match (new_token, draft_tokens.get(i)) {
+                // The draft token was already fed to the model, so we can sample the next token without running the model again
+                (Some(new_token), Some(draft_token)) if new_token == *draft_token => {}
+                _ => return Ok((new_token, draft_tokens.len().saturating_sub(i))),
+            }
+        }
+        Ok((None, 0))
+    }
+
+    /// Finish generation, flushing any text that was held back while checking for the stop sequence.
+    pub fn finish(
+        self,
+        mut on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<()> {
+        if let Some(stop_string) = self.stop_on_lowercase.as_deref() {
+            if !self.queued_text_matching_stop_on.starts_with(stop_string) {
+                on_token(self.queued_text_matching_stop_on)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+type OnToken = Box<dyn FnMut(String) -> anyhow::Result<ModelFeedback>>;
+
+struct BatchedRequest<S> {
+    session: S,
+    state: TextGenerationState,
+    next_tokens: Vec<u32>,
+    logits: Vec<Vec<f32>>,
+    draft_tokens: Vec<u32>,
+    on_token: OnToken,
+}
+
+/// A scheduler that generates text for many requests at once. Each call to [`BatchedTextGeneration::step`] feeds the pending tokens of several requests to the model in a single batch with [`SyncModel::feed_tokens_batch`].
+///
+/// New requests can be pushed between steps, so concurrent requests are coalesced into the same batches as they arrive.
+pub struct BatchedTextGeneration<M: SyncModel> {
+    requests: Vec<BatchedRequest<M::Session>>,
+    max_batch_size: usize,
+}
+
+impl<M: SyncModel> Default for BatchedTextGeneration<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: SyncModel> BatchedTextGeneration<M> {
+    /// Create a new scheduler with no requests.
+    pub fn new() -> Self {
+        Self {
+            requests: Vec::new(),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+        }
+    }
+
+    /// Set the maximum number of sequences that are fed to the model in one batch. (Defaults to [`DEFAULT_MAX_BATCH_SIZE`])
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size.max(1);
+        self
+    }
+
+    /// Add a new request to the scheduler. `on_token` is called every time new text is generated for the request.
+    pub fn push(
+        &mut self,
+        model: &M,
+        prompt: &str,
+        max_tokens: Option<u32>,
+        stop_on: Option<&str>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback> + 'static,
+    ) -> anyhow::Result<()> {
+        let (state, next_tokens) =
+            TextGenerationState::new(model, prompt, max_tokens, stop_on, sampler)?;
+        if next_tokens.is_empty() {
+            anyhow::bail!("Cannot run model on empty input");
+        }
+        self.requests.push(BatchedRequest {
+            session: model.new_session()?,
+            state,
+            next_tokens,
+            logits: vec![Vec::new()],
+            draft_tokens: Vec::new(),
+            on_token: Box::new(on_token),
+        });
+        Ok(())
+    }
+
+    /// Returns the number of requests that are still generating.
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// Returns true if there are no requests left to generate.
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Run one step of generation. Prompts are fed one at a time because they are much longer than the single token every other request is waiting on, and padding the batch to the longest prompt would waste most of the forward pass. Every other request in the batch is fed together.
+    ///
+    /// If the model fails, every request in the batch is dropped and the error is returned.
+    pub fn step(&mut self, model: &M) -> anyhow::Result<()> {
+        let batch_size = self.requests.len().min(self.max_batch_size);
+        if batch_size == 0 {
+            return Ok(());
+        }
+        // Rotate the queue after each step so every request gets a turn when there are more requests than fit in a batch
+        let mut batch: Vec<_> = self.requests.drain(..batch_size).collect();
+
+        let (prompts, mut decoding): (Vec<_>, Vec<_>) = batch
+            .iter_mut()
+            .partition(|request| request.next_tokens.len() > 1);
+        for request in prompts {
+            request.draft_tokens = model.feed_tokens_speculative(
+                &mut request.session,
+                &request.next_tokens,
+                &mut request.logits,
+            )?;
+        }
+        match decoding.as_mut_slice() {
+            [] => {}
+            // A single request can't be batched, but the model may be able to speculate several tokens at once
+            [request] => {
+                request.draft_tokens = model.feed_tokens_speculative(
+                    &mut request.session,
+                    &request.next_tokens,
+                    &mut request.logits,
+                )?;
+            }
+            decoding => {
+                let mut sessions = Vec::with_capacity(decoding.len());
+                let mut tokens = Vec::with_capacity(decoding.len());
+                let mut logits = Vec::with_capacity(decoding.len());
+                for request in decoding.iter_mut() {
+                    request.draft_tokens.clear();
+                    request.logits.truncate(1);
+                    sessions.push(&mut request.session);
+                    tokens.push(request.next_tokens.as_slice());
+                    logits.push(std::mem::take(&mut request.logits[0]));
+                }
+                model.feed_tokens_batch(&mut sessions, &tokens, &mut logits)?;
+                for (request, logits) in decoding.iter_mut().zip(logits) {
+                    request.logits[0] = logits;
+                }
+            }
+        }
+
+        for mut request in batch {
+            let result = request
+                .state
+                .next_token_speculative(
+                    &request.logits,
+                    &request.draft_tokens,
+                    &mut request.on_token,
+                )
+                .and_then(|(new_token, rejected)| {
+                    model.reject_draft_tokens(&mut request.session, rejected)?;
+                    Ok(new_token)
+                });
+            match result {
+                Ok(Some(new_token)) => {
+                    request.next_tokens = vec![new_token];
+                    self.requests.push(request);
+                }
+                Ok(None) => {
+                    if let Err(err) = request.state.finish(&mut request.on_token) {
+                        tracing::error!("Failed to finish generation: {err}");
+                    }
+                }
+                // The request failed (for example because the receiver was dropped), but the rest of the batch can keep going
+                Err(err) => tracing::trace!("Stopping generation: {err}"),
+            }
+        }
+
+        Ok(())
+    }
+}
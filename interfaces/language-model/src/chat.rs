@@ -0,0 +1,61 @@
+/// The type of a chat message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MessageType {
+    /// A system prompt.
+    SystemPrompt,
+    /// A user message.
+    UserMessage,
+    /// A model answer.
+    ModelAnswer,
+}
+
+/// A single item in the chat history.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChatHistoryItem {
+    ty: MessageType,
+    contents: String,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    name: Option<String>,
+}
+
+impl ChatHistoryItem {
+    /// Creates a new chat history item.
+    pub fn new(ty: MessageType, contents: impl Into<String>) -> Self {
+        Self {
+            ty,
+            contents: contents.into(),
+            name: None,
+        }
+    }
+
+    /// Sets the name of the participant who wrote the item. Names tell apart the speakers in a chat with more than one user or assistant.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Returns the type of the item.
+    pub fn ty(&self) -> MessageType {
+        self.ty
+    }
+
+    /// Returns the name of the participant who wrote the item, if it is set.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the contents of the item.
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+
+    /// Consumes the item and returns the contents.
+    pub fn into_contents(self) -> String {
+        self.contents
+    }
+}
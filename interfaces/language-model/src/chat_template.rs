@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+
+use minijinja::{Environment, ErrorKind};
+use serde_json::{json, Value};
+
+use crate::{ChatHistoryItem, MessageType, ToolChatMessage, ToolDefinition};
+
+/// A chat template that renders a chat history into a prompt for a model.
+///
+/// Chat templates are written in [Jinja](https://jinja.palletsprojects.com/) and are usually distributed with the model, for example in the `tokenizer.chat_template` metadata of GGUF files. The template receives the same variables as chat templates in Hugging Face transformers: `messages`, `tools`, `add_generation_prompt`, `bos_token` and `eos_token`. Messages with a [`ChatHistoryItem::name`] have a `name` field.
+///
+/// # Example
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let template = ChatTemplate::new(
+///     "{% for message in messages %}<|im_start|>{{ message.role }}\n{{ message.content }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}",
+///     "",
+///     "<|im_end|>",
+/// )
+/// .unwrap();
+/// let prompt = template
+///     .render(
+///         &[ChatHistoryItem::new(MessageType::UserMessage, "Hello!")],
+///         true,
+///     )
+///     .unwrap();
+/// assert_eq!(prompt, "<|im_start|>user\nHello!<|im_end|>\n<|im_start|>assistant\n");
+/// assert_eq!(template.end_assistant_marker(), "<|im_end|>");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatTemplate {
+    source: String,
+    bos_token: String,
+    eos_token: String,
+    end_assistant_marker: String,
+}
+
+impl ChatTemplate {
+    /// Create a new chat template from the Jinja source of the template and the text of the beginning and end of sequence tokens of the model.
+    ///
+    /// Returns an error if the template can't be compiled.
+    pub fn new(
+        source: impl ToString,
+        bos_token: impl ToString,
+        eos_token: impl ToString,
+    ) -> anyhow::Result<Self> {
+        let mut template = Self {
+            source: source.to_string(),
+            bos_token: bos_token.to_string(),
+            eos_token: eos_token.to_string(),
+            end_assistant_marker: String::new(),
+        };
+        environment().template_from_str(&template.source)?;
+        template.end_assistant_marker = template.find_end_assistant_marker();
+        Ok(template)
+    }
+
+    /// Get the Jinja source of the template.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Get the text of the beginning of sequence token.
+    pub fn bos_token(&self) -> &str {
+        &self.bos_token
+    }
+
+    /// Get the text of the end of sequence token.
+    pub fn eos_token(&self) -> &str {
+        &self.eos_token
+    }
+
+    /// Get the text the template adds after an assistant message. The model generates this text when it is done with its response, so it can be used to stop generation.
+    ///
+    /// If the end of assistant messages can't be found in the template, this is the end of sequence token.
+    pub fn end_assistant_marker(&self) -> &str {
+        &self.end_assistant_marker
+    }
+
+    /// Render a chat history into a prompt. If `add_generation_prompt` is true, the text that starts a new assistant message is added to the end of the prompt.
+    ///
+    /// Many templates only accept a single system prompt at the start of the chat, or no system prompt at all. If the history doesn't render as is, system prompts are merged into one system prompt at the start of the chat, and then into the first user message.
+    pub fn render(
+        &self,
+        messages: &[ChatHistoryItem],
+        add_generation_prompt: bool,
+    ) -> anyhow::Result<String> {
+        let messages: Vec<_> = messages
+            .iter()
+            .cloned()
+            .map(ToolChatMessage::from)
+            .collect();
+        self.render_with_tools(&messages, &[], add_generation_prompt)
+    }
+
+    /// Render a chat history with tool calls and results into a prompt. The tools are passed to the template in the same format as the OpenAI function calling API.
+    ///
+    /// Tool calls are rendered as assistant messages with `tool_calls`, and tool results are rendered as messages with the `tool` role.
+    pub fn render_with_tools(
+        &self,
+        messages: &[ToolChatMessage],
+        tools: &[ToolDefinition],
+        add_generation_prompt: bool,
+    ) -> anyhow::Result<String> {
+        let tools = (!tools.is_empty()).then(|| tools.iter().map(tool_json).collect::<Vec<_>>());
+        let messages = messages_json(messages);
+
+        let mut first_error = None;
+        for layout in [
+            SystemLayout::AsIs,
+            SystemLayout::Merged,
+            SystemLayout::InFirstUserMessage,
+        ] {
+            let Some(messages) = layout.apply(&messages) else {
+                continue;
+            };
+            match self.render_json(&messages, tools.as_deref(), add_generation_prompt) {
+                Ok(prompt) => return Ok(prompt),
+                Err(err) => {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        Err(first_error.expect("the history is always rendered as is first"))
+    }
+
+    fn render_json(
+        &self,
+        messages: &[Value],
+        tools: Option<&[Value]>,
+        add_generation_prompt: bool,
+    ) -> anyhow::Result<String> {
+        let environment = environment();
+        let template = environment.template_from_str(&self.source)?;
+        let prompt = template.render(minijinja::context! {
+            messages => messages,
+            tools => tools,
+            add_generation_prompt => add_generation_prompt,
+            bos_token => &self.bos_token,
+            eos_token => &self.eos_token,
+        })?;
+        Ok(prompt)
+    }
+
+    /// Find the text after an assistant message by rendering a short chat with and without the answer
+    fn find_end_assistant_marker(&self) -> String {
+        const ANSWER: &str = "kalosm-answer";
+        let question = ChatHistoryItem::new(MessageType::UserMessage, "kalosm-question");
+        let answer = ChatHistoryItem::new(MessageType::ModelAnswer, ANSWER);
+        let (Ok(prompt), Ok(chat)) = (
+            self.render(std::slice::from_ref(&question), true),
+            self.render(&[question, answer], false),
+        ) else {
+            return self.eos_token.clone();
+        };
+        match chat
+            .strip_prefix(&prompt)
+            .and_then(|rest| rest.strip_prefix(ANSWER))
+            .map(str::trim)
+        {
+            Some(marker) if !marker.is_empty() => marker.to_string(),
+            _ => self.eos_token.clone(),
+        }
+    }
+}
+
+/// Create an environment that behaves like the Jinja environment Hugging Face transformers renders chat templates with
+fn environment<'source>() -> Environment<'source> {
+    let mut environment = Environment::new();
+    environment.set_trim_blocks(true);
+    environment.set_lstrip_blocks(true);
+    // Templates are written for Python Jinja and often call Python string methods like `strip` or `startswith`
+    environment.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    environment.add_function("raise_exception", |message: String| {
+        Err::<String, _>(minijinja::Error::new(ErrorKind::InvalidOperation, message))
+    });
+    environment
+}
+
+/// How system prompts are laid out when the chat is rendered
+#[derive(Clone, Copy)]
+enum SystemLayout {
+    /// Keep every message where it is
+    AsIs,
+    /// Merge every system prompt into one system prompt at the start of the chat
+    Merged,
+    /// Merge every system prompt into the start of the first user message
+    InFirstUserMessage,
+}
+
+impl SystemLayout {
+    /// Lay out the messages. Returns `None` if the layout doesn't change the messages
+    fn apply(self, messages: &[Value]) -> Option<Vec<Value>> {
+        let is_system = |message: &Value| message["role"] == "system";
+        let system_prompts: Vec<&str> = messages
+            .iter()
+            .filter(|message| is_system(message))
+            .filter_map(|message| message["content"].as_str())
+            .collect();
+        let others = messages
+            .iter()
+            .filter(|message| !is_system(message))
+            .cloned();
+
+        match self {
+            SystemLayout::AsIs => Some(messages.to_vec()),
+            SystemLayout::Merged => {
+                let already_merged = messages.iter().skip(1).all(|message| !is_system(message));
+                if system_prompts.is_empty() || already_merged {
+                    return None;
+                }
+                let system = json!({ "role": "system", "content": system_prompts.join("\n\n") });
+                Some(std::iter::once(system).chain(others).collect())
+            }
+            SystemLayout::InFirstUserMessage => {
+                if system_prompts.is_empty() {
+                    return None;
+                }
+                let mut messages: Vec<_> = others.collect();
+                let first_user = messages
+                    .iter_mut()
+                    .find(|message| message["role"] == "user")?;
+                let content = first_user["content"].as_str().unwrap_or_default();
+                first_user["content"] =
+                    format!("{}\n\n{content}", system_prompts.join("\n\n")).into();
+                Some(messages)
+            }
+        }
+    }
+}
+
+/// Convert messages to the format chat templates expect
+fn messages_json(messages: &[ToolChatMessage]) -> Vec<Value> {
+    // Some templates need the name of the tool with the result of a call
+    let mut tool_names = HashMap::new();
+    messages
+        .iter()
+        .map(|message| match message {
+            ToolChatMessage::Chat(item) => {
+                let role = match item.ty() {
+                    MessageType::SystemPrompt => "system",
+                    MessageType::UserMessage => "user",
+                    MessageType::ModelAnswer => "assistant",
+                };
+                let mut message = json!({ "role": role, "content": item.contents() });
+                if let Some(name) = item.name() {
+                    message["name"] = name.into();
+                }
+                message
+            }
+            ToolChatMessage::ToolCalls(calls) => {
+                let calls: Vec<_> = calls
+                    .iter()
+                    .map(|call| {
+                        tool_names.insert(call.id(), call.name());
+                        // Templates usually format the arguments with `tojson`, so pass them as JSON if they are valid JSON
+                        let arguments = serde_json::from_str::<Value>(call.arguments())
+                            .unwrap_or_else(|_| call.arguments().into());
+                        json!({
+                            "id": call.id(),
+                            "type": "function",
+                            "function": { "name": call.name(), "arguments": arguments },
+                        })
+                    })
+                    .collect();
+                json!({ "role": "assistant", "content": "", "tool_calls": calls })
+            }
+            ToolChatMessage::ToolResult { id, output } => json!({
+                "role": "tool",
+                "tool_call_id": id,
+                "name": tool_names.get(id.as_str()),
+                "content": output,
+            }),
+        })
+        .collect()
+}
+
+/// Convert a tool definition to the format chat templates expect
+fn tool_json(tool: &ToolDefinition) -> Value {
+    let parameters = serde_json::from_str::<Value>(&tool.parameters().to_string())
+        .unwrap_or_else(|_| json!({ "type": "object" }));
+    json!({
+        "type": "function",
+        "function": {
+            "name": tool.name(),
+            "description": tool.description(),
+            "parameters": parameters,
+        },
+    })
+}
+
+#[test]
+fn system_prompts_fall_back_to_layouts_the_template_accepts() {
+    let system = |text| ChatHistoryItem::new(MessageType::SystemPrompt, text);
+    let user = |text| ChatHistoryItem::new(MessageType::UserMessage, text);
+
+    // Only accepts a system prompt at the start of the chat
+    let one_system_prompt = ChatTemplate::new(
+        "{% for message in messages %}{% if message.role == 'system' and not loop.first %}{{ raise_exception('System prompts must be first') }}{% endif %}[{{ message.role }}] {{ message.content }}\n{% endfor %}",
+        "",
+        "",
+    )
+    .unwrap();
+    assert_eq!(
+        one_system_prompt
+            .render(&[system("first"), user("question")], false)
+            .unwrap(),
+        "[system] first\n[user] question\n"
+    );
+    assert_eq!(
+        one_system_prompt
+            .render(
+                &[system("first"), user("question"), system("second")],
+                false
+            )
+            .unwrap(),
+        "[system] first\n\nsecond\n[user] question\n"
+    );
+
+    // Doesn't accept system prompts at all
+    let no_system_prompt = ChatTemplate::new(
+        "{% for message in messages %}{% if message.role == 'system' %}{{ raise_exception('System prompts are not supported') }}{% endif %}[{{ message.role }}] {{ message.content }}\n{% endfor %}",
+        "",
+        "",
+    )
+    .unwrap();
+    assert_eq!(
+        no_system_prompt
+            .render(
+                &[system("first"), user("question"), system("second")],
+                false
+            )
+            .unwrap(),
+        "[user] first\n\nsecond\n\nquestion\n"
+    );
+    // Without a user message to merge into, the error from rendering the history as is is returned
+    let error = no_system_prompt
+        .render(&[system("first")], false)
+        .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("System prompts are not supported"));
+}
+
+#[test]
+fn end_assistant_marker_is_found_from_the_template() {
+    let chat_ml = ChatTemplate::new(
+        "{% for message in messages %}<|im_start|>{{ message.role }}\n{{ message.content }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}",
+        "<s>",
+        "</s>",
+    )
+    .unwrap();
+    assert_eq!(chat_ml.end_assistant_marker(), "<|im_end|>");
+
+    let eos_after_answer = ChatTemplate::new(
+        "{% for message in messages %}{% if message.role == 'user' %}[INST] {{ message.content }} [/INST]{% else %}{{ message.content }}{{ eos_token }}{% endif %}{% endfor %}",
+        "<s>",
+        "</s>",
+    )
+    .unwrap();
+    assert_eq!(eos_after_answer.end_assistant_marker(), "</s>");
+
+    // Nothing marks the end of an answer, so the end of sequence token is used
+    let no_marker = ChatTemplate::new(
+        "{% for message in messages %}{{ message.content }}\n{% endfor %}",
+        "<s>",
+        "</s>",
+    )
+    .unwrap();
+    assert_eq!(no_marker.end_assistant_marker(), "</s>");
+
+    // The template can't render a chat with an answer, so the end of sequence token is used
+    let no_answers = ChatTemplate::new(
+        "{% for message in messages %}{% if message.role == 'assistant' %}{{ raise_exception('No answers') }}{% endif %}{{ message.content }}<|end|>{% endfor %}",
+        "<s>",
+        "</s>",
+    )
+    .unwrap();
+    assert_eq!(no_answers.end_assistant_marker(), "</s>");
+}
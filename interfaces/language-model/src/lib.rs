@@ -30,11 +30,23 @@ mod remote;
 #[cfg(feature = "remote")]
 pub use remote::*;
 
+mod batch;
+pub use batch::*;
+mod chat;
+pub use chat::*;
+mod chat_template;
+pub use chat_template::*;
+mod logprobs;
+pub use logprobs::*;
+mod search;
+pub use search::SearchStrategy;
 mod structured;
 mod token_stream;
 pub use token_stream::*;
 
 mod embedding;
 pub use embedding::*;
+mod tool;
+pub use tool::*;
 mod model;
 pub use model::*;
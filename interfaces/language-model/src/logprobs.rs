@@ -0,0 +1,175 @@
+use futures_util::Stream;
+use std::pin::Pin;
+use tokenizers::tokenizer::Tokenizer;
+
+/// A stream of [`TokenEvent`]s that ends with an error if the model fails to finish generating. See [`ModelExt::stream_text_with_logprobs`](crate::ModelExt::stream_text_with_logprobs).
+pub type TokenEventStream = Pin<Box<dyn Stream<Item = anyhow::Result<TokenEvent>> + Send>>;
+
+/// A chunk of text a model generated along with the tokens that produced it and how likely the model thought they were.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenEvent {
+    /// The text that was added to the output. This may be empty if the token only completes part of a character or part of a stop sequence.
+    pub text: String,
+    /// The tokens that produced the text. A sampled token produces one event, but text that is forced by the constraints in structured generation may span several tokens.
+    pub tokens: Vec<u32>,
+    /// The log probability the model assigned to the sampled token before the sampler was applied. This is `None` if the text was forced by the constraints instead of sampled.
+    pub logprob: Option<f32>,
+    /// The most likely tokens the model could have generated instead, sorted from most to least likely. This is empty if the text was forced by the constraints.
+    pub top_logprobs: Vec<TokenLogprob>,
+}
+
+impl TokenEvent {
+    /// Create an event for text that was forced instead of sampled.
+    pub(crate) fn forced(text: String, tokens: Vec<u32>) -> Self {
+        Self {
+            text,
+            tokens,
+            logprob: None,
+            top_logprobs: Vec::new(),
+        }
+    }
+
+    /// Create an event for a sampled token with the logits the model produced for it. Returns an event with only the text and token if `top_logprobs` is `None`.
+    pub(crate) fn sampled(
+        text: String,
+        token: u32,
+        logits: &[f32],
+        top_logprobs: Option<usize>,
+        tokenizer: &Tokenizer,
+    ) -> Self {
+        let Some(top_logprobs) = top_logprobs else {
+            return Self::forced(text, vec![token]);
+        };
+        let (logprob, top) = log_softmax_top_k(logits, token, top_logprobs);
+        Self {
+            text,
+            tokens: vec![token],
+            logprob,
+            top_logprobs: top
+                .into_iter()
+                .map(|(token, logprob)| TokenLogprob {
+                    token,
+                    text: tokenizer.decode(&[token], false).unwrap_or_default(),
+                    logprob,
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the probability of the sampled token between 0 and 1 if the token was sampled.
+    pub fn probability(&self) -> Option<f32> {
+        self.logprob.map(f32::exp)
+    }
+}
+
+/// A token the model could have generated with its log probability.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogprob {
+    /// The id of the token.
+    pub token: u32,
+    /// The text of the token on its own.
+    pub text: String,
+    /// The log probability the model assigned to the token.
+    pub logprob: f32,
+}
+
+/// Calculate the perplexity of a model over the sampled tokens in a list of events. Text that was forced by constraints is skipped. Lower perplexity means the model was more confident in the text.
+///
+/// Returns `None` if none of the events have a log probability.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use kalosm_language_model::Model;
+/// use std::sync::{Arc, Mutex};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new().await.unwrap();
+///
+///     llm.run_sync(move |llm: &mut <Llama as Model>::SyncModel| {
+///         Box::pin(async move {
+///             let mut session = llm.new_session().unwrap();
+///             let sampler = Arc::new(Mutex::new(GenerationParameters::default().sampler()));
+///             let mut events = Vec::new();
+///             llm.stream_text_with_logprobs(
+///                 &mut session,
+///                 "The capital of France is",
+///                 Some(10),
+///                 None,
+///                 sampler,
+///                 5,
+///                 |event| {
+///                     events.push(event);
+///                     Ok(ModelFeedback::Continue)
+///                 },
+///             )
+///             .unwrap();
+///
+///             println!("perplexity: {:?}", perplexity(&events));
+///         })
+///     })
+///     .unwrap();
+/// }
+/// ```
+pub fn perplexity<'a>(events: impl IntoIterator<Item = &'a TokenEvent>) -> Option<f32> {
+    let (sum, count) = events
+        .into_iter()
+        .filter_map(|event| event.logprob)
+        .fold((0., 0), |(sum, count), logprob| (sum + logprob, count + 1));
+    (count > 0).then(|| (-sum / count as f32).exp())
+}
+
+/// Get the log of the sum of the exponentials of the logits. Subtracting this from a logit gives the log probability of the token
+pub(crate) fn log_sum_exp(logits: &[f32]) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    logits
+        .iter()
+        .map(|logit| (logit - max).exp())
+        .sum::<f32>()
+        .ln()
+        + max
+}
+
+/// Get the log probability of a token and the `k` most likely tokens from the raw logits of a model.
+fn log_softmax_top_k(logits: &[f32], token: u32, k: usize) -> (Option<f32>, Vec<(u32, f32)>) {
+    let log_sum_exp = log_sum_exp(logits);
+    let logprob = logits.get(token as usize).map(|logit| logit - log_sum_exp);
+
+    let mut top: Vec<_> = (0..logits.len() as u32).collect();
+    let k = k.min(top.len());
+    let by_logit = |a: &u32, b: &u32| logits[*b as usize].total_cmp(&logits[*a as usize]);
+    if k < top.len() {
+        top.select_nth_unstable_by(k, by_logit);
+        top.truncate(k);
+    }
+    top.sort_unstable_by(by_logit);
+    let top = top
+        .into_iter()
+        .map(|token| (token, logits[token as usize] - log_sum_exp))
+        .collect();
+
+    (logprob, top)
+}
+
+#[test]
+fn log_softmax_of_logits() {
+    let logits = [1f32.ln(), 2f32.ln(), 4f32.ln(), 1f32.ln()];
+    let (logprob, top) = log_softmax_top_k(&logits, 1, 2);
+    assert!((logprob.unwrap() - 0.25f32.ln()).abs() < 1e-6);
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[0].0, 2);
+    assert!((top[0].1 - 0.5f32.ln()).abs() < 1e-6);
+    assert_eq!(top[1].0, 1);
+
+    let events = [0.5f32, 0.25]
+        .map(|probability| TokenEvent {
+            logprob: Some(probability.ln()),
+            ..TokenEvent::forced(String::new(), Vec::new())
+        })
+        .into_iter()
+        .chain([TokenEvent::forced("forced".to_string(), vec![0])])
+        .collect::<Vec<_>>();
+    // The geometric mean of the probabilities is sqrt(1/8), and forced text is skipped
+    assert!((perplexity(&events).unwrap() - 8f32.sqrt()).abs() < 1e-5);
+}
@@ -1,15 +1,23 @@
-use crate::structured::generate_structured;
-use crate::TokenOutputStream;
+use crate::search::generate_structured_with_search;
+use crate::structured::{generate_structured, parse_json_stream};
+use crate::SearchStrategy;
+use crate::{
+    ChatHistoryItem, ChatTemplate, MessageType, ToolChatMessage, ToolChatResponse, ToolDefinition,
+};
+use crate::{TextGenerationState, TokenEvent, TokenEventStream};
 use futures_util::{Future, FutureExt};
 use futures_util::{Stream, StreamExt};
 use kalosm_common::*;
 use kalosm_sample::StopOn;
-use kalosm_sample::{CreateParserState, Parse};
+use kalosm_sample::{parse_partial_json, CreateParserState, Parse, Schema, SchemaType};
 use kalosm_sample::{LiteralParser, Parser};
 use kalosm_streams::text_stream::ChannelTextStream;
-use llm_samplers::configure::SamplerChainBuilder;
 use llm_samplers::prelude::*;
+use llm_samplers::types::{HasSamplerResources, SamplerError};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::any::Any;
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::future::IntoFuture;
 use std::path::Path;
@@ -160,10 +168,23 @@ impl<'a, M: Model> IntoFuture for StreamTextBuilder<'a, M> {
             parameters,
             future,
         } = self;
+        if let Err(err) = check_unstructured_search_strategy(&parameters) {
+            return Box::pin(std::future::ready(Err(err)));
+        }
         future(self_, prompt, parameters)
     }
 }
 
+/// Search strategies only pick between outputs of structured generation, so unstructured generation rejects every strategy other than [`SearchStrategy::Sample`] instead of silently ignoring it.
+fn check_unstructured_search_strategy(parameters: &GenerationParameters) -> anyhow::Result<()> {
+    match parameters.search_strategy() {
+        SearchStrategy::Sample => Ok(()),
+        search => Err(anyhow::anyhow!(
+            "{search:?} is only supported for structured generation. Use ModelExt::generate_parsed_with_parameters instead"
+        )),
+    }
+}
+
 /// A builder for the [`ModelExt::generate_text`] method.
 #[allow(clippy::type_complexity)]
 pub struct GenerateTextBuilder<'a, M: Model> {
@@ -266,6 +287,9 @@ impl<'a, M: Model> IntoFuture for GenerateTextBuilder<'a, M> {
             parameters,
             future,
         } = self;
+        if let Err(err) = check_unstructured_search_strategy(&parameters) {
+            return Box::pin(std::future::ready(Err(err)));
+        }
         future(self_, prompt, parameters)
     }
 }
@@ -329,6 +353,67 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         })
     }
 
+    /// Stream text with the given prompt like [`ModelExt::stream_text`], but with a [`TokenEvent`] for every generated token. Each event includes the log probability the model assigned to the token and the `top_logprobs` most likely alternatives.
+    ///
+    /// The stream ends with an error if generation fails. This requires a local model that supports [`ModelExt::run_sync`]. Like [`ModelExt::stream_text`], this returns an error for any [`SearchStrategy`] other than [`SearchStrategy::Sample`].
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let model = Llama::new().await.unwrap();
+    ///     let mut events = model
+    ///         .stream_text_with_logprobs("The capital of France is", GenerationParameters::default(), 5)
+    ///         .unwrap();
+    ///
+    ///     while let Some(event) = events.next().await {
+    ///         let event = event.unwrap();
+    ///         println!("{:?} ({:?})", event.text, event.probability());
+    ///     }
+    /// }
+    /// ```
+    fn stream_text_with_logprobs(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+        top_logprobs: usize,
+    ) -> anyhow::Result<TokenEventStream> {
+        check_unstructured_search_strategy(&parameters)?;
+        let max_tokens = parameters.max_length();
+        let stop_on = parameters.stop_on().map(ToString::to_string);
+        let sampler = parameters.sampler_with_tokenizer(&self.tokenizer())?;
+        let sampler: Arc<Mutex<dyn Sampler>> = Arc::new(Mutex::new(sampler));
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let prompt = prompt.to_string();
+        self.run_sync(move |llm: &mut Self::SyncModel| {
+            Box::pin(async move {
+                let result = llm.new_session().and_then(|mut session| {
+                    llm.stream_text_with_logprobs(
+                        &mut session,
+                        &prompt,
+                        Some(max_tokens),
+                        stop_on.as_deref(),
+                        sampler,
+                        top_logprobs,
+                        // Stop generating once the stream is dropped
+                        |event| match sender.send(Ok(event)) {
+                            Ok(()) => Ok(ModelFeedback::Continue),
+                            Err(_) => Ok(ModelFeedback::Stop),
+                        },
+                    )
+                });
+                if let Err(err) = result {
+                    _ = sender.send(Err(err));
+                }
+            })
+        })?;
+
+        Ok(Box::pin(futures_util::stream::poll_fn(move |cx| {
+            receiver.poll_recv(cx)
+        })))
+    }
+
     /// Run some code synchronously with the model.
     ///
     /// # Example
@@ -393,6 +478,8 @@ pub trait ModelExt: Model + Send + Sync + 'static {
     /// # Ok(())
     /// # }
     /// ```
+    ///
+    /// Models that generate JSON natively (see [`Model::supports_json_schema`]) generate any JSON value and the response is validated with the parser. Use [`ModelExt::generate_parsed_with_schema`] for types that also implement [`Schema`] to send the schema to the model as well.
     fn generate_parsed<P: Parse + 'static>(
         &self,
         prompt: &str,
@@ -403,6 +490,102 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         self.stream_structured_text(prompt, P::new_parser())
     }
 
+    /// Generate a type that implements [`Parse`] with the given prompt and generation parameters. The parameters control the sampler and the [`SearchStrategy`] used to pick the output.
+    ///
+    /// Models that generate JSON natively (see [`Model::supports_json_schema`]) receive the parameters with the request. They can't search over outputs, so any [`SearchStrategy`] other than [`SearchStrategy::Sample`] returns an error.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[derive(Parse, Clone, Debug)]
+    /// enum Size {
+    ///     Small,
+    ///     Medium,
+    ///     Large,
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let llm = Llama::new().await?;
+    /// // Keep the 3 most likely outputs at every step instead of committing to the first sampled token
+    /// let parameters = GenerationParameters::default()
+    ///     .with_search_strategy(SearchStrategy::BeamSearch {
+    ///         width: 3,
+    ///         max_length: 8,
+    ///     });
+    /// let size: Size = llm
+    ///     .generate_parsed_with_parameters("An elephant is ", parameters)
+    ///     .await?;
+    /// println!("{size:?}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_parsed_with_parameters<P: Parse + 'static>(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+    ) -> StructureParserResult<Self::TextStream, P>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    {
+        stream_structured_text_with_parameters(self, prompt, None, P::new_parser(), parameters)
+    }
+
+    /// Generate a type that implements [`Parse`] and [`Schema`] with the given prompt.
+    ///
+    /// Remote models that support JSON schemas receive the schema from [`Schema::schema`] and the response is validated locally with the type's parser. Local models use the parser to constrain generation like [`ModelExt::generate_parsed`]. Use [`ModelExt::stream_structured_text_with_schema`] to set the generation parameters.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[derive(Parse, Schema, Clone, Debug)]
+    /// struct Account {
+    ///     username: String,
+    ///     age: u8,
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let llm = Gpt4Mini::default();
+    /// let prompt = "An account with a random realistic username and age";
+    ///
+    /// let account: Account = llm.generate_parsed_with_schema(prompt).await?;
+    /// println!("{:#?}", account);
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn generate_parsed_with_schema<P: Parse + Schema + 'static>(
+        &self,
+        prompt: &str,
+    ) -> StructureParserResult<Self::TextStream, P>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    {
+        self.stream_structured_text_with_schema(
+            prompt,
+            P::schema(),
+            P::new_parser(),
+            GenerationParameters::default(),
+        )
+    }
+
+    /// Generate structured text that follows both a JSON schema and the constraints with the given generation parameters. If the model supports JSON schemas (see [`Model::supports_json_schema`]), the schema and the parameters are sent to the model and the response is validated with the constraints once it finishes. Otherwise, this is the same as [`ModelExt::generate_parsed_with_parameters`] with the constraints.
+    fn stream_structured_text_with_schema<P>(
+        &self,
+        prompt: &str,
+        schema: SchemaType,
+        parser: P,
+        parameters: GenerationParameters,
+    ) -> StructureParserResult<Self::TextStream, P::Output>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+        P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
+    {
+        stream_structured_text_with_parameters(self, prompt, Some(&schema), parser, parameters)
+    }
+
     /// Generate structured text with the given prompt and constraints.
     ///
     /// # Example
@@ -432,6 +615,16 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
         P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
     {
+        if self.supports_json_schema() {
+            return stream_structured_text_with_native_schema(
+                self,
+                prompt,
+                None,
+                parser,
+                GenerationParameters::default(),
+            );
+        }
+
         let sampler = Arc::new(Mutex::new(GenerationParameters::default().sampler()));
         let parser_state = parser.create_parser_state();
         self.stream_structured_text_with_sampler(prompt, parser, parser_state, sampler)
@@ -449,6 +642,39 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
         P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
     {
+        self.stream_structured_text_with_search(
+            prompt,
+            parser,
+            parser_state,
+            sampler,
+            SearchStrategy::Sample,
+        )
+    }
+
+    /// Generate structured text with the given prompt, sampler and [`SearchStrategy`]. See [`ModelExt::stream_structured_text`] for more information.
+    ///
+    /// Beam search and best-of-N sampling only stream the text once the search finishes.
+    ///
+    /// A sampler can't be sent to models that generate JSON natively (see [`Model::supports_json_schema`]), so this returns an error for them. Use [`ModelExt::generate_parsed_with_parameters`] or [`ModelExt::stream_structured_text_with_schema`] to send [`GenerationParameters`] instead.
+    fn stream_structured_text_with_search<P>(
+        &self,
+        prompt: &str,
+        parser: P,
+        parser_state: P::PartialState,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        search: SearchStrategy,
+    ) -> StructureParserResult<Self::TextStream, P::Output>
+    where
+        Self::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+        P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
+    {
+        // Models without a local sync model can't sample with the sampler, so returning an error is better than silently ignoring it
+        if self.supports_json_schema() {
+            return failed_structured_text(anyhow::anyhow!(
+                "Models that generate JSON natively can't use a custom sampler or search strategy. Use generate_parsed_with_parameters or stream_structured_text_with_schema to send generation parameters instead"
+            ));
+        }
+
         let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
         let (result_sender, result_receiver) = tokio::sync::oneshot::channel();
 
@@ -458,14 +684,15 @@ pub trait ModelExt: Model + Send + Sync + 'static {
         if let Err(err) = self.run_sync(move |llm: &mut Self::SyncModel| {
             let mut session = llm.new_session().unwrap();
             Box::pin(async move {
-                let result = llm.generate_structured(
+                let result = llm.generate_structured_with_search(
                     &mut session,
                     prompt,
                     parser,
                     parser_state,
                     sampler,
-                    |token| Ok(sender.send(token)?),
+                    |event| Ok(sender.send(event.text)?),
                     Some(64),
+                    search,
                 );
                 if let Some(sender) = result_sender.lock().unwrap().take() {
                     _ = sender.send(result);
@@ -495,6 +722,80 @@ pub trait ModelExt: Model + Send + Sync + 'static {
     }
 }
 
+/// Generate structured text with the generation parameters. Models that generate JSON natively receive the parameters with the request, and other models build a sampler from them.
+fn stream_structured_text_with_parameters<M, P>(
+    model: &M,
+    prompt: &str,
+    schema: Option<&SchemaType>,
+    parser: P,
+    parameters: GenerationParameters,
+) -> StructureParserResult<M::TextStream, P::Output>
+where
+    M: ModelExt + ?Sized,
+    M::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    P: CreateParserState<PartialState: Send, Output: Send> + Send + 'static,
+{
+    if model.supports_json_schema() {
+        // Models that generate JSON natively only generate one response, so they can't search over outputs
+        if parameters.search_strategy() != SearchStrategy::Sample {
+            return failed_structured_text(anyhow::anyhow!(
+                "Models that generate JSON natively only support SearchStrategy::Sample"
+            ));
+        }
+        return stream_structured_text_with_native_schema(
+            model, prompt, schema, parser, parameters,
+        );
+    }
+    let parser_state = parser.create_parser_state();
+    let search = parameters.search_strategy();
+    // Only tokenize text biases if there are any because remote models don't have a tokenizer
+    let sampler = if parameters.logit_bias().text().is_empty() {
+        parameters.sampler()
+    } else {
+        match parameters.sampler_with_tokenizer(&model.tokenizer()) {
+            Ok(sampler) => sampler,
+            Err(err) => return failed_structured_text(err),
+        }
+    };
+    let sampler = Arc::new(Mutex::new(sampler));
+    model.stream_structured_text_with_search(prompt, parser, parser_state, sampler, search)
+}
+
+/// Create a structured result that fails with the error without generating any text.
+fn failed_structured_text<S, O>(err: anyhow::Error) -> StructureParserResult<S, O>
+where
+    S: Stream<Item = String> + Send + Unpin + 'static,
+    S: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+{
+    let (_, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (result_sender, result_receiver) = tokio::sync::oneshot::channel();
+    _ = result_sender.send(Err(err));
+    StructureParserResult::new(S::from(receiver), result_receiver)
+}
+
+/// Generate JSON with the model's native schema support and validate the response with the parser once it finishes.
+fn stream_structured_text_with_native_schema<M, P>(
+    model: &M,
+    prompt: &str,
+    schema: Option<&SchemaType>,
+    parser: P,
+    parameters: GenerationParameters,
+) -> StructureParserResult<M::TextStream, P::Output>
+where
+    M: Model + ?Sized,
+    M::TextStream: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+    P: CreateParserState<Output: Send> + Send + 'static,
+{
+    let messages = [ChatHistoryItem::new(MessageType::UserMessage, prompt)];
+    match model.stream_chat_with_schema_inner(&messages, schema, parameters) {
+        Ok(stream) => StructureParserResult::from_json_stream(stream, schema, parser),
+        Err(err) => failed_structured_text(err),
+    }
+}
+
+/// A stream of text that ends with an error if the model fails to finish the response.
+pub type FallibleTextStream = Pin<Box<dyn Stream<Item = anyhow::Result<String>> + Send>>;
+
 /// The result of a structured parser stream.
 pub struct StructureParserResult<S: Stream<Item = String> + Send + Unpin + 'static, O> {
     stream: S,
@@ -507,6 +808,23 @@ impl<S: Stream<Item = String> + Send + Unpin + 'static, O> StructureParserResult
         Self { stream, result }
     }
 
+    /// Create a structured parser result from a stream of JSON text. The text is forwarded as it arrives and parsed with the parser once the stream finishes. If the stream ends with an error, the result is that error.
+    ///
+    /// This is useful for models that generate JSON with a schema (see [`Model::stream_chat_with_schema_inner`]) instead of constraining generation with the parser. If the schema is known, object keys may be in any order and properties that can be null may be left out of the response.
+    pub fn from_json_stream<P>(
+        stream: impl Stream<Item = anyhow::Result<String>> + Send + Unpin + 'static,
+        schema: Option<&SchemaType>,
+        parser: P,
+    ) -> Self
+    where
+        S: From<tokio::sync::mpsc::UnboundedReceiver<String>>,
+        P: CreateParserState<Output = O> + Send + 'static,
+        O: Send + 'static,
+    {
+        let (receiver, result_receiver) = parse_json_stream(stream, schema, parser);
+        Self::new(S::from(receiver), result_receiver)
+    }
+
     /// Get the final result of the structured parser.
     pub async fn result(self) -> anyhow::Result<O> {
         self.result.await?
@@ -526,6 +844,44 @@ impl<S: Stream<Item = String> + Send + Unpin + 'static, O> StructureParserResult
     pub fn split(self) -> (S, tokio::sync::oneshot::Receiver<anyhow::Result<O>>) {
         (self.stream, self.result)
     }
+
+    /// Split the stream into a stream of snapshots of the partially generated value and the final result.
+    ///
+    /// Every time a token is generated, the text generated so far is read with [`parse_partial_json`]. The stream yields a new snapshot whenever it changes. This works with any parser that generates JSON, including the parsers derived with [`Parse`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[derive(Parse, Clone, Debug)]
+    /// struct Account {
+    ///     username: String,
+    ///     age: u8,
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let llm = Llama::new().await?;
+    /// let prompt = "An account with a random realistic username and age in JSON format: ";
+    ///
+    /// let (mut snapshots, result) = llm.generate_parsed::<Account>(prompt).split_partial_json();
+    /// while let Some(snapshot) = snapshots.next().await {
+    ///     // Render the fields that have been filled in so far
+    ///     println!("username: {}", snapshot["username"].as_str().unwrap_or("..."));
+    /// }
+    /// let account = result.await??;
+    /// println!("{account:#?}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn split_partial_json(
+        self,
+    ) -> (
+        PartialJsonStream<S>,
+        tokio::sync::oneshot::Receiver<anyhow::Result<O>>,
+    ) {
+        (PartialJsonStream::new(self.stream), self.result)
+    }
 }
 
 impl<S: Stream<Item = String> + Send + Unpin + 'static, O> Future for StructureParserResult<S, O> {
@@ -552,6 +908,52 @@ impl<S: Stream<Item = String> + Send + Unpin + 'static, O> Stream for StructureP
     }
 }
 
+/// A stream of snapshots of a JSON value that is still being generated. Created with [`StructureParserResult::split_partial_json`].
+pub struct PartialJsonStream<S> {
+    stream: S,
+    text: String,
+    last_snapshot: Option<serde_json::Value>,
+}
+
+impl<S> PartialJsonStream<S> {
+    /// Create a new stream of snapshots from a stream of the JSON text as it is generated.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            text: String::new(),
+            last_snapshot: None,
+        }
+    }
+
+    /// Get the text that has been generated so far.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl<S: Stream<Item = String> + Unpin> Stream for PartialJsonStream<S> {
+    type Item = serde_json::Value;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let Some(token) = futures_util::ready!(this.stream.poll_next_unpin(cx)) else {
+                return std::task::Poll::Ready(None);
+            };
+            this.text.push_str(&token);
+            if let Some(snapshot) = parse_partial_json(&this.text) {
+                if this.last_snapshot.as_ref() != Some(&snapshot) {
+                    this.last_snapshot = Some(snapshot.clone());
+                    return std::task::Poll::Ready(Some(snapshot));
+                }
+            }
+        }
+    }
+}
+
 impl<M: Model + Send + Sync + 'static> ModelExt for M {}
 
 /// A raw interface for a model that can be used to generate text synchronously. This provides a very low level interface to a model's session:
@@ -607,6 +1009,58 @@ pub trait SyncModel {
         into: &mut Vec<f32>,
     ) -> anyhow::Result<()>;
 
+    /// Run the model synchronously on a batch of sessions, each with its own pre-tokenized input. The logits for each session are written into the matching entry of `into`.
+    ///
+    /// The default implementation feeds each session one at a time. Models that can run several sequences in one forward pass should override this.
+    fn feed_tokens_batch(
+        &self,
+        sessions: &mut [&mut Self::Session],
+        tokens: &[&[u32]],
+        into: &mut [Vec<f32>],
+    ) -> anyhow::Result<()> {
+        if sessions.len() != tokens.len() || tokens.len() != into.len() {
+            anyhow::bail!(
+                "expected the same number of sessions, inputs and outputs, found {}, {} and {}",
+                sessions.len(),
+                tokens.len(),
+                into.len()
+            );
+        }
+        for ((session, tokens), into) in sessions.iter_mut().zip(tokens).zip(into) {
+            self.feed_tokens(session, tokens, into)?;
+        }
+        Ok(())
+    }
+
+    /// Run the model synchronously with a pre-tokenized input, and let the model speculatively continue the sequence with draft tokens it predicts cheaply (for example with a smaller draft model). Returns the draft tokens that were fed after `tokens`.
+    ///
+    /// `into` is filled with one set of logits for each position: first the logits after `tokens`, then the logits after each draft token. Any draft tokens that don't match the tokens the caller samples must be removed with [`SyncModel::reject_draft_tokens`] before the session is fed again.
+    ///
+    /// The default implementation doesn't draft any tokens.
+    fn feed_tokens_speculative(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<Vec<u32>> {
+        into.resize_with(1, Vec::new);
+        self.feed_tokens(session, tokens, &mut into[0])?;
+        Ok(Vec::new())
+    }
+
+    /// Remove the last `count` draft tokens fed by [`SyncModel::feed_tokens_speculative`] from the session.
+    fn reject_draft_tokens(
+        &self,
+        _session: &mut Self::Session,
+        count: usize,
+    ) -> anyhow::Result<()> {
+        if count == 0 {
+            Ok(())
+        } else {
+            Err(anyhow::Error::msg("This model does not draft tokens"))
+        }
+    }
+
     /// Get the token ID that represents the end of a sequence.
     fn stop_token(&self) -> anyhow::Result<u32>;
 
@@ -634,6 +1088,22 @@ pub trait Session {
         &[]
     }
 
+    /// Remove every token after the first `len` tokens from the session. This rewinds the session to an earlier point (for example to regenerate the last answer or edit an earlier message) without feeding the whole prompt again.
+    fn truncate(&mut self, _len: usize) -> anyhow::Result<()> {
+        Err(anyhow::Error::msg("Not implemented"))
+    }
+
+    /// Remove the last `count` tokens from the session.
+    fn rollback(&mut self, count: usize) -> anyhow::Result<()> {
+        let token_count = self.tokens().len();
+        if count > token_count {
+            anyhow::bail!(
+                "Cannot roll back {count} tokens from a session with {token_count} tokens"
+            );
+        }
+        self.truncate(token_count - count)
+    }
+
     /// Try to clone the session.
     fn try_clone(&self) -> anyhow::Result<Self>
     where
@@ -651,6 +1121,10 @@ impl Session for () {
     fn load_from(_path: impl AsRef<Path>) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn truncate(&mut self, _len: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 /// An extension trait for sync models.
@@ -664,7 +1138,7 @@ pub trait SyncModelExt: SyncModel {
         parser: P,
         parser_state: P::PartialState,
         sampler: Arc<Mutex<dyn Sampler>>,
-        on_token: impl FnMut(String) -> anyhow::Result<()>,
+        mut on_token: impl FnMut(String) -> anyhow::Result<()>,
         top_k: Option<usize>,
     ) -> anyhow::Result<P::Output> {
         generate_structured(
@@ -674,117 +1148,153 @@ pub trait SyncModelExt: SyncModel {
             parser,
             parser_state,
             sampler,
-            on_token,
+            |event| on_token(event.text),
             top_k,
+            None,
         )
     }
 
+    /// Generate new text with the given prompt that conforms to the given parser. Like [`SyncModelExt::generate_structured`], but calls `on_token` with a [`TokenEvent`] that includes the log probability of every sampled token and the `top_logprobs` most likely alternatives.
+    ///
+    /// Text that is forced by the parser is not sampled, so it is passed to `on_token` without a log probability.
     #[allow(clippy::too_many_arguments)]
-    /// Stream text, calling the on_token callback every time a new token is generated. For some models, this could be used to implement [`Model::stream_text_with_sampler`].
-    fn stream_text_with_sampler(
+    fn generate_structured_with_logprobs<P: Parser>(
         &self,
         session: &mut Self::Session,
-        prompt: &str,
-        max_tokens: Option<u32>,
-        stop_on: Option<&str>,
-        mut sampler: Arc<Mutex<dyn Sampler>>,
-        mut on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
-    ) -> anyhow::Result<()> {
-        let tokens = self
-            .tokenizer()
-            .encode(prompt, false)
-            .map_err(|e| anyhow::anyhow!(e))?;
-        let tokens = tokens.get_ids();
-        let mut text_stream = TokenOutputStream::new(self.tokenizer());
-        for &token in tokens {
-            text_stream.next_token(token)?;
-        }
-
-        let mut logit_probs = Vec::new();
-        self.feed_tokens(session, tokens, &mut logit_probs)?;
-        let mut logits = Logits::try_from_iter_top_k(logit_probs, 512)?;
-        let mut tokens_generated = 0;
-        // This stores a buffer of text that has been generated to check against the stop_on string. It should never be longer than the stop_on string.
-        let mut queued_text_matching_stop_on = String::new();
-        let stop_on_lowercase = stop_on.map(|s| s.to_lowercase());
-        let stop_on_lowercase = stop_on_lowercase.as_deref();
-        let stop_token = self.stop_token()?;
-        let mut logit_probs = Vec::new();
-
-        'generate: loop {
-            let new_token = text_stream.sample_token(&mut sampler, logits, stop_on)?;
-            if new_token == stop_token {
-                tracing::trace!("Stopping on stop token");
-                break;
-            }
-            if let Some(mut new_text) = text_stream.next_token(new_token)? {
-                if let Some(stop_on) = stop_on_lowercase {
-                    let lowercase = new_text.to_lowercase();
-
-                    // Check if the string ends with the start of the stop_on string
-                    let mut before_stop_on = None;
-                    let remaining_stop_on = stop_on
-                        .strip_prefix(&queued_text_matching_stop_on)
-                        .unwrap_or(stop_on);
-
-                    // If the remaining stop_on string is empty, we have found a match
-                    if remaining_stop_on.is_empty() {
-                        break;
-                    }
-
-                    for (i, _) in lowercase.char_indices() {
-                        let end_of_new_text = &lowercase[i..];
-                        if end_of_new_text.is_empty() {
-                            break;
-                        }
-
-                        // Check if we have matched all of the stop_on string
-                        if end_of_new_text.starts_with(remaining_stop_on) {
-                            queued_text_matching_stop_on += end_of_new_text;
-                            break 'generate;
-                        }
-
-                        // Check if the string ends with the start of the stop_on string
-                        if remaining_stop_on.starts_with(end_of_new_text) {
-                            before_stop_on = Some(lowercase[..i].to_string());
-                            queued_text_matching_stop_on += end_of_new_text;
-                            break;
-                        }
-                    }
-
-                    match before_stop_on {
-                        Some(before_stop_on) => {
-                            if let ModelFeedback::Stop = on_token(before_stop_on)? {
-                                break;
-                            }
-                        }
-                        None => {
-                            new_text =
-                                std::mem::take(&mut queued_text_matching_stop_on) + &new_text;
-                            if let ModelFeedback::Stop = on_token(new_text)? {
-                                break;
-                            }
-                        }
-                    }
-                } else if let ModelFeedback::Stop = on_token(new_text)? {
-                    break;
-                }
-            }
-            tokens_generated += 1;
-            if let Some(max_tokens) = max_tokens {
-                if tokens_generated >= max_tokens {
-                    break;
-                }
-            }
-            self.feed_tokens(session, &[new_token], &mut logit_probs)?;
-            logits = Logits::try_from_iter_top_k(logit_probs.iter().copied(), 512)?;
-        }
+        prompt: impl Display,
+        parser: P,
+        parser_state: P::PartialState,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        on_token: impl FnMut(TokenEvent) -> anyhow::Result<()>,
+        top_k: Option<usize>,
+        top_logprobs: usize,
+    ) -> anyhow::Result<P::Output> {
+        generate_structured(
+            prompt,
+            self,
+            session,
+            parser,
+            parser_state,
+            sampler,
+            on_token,
+            top_k,
+            Some(top_logprobs),
+        )
+    }
 
-        // Flush the queued text
-        if let Some(stop_string) = stop_on_lowercase {
-            if !queued_text_matching_stop_on.starts_with(stop_string) {
-                on_token(queued_text_matching_stop_on)?;
+    /// Generate new text with the given prompt that conforms to the given parser and pick the output with a [`SearchStrategy`]. `on_token` is called with a [`TokenEvent`] that includes the log probability of every sampled token.
+    ///
+    /// Beam search and best-of-N sampling explore several outputs on clones of the session, so they require a session that supports [`Session::try_clone`]. Once the search finishes, the session is replaced with the session of the best output.
+    #[allow(clippy::too_many_arguments)]
+    fn generate_structured_with_search<P: Parser>(
+        &self,
+        session: &mut Self::Session,
+        prompt: impl Display,
+        parser: P,
+        parser_state: P::PartialState,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        on_token: impl FnMut(TokenEvent) -> anyhow::Result<()>,
+        top_k: Option<usize>,
+        search: SearchStrategy,
+    ) -> anyhow::Result<P::Output> {
+        generate_structured_with_search(
+            prompt,
+            self,
+            session,
+            parser,
+            parser_state,
+            sampler,
+            on_token,
+            top_k,
+            search,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// Stream text, calling the on_token callback every time a new token is generated. For some models, this could be used to implement [`Model::stream_text_with_sampler`].
+    fn stream_text_with_sampler(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        max_tokens: Option<u32>,
+        stop_on: Option<&str>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        mut on_token: impl FnMut(String) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<()> {
+        let (mut state, tokens) =
+            TextGenerationState::new(self, prompt, max_tokens, stop_on, sampler)?;
+
+        let mut logits = Vec::new();
+        let mut draft_tokens = self.feed_tokens_speculative(session, &tokens, &mut logits)?;
+        loop {
+            let (new_token, rejected) =
+                state.next_token_speculative(&logits, &draft_tokens, &mut on_token)?;
+            self.reject_draft_tokens(session, rejected)?;
+            let Some(new_token) = new_token else {
+                break;
+            };
+            draft_tokens = self.feed_tokens_speculative(session, &[new_token], &mut logits)?;
+        }
+
+        state.finish(on_token)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// Stream text like [`SyncModelExt::stream_text_with_sampler`], but call `on_token` with a [`TokenEvent`] for every generated token. Each event includes the log probability the model assigned to the token and the `top_logprobs` most likely alternatives.
+    ///
+    /// This can be used to score the confidence of the model, highlight uncertain spans of the output or compute the [`perplexity`](crate::perplexity) of the model.
+    fn stream_text_with_logprobs(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        max_tokens: Option<u32>,
+        stop_on: Option<&str>,
+        sampler: Arc<Mutex<dyn Sampler>>,
+        top_logprobs: usize,
+        mut on_token: impl FnMut(TokenEvent) -> anyhow::Result<ModelFeedback>,
+    ) -> anyhow::Result<()> {
+        let (mut state, tokens) =
+            TextGenerationState::new(self, prompt, max_tokens, stop_on, sampler)?;
+
+        // The last event is held back until the next token is generated. If generation stops while part of the stop sequence is held back, the text is added to the last event
+        let mut last_event = None;
+        let mut stopped = false;
+        let mut logits = Vec::new();
+        let mut draft_tokens = self.feed_tokens_speculative(session, &tokens, &mut logits)?;
+        loop {
+            let (new_token, rejected) = state.next_token_speculative_with_logprobs(
+                &logits,
+                &draft_tokens,
+                top_logprobs,
+                |event| match last_event.replace(event) {
+                    Some(event) => {
+                        let feedback = on_token(event)?;
+                        stopped = matches!(feedback, ModelFeedback::Stop);
+                        Ok(feedback)
+                    }
+                    None => Ok(ModelFeedback::Continue),
+                },
+            )?;
+            self.reject_draft_tokens(session, rejected)?;
+            let Some(new_token) = new_token else {
+                break;
+            };
+            draft_tokens = self.feed_tokens_speculative(session, &[new_token], &mut logits)?;
+        }
+
+        // The consumer asked to stop, so the event that was held back is never sent
+        if stopped {
+            return Ok(());
+        }
+
+        state.finish(|text| {
+            if let Some(event) = &mut last_event {
+                event.text += &text;
             }
+            Ok(ModelFeedback::Continue)
+        })?;
+        if let Some(event) = last_event {
+            on_token(event)?;
         }
 
         Ok(())
@@ -891,9 +1401,9 @@ pub trait Model: Send + Sync + 'static {
     ) -> anyhow::Result<String> {
         let mut text = String::new();
 
-        let mut stream = self.stream_text_inner(prompt, parameters).await?;
+        let mut stream = self.stream_text_fallible_inner(prompt, parameters).await?;
         while let Some(new) = stream.next().await {
-            text.push_str(&new);
+            text.push_str(&new?);
         }
         Ok(text)
     }
@@ -918,10 +1428,84 @@ pub trait Model: Send + Sync + 'static {
         parameters: GenerationParameters,
     ) -> anyhow::Result<Self::TextStream>;
 
+    /// Like [`Model::stream_text_inner`], but the stream ends with an error if generation fails partway through the response. A plain text stream can't carry the error, so a response that was cut off looks like a response that finished.
+    ///
+    /// The default implementation can't see errors after the response starts, so it wraps each item of [`Model::stream_text_inner`] in `Ok`. Models that generate text over a network should override it.
+    async fn stream_text_fallible_inner(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let stream = self.stream_text_inner(prompt, parameters).await?;
+        Ok(Box::pin(stream.map(Ok)))
+    }
+
+    /// Generate the next assistant message for a list of chat messages.
+    ///
+    /// Models that don't expose [`ChatMarkers`] can implement this to generate chat responses with a native chat API (like the OpenAI chat completions endpoint) instead.
+    async fn stream_chat_inner(
+        &self,
+        _messages: &[ChatHistoryItem],
+        _parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        Err(anyhow::Error::msg("Not implemented"))
+    }
+
+    /// Like [`Model::stream_chat_inner`], but the stream ends with an error if generation fails partway through the response. See [`Model::stream_text_fallible_inner`].
+    async fn stream_chat_fallible_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let stream = self.stream_chat_inner(messages, parameters).await?;
+        Ok(Box::pin(stream.map(Ok)))
+    }
+
+    /// Returns true if the model can constrain a chat response to a JSON schema with [`Model::stream_chat_with_schema_inner`].
+    fn supports_json_schema(&self) -> bool {
+        false
+    }
+
+    /// Start generating the next assistant message for a list of chat messages. The response will be JSON that follows the schema. If there is no schema, the response can be any JSON value.
+    ///
+    /// Models that don't expose a [`SyncModel`] can implement this (along with [`Model::supports_json_schema`]) to support structured generation with a native schema API (like the OpenAI `json_schema` response format) instead. If the request fails, the stream ends with the error.
+    fn stream_chat_with_schema_inner(
+        &self,
+        _messages: &[ChatHistoryItem],
+        _schema: Option<&SchemaType>,
+        _parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        Err(anyhow::Error::msg("Not implemented"))
+    }
+
+    /// Returns true if the model can call tools natively with [`Model::chat_with_tools_inner`].
+    fn supports_tool_calls(&self) -> bool {
+        false
+    }
+
+    /// Generate the next assistant response for a chat with tools the model can call. The model either answers with a message or calls one or more tools.
+    ///
+    /// Models that don't expose a [`SyncModel`] can implement this (along with [`Model::supports_tool_calls`]) to support tools with a native function calling API.
+    async fn chat_with_tools_inner(
+        &self,
+        _messages: &[ToolChatMessage],
+        _tools: &[ToolDefinition],
+        _parameters: GenerationParameters,
+    ) -> anyhow::Result<ToolChatResponse> {
+        Err(anyhow::Error::msg("Not implemented"))
+    }
+
     /// Returns the chat markers to use for the model if this is a chat model.
+    ///
+    /// If the model also has a [`Model::chat_template`], chat sessions use the template instead.
     fn chat_markers(&self) -> Option<ChatMarkers> {
         None
     }
+
+    /// Returns the chat template the model was trained with if this is a chat model. This is usually read from the model file, so it also works with models that don't have chat markers.
+    fn chat_template(&self) -> Option<ChatTemplate> {
+        None
+    }
 }
 
 /// An extension trait for models that can be converted into a trait object.
@@ -978,6 +1562,88 @@ impl Model for DynModel {
               + Send) = self.as_ref();
         self_ref.stream_text_inner(prompt, parameters).await
     }
+
+    async fn stream_text_fallible_inner(
+        &self,
+        prompt: &str,
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref
+            .stream_text_fallible_inner(prompt, parameters)
+            .await
+    }
+
+    async fn stream_chat_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.stream_chat_inner(messages, parameters).await
+    }
+
+    async fn stream_chat_fallible_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref
+            .stream_chat_fallible_inner(messages, parameters)
+            .await
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.supports_json_schema()
+    }
+
+    fn stream_chat_with_schema_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        schema: Option<&SchemaType>,
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.stream_chat_with_schema_inner(messages, schema, parameters)
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.supports_tool_calls()
+    }
+
+    async fn chat_with_tools_inner(
+        &self,
+        messages: &[ToolChatMessage],
+        tools: &[ToolDefinition],
+        parameters: GenerationParameters,
+    ) -> anyhow::Result<ToolChatResponse> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref
+            .chat_with_tools_inner(messages, tools, parameters)
+            .await
+    }
+
+    fn chat_markers(&self) -> Option<ChatMarkers> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.chat_markers()
+    }
+
+    fn chat_template(&self) -> Option<ChatTemplate> {
+        let self_ref: &(dyn Model<TextStream = ChannelTextStream, SyncModel = BoxedSyncModel>
+              + Send) = self.as_ref();
+        self_ref.chat_template()
+    }
 }
 
 /// A trait object for a sync model.
@@ -985,12 +1651,24 @@ pub type BoxedSyncModel = Box<dyn SyncModel<Session = AnySession>>;
 
 trait AnySessionTrait {
     fn save_to(&self, path: &Path) -> anyhow::Result<()>;
+
+    fn tokens(&self) -> &[u32];
+
+    fn truncate(&mut self, len: usize) -> anyhow::Result<()>;
 }
 
 impl<S: Any + Session> AnySessionTrait for S {
     fn save_to(&self, path: &Path) -> anyhow::Result<()> {
         Session::save_to(self, path)
     }
+
+    fn tokens(&self) -> &[u32] {
+        Session::tokens(self)
+    }
+
+    fn truncate(&mut self, len: usize) -> anyhow::Result<()> {
+        Session::truncate(self, len)
+    }
 }
 
 /// A type-erased session.
@@ -1004,6 +1682,14 @@ impl Session for AnySession {
     fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
         self.session.save_to(path.as_ref())
     }
+
+    fn tokens(&self) -> &[u32] {
+        self.session.tokens()
+    }
+
+    fn truncate(&mut self, len: usize) -> anyhow::Result<()> {
+        self.session.truncate(len)
+    }
 }
 
 impl SyncModel for BoxedSyncModel {
@@ -1034,6 +1720,31 @@ impl SyncModel for BoxedSyncModel {
         self_ref.feed_tokens(session, tokens, into)
     }
 
+    fn feed_tokens_batch(
+        &self,
+        sessions: &mut [&mut Self::Session],
+        tokens: &[&[u32]],
+        into: &mut [Vec<f32>],
+    ) -> anyhow::Result<()> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.feed_tokens_batch(sessions, tokens, into)
+    }
+
+    fn feed_tokens_speculative(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<Vec<u32>> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.feed_tokens_speculative(session, tokens, into)
+    }
+
+    fn reject_draft_tokens(&self, session: &mut Self::Session, count: usize) -> anyhow::Result<()> {
+        let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
+        self_ref.reject_draft_tokens(session, count)
+    }
+
     fn stop_token(&self) -> anyhow::Result<u32> {
         let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
         self_ref.stop_token()
@@ -1067,6 +1778,14 @@ where
         self.0.stream_text_inner(prompt, params).await
     }
 
+    async fn stream_text_fallible_inner(
+        &self,
+        prompt: &str,
+        params: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        self.0.stream_text_fallible_inner(prompt, params).await
+    }
+
     async fn stream_text_with_sampler(
         &self,
         prompt: &str,
@@ -1078,10 +1797,180 @@ where
             .stream_text_with_sampler(prompt, max_tokens, stop_on, sampler)
             .await
     }
+
+    async fn stream_chat_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        params: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        self.0.stream_chat_inner(messages, params).await
+    }
+
+    async fn stream_chat_fallible_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        params: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        self.0.stream_chat_fallible_inner(messages, params).await
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.0.supports_json_schema()
+    }
+
+    fn stream_chat_with_schema_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        schema: Option<&SchemaType>,
+        params: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        self.0
+            .stream_chat_with_schema_inner(messages, schema, params)
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        self.0.supports_tool_calls()
+    }
+
+    async fn chat_with_tools_inner(
+        &self,
+        messages: &[ToolChatMessage],
+        tools: &[ToolDefinition],
+        params: GenerationParameters,
+    ) -> anyhow::Result<ToolChatResponse> {
+        self.0.chat_with_tools_inner(messages, tools, params).await
+    }
+
+    fn chat_markers(&self) -> Option<ChatMarkers> {
+        self.0.chat_markers()
+    }
+
+    fn chat_template(&self) -> Option<ChatTemplate> {
+        self.0.chat_template()
+    }
+}
+
+/// The bias of a token in a [`LogitBias`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenBias {
+    /// Add the bias to the logit of the token. A positive bias makes the token more likely and a negative bias makes it less likely.
+    Add(f32),
+    /// Never sample the token.
+    Ban,
+}
+
+impl From<f32> for TokenBias {
+    /// Convert a bias to add to the logit of a token. A bias of [`f32::NEG_INFINITY`] bans the token.
+    fn from(bias: f32) -> Self {
+        if bias == f32::NEG_INFINITY {
+            Self::Ban
+        } else {
+            Self::Add(bias)
+        }
+    }
+}
+
+impl TokenBias {
+    /// The value added to the logit of the token. Banned tokens get a bias of [`f32::NEG_INFINITY`].
+    pub fn value(&self) -> f32 {
+        match self {
+            Self::Add(bias) => *bias,
+            Self::Ban => f32::NEG_INFINITY,
+        }
+    }
+}
+
+/// A bias added to the logits of tokens before sampling. A positive bias makes a token more likely, a negative bias makes it less likely and [`TokenBias::Ban`] bans the token.
+///
+/// Biases can be keyed by token id or by text. A text bias applies to every token the text is tokenized into.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct LogitBias {
+    tokens: BTreeMap<u32, TokenBias>,
+    text: BTreeMap<String, TokenBias>,
+}
+
+impl LogitBias {
+    /// Create a new empty logit bias.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a bias to a token id. A bias of [`f32::NEG_INFINITY`] bans the token.
+    pub fn with_token(mut self, token: u32, bias: f32) -> Self {
+        self.tokens.insert(token, bias.into());
+        self
+    }
+
+    /// Add a bias to every token the text is tokenized into. A bias of [`f32::NEG_INFINITY`] bans the tokens.
+    pub fn with_text(mut self, text: impl ToString, bias: f32) -> Self {
+        self.text.insert(text.to_string(), bias.into());
+        self
+    }
+
+    /// Never sample a token id.
+    pub fn with_banned_token(mut self, token: u32) -> Self {
+        self.tokens.insert(token, TokenBias::Ban);
+        self
+    }
+
+    /// Never sample any of the tokens the text is tokenized into.
+    pub fn with_banned_text(mut self, text: impl ToString) -> Self {
+        self.text.insert(text.to_string(), TokenBias::Ban);
+        self
+    }
+
+    /// Get the biases keyed by token id.
+    pub fn tokens(&self) -> &BTreeMap<u32, TokenBias> {
+        &self.tokens
+    }
+
+    /// Get the biases keyed by text.
+    pub fn text(&self) -> &BTreeMap<String, TokenBias> {
+        &self.text
+    }
+
+    /// Check if there are no biases.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty() && self.text.is_empty()
+    }
+
+    /// Resolve the biases to a bias for each token id. If a token is biased by both a token id and a text bias, the token id bias is used.
+    pub fn resolve(&self, tokenizer: &Tokenizer) -> anyhow::Result<Vec<(u32, f32)>> {
+        let mut biases = BTreeMap::new();
+        for (text, bias) in &self.text {
+            let encoding = tokenizer
+                .encode(text.as_str(), false)
+                .map_err(anyhow::Error::msg)?;
+            for token in encoding.get_ids() {
+                biases.insert(*token, bias.value());
+            }
+        }
+        biases.extend(
+            self.tokens
+                .iter()
+                .map(|(token, bias)| (*token, bias.value())),
+        );
+        Ok(biases.into_iter().collect())
+    }
+
+    /// The biases keyed by token id
+    fn token_biases(&self) -> Vec<(u32, f32)> {
+        self.tokens
+            .iter()
+            .map(|(token, bias)| (*token, bias.value()))
+            .collect()
+    }
 }
 
 /// Parameters to use when generating text.
+///
+/// With the `serde` feature enabled, the parameters can be serialized to save an experiment and replay it later. Set a seed with [`GenerationParameters::with_seed`] to make sampling deterministic.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct GenerationParameters {
     pub(crate) temperature: f32,
     pub(crate) tau: f32,
@@ -1089,8 +1978,17 @@ pub struct GenerationParameters {
     pub(crate) mu: f32,
     pub(crate) repetition_penalty: f32,
     pub(crate) repetition_penalty_range: u32,
+    pub(crate) frequency_penalty: f32,
+    pub(crate) presence_penalty: f32,
+    pub(crate) top_k: Option<usize>,
+    pub(crate) top_p: Option<f32>,
+    pub(crate) min_p: Option<f32>,
+    pub(crate) typical_p: Option<f32>,
+    pub(crate) logit_bias: LogitBias,
+    pub(crate) seed: Option<u64>,
     pub(crate) max_length: u32,
     pub(crate) stop_on: Option<String>,
+    pub(crate) search_strategy: SearchStrategy,
 }
 
 impl Default for GenerationParameters {
@@ -1102,59 +2000,34 @@ impl Default for GenerationParameters {
             mu: 10.,
             repetition_penalty: 1.3,
             repetition_penalty_range: 64,
+            frequency_penalty: 0.,
+            presence_penalty: 0.,
+            top_k: None,
+            top_p: None,
+            min_p: None,
+            typical_p: None,
+            logit_bias: LogitBias::default(),
+            seed: None,
             max_length: 128,
             stop_on: None,
+            search_strategy: SearchStrategy::Sample,
         }
     }
 }
 
 impl crate::model::GenerationParameters {
     /// Create a sampler chain from the generation parameters.
+    ///
+    /// Logit biases keyed by text need a tokenizer and are ignored. Use [`GenerationParameters::sampler_with_tokenizer`] to include them.
     pub fn sampler(self) -> SamplerChain {
-        use llm_samplers::configure::SamplerSlot;
-        let GenerationParameters {
-            temperature,
-            tau,
-            eta,
-            mu,
-            repetition_penalty,
-            repetition_penalty_range,
-            max_length: _,
-            stop_on: _,
-        } = self;
-        SamplerChainBuilder::from([
-            (
-                "repetition",
-                SamplerSlot::new_static(move || {
-                    Box::new(
-                        SampleRepetition::default()
-                            .penalty(repetition_penalty)
-                            .last_n(repetition_penalty_range as usize),
-                    )
-                }),
-            ),
-            (
-                "freqpresence",
-                SamplerSlot::new_static(move || Box::new(SampleFreqPresence::default().last_n(64))),
-            ),
-            (
-                "seqrepetition",
-                SamplerSlot::new_static(move || Box::<SampleSeqRepetition>::default()),
-            ),
-            (
-                "temperature",
-                SamplerSlot::new_static(move || {
-                    Box::new(SampleTemperature::default().temperature(temperature))
-                }),
-            ),
-            (
-                "mirostat2",
-                SamplerSlot::new_static(move || {
-                    Box::new(SampleMirostat2::default().tau(tau).eta(eta).mu(mu))
-                }),
-            ),
-        ])
-        .into_chain()
+        let token_biases = self.logit_bias.token_biases();
+        self.build_sampler(token_biases, true)
+    }
+
+    /// Create a sampler chain from the generation parameters. Logit biases keyed by text are tokenized with the tokenizer.
+    pub fn sampler_with_tokenizer(self, tokenizer: &Tokenizer) -> anyhow::Result<SamplerChain> {
+        let token_biases = self.logit_bias.resolve(tokenizer)?;
+        Ok(self.build_sampler(token_biases, true))
     }
 
     /// Get the mirostat2 sampler from the generation parameters.
@@ -1166,41 +2039,76 @@ impl crate::model::GenerationParameters {
     }
 
     /// Create a sampler chain from the generation parameters without removing any tokens. This can be useful in combination with [`ModelExt::stream_structured_text_with_sampler`] which may pick unlikely tokens.
+    ///
+    /// Top-k, top-p, min-p, typical-p and mirostat are left out of the chain and logit biases keyed by text are ignored.
     pub fn bias_only_sampler(self) -> SamplerChain {
-        use llm_samplers::configure::SamplerSlot;
+        let token_biases = self.logit_bias.token_biases();
+        self.build_sampler(token_biases, false)
+    }
+
+    /// Build the sampler chain. If `truncate` is false, samplers that remove tokens are left out
+    fn build_sampler(self, token_biases: Vec<(u32, f32)>, truncate: bool) -> SamplerChain {
         let GenerationParameters {
             temperature,
+            tau,
+            eta,
+            mu,
             repetition_penalty,
             repetition_penalty_range,
-            ..
+            frequency_penalty,
+            presence_penalty,
+            top_k,
+            top_p,
+            min_p,
+            typical_p,
+            logit_bias: _,
+            seed,
+            max_length: _,
+            stop_on: _,
+            search_strategy: _,
         } = self;
-        SamplerChainBuilder::from([
-            (
-                "repetition",
-                SamplerSlot::new_static(move || {
-                    Box::new(
-                        SampleRepetition::default()
-                            .penalty(repetition_penalty)
-                            .last_n(repetition_penalty_range as usize),
-                    )
-                }),
-            ),
-            (
-                "freqpresence",
-                SamplerSlot::new_static(move || Box::new(SampleFreqPresence::default().last_n(64))),
-            ),
-            (
-                "seqrepetition",
-                SamplerSlot::new_static(move || Box::<SampleSeqRepetition>::default()),
-            ),
-            (
-                "temperature",
-                SamplerSlot::new_static(move || {
-                    Box::new(SampleTemperature::default().temperature(temperature))
-                }),
-            ),
-        ])
-        .into_chain()
+
+        let mut chain = SamplerChain::new();
+        if !token_biases.is_empty() {
+            chain += SampleFlatBias::new(token_biases);
+        }
+        chain += SampleRepetition::default()
+            .penalty(repetition_penalty)
+            .last_n(repetition_penalty_range as usize);
+        chain += SampleFreqPresence::default()
+            .frequency_penalty(frequency_penalty)
+            .presence_penalty(presence_penalty)
+            .last_n(64);
+        chain += SampleSeqRepetition::default();
+        if truncate {
+            if let Some(k) = top_k {
+                chain += SampleTopK::default().k(k);
+            }
+            if let Some(p) = typical_p {
+                chain += SampleLocallyTypical::default().p(p);
+            }
+            if let Some(p) = top_p {
+                chain += SampleTopP::default().p(p);
+            }
+            if let Some(p) = min_p {
+                chain += SampleMinP::default().p(p);
+            }
+        }
+        chain += SampleTemperature::default().temperature(temperature);
+        if truncate {
+            chain += SampleMirostat2::default().tau(tau).eta(eta).mu(mu);
+        }
+
+        match seed {
+            Some(seed) => {
+                SamplerChain::new()
+                    + SeededSampler {
+                        sampler: chain,
+                        rng: StdRng::seed_from_u64(seed),
+                    }
+            }
+            None => chain,
+        }
     }
 
     /// Set the temperature to use when generating text.
@@ -1239,6 +2147,66 @@ impl crate::model::GenerationParameters {
         self
     }
 
+    /// Set the frequency penalty to use when generating text. The penalty is subtracted from the logit of a token once for every time it appears in the last 64 tokens.
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = frequency_penalty;
+        self
+    }
+
+    /// Set the presence penalty to use when generating text. The penalty is subtracted from the logit of every token that appears in the last 64 tokens.
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = presence_penalty;
+        self
+    }
+
+    /// Set the number of most likely tokens to sample from.
+    pub fn with_top_k(mut self, top_k: impl Into<Option<usize>>) -> Self {
+        self.top_k = top_k.into();
+        self
+    }
+
+    /// Only sample from the most likely tokens with a total probability of `top_p`.
+    pub fn with_top_p(mut self, top_p: impl Into<Option<f32>>) -> Self {
+        self.top_p = top_p.into();
+        self
+    }
+
+    /// Only sample from tokens with a probability of at least `min_p` times the probability of the most likely token.
+    pub fn with_min_p(mut self, min_p: impl Into<Option<f32>>) -> Self {
+        self.min_p = min_p.into();
+        self
+    }
+
+    /// Only sample from the most locally typical tokens with a total probability of `typical_p`.
+    pub fn with_typical_p(mut self, typical_p: impl Into<Option<f32>>) -> Self {
+        self.typical_p = typical_p.into();
+        self
+    }
+
+    /// Set the logit bias to use when generating text. See [`LogitBias`] for more information.
+    pub fn with_logit_bias(mut self, logit_bias: LogitBias) -> Self {
+        self.logit_bias = logit_bias;
+        self
+    }
+
+    /// Add a bias to the logit of a token id. A bias of [`f32::NEG_INFINITY`] bans the token.
+    pub fn with_token_bias(mut self, token: u32, bias: f32) -> Self {
+        self.logit_bias = self.logit_bias.with_token(token, bias);
+        self
+    }
+
+    /// Add a bias to the logits of every token the text is tokenized into. A bias of [`f32::NEG_INFINITY`] bans the tokens.
+    pub fn with_text_bias(mut self, text: impl ToString, bias: f32) -> Self {
+        self.logit_bias = self.logit_bias.with_text(text, bias);
+        self
+    }
+
+    /// Set the seed of the random number generator used for sampling. Generating text from the same prompt with the same seed and parameters produces the same text.
+    pub fn with_seed(mut self, seed: impl Into<Option<u64>>) -> Self {
+        self.seed = seed.into();
+        self
+    }
+
     /// Set the maximum length to use when generating text.
     pub fn with_max_length(mut self, max_length: u32) -> Self {
         self.max_length = max_length;
@@ -1251,6 +2219,12 @@ impl crate::model::GenerationParameters {
         self
     }
 
+    /// Set the search strategy to use when generating structured text. Unstructured generation with [`ModelExt::stream_text`] or [`ModelExt::generate_text`] returns an error for any strategy other than [`SearchStrategy::Sample`]. See [`SearchStrategy`] for more information.
+    pub fn with_search_strategy(mut self, search_strategy: SearchStrategy) -> Self {
+        self.search_strategy = search_strategy;
+        self
+    }
+
     /// Get the temperature to use when generating text.
     pub fn temperature(&self) -> f32 {
         self.temperature
@@ -1281,6 +2255,46 @@ impl crate::model::GenerationParameters {
         self.repetition_penalty_range
     }
 
+    /// Get the frequency penalty to use when generating text.
+    pub fn frequency_penalty(&self) -> f32 {
+        self.frequency_penalty
+    }
+
+    /// Get the presence penalty to use when generating text.
+    pub fn presence_penalty(&self) -> f32 {
+        self.presence_penalty
+    }
+
+    /// Get the number of most likely tokens to sample from.
+    pub fn top_k(&self) -> Option<usize> {
+        self.top_k
+    }
+
+    /// Get the total probability of the most likely tokens to sample from.
+    pub fn top_p(&self) -> Option<f32> {
+        self.top_p
+    }
+
+    /// Get the minimum probability of a token relative to the most likely token.
+    pub fn min_p(&self) -> Option<f32> {
+        self.min_p
+    }
+
+    /// Get the total probability of the most locally typical tokens to sample from.
+    pub fn typical_p(&self) -> Option<f32> {
+        self.typical_p
+    }
+
+    /// Get the logit bias to use when generating text.
+    pub fn logit_bias(&self) -> &LogitBias {
+        &self.logit_bias
+    }
+
+    /// Get the seed of the random number generator used for sampling.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
     /// Get the maximum length to use when generating text.
     pub fn max_length(&self) -> u32 {
         self.max_length
@@ -1290,4 +2304,106 @@ impl crate::model::GenerationParameters {
     pub fn stop_on(&self) -> Option<&str> {
         self.stop_on.as_deref()
     }
+
+    /// Get the search strategy to use when generating structured text.
+    pub fn search_strategy(&self) -> SearchStrategy {
+        self.search_strategy
+    }
+}
+
+/// A sampler that samples with its own seeded random number generator instead of the one passed in
+#[derive(Debug)]
+struct SeededSampler {
+    sampler: SamplerChain,
+    rng: StdRng,
+}
+
+impl Sampler for SeededSampler {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        self.sampler.sample(
+            &mut SeededResources {
+                rng: &mut self.rng,
+                resources: res,
+            },
+            logits,
+        )
+    }
+
+    fn sampled_token_id(&self) -> Option<u32> {
+        self.sampler.sampled_token_id()
+    }
+}
+
+#[derive(Debug)]
+struct SeededResources<'a> {
+    rng: &'a mut StdRng,
+    resources: &'a mut dyn HasSamplerResources,
+}
+
+impl HasSamplerResources for SeededResources<'_> {
+    fn with_rng_mut(
+        &mut self,
+        fun: &mut dyn FnMut(&mut dyn rand::RngCore),
+    ) -> Result<(), SamplerError> {
+        fun(self.rng);
+        Ok(())
+    }
+
+    fn with_last_tokens(&self, fun: &mut dyn FnMut(&[u32])) -> Result<(), SamplerError> {
+        self.resources.with_last_tokens(fun)
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn generation_parameters_serde_round_trip() {
+    let parameters = GenerationParameters::default()
+        .with_seed(42)
+        .with_logit_bias(
+            LogitBias::new()
+                .with_token(1, 2.5)
+                .with_banned_token(2)
+                .with_token(3, f32::NEG_INFINITY)
+                .with_banned_text("hello"),
+        );
+    assert_eq!(
+        parameters.logit_bias().tokens().get(&3),
+        Some(&TokenBias::Ban)
+    );
+
+    let json = serde_json::to_string(&parameters).unwrap();
+    let deserialized: GenerationParameters = serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized, parameters);
+}
+
+#[test]
+fn same_seed_samples_same_tokens() {
+    fn sample_tokens(seed: u64, resources_seed: u64) -> Vec<u32> {
+        let mut sampler = GenerationParameters::default().with_seed(seed).sampler();
+        // The seeded sampler ignores the random number generator passed in
+        let mut rng = StdRng::seed_from_u64(resources_seed);
+        let mut previous_tokens = Vec::new();
+        for _ in 0..16 {
+            let logits = (0..32).map(|i| (i as f32).sin()).collect::<Vec<_>>();
+            let mut logits = Logits::try_from_iter_top_k(logits.iter().copied(), 32).unwrap();
+            let token = sampler
+                .sample_token(
+                    &mut crate::structured::SamplerResources {
+                        rng: &mut rng,
+                        previous_tokens: &previous_tokens,
+                    },
+                    &mut logits,
+                )
+                .unwrap()
+                .unwrap();
+            previous_tokens.push(token);
+        }
+        previous_tokens
+    }
+
+    assert_eq!(sample_tokens(42, 0), sample_tokens(42, 1));
 }
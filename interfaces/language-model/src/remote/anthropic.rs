@@ -0,0 +1,383 @@
+use kalosm_common::*;
+use kalosm_streams::text_stream::ChannelTextStream;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokenizers::tokenizer::Tokenizer;
+
+use super::{check_status, read_lines, warn_unsupported_parameters};
+use crate::{ChatHistoryItem, FallibleTextStream, GenerationParameters, MessageType, ModelBuilder};
+
+const DEFAULT_ANTHROPIC_URL: &str = "https://api.anthropic.com";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// A model that uses Anthropic's messages API.
+pub struct Anthropic {
+    model: String,
+    api_key: String,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+/// A builder for a model hosted by Anthropic.
+#[derive(Debug, Default)]
+pub struct AnthropicBuilder<const WITH_NAME: bool> {
+    model: Option<String>,
+    api_key: Option<String>,
+    base_url: Option<String>,
+}
+
+impl AnthropicBuilder<false> {
+    /// Creates a new builder
+    pub fn new() -> Self {
+        Self {
+            model: None,
+            api_key: None,
+            base_url: None,
+        }
+    }
+
+    /// Set the name of the model to use (for example `claude-3-5-sonnet-latest`).
+    pub fn with_model(self, model: impl ToString) -> AnthropicBuilder<true> {
+        AnthropicBuilder {
+            model: Some(model.to_string()),
+            api_key: self.api_key,
+            base_url: self.base_url,
+        }
+    }
+}
+
+impl<const WITH_NAME: bool> AnthropicBuilder<WITH_NAME> {
+    /// Sets the API key for the builder. If no API key is set, the `ANTHROPIC_API_KEY` environment variable is used.
+    pub fn with_api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// Set the base URL of the API.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+}
+
+impl AnthropicBuilder<true> {
+    /// Build the model.
+    pub fn build(self) -> Anthropic {
+        Anthropic {
+            model: self.model.unwrap(),
+            api_key: self
+                .api_key
+                .or_else(|| std::env::var("ANTHROPIC_API_KEY").ok())
+                .unwrap_or_default(),
+            base_url: self
+                .base_url
+                .as_deref()
+                .unwrap_or(DEFAULT_ANTHROPIC_URL)
+                .trim_end_matches('/')
+                .to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ModelBuilder for AnthropicBuilder<true> {
+    type Model = Anthropic;
+
+    async fn start_with_loading_handler(
+        self,
+        _: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> anyhow::Result<Anthropic> {
+        Ok(self.build())
+    }
+
+    fn requires_download(&self) -> bool {
+        false
+    }
+}
+
+impl Anthropic {
+    /// Creates a new builder
+    pub fn builder() -> AnthropicBuilder<false> {
+        AnthropicBuilder::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::model::Model for Anthropic {
+    type TextStream = ChannelTextStream;
+    type SyncModel = crate::SyncModelNotSupported;
+
+    fn tokenizer(&self) -> Arc<Tokenizer> {
+        panic!("Anthropic does not expose tokenization")
+    }
+
+    async fn stream_text_inner(
+        &self,
+        prompt: &str,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        let stream = self
+            .stream_text_fallible_inner(prompt, generation_parameters)
+            .await?;
+        Ok(super::forward_to_text_stream("Anthropic", stream))
+    }
+
+    async fn stream_text_fallible_inner(
+        &self,
+        prompt: &str,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let messages = [ChatHistoryItem::new(MessageType::UserMessage, prompt)];
+        self.stream_chat_fallible_inner(&messages, generation_parameters)
+            .await
+    }
+
+    async fn stream_chat_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        let stream = self
+            .stream_chat_fallible_inner(messages, generation_parameters)
+            .await?;
+        Ok(super::forward_to_text_stream("Anthropic", stream))
+    }
+
+    async fn stream_chat_fallible_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        // Anthropic takes the system prompt separately from the messages
+        let system = messages
+            .iter()
+            .filter(|item| item.ty() == MessageType::SystemPrompt)
+            .map(|item| item.contents())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let messages = messages
+            .iter()
+            .filter_map(|item| {
+                let role = match item.ty() {
+                    MessageType::SystemPrompt => return None,
+                    MessageType::UserMessage => "user",
+                    MessageType::ModelAnswer => "assistant",
+                };
+                Some(AnthropicMessage {
+                    role,
+                    content: item.contents(),
+                })
+            })
+            .collect();
+        warn_unsupported_parameters(
+            "Anthropic",
+            &[
+                ("seed", generation_parameters.seed.is_some()),
+                ("min_p", generation_parameters.min_p.is_some()),
+                ("typical_p", generation_parameters.typical_p.is_some()),
+                ("logit bias", !generation_parameters.logit_bias.is_empty()),
+            ],
+        );
+        // Anthropic only accepts temperatures between 0 and 1
+        let temperature = generation_parameters.temperature;
+        if !(0. ..=1.).contains(&temperature) {
+            log::warn!(
+                "Anthropic only accepts temperatures between 0 and 1. The temperature {temperature} is clamped to {}",
+                temperature.clamp(0., 1.)
+            );
+        }
+        let request = AnthropicRequest {
+            model: &self.model,
+            system: (!system.is_empty()).then_some(system),
+            messages,
+            max_tokens: generation_parameters.max_length,
+            temperature: temperature.clamp(0., 1.),
+            top_k: generation_parameters.top_k,
+            top_p: generation_parameters.top_p,
+            stop_sequences: generation_parameters.stop_on.iter().cloned().collect(),
+            stream: true,
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(&request)
+            .send()
+            .await?;
+        let response = check_status(response).await?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let result = read_lines(response, |line| {
+                // Only the data lines of the event stream contain the message
+                let Some(data) = line.strip_prefix("data:") else {
+                    return Ok(true);
+                };
+                let event: AnthropicStreamEvent = serde_json::from_str(data.trim())?;
+                match event {
+                    AnthropicStreamEvent::ContentBlockDelta {
+                        delta: AnthropicDelta::TextDelta { text },
+                    } => Ok(tx.send(Ok(text)).is_ok()),
+                    AnthropicStreamEvent::MessageStop => Ok(false),
+                    AnthropicStreamEvent::Error { error } => {
+                        Err(anyhow::anyhow!("{}: {}", error.r#type, error.message))
+                    }
+                    _ => Ok(true),
+                }
+            })
+            .await;
+            if let Err(err) = result {
+                _ = tx.send(Err(err));
+            }
+        });
+
+        Ok(Box::pin(futures_util::stream::poll_fn(move |cx| {
+            rx.poll_recv(cx)
+        })))
+    }
+}
+
+#[derive(Serialize)]
+struct AnthropicRequest<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage<'a>>,
+    max_tokens: u32,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+    stream: bool,
+}
+
+#[derive(Serialize)]
+struct AnthropicMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicStreamEvent {
+    ContentBlockDelta {
+        delta: AnthropicDelta,
+    },
+    MessageStop,
+    Error {
+        error: AnthropicError,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicDelta {
+    TextDelta {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct AnthropicError {
+    r#type: String,
+    message: String,
+}
+
+#[tokio::test]
+async fn anthropic_streams_messages() {
+    use crate::ModelExt;
+
+    let server = super::StubServer::start(
+        "text/event-stream",
+        concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"role\":\"assistant\",\"content\":[]}}\n\n",
+            "event: content_block_start\n",
+            "data: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" world\"}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        ),
+    )
+    .await;
+    let model = Anthropic::builder()
+        .with_model("claude-3-5-haiku-latest")
+        .with_api_key("test-key")
+        .with_base_url(&server.url)
+        .build();
+
+    let text = model
+        .generate_text("Say hello")
+        .with_max_length(10)
+        .await
+        .unwrap();
+    assert_eq!(text, "Hello world");
+
+    let request = server.request().await;
+    assert!(request.starts_with("POST /v1/messages"));
+    assert!(request.contains("x-api-key: test-key"));
+    let body: serde_json::Value =
+        serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(body["max_tokens"], 10);
+    assert_eq!(
+        body["messages"],
+        serde_json::json!([{ "role": "user", "content": "Say hello" }])
+    );
+}
+
+#[tokio::test]
+async fn anthropic_errors_midway_end_the_stream() {
+    use crate::ModelExt;
+    use futures_util::StreamExt;
+
+    let body = concat!(
+        "event: content_block_delta\n",
+        "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+        "event: error\n",
+        "data: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n",
+    );
+    let server = super::StubServer::start("text/event-stream", body).await;
+    let model = Anthropic::builder()
+        .with_model("claude-3-5-haiku-latest")
+        .with_base_url(&server.url)
+        .build();
+
+    let messages = [ChatHistoryItem::new(MessageType::UserMessage, "Hello!")];
+    let stream = crate::Model::stream_chat_fallible_inner(
+        &model,
+        &messages,
+        GenerationParameters::default(),
+    )
+    .await
+    .unwrap();
+    let items: Vec<_> = stream.collect().await;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap(), "Hello");
+    assert!(items[1]
+        .as_ref()
+        .unwrap_err()
+        .to_string()
+        .contains("overloaded_error"));
+
+    // Generating the whole text returns the error instead of the text before it
+    let server = super::StubServer::start("text/event-stream", body).await;
+    let model = Anthropic::builder()
+        .with_model("claude-3-5-haiku-latest")
+        .with_base_url(&server.url)
+        .build();
+    assert!(model.generate_text("Say hello").await.is_err());
+}
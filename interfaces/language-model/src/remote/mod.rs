@@ -1,2 +1,149 @@
 mod open_ai;
 pub use open_ai::*;
+mod ollama;
+pub use ollama::*;
+mod anthropic;
+pub use anthropic::*;
+
+use futures_util::StreamExt;
+
+/// Return an error with the response body if the request failed.
+async fn check_status(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        let body = response.text().await.unwrap_or_default();
+        Err(anyhow::anyhow!(
+            "Request failed with status {status}: {body}"
+        ))
+    }
+}
+
+/// Read a streaming response line by line. Reading stops early if `on_line` returns `false`.
+async fn read_lines(
+    response: reqwest::Response,
+    mut on_line: impl FnMut(&str) -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    let mut stream = response.bytes_stream();
+    let mut buffer = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        buffer.extend_from_slice(&chunk?);
+        while let Some(newline) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line = buffer.drain(..=newline).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if !line.is_empty() && !on_line(line)? {
+                return Ok(());
+            }
+        }
+    }
+    let line = String::from_utf8_lossy(&buffer);
+    let line = line.trim();
+    if !line.is_empty() {
+        on_line(line)?;
+    }
+    Ok(())
+}
+
+/// Forward a fallible stream into a text stream. A text stream can't carry errors, so an error is logged and ends the stream early. The fallible methods of [`Model`](crate::Model) return the error instead.
+fn forward_to_text_stream(
+    api: &'static str,
+    mut stream: crate::FallibleTextStream,
+) -> kalosm_streams::text_stream::ChannelTextStream {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(text) = stream.next().await {
+            match text {
+                Ok(text) => {
+                    if tx.send(text).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    log::error!("Error in {api} stream: {err}");
+                    break;
+                }
+            }
+        }
+    });
+    rx.into()
+}
+
+/// Warn about each generation parameter that is set but not supported by the API so it isn't ignored silently.
+fn warn_unsupported_parameters(api: &str, parameters: &[(&str, bool)]) {
+    for (parameter, set) in parameters {
+        if *set {
+            log::warn!(
+                "{api} does not support the {parameter} generation parameter, it will be ignored"
+            );
+        }
+    }
+}
+
+/// A local HTTP server that answers one request with a recorded response.
+#[cfg(test)]
+struct StubServer {
+    url: String,
+    request: tokio::sync::oneshot::Receiver<String>,
+}
+
+#[cfg(test)]
+impl StubServer {
+    /// Start a server that answers the next request with the body and content type.
+    async fn start(content_type: &'static str, body: &'static str) -> Self {
+        Self::start_with_status("200 OK", content_type, body).await
+    }
+
+    /// Start a server that answers the next request with the status, body and content type.
+    async fn start_with_status(
+        status: &'static str,
+        content_type: &'static str,
+        body: &'static str,
+    ) -> Self {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            // Read the headers and then the body
+            loop {
+                let read = socket.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some(header_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..header_end]
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().ok())?
+                        })
+                        .unwrap_or(0);
+                    if request.len() >= header_end + 4 + content_length {
+                        break;
+                    }
+                }
+                if read == 0 {
+                    break;
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            _ = tx.send(String::from_utf8_lossy(&request).to_string());
+        });
+        Self { url, request: rx }
+    }
+
+    /// Wait for the raw request the server received.
+    async fn request(self) -> String {
+        self.request.await.unwrap()
+    }
+}
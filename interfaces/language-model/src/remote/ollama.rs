@@ -0,0 +1,629 @@
+use futures_util::Future;
+use kalosm_common::*;
+use kalosm_sample::SchemaType;
+use kalosm_streams::text_stream::ChannelTextStream;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokenizers::tokenizer::Tokenizer;
+
+use super::{check_status, read_lines, warn_unsupported_parameters};
+use crate::{
+    ChatHistoryItem, Embedder, Embedding, FallibleTextStream, GenerationParameters, MessageType,
+    ModelBuilder, UnknownVectorSpace,
+};
+
+const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+
+/// A model that uses Ollama's native API.
+pub struct Ollama {
+    model: String,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+/// A builder for a model hosted with Ollama.
+#[derive(Debug, Default)]
+pub struct OllamaBuilder<const WITH_NAME: bool> {
+    model: Option<String>,
+    base_url: Option<String>,
+}
+
+impl OllamaBuilder<false> {
+    /// Creates a new builder
+    pub fn new() -> Self {
+        Self {
+            model: None,
+            base_url: None,
+        }
+    }
+
+    /// Set the name of the model to use (for example `llama3.1`).
+    pub fn with_model(self, model: impl ToString) -> OllamaBuilder<true> {
+        OllamaBuilder {
+            model: Some(model.to_string()),
+            base_url: self.base_url,
+        }
+    }
+}
+
+impl<const WITH_NAME: bool> OllamaBuilder<WITH_NAME> {
+    /// Set the base URL of the Ollama server (defaults to `http://localhost:11434`).
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+}
+
+impl OllamaBuilder<true> {
+    /// Build the model.
+    pub fn build(self) -> Ollama {
+        Ollama {
+            model: self.model.unwrap(),
+            base_url: base_url(self.base_url),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ModelBuilder for OllamaBuilder<true> {
+    type Model = Ollama;
+
+    async fn start_with_loading_handler(
+        self,
+        _: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> anyhow::Result<Ollama> {
+        Ok(self.build())
+    }
+
+    fn requires_download(&self) -> bool {
+        false
+    }
+}
+
+impl Ollama {
+    /// Creates a new builder
+    pub fn builder() -> OllamaBuilder<false> {
+        OllamaBuilder::new()
+    }
+
+    /// Send a streaming request to an Ollama endpoint. The stream ends with an error if the response fails partway through.
+    async fn send(
+        client: &reqwest::Client,
+        url: String,
+        request: &OllamaRequest<'_>,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let response = check_status(client.post(url).json(request).send().await?).await?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let result = read_response(response, |text| tx.send(Ok(text)).is_ok()).await;
+            if let Err(err) = result {
+                _ = tx.send(Err(err));
+            }
+        });
+
+        Ok(Box::pin(futures_util::stream::poll_fn(move |cx| {
+            rx.poll_recv(cx)
+        })))
+    }
+}
+
+/// Read the text from a streaming Ollama response. Reading stops early if `on_text` returns `false`.
+async fn read_response(
+    response: reqwest::Response,
+    mut on_text: impl FnMut(String) -> bool,
+) -> anyhow::Result<()> {
+    read_lines(response, |line| {
+        let chunk: OllamaStreamChunk = serde_json::from_str(line)?;
+        if let Some(error) = chunk.error {
+            return Err(anyhow::anyhow!(error));
+        }
+        let text = chunk
+            .response
+            .or(chunk.message.map(|message| message.content));
+        if let Some(text) = text.filter(|text| !text.is_empty()) {
+            if !on_text(text) {
+                return Ok(false);
+            }
+        }
+        Ok(!chunk.done)
+    })
+    .await
+}
+
+#[async_trait::async_trait]
+impl crate::model::Model for Ollama {
+    type TextStream = ChannelTextStream;
+    type SyncModel = crate::SyncModelNotSupported;
+
+    fn tokenizer(&self) -> Arc<Tokenizer> {
+        panic!("Ollama does not expose tokenization")
+    }
+
+    async fn stream_text_inner(
+        &self,
+        prompt: &str,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        let stream = self
+            .stream_text_fallible_inner(prompt, generation_parameters)
+            .await?;
+        Ok(super::forward_to_text_stream("Ollama", stream))
+    }
+
+    async fn stream_text_fallible_inner(
+        &self,
+        prompt: &str,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let request = OllamaRequest {
+            model: &self.model,
+            prompt: Some(prompt),
+            messages: None,
+            format: None,
+            stream: true,
+            options: OllamaOptions::from(&generation_parameters),
+        };
+        Self::send(
+            &self.client,
+            format!("{}/api/generate", self.base_url),
+            &request,
+        )
+        .await
+    }
+
+    async fn stream_chat_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        let stream = self
+            .stream_chat_fallible_inner(messages, generation_parameters)
+            .await?;
+        Ok(super::forward_to_text_stream("Ollama", stream))
+    }
+
+    async fn stream_chat_fallible_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let request = OllamaRequest {
+            model: &self.model,
+            prompt: None,
+            messages: Some(messages.iter().map(OllamaMessage::from).collect()),
+            format: None,
+            stream: true,
+            options: OllamaOptions::from(&generation_parameters),
+        };
+        Self::send(
+            &self.client,
+            format!("{}/api/chat", self.base_url),
+            &request,
+        )
+        .await
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        true
+    }
+
+    fn stream_chat_with_schema_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        schema: Option<&SchemaType>,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let model = self.model.clone();
+        let messages = messages.iter().map(OllamaMessage::from).collect::<Vec<_>>();
+        // Ollama generates any JSON value with the `json` format
+        let format = match schema {
+            Some(schema) => serde_json::from_str(&schema.to_string())?,
+            None => serde_json::Value::from("json"),
+        };
+        let client = self.client.clone();
+        let url = format!("{}/api/chat", self.base_url);
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let request = OllamaRequest {
+                model: &model,
+                prompt: None,
+                messages: Some(messages),
+                format: Some(format),
+                stream: true,
+                options: OllamaOptions::from(&generation_parameters),
+            };
+            let result = async {
+                let response = check_status(client.post(url).json(&request).send().await?).await?;
+                read_response(response, |text| tx.send(Ok(text)).is_ok()).await
+            }
+            .await;
+            if let Err(err) = result {
+                _ = tx.send(Err(err));
+            }
+        });
+
+        Ok(Box::pin(futures_util::stream::poll_fn(move |cx| {
+            rx.poll_recv(cx)
+        })))
+    }
+}
+
+/// An embedding model hosted with Ollama.
+pub struct OllamaEmbedder {
+    model: String,
+    base_url: String,
+    client: reqwest::Client,
+}
+
+/// A builder for an embedding model hosted with Ollama.
+#[derive(Debug, Default)]
+pub struct OllamaEmbedderBuilder<const WITH_NAME: bool> {
+    model: Option<String>,
+    base_url: Option<String>,
+}
+
+impl OllamaEmbedderBuilder<false> {
+    /// Creates a new builder
+    pub fn new() -> Self {
+        Self {
+            model: None,
+            base_url: None,
+        }
+    }
+
+    /// Set the name of the embedding model to use (for example `nomic-embed-text`).
+    pub fn with_model(self, model: impl ToString) -> OllamaEmbedderBuilder<true> {
+        OllamaEmbedderBuilder {
+            model: Some(model.to_string()),
+            base_url: self.base_url,
+        }
+    }
+}
+
+impl<const WITH_NAME: bool> OllamaEmbedderBuilder<WITH_NAME> {
+    /// Set the base URL of the Ollama server (defaults to `http://localhost:11434`).
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+}
+
+impl OllamaEmbedderBuilder<true> {
+    /// Build the model.
+    pub fn build(self) -> OllamaEmbedder {
+        OllamaEmbedder {
+            model: self.model.unwrap(),
+            base_url: base_url(self.base_url),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ModelBuilder for OllamaEmbedderBuilder<true> {
+    type Model = OllamaEmbedder;
+
+    async fn start_with_loading_handler(
+        self,
+        _: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> anyhow::Result<OllamaEmbedder> {
+        Ok(self.build())
+    }
+
+    fn requires_download(&self) -> bool {
+        false
+    }
+}
+
+impl OllamaEmbedder {
+    /// Creates a new builder
+    pub fn builder() -> OllamaEmbedderBuilder<false> {
+        OllamaEmbedderBuilder::new()
+    }
+}
+
+impl Embedder for OllamaEmbedder {
+    type VectorSpace = UnknownVectorSpace;
+
+    fn embed_for(
+        &self,
+        input: crate::EmbeddingInput,
+    ) -> BoxedFuture<'_, anyhow::Result<Embedding<Self::VectorSpace>>> {
+        self.embed_string(input.text)
+    }
+
+    fn embed_vec_for(
+        &self,
+        inputs: Vec<crate::EmbeddingInput>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| input.text)
+            .collect::<Vec<_>>();
+        self.embed_vec(inputs)
+    }
+
+    /// Embed a single string.
+    fn embed_string(
+        &self,
+        input: String,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Embedding<UnknownVectorSpace>>> + Send + '_>>
+    {
+        Box::pin(async move {
+            self.embed_vec(vec![input])
+                .await?
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("Ollama returned no embeddings"))
+        })
+    }
+
+    /// Embed a list of strings.
+    fn embed_vec(
+        &self,
+        input: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Vec<Embedding<UnknownVectorSpace>>>> + Send + '_>>
+    {
+        Box::pin(async move {
+            let request = OllamaEmbedRequest {
+                model: &self.model,
+                input,
+            };
+            let response = self
+                .client
+                .post(format!("{}/api/embed", self.base_url))
+                .json(&request)
+                .send()
+                .await?;
+            let response: OllamaEmbedResponse = check_status(response).await?.json().await?;
+
+            Ok(response
+                .embeddings
+                .into_iter()
+                .map(|embedding| Embedding::from(embedding.into_iter()))
+                .collect())
+        })
+    }
+}
+
+fn base_url(base_url: Option<String>) -> String {
+    base_url
+        .as_deref()
+        .unwrap_or(DEFAULT_OLLAMA_URL)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+#[derive(Serialize)]
+struct OllamaRequest<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    messages: Option<Vec<OllamaMessage>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    temperature: f32,
+    num_predict: u32,
+    repeat_penalty: f32,
+    repeat_last_n: u32,
+    frequency_penalty: f32,
+    presence_penalty: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    typical_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+}
+
+impl From<&GenerationParameters> for OllamaOptions {
+    fn from(parameters: &GenerationParameters) -> Self {
+        warn_unsupported_parameters(
+            "Ollama",
+            &[("logit bias", !parameters.logit_bias.is_empty())],
+        );
+        Self {
+            temperature: parameters.temperature,
+            num_predict: parameters.max_length,
+            repeat_penalty: parameters.repetition_penalty,
+            repeat_last_n: parameters.repetition_penalty_range,
+            frequency_penalty: parameters.frequency_penalty,
+            presence_penalty: parameters.presence_penalty,
+            top_k: parameters.top_k,
+            top_p: parameters.top_p,
+            min_p: parameters.min_p,
+            typical_p: parameters.typical_p,
+            seed: parameters.seed,
+            stop: parameters.stop_on.iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaMessage {
+    #[serde(default)]
+    role: String,
+    content: String,
+}
+
+impl From<&ChatHistoryItem> for OllamaMessage {
+    fn from(item: &ChatHistoryItem) -> Self {
+        let role = match item.ty() {
+            MessageType::SystemPrompt => "system",
+            MessageType::UserMessage => "user",
+            MessageType::ModelAnswer => "assistant",
+        };
+        Self {
+            role: role.to_string(),
+            content: item.contents().to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OllamaStreamChunk {
+    response: Option<String>,
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OllamaEmbedRequest<'a> {
+    model: &'a str,
+    input: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct OllamaEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[tokio::test]
+async fn ollama_streams_generate_response() {
+    use crate::ModelExt;
+
+    let server = super::StubServer::start(
+        "application/x-ndjson",
+        concat!(
+            "{\"model\":\"llama3.1\",\"response\":\"Hello\",\"done\":false}\n",
+            "{\"model\":\"llama3.1\",\"response\":\" world\",\"done\":false}\n",
+            "{\"model\":\"llama3.1\",\"response\":\"\",\"done\":true}\n",
+        ),
+    )
+    .await;
+    let model = Ollama::builder()
+        .with_model("llama3.1")
+        .with_base_url(&server.url)
+        .build();
+
+    let text = model
+        .generate_text("Say hello")
+        .with_max_length(10)
+        .with_stop_on(Some("!".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(text, "Hello world");
+
+    let request = server.request().await;
+    assert!(request.starts_with("POST /api/generate"));
+    let body: serde_json::Value =
+        serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(body["prompt"], "Say hello");
+    assert_eq!(body["options"]["num_predict"], 10);
+    assert_eq!(body["options"]["stop"], serde_json::json!(["!"]));
+}
+
+#[tokio::test]
+async fn ollama_json_errors_end_the_stream() {
+    let server = super::StubServer::start_with_status(
+        "404 Not Found",
+        "application/json",
+        "{\"error\":\"model 'llama3.1' not found\"}",
+    )
+    .await;
+    let model = Ollama::builder()
+        .with_model("llama3.1")
+        .with_base_url(&server.url)
+        .build();
+
+    let messages = [ChatHistoryItem::new(MessageType::UserMessage, "Say hello")];
+    let mut stream = crate::Model::stream_chat_with_schema_inner(
+        &model,
+        &messages,
+        None,
+        GenerationParameters::default(),
+    )
+    .unwrap();
+    let error = futures_util::StreamExt::next(&mut stream)
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert!(error.to_string().contains("not found"));
+
+    let request = server.request().await;
+    let body: serde_json::Value =
+        serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(body["format"], "json");
+}
+
+#[tokio::test]
+async fn ollama_errors_midway_end_the_stream() {
+    use crate::ModelExt;
+    use futures_util::StreamExt;
+
+    let body = concat!(
+        "{\"model\":\"llama3.1\",\"response\":\"Hello\",\"done\":false}\n",
+        "{\"error\":\"the model runner stopped\"}\n",
+    );
+    let server = super::StubServer::start("application/x-ndjson", body).await;
+    let model = Ollama::builder()
+        .with_model("llama3.1")
+        .with_base_url(&server.url)
+        .build();
+
+    let stream = crate::Model::stream_text_fallible_inner(
+        &model,
+        "Say hello",
+        GenerationParameters::default(),
+    )
+    .await
+    .unwrap();
+    let items: Vec<_> = stream.collect().await;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap(), "Hello");
+    assert_eq!(
+        items[1].as_ref().unwrap_err().to_string(),
+        "the model runner stopped"
+    );
+
+    // Generating the whole text returns the error instead of the text before it
+    let server = super::StubServer::start("application/x-ndjson", body).await;
+    let model = Ollama::builder()
+        .with_model("llama3.1")
+        .with_base_url(&server.url)
+        .build();
+    assert!(model.generate_text("Say hello").await.is_err());
+}
+
+#[tokio::test]
+async fn ollama_embeds_batches() {
+    let server = super::StubServer::start(
+        "application/json",
+        "{\"model\":\"nomic-embed-text\",\"embeddings\":[[0.5,1.0],[1.5,2.0]]}",
+    )
+    .await;
+    let embedder = OllamaEmbedder::builder()
+        .with_model("nomic-embed-text")
+        .with_base_url(&server.url)
+        .build();
+
+    let embeddings = embedder
+        .embed_vec(vec!["a".to_string(), "b".to_string()])
+        .await
+        .unwrap();
+    assert_eq!(embeddings.len(), 2);
+    assert_eq!(embeddings[1].to_vec(), vec![1.5, 2.0]);
+
+    let request = server.request().await;
+    assert!(request.starts_with("POST /api/embed"));
+}
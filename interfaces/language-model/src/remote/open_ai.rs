@@ -1,17 +1,43 @@
-use async_openai::types::CreateEmbeddingRequestArgs;
+use async_openai::types::{
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
+    ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessageArgs,
+    ChatCompletionResponseStream, ChatCompletionTool, ChatCompletionToolArgs,
+    ChatCompletionToolType, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+    CreateChatCompletionStreamResponse, CreateEmbeddingRequestArgs, FunctionCall,
+    FunctionObjectArgs, ResponseFormat, ResponseFormatJsonSchema,
+};
 use async_openai::{types::CreateCompletionRequestArgs, Client};
 use futures_util::{Future, StreamExt};
 use kalosm_common::*;
 use kalosm_streams::text_stream::ChannelTextStream;
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokenizers::tokenizer::Tokenizer;
 
-use crate::{Embedder, Embedding, GenerationParameters, ModelBuilder, VectorSpace};
+use kalosm_sample::SchemaType;
+
+use super::warn_unsupported_parameters;
+use crate::{
+    ChatHistoryItem, Embedder, Embedding, FallibleTextStream, GenerationParameters, MessageType,
+    ModelBuilder, ToolCall, ToolChatMessage, ToolChatResponse, ToolDefinition, VectorSpace,
+};
+
+/// The endpoint a [`RemoteOpenAICompatibleModel`] uses to generate text from a prompt.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OpenAICompatibleApi {
+    /// The legacy `/completions` endpoint. The prompt is sent as raw text.
+    #[default]
+    Completions,
+    /// The `/chat/completions` endpoint. The prompt is sent as a single user message.
+    ChatCompletions,
+}
 
 /// A model that uses OpenAI's API.
 pub struct RemoteOpenAICompatibleModel {
     model: String,
+    api: OpenAICompatibleApi,
     client: Client<async_openai::config::OpenAIConfig>,
 }
 
@@ -19,6 +45,7 @@ pub struct RemoteOpenAICompatibleModel {
 #[derive(Debug, Default)]
 pub struct RemoteOpenAICompatibleModelBuilder<const WITH_NAME: bool> {
     model: Option<String>,
+    api: OpenAICompatibleApi,
     config: async_openai::config::OpenAIConfig,
 }
 
@@ -27,6 +54,7 @@ impl RemoteOpenAICompatibleModelBuilder<false> {
     pub fn new() -> Self {
         Self {
             model: None,
+            api: OpenAICompatibleApi::default(),
             config: Default::default(),
         }
     }
@@ -35,6 +63,7 @@ impl RemoteOpenAICompatibleModelBuilder<false> {
     pub fn with_model(self, model: impl ToString) -> RemoteOpenAICompatibleModelBuilder<true> {
         RemoteOpenAICompatibleModelBuilder {
             model: Some(model.to_string()),
+            api: self.api,
             config: self.config,
         }
     }
@@ -58,6 +87,19 @@ impl<const WITH_NAME: bool> RemoteOpenAICompatibleModelBuilder<WITH_NAME> {
         self.config = self.config.with_org_id(organization_id);
         self
     }
+
+    /// Set the endpoint used to generate text from a prompt (defaults to [`OpenAICompatibleApi::Completions`]).
+    ///
+    /// Chat sessions always use the chat completions endpoint.
+    pub fn with_api(mut self, api: OpenAICompatibleApi) -> Self {
+        self.api = api;
+        self
+    }
+
+    /// Generate text from prompts with the `/chat/completions` endpoint. Most local servers (llama.cpp, vLLM, Ollama) and hosted models only support this endpoint.
+    pub fn with_chat_completions(self) -> Self {
+        self.with_api(OpenAICompatibleApi::ChatCompletions)
+    }
 }
 
 impl RemoteOpenAICompatibleModelBuilder<true> {
@@ -65,6 +107,7 @@ impl RemoteOpenAICompatibleModelBuilder<true> {
     pub fn build(self) -> RemoteOpenAICompatibleModel {
         RemoteOpenAICompatibleModel {
             model: self.model.unwrap(),
+            api: self.api,
             client: Client::with_config(self.config),
         }
     }
@@ -91,49 +134,388 @@ impl crate::model::Model for RemoteOpenAICompatibleModel {
         prompt: &str,
         generation_parameters: GenerationParameters,
     ) -> anyhow::Result<Self::TextStream> {
+        let stream = self
+            .stream_text_fallible_inner(prompt, generation_parameters)
+            .await?;
+        Ok(super::forward_to_text_stream("OpenAI", stream))
+    }
+
+    async fn stream_text_fallible_inner(
+        &self,
+        prompt: &str,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        if self.api == OpenAICompatibleApi::ChatCompletions {
+            let messages = [ChatHistoryItem::new(MessageType::UserMessage, prompt)];
+            return self
+                .stream_chat_fallible_inner(&messages, generation_parameters)
+                .await;
+        }
+
+        warn_unsupported_open_ai_parameters(&generation_parameters);
         let mut builder = CreateCompletionRequestArgs::default();
         builder
             .model(&self.model)
             .n(1)
             .prompt(prompt)
             .stream(true)
-            .frequency_penalty(generation_parameters.repetition_penalty)
+            .frequency_penalty(generation_parameters.frequency_penalty)
+            .presence_penalty(generation_parameters.presence_penalty)
             .temperature(generation_parameters.temperature)
             .max_tokens(generation_parameters.max_length as u16);
+        if let Some(top_p) = generation_parameters.top_p {
+            builder.top_p(top_p);
+        }
+        if let Some(seed) = generation_parameters.seed {
+            builder.seed(seed as i64);
+        }
+        if !generation_parameters.logit_bias.tokens().is_empty() {
+            builder.logit_bias(open_ai_logit_bias(&generation_parameters));
+        }
         if let Some(stop_on) = generation_parameters.stop_on {
             builder.stop(stop_on);
         }
         let request = builder.build()?;
 
-        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
         let mut stream = self.client.completions().create_stream(request).await?;
 
         tokio::spawn(async move {
             while let Some(response) = stream.next().await {
-                match response {
-                    Ok(response) => {
-                        let text = response.choices[0].text.clone();
-                        if tx.send(text).is_err() {
+                let text = response
+                    .map(|response| response.choices[0].text.clone())
+                    .map_err(anyhow::Error::from);
+                // The stream ends with the first error
+                let failed = text.is_err();
+                if tx.send(text).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Box::pin(futures_util::stream::poll_fn(move |cx| {
+            rx.poll_recv(cx)
+        })))
+    }
+
+    async fn stream_chat_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        let stream = self
+            .stream_chat_fallible_inner(messages, generation_parameters)
+            .await?;
+        Ok(super::forward_to_text_stream("OpenAI", stream))
+    }
+
+    async fn stream_chat_fallible_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let messages = messages
+            .iter()
+            .map(chat_completion_message)
+            .collect::<Result<Vec<_>, _>>()?;
+        let request = self
+            .chat_completion_request(messages, generation_parameters)
+            .stream(true)
+            .build()?;
+
+        stream_chat_completion(&self.client, request).await
+    }
+
+    fn supports_json_schema(&self) -> bool {
+        self.api == OpenAICompatibleApi::ChatCompletions
+    }
+
+    fn stream_chat_with_schema_inner(
+        &self,
+        messages: &[ChatHistoryItem],
+        schema: Option<&SchemaType>,
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<FallibleTextStream> {
+        let response_format = match schema {
+            Some(schema) => ResponseFormat::JsonSchema {
+                json_schema: ResponseFormatJsonSchema {
+                    description: None,
+                    name: "response".to_string(),
+                    schema: Some(serde_json::from_str(&schema.to_string())?),
+                    // Strict mode requires every property to be required. The response is validated locally instead.
+                    strict: None,
+                },
+            },
+            None => ResponseFormat::JsonObject,
+        };
+        let messages = messages
+            .iter()
+            .map(chat_completion_message)
+            .collect::<Result<Vec<_>, _>>()?;
+        let request = self
+            .chat_completion_request(messages, generation_parameters)
+            .stream(true)
+            .response_format(response_format)
+            .build()?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let result = async {
+                let mut stream = client.chat().create_stream(request).await?;
+                while let Some(response) = stream.next().await {
+                    if let Some(text) = chunk_text(response?) {
+                        if tx.send(Ok(text)).is_err() {
                             break;
                         }
                     }
-                    Err(e) => {
-                        log::error!("Error in OpenAI stream: {}", e);
-                        break;
-                    }
                 }
+                Ok::<_, anyhow::Error>(())
+            }
+            .await;
+            if let Err(err) = result {
+                _ = tx.send(Err(err));
             }
-
-            Ok::<(), anyhow::Error>(())
         });
 
-        Ok(rx.into())
+        Ok(Box::pin(futures_util::stream::poll_fn(move |cx| {
+            rx.poll_recv(cx)
+        })))
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        self.api == OpenAICompatibleApi::ChatCompletions
+    }
+
+    async fn chat_with_tools_inner(
+        &self,
+        messages: &[ToolChatMessage],
+        tools: &[ToolDefinition],
+        generation_parameters: GenerationParameters,
+    ) -> anyhow::Result<ToolChatResponse> {
+        let messages = messages
+            .iter()
+            .map(tool_chat_completion_message)
+            .collect::<Result<Vec<_>, _>>()?;
+        let tools = tools
+            .iter()
+            .map(chat_completion_tool)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let request = self
+            .chat_completion_request(messages, generation_parameters)
+            .tools(tools)
+            .build()?;
+
+        let response = self.client.chat().create(request).await?;
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("OpenAI response has no choices"))?
+            .message;
+
+        Ok(match message.tool_calls {
+            Some(tool_calls) if !tool_calls.is_empty() => ToolChatResponse::ToolCalls(
+                tool_calls
+                    .into_iter()
+                    .map(|call| ToolCall::new(call.id, call.function.name, call.function.arguments))
+                    .collect(),
+            ),
+            _ => ToolChatResponse::Message(message.content.unwrap_or_default()),
+        })
     }
 }
 
-macro_rules! openai_completion_model {
-    ($ty: ident, $tybuilder: ident, $model: literal) => {
+impl RemoteOpenAICompatibleModel {
+    /// Create a chat completion request builder with the generation parameters.
+    fn chat_completion_request(
+        &self,
+        messages: Vec<ChatCompletionRequestMessage>,
+        generation_parameters: GenerationParameters,
+    ) -> CreateChatCompletionRequestArgs {
+        warn_unsupported_open_ai_parameters(&generation_parameters);
+        let mut builder = CreateChatCompletionRequestArgs::default();
+        builder
+            .model(&self.model)
+            .n(1)
+            .messages(messages)
+            .frequency_penalty(generation_parameters.frequency_penalty)
+            .presence_penalty(generation_parameters.presence_penalty)
+            .temperature(generation_parameters.temperature)
+            .max_tokens(generation_parameters.max_length);
+        if let Some(top_p) = generation_parameters.top_p {
+            builder.top_p(top_p);
+        }
+        if let Some(seed) = generation_parameters.seed {
+            builder.seed(seed as i64);
+        }
+        if !generation_parameters.logit_bias.tokens().is_empty() {
+            builder.logit_bias(open_ai_logit_bias(&generation_parameters));
+        }
+        if let Some(stop_on) = generation_parameters.stop_on {
+            builder.stop(stop_on);
+        }
+        builder
+    }
+}
+
+/// Warn about the generation parameters the OpenAI API doesn't accept.
+fn warn_unsupported_open_ai_parameters(generation_parameters: &GenerationParameters) {
+    warn_unsupported_parameters(
+        "OpenAI",
+        &[
+            ("top_k", generation_parameters.top_k.is_some()),
+            ("min_p", generation_parameters.min_p.is_some()),
+            ("typical_p", generation_parameters.typical_p.is_some()),
+            (
+                "text logit bias",
+                !generation_parameters.logit_bias.text().is_empty(),
+            ),
+        ],
+    );
+}
+
+/// Convert the logit biases keyed by token id to the format OpenAI expects. OpenAI doesn't expose its tokenizer, so biases keyed by text are not sent
+fn open_ai_logit_bias(
+    generation_parameters: &GenerationParameters,
+) -> HashMap<String, serde_json::Value> {
+    generation_parameters
+        .logit_bias
+        .tokens()
+        .iter()
+        .map(|(token, bias)| {
+            // OpenAI only accepts biases between -100 and 100. A bias of -100 bans the token
+            (
+                token.to_string(),
+                serde_json::Value::from(bias.value().clamp(-100., 100.)),
+            )
+        })
+        .collect()
+}
+
+/// Send a streaming chat completion request. The request is only sent once the response stream is polled, so this waits for the first chunk to return an error if the request failed. Errors after the first chunk end the returned stream.
+async fn stream_chat_completion(
+    client: &Client<async_openai::config::OpenAIConfig>,
+    request: CreateChatCompletionRequest,
+) -> anyhow::Result<FallibleTextStream> {
+    let mut stream = client.chat().create_stream(request).await?;
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    if let Some(text) = stream.next().await.transpose()?.and_then(chunk_text) {
+        _ = tx.send(Ok(text));
+    }
+    tokio::spawn(forward_chat_completion_stream(stream, tx));
+
+    Ok(Box::pin(futures_util::stream::poll_fn(move |cx| {
+        rx.poll_recv(cx)
+    })))
+}
+
+/// Get the text of a chunk of a streaming chat completion.
+fn chunk_text(response: CreateChatCompletionStreamResponse) -> Option<String> {
+    response
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.delta.content)
+}
+
+/// Forward the text of a streaming chat completion into a channel. If the stream fails, the error is sent and forwarding stops.
+async fn forward_chat_completion_stream(
+    mut stream: ChatCompletionResponseStream,
+    tx: tokio::sync::mpsc::UnboundedSender<anyhow::Result<String>>,
+) {
+    while let Some(response) = stream.next().await {
+        match response {
+            Ok(response) => {
+                let Some(text) = chunk_text(response) else {
+                    continue;
+                };
+                if tx.send(Ok(text)).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                _ = tx.send(Err(err.into()));
+                break;
+            }
+        }
+    }
+}
+
+/// Convert a chat history item into a role-tagged chat completion message.
+fn chat_completion_message(
+    item: &ChatHistoryItem,
+) -> Result<ChatCompletionRequestMessage, async_openai::error::OpenAIError> {
+    let contents = item.contents().to_string();
+    Ok(match item.ty() {
+        MessageType::SystemPrompt => ChatCompletionRequestSystemMessageArgs::default()
+            .content(contents)
+            .build()?
+            .into(),
+        MessageType::UserMessage => ChatCompletionRequestUserMessageArgs::default()
+            .content(contents)
+            .build()?
+            .into(),
+        MessageType::ModelAnswer => ChatCompletionRequestAssistantMessageArgs::default()
+            .content(contents)
+            .build()?
+            .into(),
+    })
+}
+
+/// Convert a message in a chat with tools into a chat completion message.
+fn tool_chat_completion_message(
+    message: &ToolChatMessage,
+) -> Result<ChatCompletionRequestMessage, async_openai::error::OpenAIError> {
+    Ok(match message {
+        ToolChatMessage::Chat(item) => chat_completion_message(item)?,
+        ToolChatMessage::ToolCalls(calls) => ChatCompletionRequestAssistantMessageArgs::default()
+            .tool_calls(
+                calls
+                    .iter()
+                    .map(|call| ChatCompletionMessageToolCall {
+                        id: call.id().to_string(),
+                        r#type: ChatCompletionToolType::Function,
+                        function: FunctionCall {
+                            name: call.name().to_string(),
+                            arguments: call.arguments().to_string(),
+                        },
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .build()?
+            .into(),
+        ToolChatMessage::ToolResult { id, output } => {
+            ChatCompletionRequestToolMessageArgs::default()
+                .tool_call_id(id.clone())
+                .content(output.clone())
+                .build()?
+                .into()
+        }
+    })
+}
+
+/// Convert a tool definition into a chat completion function tool.
+fn chat_completion_tool(tool: &ToolDefinition) -> anyhow::Result<ChatCompletionTool> {
+    let function = FunctionObjectArgs::default()
+        .name(tool.name())
+        .description(tool.description())
+        .parameters(serde_json::from_str::<serde_json::Value>(
+            &tool.parameters().to_string(),
+        )?)
+        .build()?;
+    Ok(ChatCompletionToolArgs::default()
+        .r#type(ChatCompletionToolType::Function)
+        .function(function)
+        .build()?)
+}
+
+macro_rules! openai_model {
+    ($ty: ident, $tybuilder: ident, $model: literal, $api: expr) => {
         /// A model that uses OpenAI's API.
         pub struct $ty {
             inner: RemoteOpenAICompatibleModel,
@@ -150,7 +532,9 @@ macro_rules! openai_completion_model {
             /// Creates a new builder
             pub fn new() -> Self {
                 Self {
-                    inner: RemoteOpenAICompatibleModelBuilder::new().with_model($model),
+                    inner: RemoteOpenAICompatibleModelBuilder::new()
+                        .with_model($model)
+                        .with_api($api),
                 }
             }
 
@@ -229,16 +613,100 @@ macro_rules! openai_completion_model {
                     .stream_text_inner(prompt, generation_parameters)
                     .await
             }
+
+            async fn stream_text_fallible_inner(
+                &self,
+                prompt: &str,
+                generation_parameters: GenerationParameters,
+            ) -> anyhow::Result<FallibleTextStream> {
+                self.inner
+                    .stream_text_fallible_inner(prompt, generation_parameters)
+                    .await
+            }
+
+            async fn stream_chat_inner(
+                &self,
+                messages: &[ChatHistoryItem],
+                generation_parameters: GenerationParameters,
+            ) -> anyhow::Result<Self::TextStream> {
+                self.inner
+                    .stream_chat_inner(messages, generation_parameters)
+                    .await
+            }
+
+            async fn stream_chat_fallible_inner(
+                &self,
+                messages: &[ChatHistoryItem],
+                generation_parameters: GenerationParameters,
+            ) -> anyhow::Result<FallibleTextStream> {
+                self.inner
+                    .stream_chat_fallible_inner(messages, generation_parameters)
+                    .await
+            }
+
+            fn supports_json_schema(&self) -> bool {
+                self.inner.supports_json_schema()
+            }
+
+            fn stream_chat_with_schema_inner(
+                &self,
+                messages: &[ChatHistoryItem],
+                schema: Option<&SchemaType>,
+                generation_parameters: GenerationParameters,
+            ) -> anyhow::Result<FallibleTextStream> {
+                self.inner
+                    .stream_chat_with_schema_inner(messages, schema, generation_parameters)
+            }
+
+            fn supports_tool_calls(&self) -> bool {
+                self.inner.supports_tool_calls()
+            }
+
+            async fn chat_with_tools_inner(
+                &self,
+                messages: &[ToolChatMessage],
+                tools: &[ToolDefinition],
+                generation_parameters: GenerationParameters,
+            ) -> anyhow::Result<ToolChatResponse> {
+                self.inner
+                    .chat_with_tools_inner(messages, tools, generation_parameters)
+                    .await
+            }
         }
     };
 }
 
-openai_completion_model!(Gpt3_5, Gpt3_5Builder, "gpt-3.5-turbo-instruct");
-// The rest of the openai models only support the chat API which currently isn't supported for remote models in kalosm
-// openai_chat_model!(Gpt4, Gpt4Builder, "gpt-4");
-// openai_chat_model!(Gpt4Turbo, Gpt4TurboBuilder, "gpt-4-turbo");
-// openai_chat_model!(Gpt4O, Gpt4OBuilder, "gpt-4o");
-// openai_chat_model!(Gpt4Mini, Gpt4MiniBuilder, "gpt-4o-mini");
+openai_model!(
+    Gpt3_5,
+    Gpt3_5Builder,
+    "gpt-3.5-turbo-instruct",
+    OpenAICompatibleApi::Completions
+);
+// The rest of the openai models only support the chat API
+openai_model!(
+    Gpt4,
+    Gpt4Builder,
+    "gpt-4",
+    OpenAICompatibleApi::ChatCompletions
+);
+openai_model!(
+    Gpt4Turbo,
+    Gpt4TurboBuilder,
+    "gpt-4-turbo",
+    OpenAICompatibleApi::ChatCompletions
+);
+openai_model!(
+    Gpt4O,
+    Gpt4OBuilder,
+    "gpt-4o",
+    OpenAICompatibleApi::ChatCompletions
+);
+openai_model!(
+    Gpt4Mini,
+    Gpt4MiniBuilder,
+    "gpt-4o-mini",
+    OpenAICompatibleApi::ChatCompletions
+);
 
 /// An embedder that uses OpenAI's API for the Ada embedding model.
 #[derive(Debug)]
@@ -387,3 +855,187 @@ impl Embedder for AdaEmbedder {
         })
     }
 }
+
+#[test]
+fn chat_history_maps_to_chat_completion_roles() {
+    let history = [
+        ChatHistoryItem::new(MessageType::SystemPrompt, "You are a pirate."),
+        ChatHistoryItem::new(MessageType::UserMessage, "Hello!"),
+        ChatHistoryItem::new(MessageType::ModelAnswer, "Arrr matey"),
+    ];
+    let messages = history
+        .iter()
+        .map(chat_completion_message)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert!(matches!(
+        messages.as_slice(),
+        [
+            ChatCompletionRequestMessage::System(_),
+            ChatCompletionRequestMessage::User(_),
+            ChatCompletionRequestMessage::Assistant(_),
+        ]
+    ));
+}
+
+#[tokio::test]
+async fn chat_completions_stream_from_server() {
+    use crate::ModelExt;
+
+    let server = super::StubServer::start(
+        "text/event-stream",
+        concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" world\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ),
+    )
+    .await;
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("gpt-4o")
+        .with_api_key("test-key")
+        .with_base_url(&server.url)
+        .with_chat_completions()
+        .build();
+
+    let text = model
+        .generate_text("Say hello")
+        .with_max_length(10)
+        .await
+        .unwrap();
+    assert_eq!(text, "Hello world");
+
+    let request = server.request().await;
+    assert!(request.starts_with("POST /chat/completions"));
+    assert!(request.contains("authorization: Bearer test-key"));
+    let body: serde_json::Value =
+        serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(body["max_tokens"], 10);
+    assert_eq!(
+        body["messages"],
+        serde_json::json!([{ "role": "user", "content": "Say hello" }])
+    );
+}
+
+#[tokio::test]
+async fn failed_chat_completions_return_an_error() {
+    let server = super::StubServer::start_with_status(
+        "500 Internal Server Error",
+        "application/json",
+        "{\"error\":{\"message\":\"The server is overloaded\",\"type\":\"server_error\",\"param\":null,\"code\":null}}",
+    )
+    .await;
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("gpt-4o")
+        .with_base_url(&server.url)
+        .build();
+
+    let messages = [ChatHistoryItem::new(MessageType::UserMessage, "Hello!")];
+    let result =
+        crate::Model::stream_chat_inner(&model, &messages, GenerationParameters::default()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn chat_completions_that_fail_midway_end_with_an_error() {
+    use crate::ModelExt;
+
+    let server = super::StubServer::start(
+        "text/event-stream",
+        concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"error\": \"The connection was reset\"\n\n",
+        ),
+    )
+    .await;
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("gpt-4o")
+        .with_base_url(&server.url)
+        .with_chat_completions()
+        .build();
+
+    let messages = [ChatHistoryItem::new(MessageType::UserMessage, "Hello!")];
+    let stream = crate::Model::stream_chat_fallible_inner(
+        &model,
+        &messages,
+        GenerationParameters::default(),
+    )
+    .await
+    .unwrap();
+    let items: Vec<_> = stream.collect().await;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap(), "Hello");
+    assert!(items[1].is_err());
+
+    // Generating the whole text returns the error instead of the text before it
+    let server = super::StubServer::start(
+        "text/event-stream",
+        concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"error\": \"The connection was reset\"\n\n",
+        ),
+    )
+    .await;
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("gpt-4o")
+        .with_base_url(&server.url)
+        .with_chat_completions()
+        .build();
+    assert!(model.generate_text("Say hello").await.is_err());
+}
+
+#[tokio::test]
+async fn structured_generation_sends_the_generation_parameters() {
+    use crate::{ModelExt, SearchStrategy};
+    use kalosm_sample::{Parse, Schema};
+
+    let server = super::StubServer::start(
+        "text/event-stream",
+        concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"42\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ),
+    )
+    .await;
+    let model = RemoteOpenAICompatibleModel::builder()
+        .with_model("gpt-4o")
+        .with_base_url(&server.url)
+        .with_chat_completions()
+        .build();
+
+    let parameters = GenerationParameters::default()
+        .with_seed(7)
+        .with_max_length(20);
+    let number = model
+        .stream_structured_text_with_schema(
+            "Pick a number",
+            i64::schema(),
+            i64::new_parser(),
+            parameters,
+        )
+        .await
+        .unwrap();
+    assert_eq!(number, 42);
+    let request = server.request().await;
+    let body: serde_json::Value =
+        serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+    assert_eq!(body["seed"], 7);
+    assert_eq!(body["max_tokens"], 20);
+
+    // Search strategies and samplers can't be sent to the model, so they return an error instead of being ignored
+    let parameters =
+        GenerationParameters::default().with_search_strategy(SearchStrategy::BestOf { n: 2 });
+    assert!(model
+        .generate_parsed_with_parameters::<i64>("Pick a number", parameters)
+        .await
+        .is_err());
+    let sampler = std::sync::Arc::new(std::sync::Mutex::new(
+        GenerationParameters::default().sampler(),
+    ));
+    let parser = i64::new_parser();
+    let state = kalosm_sample::CreateParserState::create_parser_state(&parser);
+    assert!(model
+        .stream_structured_text_with_sampler("Pick a number", parser, state, sampler)
+        .await
+        .is_err());
+}
@@ -0,0 +1,659 @@
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+use kalosm_sample::{CreateParserState, LiteralParser, ParseStatus, Parser, ParserExt};
+use llm_samplers::types::Sampler;
+
+use crate::logprobs::log_sum_exp;
+use crate::structured::{
+    generate_structured, heal_prompt, token_vocabulary, update_state, HealedPrompt,
+};
+use crate::{Session, SyncModel, TokenEvent, TokenOutputStream};
+
+/// The strategy used to pick the output of structured generation. Unstructured generation like [`ModelExt::stream_text`](crate::ModelExt::stream_text) only supports [`SearchStrategy::Sample`] and returns an error for any other strategy.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[derive(Parse, Clone, Debug)]
+/// enum Answer {
+///     Yes,
+///     No,
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let llm = Llama::new().await?;
+/// let parameters =
+///     GenerationParameters::default().with_search_strategy(SearchStrategy::BeamSearch {
+///         width: 4,
+///         max_length: 16,
+///     });
+/// let answer: Answer = llm
+///     .generate_parsed_with_parameters("Is the sky blue? ", parameters)
+///     .await?;
+/// println!("{answer:?}");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SearchStrategy {
+    /// Sample one token at a time with the sampler. This is the fastest strategy, but the model can't go back if it commits to an unlikely path early.
+    #[default]
+    Sample,
+    /// Keep the `width` most likely partial outputs at every step and return the complete output with the highest total log probability. This ignores the sampler and requires a model with sessions that support [`Session::try_clone`].
+    ///
+    /// The text is only passed to the token callback once the search finishes.
+    BeamSearch {
+        /// The number of partial outputs to keep at every step.
+        width: usize,
+        /// The maximum number of tokens in an output. If no output is complete after this many tokens, the search fails.
+        max_length: usize,
+    },
+    /// Generate `n` complete outputs with the sampler and return the output with the highest total log probability. This requires a model with sessions that support [`Session::try_clone`].
+    ///
+    /// The text is only passed to the token callback once every output is generated.
+    BestOf {
+        /// The number of outputs to generate.
+        n: usize,
+    },
+}
+
+/// Generate structured text with a search strategy. The log probability of every sampled token is included in the events passed to `on_token`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn generate_structured_with_search<M: ?Sized + SyncModel, P: Parser>(
+    prompt: impl Display,
+    llm: &M,
+    session: &mut M::Session,
+    parser: P,
+    parser_state: P::PartialState,
+    sampler: Arc<Mutex<dyn Sampler>>,
+    on_token: impl FnMut(TokenEvent) -> anyhow::Result<()>,
+    top_k: Option<usize>,
+    search: SearchStrategy,
+) -> anyhow::Result<P::Output> {
+    match search {
+        SearchStrategy::Sample => generate_structured(
+            prompt,
+            llm,
+            session,
+            parser,
+            parser_state,
+            sampler,
+            on_token,
+            top_k,
+            Some(0),
+        ),
+        SearchStrategy::BeamSearch { width, max_length } => beam_search(
+            prompt,
+            llm,
+            session,
+            parser,
+            parser_state,
+            on_token,
+            width,
+            max_length,
+        ),
+        SearchStrategy::BestOf { n } => best_of(
+            prompt,
+            llm,
+            session,
+            parser,
+            parser_state,
+            sampler,
+            on_token,
+            top_k,
+            n,
+        ),
+    }
+}
+
+/// The total log probability of the sampled tokens in a list of events
+fn total_logprob(events: &[TokenEvent]) -> f32 {
+    events.iter().filter_map(|event| event.logprob).sum()
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn best_of<M: ?Sized + SyncModel, P: Parser>(
+    prompt: impl Display,
+    llm: &M,
+    session: &mut M::Session,
+    parser: P,
+    parser_state: P::PartialState,
+    sampler: Arc<Mutex<dyn Sampler>>,
+    mut on_token: impl FnMut(TokenEvent) -> anyhow::Result<()>,
+    top_k: Option<usize>,
+    n: usize,
+) -> anyhow::Result<P::Output> {
+    let prompt = prompt.to_string();
+    let mut best: Option<(f32, P::Output, Vec<TokenEvent>, M::Session)> = None;
+    for _ in 0..n.max(1) {
+        let mut candidate_session = session.try_clone()?;
+        let mut events = Vec::new();
+        let output = generate_structured(
+            &prompt,
+            llm,
+            &mut candidate_session,
+            &parser,
+            parser_state.clone(),
+            sampler.clone(),
+            |event| {
+                events.push(event);
+                Ok(())
+            },
+            top_k,
+            Some(0),
+        )?;
+        let logprob = total_logprob(&events);
+        if best
+            .as_ref()
+            .is_none_or(|(best_logprob, ..)| logprob > *best_logprob)
+        {
+            best = Some((logprob, output, events, candidate_session));
+        }
+    }
+
+    let (_, output, events, best_session) = best.expect("at least one output is generated");
+    *session = best_session;
+    for event in events {
+        on_token(event)?;
+    }
+    Ok(output)
+}
+
+/// A partial output in beam search
+struct Beam<S, PS> {
+    session: S,
+    token_stream: TokenOutputStream,
+    parser_state: PS,
+    /// The number of tokens in the token stream that have not been fed to the session yet
+    unprocessed_token_count: usize,
+    events: Vec<TokenEvent>,
+    logprob: f32,
+    /// If the text of the prompt that was removed for prompt healing still needs to be stripped from the output
+    strip_remaining_prompt: bool,
+}
+
+impl<S: Session, PS: Clone> Beam<S, PS> {
+    fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(Self {
+            session: self.session.try_clone()?,
+            token_stream: self.token_stream.clone(),
+            parser_state: self.parser_state.clone(),
+            unprocessed_token_count: self.unprocessed_token_count,
+            events: self.events.clone(),
+            logprob: self.logprob,
+            strip_remaining_prompt: self.strip_remaining_prompt,
+        })
+    }
+}
+
+/// A token that can extend a beam
+struct BeamCandidate<PS, O> {
+    beam: usize,
+    token_id: u32,
+    logprob: f32,
+    result: ParseStatus<'static, PS, O>,
+    parsed_bytes: usize,
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn beam_search<M: ?Sized + SyncModel, P: Parser>(
+    prompt: impl Display,
+    llm: &M,
+    session: &mut M::Session,
+    parser: P,
+    parser_state: P::PartialState,
+    mut on_token: impl FnMut(TokenEvent) -> anyhow::Result<()>,
+    width: usize,
+    max_length: usize,
+) -> anyhow::Result<P::Output> {
+    let width = width.max(1);
+    let tokenizer = llm.tokenizer();
+
+    let HealedPrompt {
+        token_stream,
+        unprocessed_token_count,
+        remaining_prompt_text,
+    } = heal_prompt(prompt, &tokenizer)?;
+
+    let parser = LiteralParser::new(remaining_prompt_text.clone())
+        .ignore_output_then(parser.with_initial_state(move || parser_state.clone()));
+    let vocabulary = parser
+        .token_mask()
+        .map(|_| token_vocabulary(&tokenizer))
+        .transpose()?;
+
+    let mut beams = vec![Beam {
+        session: session.try_clone()?,
+        token_stream,
+        parser_state: parser.create_parser_state(),
+        unprocessed_token_count,
+        events: Vec::new(),
+        logprob: 0.,
+        strip_remaining_prompt: true,
+    }];
+    let mut finished: Vec<(f32, P::Output, Beam<M::Session, _>)> = Vec::new();
+    let mut logits = Vec::new();
+    let mut length = 0;
+
+    while !beams.is_empty() {
+        if length == max_length {
+            if finished.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Beam search did not finish an output within {max_length} tokens"
+                ));
+            }
+            break;
+        }
+        length += 1;
+
+        // Feed the new tokens of every beam in one batch
+        let new_tokens: Vec<Vec<u32>> = beams
+            .iter()
+            .map(|beam| {
+                let tokens = beam.token_stream.tokens();
+                tokens[tokens.len() - beam.unprocessed_token_count..].to_vec()
+            })
+            .collect();
+        let new_tokens: Vec<&[u32]> = new_tokens.iter().map(Vec::as_slice).collect();
+        let mut sessions: Vec<_> = beams.iter_mut().map(|beam| &mut beam.session).collect();
+        logits.resize_with(new_tokens.len(), Vec::new);
+        llm.feed_tokens_batch(&mut sessions, &new_tokens, &mut logits[..new_tokens.len()])?;
+
+        // Find the most likely valid tokens for each beam. No beam needs more than `width` tokens because only `width` beams are kept
+        let mut candidates = Vec::new();
+        for (index, (beam, logits)) in beams.iter().zip(&logits).enumerate() {
+            let log_sum_exp = log_sum_exp(logits);
+            let mut tokens: Vec<u32> = match (parser.token_mask(), &vocabulary) {
+                (Some(mask), Some(vocabulary)) => mask
+                    .allowed_tokens(&beam.parser_state, vocabulary)
+                    .iter()
+                    .filter(|token| (*token as usize) < logits.len())
+                    .collect(),
+                _ => (0..logits.len() as u32).collect(),
+            };
+            tokens.sort_unstable_by(|a, b| logits[*b as usize].total_cmp(&logits[*a as usize]));
+
+            let mut found = 0;
+            for token_id in tokens {
+                if found == width {
+                    break;
+                }
+                let Some(text) = beam.token_stream.peek_token(token_id)? else {
+                    continue;
+                };
+                let Ok(result) = parser.parse(&beam.parser_state, text.as_bytes()) else {
+                    continue;
+                };
+                let parsed_bytes = match result {
+                    ParseStatus::Finished { remaining, .. } => text.len() - remaining.len(),
+                    ParseStatus::Incomplete { .. } => text.len(),
+                };
+                candidates.push(BeamCandidate {
+                    beam: index,
+                    token_id,
+                    logprob: beam.logprob + logits[token_id as usize] - log_sum_exp,
+                    result: result.without_remaining(),
+                    parsed_bytes,
+                });
+                found += 1;
+            }
+        }
+
+        if candidates.is_empty() && finished.is_empty() {
+            return Err(anyhow::anyhow!("No valid tokens found"));
+        }
+        candidates.sort_unstable_by(|a, b| b.logprob.total_cmp(&a.logprob));
+        candidates.truncate(width);
+
+        // Adding tokens only makes the log probability lower. Once the best finished output is more likely than every partial output, it is the best output
+        if let (Some(best_finished), Some(best_candidate)) = (
+            finished
+                .iter()
+                .map(|(logprob, ..)| *logprob)
+                .max_by(f32::total_cmp),
+            candidates.first(),
+        ) {
+            if best_finished >= best_candidate.logprob {
+                break;
+            }
+        }
+
+        // Extend the beams with the candidates. A beam is only cloned if more than one candidate extends it
+        let mut uses = vec![0; beams.len()];
+        for candidate in &candidates {
+            uses[candidate.beam] += 1;
+        }
+        let mut old_beams: Vec<_> = beams.drain(..).map(Some).collect();
+        for candidate in candidates {
+            uses[candidate.beam] -= 1;
+            let mut beam = if uses[candidate.beam] == 0 {
+                old_beams[candidate.beam]
+                    .take()
+                    .expect("each beam is only taken once")
+            } else {
+                old_beams[candidate.beam]
+                    .as_ref()
+                    .expect("each beam is only taken once")
+                    .try_clone()?
+            };
+
+            beam.logprob = candidate.logprob;
+            beam.unprocessed_token_count = 1;
+            let mut token = beam
+                .token_stream
+                .next_token(candidate.token_id)?
+                .unwrap_or_default();
+            token.truncate(candidate.parsed_bytes);
+            if beam.strip_remaining_prompt {
+                if let Some(stripped) = token.strip_prefix(&remaining_prompt_text) {
+                    token = stripped.to_string();
+                }
+                beam.strip_remaining_prompt = false;
+            }
+            beam.events.push(TokenEvent::sampled(
+                token,
+                candidate.token_id,
+                &logits[candidate.beam],
+                Some(0),
+                &tokenizer,
+            ));
+
+            let Beam {
+                token_stream,
+                parser_state,
+                unprocessed_token_count,
+                events,
+                ..
+            } = &mut beam;
+            let output = update_state(
+                &parser,
+                parser_state,
+                candidate.result,
+                &tokenizer,
+                token_stream,
+                &mut |event| {
+                    events.push(event);
+                    Ok(())
+                },
+                unprocessed_token_count,
+            )?;
+            match output {
+                Some(output) => finished.push((beam.logprob, output, beam)),
+                None => beams.push(beam),
+            }
+        }
+
+        if finished.len() >= width {
+            break;
+        }
+    }
+
+    let (_, output, beam) = finished
+        .into_iter()
+        .max_by(|(a, ..), (b, ..)| a.total_cmp(b))
+        .ok_or_else(|| anyhow::anyhow!("No valid tokens found"))?;
+    *session = beam.session;
+    for event in beam.events {
+        on_token(event)?;
+    }
+    Ok(output)
+}
+
+/// A model that picks the logits from a function of the text in the session. Tokens the function doesn't mention are unlikely
+#[cfg(test)]
+struct ScriptedModel {
+    tokenizer: Arc<tokenizers::Tokenizer>,
+    logits: fn(&str) -> Vec<(&'static str, f32)>,
+}
+
+#[cfg(test)]
+impl ScriptedModel {
+    fn new(logits: fn(&str) -> Vec<(&'static str, f32)>) -> Self {
+        Self {
+            tokenizer: crate::structured::test_tokenizer(),
+            logits,
+        }
+    }
+
+    fn token(&self, text: &str) -> u32 {
+        self.tokenizer.token_to_id(text).unwrap()
+    }
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct ScriptedSession {
+    tokens: Vec<u32>,
+}
+
+#[cfg(test)]
+impl Session for ScriptedSession {
+    fn tokens(&self) -> &[u32] {
+        &self.tokens
+    }
+
+    fn truncate(&mut self, len: usize) -> anyhow::Result<()> {
+        self.tokens.truncate(len);
+        Ok(())
+    }
+
+    fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(Self {
+            tokens: self.tokens.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+impl SyncModel for ScriptedModel {
+    type Session = ScriptedSession;
+
+    fn new_session(&self) -> anyhow::Result<Self::Session> {
+        Ok(ScriptedSession::default())
+    }
+
+    fn feed_text(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        let tokens = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(anyhow::Error::msg)?;
+        self.feed_tokens(session, tokens.get_ids(), into)
+    }
+
+    fn feed_tokens(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        session.tokens.extend_from_slice(tokens);
+        let text = self
+            .tokenizer
+            .decode(&session.tokens, false)
+            .map_err(anyhow::Error::msg)?;
+        into.clear();
+        into.resize(self.tokenizer.get_vocab_size(true), -10.);
+        for (token, logit) in (self.logits)(&text) {
+            into[self.token(token) as usize] = logit;
+        }
+        Ok(())
+    }
+
+    fn stop_token(&self) -> anyhow::Result<u32> {
+        Ok(0)
+    }
+
+    fn tokenizer(&self) -> Arc<tokenizers::Tokenizer> {
+        self.tokenizer.clone()
+    }
+}
+
+/// A sampler that picks the next token from a list
+#[cfg(test)]
+#[derive(Debug, Default)]
+struct ScriptedSampler {
+    tokens: Vec<u32>,
+    sampled: Option<u32>,
+}
+
+#[cfg(test)]
+impl Sampler for ScriptedSampler {
+    fn sample<'a>(
+        &mut self,
+        _: &mut dyn llm_samplers::types::HasSamplerResources,
+        logits: &'a mut llm_samplers::types::Logits,
+    ) -> anyhow::Result<&'a mut llm_samplers::types::Logits> {
+        self.sampled = (!self.tokens.is_empty()).then(|| self.tokens.remove(0));
+        Ok(logits)
+    }
+
+    fn sampled_token_id(&self) -> Option<u32> {
+        self.sampled
+    }
+}
+
+/// Generate two characters of `a` or `b` with a search strategy and return the text and the session
+#[cfg(test)]
+fn search_two_characters(
+    model: &ScriptedModel,
+    sampler: ScriptedSampler,
+    search: SearchStrategy,
+) -> anyhow::Result<(String, ScriptedSession)> {
+    let parser = kalosm_sample::RegexParser::new("[ab][ab]").unwrap();
+    let mut session = model.new_session()?;
+    let mut text = String::new();
+    generate_structured_with_search(
+        "",
+        model,
+        &mut session,
+        &parser,
+        parser.create_parser_state(),
+        Arc::new(Mutex::new(sampler)),
+        |event| {
+            text += &event.text;
+            Ok(())
+        },
+        None,
+        search,
+    )?;
+    Ok((text, session))
+}
+
+/// "a" is the most likely first token, but every output that starts with it is unlikely
+#[cfg(test)]
+fn unlikely_after_a(text: &str) -> Vec<(&'static str, f32)> {
+    match text {
+        "" => vec![("a", 1.), ("b", 0.5)],
+        "a" => vec![("é", 3.), ("a", -3.)],
+        _ => vec![("b", 3.)],
+    }
+}
+
+#[test]
+fn beam_search_recovers_from_likely_first_token() {
+    let model = ScriptedModel::new(unlikely_after_a);
+
+    // A single beam commits to the most likely first token
+    let (text, _) = search_two_characters(
+        &model,
+        ScriptedSampler::default(),
+        SearchStrategy::BeamSearch {
+            width: 1,
+            max_length: 8,
+        },
+    )
+    .unwrap();
+    assert_eq!(text, "aa");
+
+    let (text, session) = search_two_characters(
+        &model,
+        ScriptedSampler::default(),
+        SearchStrategy::BeamSearch {
+            width: 2,
+            max_length: 8,
+        },
+    )
+    .unwrap();
+    assert_eq!(text, "bb");
+    // The session of the best output replaces the original session
+    assert_eq!(session.tokens, [model.token("b")]);
+}
+
+#[test]
+fn beam_search_stops_at_max_length() {
+    let model = ScriptedModel::new(unlikely_after_a);
+    let result = search_two_characters(
+        &model,
+        ScriptedSampler::default(),
+        SearchStrategy::BeamSearch {
+            width: 2,
+            max_length: 1,
+        },
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn best_of_picks_the_most_likely_output() {
+    let model = ScriptedModel::new(unlikely_after_a);
+    let a = model.token("a");
+    let b = model.token("b");
+    let sampler = ScriptedSampler {
+        tokens: vec![a, a, b, b],
+        sampled: None,
+    };
+    let (text, session) =
+        search_two_characters(&model, sampler, SearchStrategy::BestOf { n: 2 }).unwrap();
+    assert_eq!(text, "bb");
+    assert_eq!(session.tokens, [b]);
+}
+
+#[test]
+fn validation_rejects_the_token_that_completes_an_invalid_value() {
+    let model = ScriptedModel::new(|_| vec![("a", 3.), ("b", 1.)]);
+    let a = model.token("a");
+    let b = model.token("b");
+    // The sampler picks "aa" first, which fails validation
+    let sampler = ScriptedSampler {
+        tokens: vec![a, a, b],
+        sampled: None,
+    };
+    let parser = kalosm_sample::RegexParser::new("[ab][ab]")
+        .unwrap()
+        .validate(|text| {
+            if text == "aa" {
+                kalosm_sample::bail!("The text must not repeat");
+            }
+            Ok(())
+        });
+    let mut session = model.new_session().unwrap();
+    let mut text = String::new();
+    let output = generate_structured_with_search(
+        "",
+        &model,
+        &mut session,
+        &parser,
+        parser.create_parser_state(),
+        Arc::new(Mutex::new(sampler)),
+        |event| {
+            text += &event.text;
+            Ok(())
+        },
+        None,
+        SearchStrategy::Sample,
+    )
+    .unwrap();
+    assert_eq!(output, "ab");
+    // The rejected token is never streamed or fed to the model
+    assert_eq!(text, "ab");
+    assert_eq!(session.tokens, [a]);
+}
@@ -1,12 +1,14 @@
 use std::{
     fmt::{Debug, Display, Formatter},
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, Weak},
 };
 
+use crate::token_stream::ends_with_complete_char;
 use crate::SyncModel;
-use crate::TokenOutputStream;
-use kalosm_sample::CreateParserState;
-use kalosm_sample::{LiteralParser, ParseStatus, Parser, ParserExt};
+use crate::{TokenEvent, TokenOutputStream};
+use futures_util::{Stream, StreamExt};
+use kalosm_sample::{CreateParserState, SchemaType};
+use kalosm_sample::{LiteralParser, ParseStatus, Parser, ParserExt, TokenVocabulary};
 use llm_samplers::prelude::{Logit, Logits};
 use llm_samplers::types::{HasSamplerResources, Sampler, SamplerError};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
@@ -20,164 +22,220 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
     parser: P,
     parser_state: P::PartialState,
     mut sampler: Arc<Mutex<dyn Sampler>>,
-    mut on_token: impl FnMut(String) -> anyhow::Result<()>,
+    mut on_token: impl FnMut(TokenEvent) -> anyhow::Result<()>,
     top_k: Option<usize>,
+    top_logprobs: Option<usize>,
 ) -> anyhow::Result<P::Output> {
     let tokenizer = llm.tokenizer();
 
-    let prompt_text = prompt.to_string();
-    let prompt_tokens = tokenizer
-        .encode(prompt_text, false)
-        .map_err(|e| anyhow::anyhow!(e))?;
-    let mut prompt_tokens = prompt_tokens.get_ids();
-
-    // Prompt healing
-    // Trim the last token and add what it would decode to into the constraints
-    let last_token = if let Some((last, tokens)) = prompt_tokens.split_last() {
-        prompt_tokens = tokens;
-        Some(*last)
-    } else {
-        None
-    };
-
-    let mut unprocessed_token_count = prompt_tokens.len();
-    let mut token_stream = TokenOutputStream::new(tokenizer.clone());
-    for token in prompt_tokens {
-        token_stream.next_token(*token)?;
-    }
-
-    let remaining_prompt_text = last_token
-        .map(|token| token_stream.peek_token(token))
-        .transpose()?
-        .flatten()
-        .unwrap_or_default();
+    let HealedPrompt {
+        mut token_stream,
+        mut unprocessed_token_count,
+        remaining_prompt_text,
+    } = heal_prompt(prompt, &tokenizer)?;
 
     let parser = LiteralParser::new(remaining_prompt_text.clone())
         .ignore_output_then(parser.with_initial_state(move || parser_state.clone()));
     let mut parser_state = parser.create_parser_state();
     let mut strip_required_next = true;
+    // Building the vocabulary decodes every token, so only build it if the parser can use it
+    let vocabulary = parser
+        .token_mask()
+        .map(|_| token_vocabulary(&tokenizer))
+        .transpose()?;
 
     let mut rng = rand::thread_rng();
     let mut state_map = vec![];
     let mut logits_indexed = Vec::new();
     let mut token_cache = DetokenizationCache::new();
     let mut logits = Logits::default();
-    let mut logit_probs = Vec::new();
+    let mut speculative_logits = Vec::new();
+    let mut draft_tokens = Vec::new();
+    let mut accepted_draft_tokens = 0;
 
     loop {
         let tokens = token_stream.tokens();
-        llm.feed_tokens(
-            session,
-            &tokens[tokens.len() - unprocessed_token_count..],
-            &mut logit_probs,
-        )?;
+        let new_tokens = &tokens[tokens.len() - unprocessed_token_count..];
+        // If the model drafted the token we just sampled, it was already fed to the model
+        if new_tokens.len() == 1 && draft_tokens.get(accepted_draft_tokens) == Some(&new_tokens[0])
+        {
+            accepted_draft_tokens += 1;
+        } else {
+            llm.reject_draft_tokens(session, draft_tokens.len() - accepted_draft_tokens)?;
+            draft_tokens =
+                llm.feed_tokens_speculative(session, new_tokens, &mut speculative_logits)?;
+            accepted_draft_tokens = 0;
+        }
+        let logit_probs = &speculative_logits[accepted_draft_tokens];
         let resources = &mut SamplerResources {
             previous_tokens: tokens,
             rng: &mut rng,
         };
 
-        // fill the state map with None for each token
-        token_cache.clear(logit_probs.len());
-        state_map.clear();
-        logits_indexed.clear();
-        logits.clear();
-        for (id, prob) in logit_probs.iter().enumerate() {
-            logits_indexed.push(Logit {
-                token_id: id as u32,
-                logit: *prob,
-                prob: 0f32,
-            });
-            state_map.push(None);
-        }
-
-        let mut valid_tokens = false;
+        // Parsers with a token mask can find the allowed tokens directly instead of parsing the text of every token
+        let masked = match (parser.token_mask(), &vocabulary) {
+            (Some(mask), Some(vocabulary)) => {
+                let allowed = mask.allowed_tokens(&parser_state, vocabulary);
+                let mut candidates = allowed
+                    .iter()
+                    .filter_map(|token_id| {
+                        Some(Logit {
+                            token_id,
+                            logit: *logit_probs.get(token_id as usize)?,
+                            prob: 0f32,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                if let Some(top_k) = top_k {
+                    if candidates.len() > top_k {
+                        candidates.select_nth_unstable_by(top_k, cmp_logits);
+                        candidates.truncate(top_k);
+                    }
+                }
+                // The mask is computed from the text of each token on its own, which may be different from the text after the previous tokens.
+                // Parse the text of the sampled token and sample again without it if it is not valid
+                loop {
+                    if candidates.is_empty() {
+                        return Err(anyhow::anyhow!("No valid tokens found"));
+                    }
+                    logits.clear();
+                    for logit in &candidates {
+                        logits.push(*logit);
+                    }
+                    let token_id = sampler
+                        .sample_token(resources, &mut logits)?
+                        .ok_or(anyhow::anyhow!("Failed to sample constrained tokens"))?;
+                    let parsed = token_stream.peek_token(token_id)?.and_then(|text| {
+                        let result = parser.parse(&parser_state, text.as_bytes()).ok()?;
+                        let parsed_bytes = match result {
+                            ParseStatus::Finished { remaining, .. } => text.len() - remaining.len(),
+                            ParseStatus::Incomplete { .. } => text.len(),
+                        };
+                        Some((result.without_remaining(), parsed_bytes))
+                    });
+                    match parsed {
+                        Some((result, parsed_bytes)) => {
+                            break Some((token_id, result, parsed_bytes))
+                        }
+                        None => candidates.retain(|logit| logit.token_id != token_id),
+                    }
+                }
+            }
+            _ => None,
+        };
+        let (token_id, result, parsed_bytes) = match masked {
+            Some(sampled) => sampled,
+            None => {
+                // fill the state map with None for each token
+                token_cache.clear(logit_probs.len());
+                state_map.clear();
+                logits_indexed.clear();
+                logits.clear();
+                for (id, prob) in logit_probs.iter().enumerate() {
+                    logits_indexed.push(Logit {
+                        token_id: id as u32,
+                        logit: *prob,
+                        prob: 0f32,
+                    });
+                    state_map.push(None);
+                }
 
-        // If we don't have a top k, then we can just cache the entire detokenization
-        if top_k.is_none() {
-            token_cache.expand(
-                &(0..logit_probs.len() as u32).collect::<Vec<_>>(),
-                &token_stream,
-            )?;
-        }
+                let mut valid_tokens = false;
 
-        const DETOKENIZATION_INITIAL_BATCH_SIZE: usize = 64;
-
-        // Constraints tend to be either very difficult to satisfy or very easy to satisfy
-        // We exponentially increase the batch size as a balance between the two
-        // If the first half of the tokens are invalid, it is unlikely that the first 64 tokens of the second half will be valid
-        let mut detokenization_batch_size = DETOKENIZATION_INITIAL_BATCH_SIZE;
-
-        let mut partitioned_logits_index = top_k.map(|_| 0);
-
-        for i in 0..logits_indexed.len() {
-            // If we have top k enabled, and there are less than top k - committed logits sorted, we need to expand the partitioned logits
-            if let (Some(top_k), Some(partitioned_index)) = (top_k, partitioned_logits_index) {
-                // If the remaining logits are less than the top k, no need to partition
-                let remaining_needed = top_k - logits.len();
-                let remaining_possible = partitioned_index - i;
-                if remaining_possible <= remaining_needed {
-                    // We batch together updates to the cache by detokenization_batch_size
-                    let logits_to_update = (remaining_needed.max(detokenization_batch_size))
-                        .min(logits_indexed.len() - 1 - i);
-                    let new_partitioned_index = i + logits_to_update;
-
-                    // If we eliminated a logit, our partitioning of the logits is no longer valid
-                    logits_indexed[i..].select_nth_unstable_by(logits_to_update, cmp_logits);
-                    logits_indexed[i..=new_partitioned_index].sort_unstable_by(cmp_logits);
-                    // Expand the cache to include the new logits
-                    partitioned_logits_index = Some(new_partitioned_index);
-                    token_cache.expand_with_logits(
-                        &logits_indexed[i..=new_partitioned_index],
+                // If we don't have a top k, then we can just cache the entire detokenization
+                if top_k.is_none() {
+                    token_cache.expand(
+                        &(0..logit_probs.len() as u32).collect::<Vec<_>>(),
                         &token_stream,
                     )?;
-
-                    // Double the batch size for next time
-                    detokenization_batch_size = detokenization_batch_size.saturating_mul(4);
                 }
-            }
 
-            let Logit {
-                token_id, logit, ..
-            } = logits_indexed[i];
-            let Some(text) = token_cache.get(token_id as usize) else {
-                continue;
-            };
-            if let Ok(result) = parser.parse(&parser_state, text.as_bytes()) {
-                let parsed_bytes = match result {
-                    ParseStatus::Finished { remaining, .. } => text.len() - remaining.len(),
-                    ParseStatus::Incomplete { .. } => text.len(),
-                };
-                let result = result.without_remaining();
-                state_map[token_id as usize] = Some((result, parsed_bytes));
-                valid_tokens = true;
-                logits.push(Logit {
-                    token_id,
-                    logit,
-                    prob: 0f32,
-                });
-                // If we only need to keep the top k logits, then we can quit early once we have enough
-                if let Some(top_k) = top_k {
-                    if logits.len() >= top_k {
-                        break;
+                const DETOKENIZATION_INITIAL_BATCH_SIZE: usize = 64;
+
+                // Constraints tend to be either very difficult to satisfy or very easy to satisfy
+                // We exponentially increase the batch size as a balance between the two
+                // If the first half of the tokens are invalid, it is unlikely that the first 64 tokens of the second half will be valid
+                let mut detokenization_batch_size = DETOKENIZATION_INITIAL_BATCH_SIZE;
+
+                let mut partitioned_logits_index = top_k.map(|_| 0);
+
+                for i in 0..logits_indexed.len() {
+                    // If we have top k enabled, and there are less than top k - committed logits sorted, we need to expand the partitioned logits
+                    if let (Some(top_k), Some(partitioned_index)) =
+                        (top_k, partitioned_logits_index)
+                    {
+                        // If the remaining logits are less than the top k, no need to partition
+                        let remaining_needed = top_k - logits.len();
+                        let remaining_possible = partitioned_index - i;
+                        if remaining_possible <= remaining_needed {
+                            // We batch together updates to the cache by detokenization_batch_size
+                            let logits_to_update = (remaining_needed
+                                .max(detokenization_batch_size))
+                            .min(logits_indexed.len() - 1 - i);
+                            let new_partitioned_index = i + logits_to_update;
+
+                            // If we eliminated a logit, our partitioning of the logits is no longer valid
+                            logits_indexed[i..]
+                                .select_nth_unstable_by(logits_to_update, cmp_logits);
+                            logits_indexed[i..=new_partitioned_index].sort_unstable_by(cmp_logits);
+                            // Expand the cache to include the new logits
+                            partitioned_logits_index = Some(new_partitioned_index);
+                            token_cache.expand_with_logits(
+                                &logits_indexed[i..=new_partitioned_index],
+                                &token_stream,
+                            )?;
+
+                            // Double the batch size for next time
+                            detokenization_batch_size = detokenization_batch_size.saturating_mul(4);
+                        }
                     }
-                }
-            }
-        }
 
-        // If there are no valid tokens, return an error
-        if !valid_tokens {
-            return Err(anyhow::anyhow!("No valid tokens found"));
-        }
-        let token_id = sampler
-            .sample_token(resources, &mut logits)?
-            .ok_or(anyhow::anyhow!("Failed to sample constrained tokens"))?;
+                    let Logit {
+                        token_id, logit, ..
+                    } = logits_indexed[i];
+                    let Some(text) = token_cache.get(token_id as usize) else {
+                        continue;
+                    };
+                    if let Ok(result) = parser.parse(&parser_state, text.as_bytes()) {
+                        let parsed_bytes = match result {
+                            ParseStatus::Finished { remaining, .. } => text.len() - remaining.len(),
+                            ParseStatus::Incomplete { .. } => text.len(),
+                        };
+                        let result = result.without_remaining();
+                        state_map[token_id as usize] = Some((result, parsed_bytes));
+                        valid_tokens = true;
+                        logits.push(Logit {
+                            token_id,
+                            logit,
+                            prob: 0f32,
+                        });
+                        // If we only need to keep the top k logits, then we can quit early once we have enough
+                        if let Some(top_k) = top_k {
+                            if logits.len() >= top_k {
+                                break;
+                            }
+                        }
+                    }
+                }
 
+                // If there are no valid tokens, return an error
+                if !valid_tokens {
+                    return Err(anyhow::anyhow!("No valid tokens found"));
+                }
+                let token_id = sampler
+                    .sample_token(resources, &mut logits)?
+                    .ok_or(anyhow::anyhow!("Failed to sample constrained tokens"))?;
+
+                let (result, parsed_bytes) =
+                    state_map
+                        .get_mut(token_id as usize)
+                        .unwrap()
+                        .take()
+                        .ok_or(anyhow::anyhow!("Token {} not found in state map", token_id))?;
+                (token_id, result, parsed_bytes)
+            }
+        };
         unprocessed_token_count = 1;
-        let (result, parsed_bytes) = state_map
-            .get_mut(token_id as usize)
-            .unwrap()
-            .take()
-            .ok_or(anyhow::anyhow!("Token {} not found in state map", token_id))?;
         let mut token = token_stream.next_token(token_id)?.unwrap();
         token.truncate(parsed_bytes);
         tracing::trace!("Adding token {} to parser", token);
@@ -188,7 +246,13 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
             }
             strip_required_next = false;
         }
-        on_token(token)?;
+        on_token(TokenEvent::sampled(
+            token,
+            token_id,
+            &speculative_logits[accepted_draft_tokens],
+            top_logprobs,
+            &tokenizer,
+        ))?;
 
         if let Some(result) = update_state(
             &parser,
@@ -199,11 +263,380 @@ pub(crate) fn generate_structured<M: ?Sized + SyncModel, P: Parser>(
             &mut on_token,
             &mut unprocessed_token_count,
         )? {
+            // Remove any draft tokens past the end of the output from the session
+            llm.reject_draft_tokens(session, draft_tokens.len() - accepted_draft_tokens)?;
             return Ok(result);
         }
     }
 }
 
+/// A tokenized prompt for structured generation
+pub(crate) struct HealedPrompt {
+    pub(crate) token_stream: TokenOutputStream,
+    /// The number of prompt tokens that have not been fed to the model yet
+    pub(crate) unprocessed_token_count: usize,
+    /// The text of the last prompt token. It must be generated again before the output of the parser
+    pub(crate) remaining_prompt_text: String,
+}
+
+/// Tokenize a prompt with prompt healing. The last token is trimmed and the text it decodes to is added to the constraints so the model can pick a tokenization that fits the constraints
+pub(crate) fn heal_prompt(
+    prompt: impl Display,
+    tokenizer: &Arc<Tokenizer>,
+) -> anyhow::Result<HealedPrompt> {
+    let prompt_text = prompt.to_string();
+    let prompt_tokens = tokenizer
+        .encode(prompt_text, false)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    let mut prompt_tokens = prompt_tokens.get_ids();
+
+    let last_token = if let Some((last, tokens)) = prompt_tokens.split_last() {
+        prompt_tokens = tokens;
+        Some(*last)
+    } else {
+        None
+    };
+
+    let mut token_stream = TokenOutputStream::new(tokenizer.clone());
+    for token in prompt_tokens {
+        token_stream.next_token(*token)?;
+    }
+
+    let remaining_prompt_text = last_token
+        .map(|token| token_stream.peek_token(token))
+        .transpose()?
+        .flatten()
+        .unwrap_or_default();
+
+    Ok(HealedPrompt {
+        token_stream,
+        unprocessed_token_count: prompt_tokens.len(),
+        remaining_prompt_text,
+    })
+}
+
+/// Forward the JSON text a model generates and parse the full response with the parser once the model finishes.
+pub(crate) fn parse_json_stream<S, P>(
+    mut stream: S,
+    schema: Option<&SchemaType>,
+    parser: P,
+) -> (
+    tokio::sync::mpsc::UnboundedReceiver<String>,
+    tokio::sync::oneshot::Receiver<anyhow::Result<P::Output>>,
+)
+where
+    S: Stream<Item = anyhow::Result<String>> + Send + Unpin + 'static,
+    P: CreateParserState<Output: Send> + Send + 'static,
+{
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (result_sender, result_receiver) = tokio::sync::oneshot::channel();
+    let schema = schema.and_then(|schema| serde_json::from_str(&schema.to_string()).ok());
+
+    tokio::spawn(async move {
+        let mut text = String::new();
+        while let Some(token) = stream.next().await {
+            let token = match token {
+                Ok(token) => token,
+                Err(err) => {
+                    _ = result_sender.send(Err(err));
+                    return;
+                }
+            };
+            text.push_str(&token);
+            // The receiver may be dropped if the caller only wants the final result
+            _ = sender.send(token);
+        }
+        _ = result_sender.send(parse_json_response_with_schema(
+            &text,
+            schema.as_ref(),
+            &parser,
+        ));
+    });
+
+    (receiver, result_receiver)
+}
+
+/// Parse a complete JSON response from a model with a parser. The JSON may use any whitespace layout and object keys must be in the order the parser expects.
+pub(crate) fn parse_json_response<P: CreateParserState>(
+    text: &str,
+    parser: &P,
+) -> anyhow::Result<P::Output> {
+    parse_json_response_with_schema(text, None, parser)
+}
+
+/// Parse a complete JSON response from a model with a parser. If the JSON schema of the response is known, the object keys are sorted in the order of the schema and properties that can be null may be left out.
+fn parse_json_response_with_schema<P: CreateParserState>(
+    text: &str,
+    schema: Option<&serde_json::Value>,
+    parser: &P,
+) -> anyhow::Result<P::Output> {
+    // Parsers expect the whitespace layout kalosm generates and numbers need a terminator
+    let normalized = schema
+        .and_then(|schema| normalize_json_for_schema(text, schema))
+        .unwrap_or_else(|| normalize_json_whitespace(text))
+        + "\n";
+    let state = parser.create_parser_state();
+    match parser.parse(&state, normalized.as_bytes()) {
+        Ok(ParseStatus::Finished { result, .. }) => Ok(result),
+        Ok(ParseStatus::Incomplete { required_next, .. }) => Err(anyhow::anyhow!(
+            "The model response ended before the value was complete. Expected {required_next:?} next in: {text}"
+        )),
+        Err(err) => Err(anyhow::anyhow!(
+            "The model response does not match the constraints: {err:?} in: {text}"
+        )),
+    }
+}
+
+/// Rewrite JSON with the layout the [`kalosm_sample::Parse`] parsers use. Object keys are sorted in the order of the properties in the schema and missing properties that can be null are set to null. Returns `None` if the text is not valid JSON.
+fn normalize_json_for_schema(json: &str, schema: &serde_json::Value) -> Option<String> {
+    let value = serde_json::from_str(json).ok()?;
+    let mut normalized = String::with_capacity(json.len());
+    write_json_for_schema(&value, Some(schema), &mut normalized);
+    Some(normalized)
+}
+
+/// Write a JSON value with the layout of [`normalize_json_for_schema`].
+fn write_json_for_schema(
+    value: &serde_json::Value,
+    schema: Option<&serde_json::Value>,
+    normalized: &mut String,
+) {
+    use serde_json::Value;
+
+    let schema = schema.and_then(|schema| schema_for_value(value, schema));
+    match value {
+        Value::Object(object) => {
+            let properties = schema
+                .and_then(|schema| schema.get("properties"))
+                .and_then(Value::as_object);
+            let mut entries = Vec::new();
+            for (name, property) in properties.into_iter().flatten() {
+                match object.get(name) {
+                    Some(value) => entries.push((name, Some(value), Some(property))),
+                    // Models may leave out optional properties even if the schema requires them
+                    None if accepts_null(property) => entries.push((name, None, Some(property))),
+                    None => {}
+                }
+            }
+            // Keep properties the schema doesn't list so the parser can reject them if they are not allowed
+            let additional_properties =
+                schema.and_then(|schema| schema.get("additionalProperties"));
+            for (name, value) in object {
+                if !properties.is_some_and(|properties| properties.contains_key(name)) {
+                    entries.push((name, Some(value), additional_properties));
+                }
+            }
+
+            if entries.is_empty() {
+                normalized.push_str("{}");
+                return;
+            }
+            normalized.push_str("{ ");
+            for (i, (name, value, schema)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    normalized.push_str(", ");
+                }
+                normalized.push_str(&Value::from(name.as_str()).to_string());
+                normalized.push_str(": ");
+                match value {
+                    Some(value) => write_json_for_schema(value, schema, normalized),
+                    None => normalized.push_str("null"),
+                }
+            }
+            normalized.push_str(" }");
+        }
+        Value::Array(items) => {
+            let prefix_items = schema
+                .and_then(|schema| schema.get("prefixItems"))
+                .and_then(Value::as_array);
+            let items_schema = schema.and_then(|schema| schema.get("items"));
+            normalized.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    normalized.push_str(", ");
+                }
+                let schema = prefix_items
+                    .and_then(|prefix_items| prefix_items.get(i))
+                    .or(items_schema);
+                write_json_for_schema(item, schema, normalized);
+            }
+            normalized.push(']');
+        }
+        value => normalized.push_str(&value.to_string()),
+    }
+}
+
+/// Find the schema that describes a value. If the schema has `anyOf` or `oneOf` variants, this is the first variant for the same type of value.
+fn schema_for_value<'a>(
+    value: &serde_json::Value,
+    schema: &'a serde_json::Value,
+) -> Option<&'a serde_json::Value> {
+    use serde_json::Value;
+
+    if let Some(variants) = schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        return variants
+            .iter()
+            .find_map(|variant| schema_for_value(value, variant));
+    }
+    let ty = schema.get("type").and_then(Value::as_str);
+    let matches = match value {
+        Value::Object(_) => matches!(ty, None | Some("object")),
+        Value::Array(_) => matches!(ty, None | Some("array")),
+        _ => true,
+    };
+    matches.then_some(schema)
+}
+
+/// Check if a schema accepts `null`.
+fn accepts_null(schema: &serde_json::Value) -> bool {
+    use serde_json::Value;
+
+    match schema
+        .get("anyOf")
+        .or_else(|| schema.get("oneOf"))
+        .and_then(Value::as_array)
+    {
+        Some(variants) => variants.iter().any(accepts_null),
+        None => {
+            schema.get("type").and_then(Value::as_str) == Some("null")
+                || schema.get("const") == Some(&Value::Null)
+        }
+    }
+}
+
+/// Rewrite JSON with the same whitespace layout the [`kalosm_sample::Parse`] parsers use: `{ "key": value, "key2": value }` and `[a, b]`.
+fn normalize_json_whitespace(json: &str) -> String {
+    let mut normalized = String::with_capacity(json.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in json.trim().chars() {
+        if in_string {
+            normalized.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                normalized.push(c);
+            }
+            c if c.is_whitespace() => {}
+            '{' => normalized.push_str("{ "),
+            '}' => {
+                if normalized.ends_with("{ ") {
+                    normalized.pop();
+                } else {
+                    normalized.push(' ');
+                }
+                normalized.push('}');
+            }
+            ',' => normalized.push_str(", "),
+            ':' => normalized.push_str(": "),
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+#[test]
+fn normalize_remote_json() {
+    assert_eq!(
+        normalize_json_whitespace("{\"name\":\"a, b: {c}\",\n  \"ages\": [1,2 ,3],\"empty\":{}}"),
+        "{ \"name\": \"a, b: {c}\", \"ages\": [1, 2, 3], \"empty\": {} }"
+    );
+    assert_eq!(
+        normalize_json_whitespace(r#"{"quote":"\"}"}"#),
+        r#"{ "quote": "\"}" }"#
+    );
+}
+
+#[test]
+fn normalize_remote_json_with_schema() {
+    let schema = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "name": { "type": "string" },
+            "nickname": { "oneOf": [{ "type": "null" }, { "type": "string" }] },
+            "friends": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": { "type": "string" },
+                        "age": { "type": "number" }
+                    },
+                    "required": ["name", "age"],
+                    "additionalProperties": false
+                }
+            }
+        },
+        "required": ["name", "nickname", "friends"],
+        "additionalProperties": false
+    });
+
+    // Keys are sorted in the order of the schema and the missing nullable property is filled in
+    assert_eq!(
+        normalize_json_for_schema(r#"{"friends":[{"age":7,"name":"b"}],"name":"a"}"#, &schema)
+            .unwrap(),
+        r#"{ "name": "a", "nickname": null, "friends": [{ "name": "b", "age": 7 }] }"#
+    );
+    // Required properties that can't be null are not filled in
+    assert_eq!(
+        normalize_json_for_schema(r#"{"name":"a","friends":[{"name":"b"}]}"#, &schema).unwrap(),
+        r#"{ "name": "a", "nickname": null, "friends": [{ "name": "b" }] }"#
+    );
+    assert!(normalize_json_for_schema("{\"name\":", &schema).is_none());
+}
+
+/// Get the vocabulary of a tokenizer for parsers with a [`kalosm_sample::TokenMask`]. Building the vocabulary decodes every token, so it is cached for each tokenizer.
+pub(crate) fn token_vocabulary(tokenizer: &Arc<Tokenizer>) -> anyhow::Result<Arc<TokenVocabulary>> {
+    static VOCABULARIES: Mutex<Vec<(Weak<Tokenizer>, Arc<TokenVocabulary>)>> =
+        Mutex::new(Vec::new());
+
+    let mut vocabularies = VOCABULARIES.lock().unwrap();
+    vocabularies.retain(|(tokenizer, _)| tokenizer.strong_count() > 0);
+    if let Some((_, vocabulary)) = vocabularies
+        .iter()
+        .find(|(cached, _)| std::ptr::eq(cached.as_ptr(), Arc::as_ptr(tokenizer)))
+    {
+        return Ok(vocabulary.clone());
+    }
+
+    // Decode each token after another token like the token stream does. Some tokenizers only add the space at the start of a word if it is not the first token
+    let anchor = *tokenizer
+        .encode("a", false)
+        .map_err(anyhow::Error::msg)?
+        .get_ids()
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("The tokenizer has no token for \"a\""))?;
+    let anchor_text = tokenizer
+        .decode(&[anchor], false)
+        .map_err(anyhow::Error::msg)?;
+    let tokens = (0..tokenizer.get_vocab_size(true) as u32)
+        .into_par_iter()
+        .map(|token| {
+            let text = tokenizer.decode(&[anchor, token], false).ok()?;
+            let text = text.strip_prefix(&anchor_text)?;
+            // The token stream holds back tokens that end partway through a character, so they are never allowed
+            ends_with_complete_char(text).then(|| text.to_string())
+        })
+        .collect::<Vec<_>>();
+    let vocabulary = Arc::new(TokenVocabulary::new(tokens));
+    vocabularies.push((Arc::downgrade(tokenizer), vocabulary.clone()));
+
+    Ok(vocabulary)
+}
+
 fn cmp_logits(a: &Logit, b: &Logit) -> std::cmp::Ordering {
     // SAFETY: Logits should never be NaN or Inf
     let compare = b.logit.partial_cmp(&a.logit);
@@ -212,13 +645,13 @@ fn cmp_logits(a: &Logit, b: &Logit) -> std::cmp::Ordering {
 }
 
 #[allow(unused, clippy::all)]
-fn update_state<P: Parser>(
+pub(crate) fn update_state<P: Parser>(
     parser: &P,
     parser_state: &mut P::PartialState,
     result: ParseStatus<P::PartialState, P::Output>,
     tokenizer: &Tokenizer,
     token_stream: &mut TokenOutputStream,
-    on_token: &mut impl FnMut(String) -> anyhow::Result<()>,
+    on_token: &mut impl FnMut(TokenEvent) -> anyhow::Result<()>,
     unprocessed_token_count: &mut usize,
 ) -> anyhow::Result<Option<P::Output>> {
     match result {
@@ -253,7 +686,10 @@ fn update_state<P: Parser>(
                     return Ok(None);
                 }
                 *unprocessed_token_count += extra_tokens.len();
-                on_token(all_required_next.clone())?;
+                on_token(TokenEvent::forced(
+                    all_required_next.clone(),
+                    extra_tokens.clone(),
+                ))?;
                 let mut result = parser
                     .parse(parser_state, all_required_next.as_bytes())
                     .unwrap_or_else(|_| {
@@ -274,9 +710,9 @@ fn update_state<P: Parser>(
     }
 }
 
-struct SamplerResources<'a, 'b, R: rand::Rng> {
-    rng: &'a mut R,
-    previous_tokens: &'b [u32],
+pub(crate) struct SamplerResources<'a, 'b, R: rand::Rng> {
+    pub(crate) rng: &'a mut R,
+    pub(crate) previous_tokens: &'b [u32],
 }
 
 impl<R> Debug for SamplerResources<'_, '_, R>
@@ -380,3 +816,84 @@ impl DetokenizationCache {
         self.vec.clear();
     }
 }
+
+/// A tokenizer with tokens that end in non-ascii characters and tokens for single bytes of a character.
+#[cfg(test)]
+pub(crate) fn test_tokenizer() -> Arc<Tokenizer> {
+    let vocab = [
+        "<unk>", "a", "b", "ab", "\"", "\"a", "é", "\"é", "caf", "café", "é\"", "<0xC3>", "<0xA9>",
+        "1", "12",
+    ];
+    let vocab = vocab
+        .iter()
+        .enumerate()
+        .map(|(id, token)| (token.to_string(), serde_json::Value::from(id)))
+        .collect::<serde_json::Map<_, _>>();
+    let json = serde_json::json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": null,
+        "post_processor": null,
+        "decoder": {
+            "type": "Sequence",
+            "decoders": [{ "type": "ByteFallback" }, { "type": "Fuse" }]
+        },
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "<unk>" }
+    });
+    Arc::new(json.to_string().parse().unwrap())
+}
+
+#[test]
+fn masked_tokens_match_parsed_tokens() {
+    use kalosm_sample::{LiteralParser, RegexParser};
+
+    fn assert_mask_matches_parser<P: CreateParserState>(parser: P, tokens: &[&str]) {
+        let tokenizer = test_tokenizer();
+        let vocabulary = token_vocabulary(&tokenizer).unwrap();
+        let mask = parser.token_mask().unwrap();
+        let mut token_stream = TokenOutputStream::new(tokenizer.clone());
+        let mut state = parser.create_parser_state();
+        for next in tokens.iter().map(Some).chain([None]) {
+            let allowed = mask.allowed_tokens(&state, &vocabulary);
+            // Masked sampling parses the sampled token again, so it accepts the same tokens as unmasked sampling if every token the parser accepts is in the mask
+            for token in 0..tokenizer.get_vocab_size(true) as u32 {
+                let accepted = token_stream
+                    .peek_token(token)
+                    .unwrap()
+                    .is_some_and(|text| parser.parse(&state, text.as_bytes()).is_ok());
+                assert!(
+                    !accepted || allowed.contains(token),
+                    "{:?} is accepted by the parser but not the mask",
+                    tokenizer.id_to_token(token)
+                );
+            }
+
+            let Some(next) = next else {
+                break;
+            };
+            let token = tokenizer.token_to_id(next).unwrap();
+            let text = token_stream.next_token(token).unwrap().unwrap();
+            state = parser
+                .parse(&state, text.as_bytes())
+                .unwrap()
+                .unwrap_incomplete()
+                .0;
+        }
+    }
+
+    assert_mask_matches_parser(LiteralParser::new("\"café\""), &["\"", "caf"]);
+    assert_mask_matches_parser(
+        RegexParser::new("\"[a-zé]+\"").unwrap(),
+        &["\"é", "caf", "é", "ab"],
+    );
+    assert_mask_matches_parser(RegexParser::new("[0-9]+a").unwrap(), &["1", "12"]);
+
+    // Half of a character is never allowed
+    let tokenizer = test_tokenizer();
+    let token_stream = TokenOutputStream::new(tokenizer.clone());
+    let half = tokenizer.token_to_id("<0xC3>").unwrap();
+    assert_eq!(token_stream.peek_token(half).unwrap(), None);
+}
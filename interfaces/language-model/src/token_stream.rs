@@ -7,8 +7,14 @@ use rayon::iter::ParallelExtend;
 use rayon::iter::ParallelIterator;
 use tokenizers::tokenizer::Tokenizer;
 
+/// Check if decoded text ends with a complete character. Tokenizers decode part of a multi-byte character as U+FFFD, so text that ends in U+FFFD is held back until the rest of the character is decoded.
+pub(crate) fn ends_with_complete_char(text: &str) -> bool {
+    !text.ends_with(char::REPLACEMENT_CHARACTER)
+}
+
 /// This is a wrapper around a tokenizer to ensure that tokens can be returned to the user in a
 /// streaming way rather than having to wait for the full decoding.
+#[derive(Clone)]
 pub struct TokenOutputStream {
     tokenizer: Arc<Tokenizer>,
     tokens: Vec<u32>,
@@ -161,7 +167,7 @@ impl TokenOutputStream {
         let prev_text = &self.current_text;
         self.tokens.push(token);
         let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_ascii() {
+        if text.len() > prev_text.len() && ends_with_complete_char(&text) {
             let text = text.split_at(prev_text.len());
             self.prev_index = self.current_index;
             self.current_index = self.tokens.len();
@@ -177,7 +183,7 @@ impl TokenOutputStream {
         let prev_text = &self.current_text;
         self.tokens.extend(tokens.iter().copied());
         let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_ascii() {
+        if text.len() > prev_text.len() && ends_with_complete_char(&text) {
             let text = text.split_at(prev_text.len());
             self.prev_index = self.current_index;
             self.current_index = self.tokens.len();
@@ -202,7 +208,7 @@ impl TokenOutputStream {
                 tokens.push(token);
                 let text = self.decode(tokens).ok()?;
                 tokens.pop();
-                if text.len() > prev_text_len && text.chars().last().unwrap().is_ascii() {
+                if text.len() > prev_text_len && ends_with_complete_char(&text) {
                     let text = text.split_at(prev_text_len);
                     Some(text.1.to_string())
                 } else {
@@ -221,7 +227,7 @@ impl TokenOutputStream {
         tokens.push(token);
         let text = self.decode(&tokens)?;
         tokens.pop();
-        if text.len() > prev_text_len && text.chars().last().unwrap().is_ascii() {
+        if text.len() > prev_text_len && ends_with_complete_char(&text) {
             let text = text.split_at(prev_text_len);
             Ok(Some(text.1.to_string()))
         } else {
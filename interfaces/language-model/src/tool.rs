@@ -0,0 +1,116 @@
+use kalosm_sample::{CreateParserState, SchemaType};
+
+use crate::structured::parse_json_response;
+use crate::ChatHistoryItem;
+
+/// A tool the model can call natively (like OpenAI function calling).
+#[derive(Clone, Debug)]
+pub struct ToolDefinition {
+    name: String,
+    description: String,
+    parameters: SchemaType,
+}
+
+impl ToolDefinition {
+    /// Creates a new tool definition. The parameters must be a JSON object schema.
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        parameters: SchemaType,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            parameters,
+        }
+    }
+
+    /// Returns the name of the tool.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the description of the tool.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the schema of the arguments the tool accepts.
+    pub fn parameters(&self) -> &SchemaType {
+        &self.parameters
+    }
+}
+
+/// A call to a tool the model made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl ToolCall {
+    /// Creates a new tool call.
+    pub fn new(
+        id: impl Into<String>,
+        name: impl Into<String>,
+        arguments: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+            arguments: arguments.into(),
+        }
+    }
+
+    /// Returns the id the model assigned to the call. The result of the call must be sent back with the same id.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Returns the name of the tool the model called.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the JSON arguments the model called the tool with.
+    pub fn arguments(&self) -> &str {
+        &self.arguments
+    }
+
+    /// Parse the JSON arguments with a parser. The JSON may use any whitespace layout and object keys must be in the order the parser expects.
+    pub fn parse_arguments<P: CreateParserState>(&self, parser: &P) -> anyhow::Result<P::Output> {
+        parse_json_response(&self.arguments, parser)
+    }
+}
+
+/// A single message in a chat with tools.
+#[derive(Clone, Debug)]
+pub enum ToolChatMessage {
+    /// A normal chat message.
+    Chat(ChatHistoryItem),
+    /// The model called one or more tools.
+    ToolCalls(Vec<ToolCall>),
+    /// The result of a tool call.
+    ToolResult {
+        /// The id of the call this is the result of.
+        id: String,
+        /// The output of the tool.
+        output: String,
+    },
+}
+
+impl From<ChatHistoryItem> for ToolChatMessage {
+    fn from(item: ChatHistoryItem) -> Self {
+        Self::Chat(item)
+    }
+}
+
+/// The response of a model in a chat with tools.
+#[derive(Clone, Debug)]
+pub enum ToolChatResponse {
+    /// The model answered with a message.
+    Message(String),
+    /// The model called one or more tools.
+    ToolCalls(Vec<ToolCall>),
+}
@@ -5,7 +5,7 @@ pub use crate::Llama;
 use crate::{InferenceSettings, Task};
 use crate::{LlamaBuilder, LlamaModel};
 use kalosm_common::ModelLoadingProgress;
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{ChatMarkers, ChatTemplate};
 use kalosm_language_model::{GenerationParameters, Model, ModelBuilder};
 use kalosm_streams::text_stream::ChannelTextStream;
 use tokenizers::Tokenizer;
@@ -61,7 +61,9 @@ impl Model for Llama {
             InferenceSettings::new(prompt)
                 .with_sample_len(max_length as usize)
                 .with_stop_on(generation_parameters.stop_on().map(|s| s.to_string())),
-            Arc::new(Mutex::new(generation_parameters.sampler())),
+            Arc::new(Mutex::new(
+                generation_parameters.sampler_with_tokenizer(&self.tokenizer())?,
+            )),
         )
         .map(Into::into)
     }
@@ -86,4 +88,8 @@ impl Model for Llama {
     fn chat_markers(&self) -> Option<ChatMarkers> {
         self.chat_markers.deref().clone()
     }
+
+    fn chat_template(&self) -> Option<ChatTemplate> {
+        self.chat_template.clone()
+    }
 }
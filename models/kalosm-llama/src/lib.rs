@@ -34,11 +34,13 @@ extern crate accelerate_src;
 
 mod language_model;
 mod model;
+mod prefix_cache;
 mod raw;
 mod session;
 mod source;
 
 pub use crate::model::LlamaModel;
+use crate::model::{DraftModel, DEFAULT_DRAFT_TOKENS};
 pub use crate::raw::cache::*;
 use crate::raw::Model;
 pub use crate::session::LlamaSession;
@@ -47,11 +49,13 @@ use candle_core::{
     Device,
 };
 pub use kalosm_common::*;
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{BatchedTextGeneration, SyncModel, DEFAULT_MAX_BATCH_SIZE};
+use kalosm_language_model::{ChatMarkers, ChatTemplate};
 use llm_samplers::types::Sampler;
 pub use source::*;
 use std::sync::{Arc, Mutex};
 use tokenizers::Tokenizer;
+use tokio::sync::mpsc::error::TryRecvError;
 
 /// A prelude of commonly used items in kalosm-llama.
 pub mod prelude {
@@ -60,7 +64,7 @@ pub mod prelude {
     pub use kalosm_language_model::*;
 }
 
-enum Task {
+enum Task<M = LlamaModel> {
     Kill,
     Infer {
         settings: InferenceSettings,
@@ -68,14 +72,12 @@ enum Task {
         sampler: Arc<Mutex<dyn Sampler>>,
     },
     RunSync {
-        callback: SyncCallback,
+        callback: SyncCallback<M>,
     },
 }
 
-type SyncCallback = Box<
-    dyn for<'a> FnOnce(
-            &'a mut LlamaModel,
-        ) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + 'a>>
+type SyncCallback<M = LlamaModel> = Box<
+    dyn for<'a> FnOnce(&'a mut M) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + 'a>>
         + Send,
 >;
 
@@ -85,6 +87,7 @@ pub struct Llama {
     task_sender: tokio::sync::mpsc::UnboundedSender<Task>,
     tokenizer: Arc<Tokenizer>,
     chat_markers: Arc<Option<ChatMarkers>>,
+    chat_template: Option<ChatTemplate>,
 }
 
 impl Drop for Llama {
@@ -131,44 +134,38 @@ impl Llama {
         tokenizer: Tokenizer,
         device: Device,
         cache: LlamaCache,
+        draft: Option<DraftModel>,
         chat_markers: Option<ChatMarkers>,
+        max_batch_size: usize,
+        prefix_cache_size: usize,
     ) -> Self {
         let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let chat_template = load_chat_template(&model, &tokenizer);
         let arc_tokenizer = Arc::new(tokenizer);
 
         std::thread::spawn({
             let arc_tokenizer = arc_tokenizer.clone();
             move || {
-                let mut inner = LlamaModel::new(model, arc_tokenizer, device, cache);
+                let mut inner = LlamaModel::new(
+                    model,
+                    arc_tokenizer,
+                    device,
+                    cache,
+                    draft,
+                    prefix_cache_size,
+                );
                 tokio::runtime::Builder::new_current_thread()
                     .enable_all()
                     .build()
                     .unwrap()
-                    .block_on(async move {
-                        while let Some(task) = task_receiver.recv().await {
-                            match task {
-                                Task::Kill => break,
-                                Task::Infer {
-                                    settings,
-                                    sender,
-                                    sampler,
-                                } => {
-                                    if let Err(err) = inner._infer(settings, sampler, sender) {
-                                        eprintln!("Error: {}", err);
-                                    }
-                                }
-                                Task::RunSync { callback } => {
-                                    callback(&mut inner).await;
-                                }
-                            }
-                        }
-                    })
+                    .block_on(run_tasks(&mut inner, &mut task_receiver, max_batch_size))
             }
         });
         Self {
             task_sender,
             tokenizer: arc_tokenizer,
             chat_markers: chat_markers.into(),
+            chat_template,
         }
     }
 
@@ -194,12 +191,108 @@ impl Llama {
     }
 }
 
+/// Run the tasks sent to a model until every handle to the model is dropped.
+async fn run_tasks<M: SyncModel>(
+    inner: &mut M,
+    task_receiver: &mut tokio::sync::mpsc::UnboundedReceiver<Task<M>>,
+    max_batch_size: usize,
+) {
+    let mut batch = BatchedTextGeneration::new().with_max_batch_size(max_batch_size);
+    loop {
+        // If nothing is generating, wait for the next task. Otherwise, pick up any tasks that are already queued and then run the next step of generation
+        let task = if batch.is_empty() {
+            match task_receiver.recv().await {
+                Some(task) => task,
+                None => break,
+            }
+        } else {
+            match task_receiver.try_recv() {
+                Ok(task) => task,
+                Err(TryRecvError::Empty) => {
+                    if let Err(err) = batch.step(inner) {
+                        eprintln!("Error: {}", err);
+                    }
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break,
+            }
+        };
+        match task {
+            Task::Kill => break,
+            Task::Infer {
+                settings,
+                sender,
+                sampler,
+            } => {
+                if let Err(err) = queue_infer(inner, &mut batch, settings, sampler, sender) {
+                    eprintln!("Error: {}", err);
+                }
+            }
+            Task::RunSync { callback } => {
+                callback(inner).await;
+            }
+        }
+    }
+
+    // The streams of requests that are still generating outlive the model, so finish them instead of cutting them off
+    while !batch.is_empty() {
+        if let Err(err) = batch.step(inner) {
+            eprintln!("Error: {}", err);
+        }
+    }
+}
+
+/// Queue a new inference request on the batch scheduler.
+fn queue_infer<M: SyncModel>(
+    model: &M,
+    batch: &mut BatchedTextGeneration<M>,
+    settings: InferenceSettings,
+    sampler: Arc<Mutex<dyn Sampler>>,
+    out: tokio::sync::mpsc::UnboundedSender<String>,
+) -> anyhow::Result<()> {
+    let InferenceSettings {
+        prompt,
+        sample_len,
+        stop_on,
+    } = settings;
+
+    batch.push(
+        model,
+        prompt.as_str(),
+        Some(sample_len as u32),
+        stop_on.as_deref(),
+        sampler,
+        move |token| {
+            out.send(token)
+                .map_err(|_| anyhow::anyhow!("Failed to send token to output channel"))
+                .map(|_| kalosm_language_model::ModelFeedback::Continue)
+        },
+    )
+}
+
 /// A builder with configuration for a Llama model.
-#[derive(Default)]
 pub struct LlamaBuilder {
     source: source::LlamaSource,
     device: Option<Device>,
     flash_attn: bool,
+    max_batch_size: usize,
+    draft_source: Option<source::LlamaSource>,
+    draft_tokens: usize,
+    prefix_cache_size: usize,
+}
+
+impl Default for LlamaBuilder {
+    fn default() -> Self {
+        Self {
+            source: Default::default(),
+            device: None,
+            flash_attn: false,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            draft_source: None,
+            draft_tokens: DEFAULT_DRAFT_TOKENS,
+            prefix_cache_size: 0,
+        }
+    }
 }
 
 impl LlamaBuilder {
@@ -215,6 +308,92 @@ impl LlamaBuilder {
         self
     }
 
+    /// Set the maximum number of concurrent requests that are generated together in one batch. (Defaults to [`DEFAULT_MAX_BATCH_SIZE`])
+    ///
+    /// Requests that are streamed at the same time share forward passes of the model, which increases throughput when serving several users at once.
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Set a small draft model to use for speculative decoding. The draft model proposes several tokens which the main model checks in a single forward pass. This speeds up generation when the draft model predicts the main model well, without changing the output.
+    ///
+    /// The draft model must use the same tokenizer as the main model (for example [`LlamaSource::qwen_2_5_0_5b_instruct`] with a larger Qwen 2.5 model or [`LlamaSource::tiny_llama_1_1b_chat`] with a Llama 2 model).
+    ///
+    /// ```rust, no_run
+    /// use kalosm_llama::prelude::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::qwen_2_5_7b_instruct())
+    ///     .with_draft_model(LlamaSource::qwen_2_5_0_5b_instruct())
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_draft_model(mut self, source: source::LlamaSource) -> Self {
+        self.draft_source = Some(source);
+        self
+    }
+
+    /// Set the number of tokens the draft model proposes at a time. (Defaults to 4)
+    pub fn with_draft_tokens(mut self, draft_tokens: usize) -> Self {
+        self.draft_tokens = draft_tokens.max(1);
+        self
+    }
+
+    /// Set the number of bytes of memory the prefix cache may use. (Defaults to 0, which disables the prefix cache)
+    ///
+    /// The prefix cache keeps the state of the model after recent prompts. New sessions whose prompt starts with the same tokens as a cached prompt (for example the same system prompt or few-shot examples) start from the cached state instead of feeding the whole prompt again. When the cache is full, the least recently used state is evicted.
+    ///
+    /// ```rust, no_run
+    /// use kalosm_llama::prelude::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     // Keep up to 1GB of cached prompts
+    ///     .with_prefix_cache_size(1024 * 1024 * 1024)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_prefix_cache_size(mut self, prefix_cache_size: usize) -> Self {
+        self.prefix_cache_size = prefix_cache_size;
+        self
+    }
+
+    /// Load the draft model for speculative decoding if one is set.
+    pub(crate) async fn load_draft_model(
+        &self,
+        device: &Device,
+        tokenizer: &Tokenizer,
+        mut handler: impl FnMut(ModelLoadingProgress),
+    ) -> anyhow::Result<Option<DraftModel>> {
+        let Some(source) = &self.draft_source else {
+            return Ok(None);
+        };
+
+        let tokenizer_source = format!("Draft tokenizer ({})", source.tokenizer);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(tokenizer_source);
+        let draft_tokenizer = source
+            .tokenizer(|progress| handler(create_progress(progress)))
+            .await?;
+        if draft_tokenizer.get_vocab(true) != tokenizer.get_vocab(true) {
+            anyhow::bail!("The draft model must use the same tokenizer as the main model");
+        }
+
+        let model_source = format!("Draft model ({})", source.model);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
+        let filename = source
+            .model(|progress| handler(create_progress(progress)))
+            .await?;
+        let model = load_model_file(&filename, source.group_query_attention, device)?;
+
+        Ok(Some(DraftModel::new(model, self.draft_tokens)))
+    }
+
     /// Set the device to run the model with. (Defaults to an accelerator if available, otherwise the CPU)
     pub fn with_device(mut self, device: Device) -> Self {
         self.device = Some(device);
@@ -284,18 +463,12 @@ impl LlamaBuilder {
         };
         let filename = filename.await??;
 
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = self.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        let model = load_model_file(&filename, self.source.group_query_attention, &device)?;
+        let draft = self
+            .load_draft_model(&device, &tokenizer, |progress| {
+                (handler.lock().unwrap())(progress)
+            })
+            .await?;
 
         let cache = LlamaCache::new(&model.config);
 
@@ -304,7 +477,10 @@ impl LlamaBuilder {
             tokenizer,
             device,
             cache,
+            draft,
             self.source.markers,
+            self.max_batch_size,
+            self.prefix_cache_size,
         ))
     }
 
@@ -315,6 +491,46 @@ impl LlamaBuilder {
     }
 }
 
+/// Load a model from a gguf or ggml file.
+pub(crate) fn load_model_file(
+    filename: &std::path::Path,
+    group_query_attention: u8,
+    device: &Device,
+) -> anyhow::Result<Model> {
+    let mut file = std::fs::File::open(filename)?;
+    let model = match filename.extension().and_then(|v| v.to_str()) {
+        Some("gguf") => {
+            let model = gguf_file::Content::read(&mut file)?;
+            Model::from_gguf(model, &mut file, device)?
+        }
+        Some("ggml" | "bin") | Some(_) | None => {
+            let model = ggml_file::Content::read(&mut file, device)?;
+            Model::from_ggml(model, group_query_attention as usize, device)?
+        }
+    };
+    Ok(model)
+}
+
+/// Load the chat template from the metadata of the model file if it has one.
+fn load_chat_template(model: &Model, tokenizer: &Tokenizer) -> Option<ChatTemplate> {
+    let template = model.chat_template.as_ref()?;
+    let token_text = |id: Option<u32>| {
+        id.and_then(|id| tokenizer.id_to_token(id))
+            .unwrap_or_default()
+    };
+    match ChatTemplate::new(
+        &template.source,
+        token_text(template.bos_token_id),
+        token_text(template.eos_token_id),
+    ) {
+        Ok(template) => Some(template),
+        Err(err) => {
+            tracing::warn!("Failed to load the chat template from the model file, falling back to the chat markers of the source: {err}");
+            None
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct InferenceSettings {
     prompt: String,
@@ -345,3 +561,88 @@ impl InferenceSettings {
         self
     }
 }
+
+/// A model that always generates the token `a`.
+#[cfg(test)]
+struct RepeatModel {
+    tokenizer: Arc<Tokenizer>,
+}
+
+#[cfg(test)]
+impl SyncModel for RepeatModel {
+    type Session = ();
+
+    fn new_session(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn feed_text(&self, session: &mut (), prompt: &str, into: &mut Vec<f32>) -> anyhow::Result<()> {
+        let tokens = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(anyhow::Error::msg)?;
+        self.feed_tokens(session, tokens.get_ids(), into)
+    }
+
+    fn feed_tokens(&self, _: &mut (), _: &[u32], into: &mut Vec<f32>) -> anyhow::Result<()> {
+        *into = vec![0., 100., 0., 0.];
+        Ok(())
+    }
+
+    fn stop_token(&self) -> anyhow::Result<u32> {
+        Ok(0)
+    }
+
+    fn tokenizer(&self) -> Arc<Tokenizer> {
+        self.tokenizer.clone()
+    }
+}
+
+#[test]
+fn dropping_the_model_finishes_running_generations() {
+    let tokenizer: Tokenizer = r#"{
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": null,
+        "post_processor": null,
+        "decoder": { "type": "Fuse" },
+        "model": { "type": "BPE", "vocab": { "</s>": 0, "a": 1, "H": 2, "i": 3 }, "merges": [] }
+    }"#
+    .parse()
+    .unwrap();
+    let mut model = RepeatModel {
+        tokenizer: Arc::new(tokenizer),
+    };
+
+    // The last handle to the model either sends a kill task or just closes the channel
+    for kill in [true, false] {
+        let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let sampler = kalosm_language_model::GenerationParameters::default().sampler();
+        task_sender
+            .send(Task::Infer {
+                settings: InferenceSettings::new("Hi").with_sample_len(3),
+                sender,
+                sampler: Arc::new(Mutex::new(sampler)),
+            })
+            .unwrap();
+        if kill {
+            task_sender.send(Task::Kill).unwrap();
+        }
+        drop(task_sender);
+
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(run_tasks(&mut model, &mut task_receiver, 1));
+
+        let mut text = String::new();
+        while let Ok(token) = receiver.try_recv() {
+            text += &token;
+        }
+        assert_eq!(text, "aaa");
+    }
+}
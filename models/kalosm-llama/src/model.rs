@@ -1,18 +1,33 @@
+use crate::prefix_cache::PrefixCache;
 use crate::raw::cache::LlamaCache;
 use crate::{raw::Model, session::LlamaSession};
 use anyhow::{Error as E, Result};
 use kalosm_common::*;
-use kalosm_language_model::SyncModelExt;
-use std::sync::Arc;
-
-use candle_core::{
-    quantized::{ggml_file, gguf_file},
-    DType, Device,
-};
-use kalosm_language_model::SyncModel;
+use std::sync::{Arc, Mutex};
+
+use candle_core::{DType, Device, IndexOp};
+use kalosm_language_model::{Session, SyncModel};
 use tokenizers::Tokenizer;
 
-use crate::InferenceSettings;
+use crate::load_model_file;
+
+/// The default number of tokens the draft model proposes at a time.
+pub(crate) const DEFAULT_DRAFT_TOKENS: usize = 4;
+
+/// A small model that drafts tokens for speculative decoding.
+pub(crate) struct DraftModel {
+    model: Model,
+    draft_tokens: usize,
+}
+
+impl DraftModel {
+    pub(crate) fn new(model: Model, draft_tokens: usize) -> Self {
+        Self {
+            model,
+            draft_tokens,
+        }
+    }
+}
 
 /// The inner, synchronous Llama model.
 pub struct LlamaModel {
@@ -20,6 +35,8 @@ pub struct LlamaModel {
     device: Device,
     tokenizer: Arc<Tokenizer>,
     cache: LlamaCache,
+    draft: Option<DraftModel>,
+    prefix_cache: Option<Mutex<PrefixCache>>,
 }
 
 impl SyncModel for LlamaModel {
@@ -27,7 +44,10 @@ impl SyncModel for LlamaModel {
 
     fn new_session(&self) -> anyhow::Result<Self::Session> {
         let cache = self.cache.clone();
-        Ok(Self::Session { cache })
+        Ok(Self::Session {
+            cache,
+            draft_cache: None,
+        })
     }
 
     fn feed_text(
@@ -47,13 +67,106 @@ impl SyncModel for LlamaModel {
         tokens: &[u32],
         logits: &mut Vec<f32>,
     ) -> anyhow::Result<()> {
+        let new_tokens = self.restore_prefix(session, tokens)?;
         Self::forward(
             &self.model,
             &self.device,
-            tokens,
+            new_tokens,
             Some(&mut session.cache),
             logits,
-        )
+        )?;
+        self.remember_prefix(session, tokens)
+    }
+
+    fn feed_tokens_batch(
+        &self,
+        sessions: &mut [&mut Self::Session],
+        tokens: &[&[u32]],
+        into: &mut [Vec<f32>],
+    ) -> anyhow::Result<()> {
+        if sessions.len() != tokens.len() || tokens.len() != into.len() {
+            anyhow::bail!(
+                "expected the same number of sessions, inputs and outputs, found {}, {} and {}",
+                sessions.len(),
+                tokens.len(),
+                into.len()
+            );
+        }
+
+        let context_length = self.model.config.context_length;
+        let mut batch_caches = Vec::with_capacity(sessions.len());
+        let mut batch_tokens = Vec::with_capacity(sessions.len());
+        let mut batch_logits = Vec::with_capacity(sessions.len());
+        for ((session, tokens), logits) in sessions.iter_mut().zip(tokens).zip(into.iter_mut()) {
+            if tokens.is_empty() {
+                return Err(anyhow::anyhow!("Cannot run model on empty input"));
+            }
+            // Sequences that overflow the context length need to recompute their cache, so they are run on their own
+            if session.cache.tokens.len() + tokens.len() > context_length {
+                self.feed_tokens(session, tokens, logits)?;
+            } else {
+                batch_caches.push(&mut session.cache);
+                batch_tokens.push(*tokens);
+                batch_logits.push(logits);
+            }
+        }
+
+        if batch_caches.is_empty() {
+            return Ok(());
+        }
+        let logits = self
+            .model
+            .forward_batch(&batch_tokens, &self.device, &mut batch_caches)?
+            .to_dtype(DType::F32)?;
+        for (i, logits_vec) in batch_logits.into_iter().enumerate() {
+            copy_tensor_into_vec(&logits.i(i)?, logits_vec)?;
+        }
+
+        Ok(())
+    }
+
+    fn feed_tokens_speculative(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<Vec<f32>>,
+    ) -> anyhow::Result<Vec<u32>> {
+        let Some(draft) = &self.draft else {
+            into.resize_with(1, Vec::new);
+            self.feed_tokens(session, tokens, &mut into[0])?;
+            return Ok(Vec::new());
+        };
+        if tokens.is_empty() {
+            return Err(anyhow::anyhow!("Cannot run model on empty input"));
+        }
+
+        let prompt = tokens;
+        let tokens = self.restore_prefix(session, tokens)?;
+        let draft_tokens = self.draft_tokens(draft, session, tokens)?;
+
+        // Verify the draft with the main model in a single forward pass
+        let all_tokens: Vec<_> = tokens.iter().chain(&draft_tokens).copied().collect();
+        let logits = self
+            .model
+            .forward_last_n(
+                &all_tokens,
+                &self.device,
+                Some(&mut session.cache),
+                draft_tokens.len() + 1,
+            )?
+            .squeeze(0)?
+            .to_dtype(DType::F32)?;
+        into.resize_with(draft_tokens.len() + 1, Vec::new);
+        for (i, logits_vec) in into.iter_mut().enumerate() {
+            copy_tensor_into_vec(&logits.i(i)?, logits_vec)?;
+        }
+        self.remember_prefix(session, prompt)?;
+
+        Ok(draft_tokens)
+    }
+
+    fn reject_draft_tokens(&self, session: &mut Self::Session, count: usize) -> anyhow::Result<()> {
+        session.rollback(count)
     }
 
     fn stop_token(&self) -> anyhow::Result<u32> {
@@ -71,6 +184,104 @@ impl SyncModel for LlamaModel {
 }
 
 impl LlamaModel {
+    /// If the session is new, start it from the cached state that shares the longest prefix with the tokens. Returns the tokens that still need to be fed.
+    fn restore_prefix<'a>(
+        &self,
+        session: &mut LlamaSession,
+        tokens: &'a [u32],
+    ) -> anyhow::Result<&'a [u32]> {
+        let Some(prefix_cache) = &self.prefix_cache else {
+            return Ok(tokens);
+        };
+        if !session.cache.tokens.is_empty() || tokens.len() < 2 {
+            return Ok(tokens);
+        }
+        // At least one token needs to be fed to get the logits for the next token
+        let cached = prefix_cache
+            .lock()
+            .unwrap()
+            .get(&tokens[..tokens.len() - 1])?;
+        match cached {
+            Some(cache) => {
+                let cached_len = cache.tokens.len();
+                session.cache = cache;
+                session.draft_cache = None;
+                Ok(&tokens[cached_len..])
+            }
+            None => Ok(tokens),
+        }
+    }
+
+    /// Save the state of a session that was just fed its prompt to the prefix cache so new sessions with the same prefix can reuse it.
+    fn remember_prefix(&self, session: &LlamaSession, prompt: &[u32]) -> anyhow::Result<()> {
+        let Some(prefix_cache) = &self.prefix_cache else {
+            return Ok(());
+        };
+        // The session may have been fed before the prompt, or the prompt may have overflowed the context
+        if prompt.len() < 2 || !session.cache.tokens.starts_with(prompt) {
+            return Ok(());
+        }
+        prefix_cache
+            .lock()
+            .unwrap()
+            .insert(&session.cache, prompt.len())?;
+        Ok(())
+    }
+
+    /// Greedily draft the next tokens after the session and the new tokens with the draft model.
+    fn draft_tokens(
+        &self,
+        draft: &DraftModel,
+        session: &mut LlamaSession,
+        tokens: &[u32],
+    ) -> anyhow::Result<Vec<u32>> {
+        let draft_cache = session
+            .draft_cache
+            .get_or_insert_with(|| LlamaCache::new(&draft.model.config));
+
+        // The draft model needs to see the same tokens as the main model. Only the tokens after the longest shared prefix need to be fed
+        let all_tokens: Vec<_> = session.cache.tokens.iter().chain(tokens).copied().collect();
+        let shared_prefix = draft_cache
+            .tokens
+            .iter()
+            .zip(&all_tokens)
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(all_tokens.len() - 1);
+        draft_cache.truncate(shared_prefix)?;
+
+        let mut logits = Vec::new();
+        Self::forward(
+            &draft.model,
+            &self.device,
+            &all_tokens[shared_prefix..],
+            Some(&mut *draft_cache),
+            &mut logits,
+        )?;
+        let mut draft_tokens = Vec::with_capacity(draft.draft_tokens);
+        loop {
+            let token = logits
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(token, _)| token as u32)
+                .ok_or_else(|| anyhow::anyhow!("The draft model returned no logits"))?;
+            draft_tokens.push(token);
+            if draft_tokens.len() >= draft.draft_tokens {
+                break;
+            }
+            Self::forward(
+                &draft.model,
+                &self.device,
+                &[token],
+                Some(&mut *draft_cache),
+                &mut logits,
+            )?;
+        }
+
+        Ok(draft_tokens)
+    }
+
     fn forward(
         model: &Model,
         device: &Device,
@@ -110,26 +321,20 @@ impl LlamaModel {
             .source
             .model(|progress| handler(create_progress(progress)))
             .await?;
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = builder.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        let model = load_model_file(&filename, builder.source.group_query_attention, &device)?;
+        let draft = builder
+            .load_draft_model(&device, &tokenizer, &mut handler)
+            .await?;
 
         let cache = LlamaCache::new(&model.config);
-        Ok(Self {
+        Ok(Self::new(
             model,
-            tokenizer: Arc::new(tokenizer),
+            Arc::new(tokenizer),
             device,
             cache,
-        })
+            draft,
+            builder.prefix_cache_size,
+        ))
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -138,42 +343,17 @@ impl LlamaModel {
         tokenizer: Arc<Tokenizer>,
         device: Device,
         cache: LlamaCache,
+        draft: Option<DraftModel>,
+        prefix_cache_size: usize,
     ) -> Self {
         Self {
             cache,
             model,
             device,
             tokenizer,
+            draft,
+            prefix_cache: (prefix_cache_size > 0)
+                .then(|| Mutex::new(PrefixCache::new(prefix_cache_size))),
         }
     }
-
-    pub(crate) fn _infer(
-        &mut self,
-        settings: InferenceSettings,
-        sampler: std::sync::Arc<std::sync::Mutex<dyn llm_samplers::prelude::Sampler>>,
-        out: tokio::sync::mpsc::UnboundedSender<String>,
-    ) -> Result<()> {
-        let InferenceSettings {
-            prompt,
-            sample_len,
-            stop_on,
-        } = settings;
-
-        let mut session = self.new_session()?;
-
-        self.stream_text_with_sampler(
-            &mut session,
-            prompt.as_str(),
-            Some(sample_len as u32),
-            stop_on.as_deref(),
-            sampler,
-            |token| {
-                out.send(token)
-                    .map_err(|_| anyhow::anyhow!("Failed to send token to output channel"))
-                    .map(|_| kalosm_language_model::ModelFeedback::Continue)
-            },
-        )?;
-
-        Ok(())
-    }
 }
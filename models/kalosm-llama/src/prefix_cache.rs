@@ -0,0 +1,262 @@
+use crate::raw::cache::LlamaCache;
+use std::{cell::Cell, collections::HashMap};
+
+/// A snapshot of the cache after feeding the tokens on the path to a node.
+struct Snapshot {
+    cache: LlamaCache,
+    memory_usage: usize,
+    last_used: Cell<u64>,
+}
+
+/// A node in the radix tree. Each edge to a child is labeled with one or more tokens.
+#[derive(Default)]
+struct Node {
+    snapshot: Option<Snapshot>,
+    /// The children of the node keyed by the first token of their edge.
+    children: HashMap<u32, Edge>,
+}
+
+struct Edge {
+    tokens: Vec<u32>,
+    node: Node,
+}
+
+/// A cache of session states shared across sessions. The states are stored in a radix tree keyed by the tokens that were fed, so a new session whose prompt starts with the same tokens as a cached state can start from that state instead of feeding the whole prompt.
+///
+/// When the states use more than the memory budget, the least recently used state is evicted.
+pub(crate) struct PrefixCache {
+    root: Node,
+    max_memory_usage: usize,
+    memory_usage: usize,
+    clock: u64,
+}
+
+impl PrefixCache {
+    /// Create a new cache that keeps at most `max_memory_usage` bytes of states.
+    pub(crate) fn new(max_memory_usage: usize) -> Self {
+        Self {
+            root: Node::default(),
+            max_memory_usage,
+            memory_usage: 0,
+            clock: 0,
+        }
+    }
+
+    /// Find the cached state that shares the longest prefix with `tokens`. Returns a copy of the state truncated to the shared prefix.
+    pub(crate) fn get(&mut self, tokens: &[u32]) -> candle_core::Result<Option<LlamaCache>> {
+        self.clock += 1;
+        let Some((snapshot, len)) = self.root.longest_prefix(tokens, 0) else {
+            return Ok(None);
+        };
+        if len == 0 {
+            return Ok(None);
+        }
+        snapshot.last_used.set(self.clock);
+        let mut cache = snapshot.cache.deep_clone()?;
+        cache.truncate(len)?;
+        Ok(Some(cache))
+    }
+
+    /// Store a copy of the state after feeding the first `len` tokens of the cache.
+    pub(crate) fn insert(&mut self, cache: &LlamaCache, len: usize) -> candle_core::Result<()> {
+        let len = len.min(cache.tokens.len());
+        if len == 0 {
+            return Ok(());
+        }
+        let mut cache = cache.deep_clone()?;
+        cache.truncate(len)?;
+        let memory_usage = cache.memory_usage();
+        // A state that doesn't fit in the budget on its own would evict everything else
+        if memory_usage > self.max_memory_usage {
+            return Ok(());
+        }
+
+        self.clock += 1;
+        let tokens = cache.tokens.clone();
+        let node = self.root.node_for(&tokens);
+        if let Some(old) = node.snapshot.take() {
+            self.memory_usage -= old.memory_usage;
+        }
+        node.snapshot = Some(Snapshot {
+            cache,
+            memory_usage,
+            last_used: Cell::new(self.clock),
+        });
+        self.memory_usage += memory_usage;
+
+        while self.memory_usage > self.max_memory_usage {
+            let Some(oldest) = self.root.oldest() else {
+                break;
+            };
+            self.memory_usage -= self.root.remove(oldest).unwrap_or_default();
+        }
+
+        Ok(())
+    }
+}
+
+impl Node {
+    /// Find the snapshot that shares the longest prefix with `tokens`. `depth` is the number of tokens on the path to this node, which every snapshot under this node shares with `tokens`.
+    fn longest_prefix(&self, tokens: &[u32], depth: usize) -> Option<(&Snapshot, usize)> {
+        if let Some(first) = tokens.first() {
+            if let Some(edge) = self.children.get(first) {
+                let shared = edge
+                    .tokens
+                    .iter()
+                    .zip(tokens)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if shared == edge.tokens.len() {
+                    if let Some(found) = edge.node.longest_prefix(&tokens[shared..], depth + shared)
+                    {
+                        return Some(found);
+                    }
+                }
+                // The tokens leave the edge partway through. Any snapshot after the edge shares the tokens before that point
+                else if let Some(snapshot) = edge.node.any_snapshot() {
+                    return Some((snapshot, depth + shared));
+                }
+            }
+        }
+
+        // Prefer the snapshot of this node because it doesn't need to be truncated
+        self.any_snapshot().map(|snapshot| (snapshot, depth))
+    }
+
+    /// Find any snapshot in this node or the nodes under it.
+    fn any_snapshot(&self) -> Option<&Snapshot> {
+        if self.snapshot.is_some() {
+            return self.snapshot.as_ref();
+        }
+        self.children
+            .values()
+            .find_map(|edge| edge.node.any_snapshot())
+    }
+
+    /// Get the node for the tokens, splitting edges and creating nodes as needed.
+    fn node_for(&mut self, tokens: &[u32]) -> &mut Node {
+        let Some(&first) = tokens.first() else {
+            return self;
+        };
+        let edge = self.children.entry(first).or_insert_with(|| Edge {
+            tokens: tokens.to_vec(),
+            node: Node::default(),
+        });
+        let shared = edge
+            .tokens
+            .iter()
+            .zip(tokens)
+            .take_while(|(a, b)| a == b)
+            .count();
+        if shared < edge.tokens.len() {
+            // Split the edge at the end of the shared tokens
+            let rest = edge.tokens.split_off(shared);
+            let child = std::mem::take(&mut edge.node);
+            edge.node.children.insert(
+                rest[0],
+                Edge {
+                    tokens: rest,
+                    node: child,
+                },
+            );
+        }
+        edge.node.node_for(&tokens[shared..])
+    }
+
+    /// Find the time the least recently used snapshot in this node or the nodes under it was used.
+    fn oldest(&self) -> Option<u64> {
+        let own = self
+            .snapshot
+            .as_ref()
+            .map(|snapshot| snapshot.last_used.get());
+        self.children
+            .values()
+            .filter_map(|edge| edge.node.oldest())
+            .chain(own)
+            .min()
+    }
+
+    /// Remove the snapshot that was last used at `last_used` and prune nodes that are no longer needed. Returns the memory the snapshot used.
+    fn remove(&mut self, last_used: u64) -> Option<usize> {
+        if self
+            .snapshot
+            .as_ref()
+            .filter(|snapshot| snapshot.last_used.get() == last_used)
+            .is_some()
+        {
+            return self.snapshot.take().map(|snapshot| snapshot.memory_usage);
+        }
+
+        let (first, removed) = self
+            .children
+            .iter_mut()
+            .find_map(|(first, edge)| Some((*first, edge.node.remove(last_used)?)))?;
+        let edge = self.children.get_mut(&first).unwrap();
+        if edge.node.snapshot.is_none() {
+            match edge.node.children.len() {
+                // The node no longer leads to any snapshot
+                0 => {
+                    self.children.remove(&first);
+                }
+                // Merge the node with its only child
+                1 => {
+                    let (_, child) = edge.node.children.drain().next().unwrap();
+                    edge.tokens.extend(child.tokens);
+                    edge.node = child.node;
+                }
+                _ => {}
+            }
+        }
+        Some(removed)
+    }
+}
+
+#[test]
+fn prefix_cache_shares_prefixes() {
+    use candle_core::{Device, Tensor};
+
+    let cache_for = |tokens: &[u32]| {
+        let device = Device::Cpu;
+        let states =
+            Tensor::zeros((1, 1, tokens.len(), 2), candle_core::DType::F32, &device).unwrap();
+        let map = [
+            (
+                "llama.cache.tokens".to_string(),
+                Tensor::new(tokens, &device).unwrap(),
+            ),
+            (
+                "llama.cache.max_seq_len".to_string(),
+                Tensor::new(16u32, &device).unwrap(),
+            ),
+            ("llama.cache.blocks.0.key".to_string(), states.clone()),
+            ("llama.cache.blocks.0.value".to_string(), states),
+        ];
+        LlamaCache::from_tensor_map(map.into_iter().collect()).unwrap()
+    };
+    let get = |prefix_cache: &mut PrefixCache, tokens: &[u32]| {
+        prefix_cache
+            .get(tokens)
+            .unwrap()
+            .map(|cache| cache.tokens.clone())
+    };
+
+    // Each token uses 16 bytes for the key and value, so the budget fits two states of 4 tokens
+    let mut prefix_cache = PrefixCache::new(2 * 4 * 16);
+    prefix_cache.insert(&cache_for(&[1, 2, 3, 4]), 4).unwrap();
+    prefix_cache.insert(&cache_for(&[1, 2, 5, 6]), 4).unwrap();
+
+    // Exact, partial and missing matches
+    assert_eq!(
+        get(&mut prefix_cache, &[1, 2, 3, 4, 7]),
+        Some(vec![1, 2, 3, 4])
+    );
+    assert_eq!(get(&mut prefix_cache, &[1, 2, 5, 8]), Some(vec![1, 2, 5]));
+    assert_eq!(get(&mut prefix_cache, &[1, 9]), Some(vec![1]));
+    assert_eq!(get(&mut prefix_cache, &[9]), None);
+
+    // [1, 2, 3, 4] was used least recently, so it is evicted first
+    get(&mut prefix_cache, &[1, 2, 5, 6]);
+    prefix_cache.insert(&cache_for(&[7, 8, 9, 10]), 4).unwrap();
+    assert_eq!(get(&mut prefix_cache, &[1, 2, 3, 4]), Some(vec![1, 2]));
+    assert_eq!(get(&mut prefix_cache, &[7, 8, 9]), Some(vec![7, 8, 9]));
+}
@@ -80,6 +80,40 @@ pub enum AttentionVariant {
     Grouped(GroupedAttention),
 }
 
+impl AttentionVariant {
+    fn project(
+        &self,
+        num_heads: usize,
+        head_dim: usize,
+        num_key_value_heads: usize,
+        hidden_states: &Tensor,
+    ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
+        match self {
+            AttentionVariant::Separate(attention) => {
+                attention.project(num_heads, head_dim, num_key_value_heads, hidden_states)
+            }
+            AttentionVariant::Grouped(attention) => {
+                attention.project(num_heads, head_dim, num_key_value_heads, hidden_states)
+            }
+        }
+    }
+
+    fn apply_rope(
+        &self,
+        rope_cache: &RopeCache,
+        query_states: &Tensor,
+        key_states: &Tensor,
+        start_pos: usize,
+    ) -> candle_core::Result<(Tensor, Tensor)> {
+        match self {
+            AttentionVariant::Separate(attention) if attention.interleaved_rope => {
+                rope_cache.forward_i(query_states, key_states, start_pos)
+            }
+            _ => rope_cache.forward(query_states, key_states, start_pos),
+        }
+    }
+}
+
 pub struct AttentionBias {
     pub bias_q: Tensor,
     pub bias_k: Tensor,
@@ -95,14 +129,13 @@ pub struct SeparateAttention {
 }
 
 impl SeparateAttention {
-    fn forward(
+    /// Project the hidden states into the query, key and value states before the rotary embeddings are applied.
+    fn project(
         &self,
         num_heads: usize,
         head_dim: usize,
         num_key_value_heads: usize,
         hidden_states: &Tensor,
-        rope_cache: &RopeCache,
-        start_pos: usize,
     ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
         let b_sz = hidden_states.dims()[0];
         let seq_len = hidden_states.dims()[1];
@@ -150,13 +183,6 @@ impl SeparateAttention {
                 let key_states = key_states.join().map_err(|_| {
                     candle_core::Error::Msg("failed to join key states".to_string())
                 })??;
-
-                let (query_states, key_states) = if self.interleaved_rope {
-                    rope_cache.forward_i(&query_states, &key_states, start_pos)?
-                } else {
-                    rope_cache.forward(&query_states, &key_states, start_pos)?
-                };
-
                 let value_states = value_states.join().map_err(|_| {
                     candle_core::Error::Msg("failed to join value states".to_string())
                 })??;
@@ -198,12 +224,6 @@ impl SeparateAttention {
                     .transpose(1, 2)?
             };
 
-            let (query_states, key_states) = if self.interleaved_rope {
-                rope_cache.forward_i(&query_states, &key_states, start_pos)?
-            } else {
-                rope_cache.forward(&query_states, &key_states, start_pos)?
-            };
-
             Ok((query_states, key_states, value_states))
         }
     }
@@ -214,14 +234,13 @@ pub struct GroupedAttention {
 }
 
 impl GroupedAttention {
-    fn forward(
+    /// Project the hidden states into the query, key and value states before the rotary embeddings are applied.
+    fn project(
         &self,
         num_heads: usize,
         head_dim: usize,
         num_key_value_heads: usize,
         x: &Tensor,
-        rope_cache: &RopeCache,
-        start_pos: usize,
     ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
         let b_sz = x.dims()[0];
         let seq_len = x.dims()[1];
@@ -246,9 +265,6 @@ impl GroupedAttention {
             .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
             .transpose(1, 2)?;
 
-        let (query_states, key_states) =
-            rope_cache.forward(&query_states, &key_states, start_pos)?;
-
         Ok((query_states, key_states, value_states))
     }
 }
@@ -276,30 +292,23 @@ impl LlamaAttention {
     ) -> candle_core::Result<Tensor> {
         let bsz = hidden_states.dims()[0];
         let q_len = hidden_states.dims()[1];
-        let hidden_size = self.hidden_size;
         let num_heads = self.n_head;
         let head_dim = self.head_dim;
         let num_key_value_heads = self.n_kv_head;
         let num_key_value_groups = num_heads / num_key_value_heads;
 
-        let (query_states, key_states, value_states) = match self.attention_variant {
-            AttentionVariant::Separate(ref attention) => attention.forward(
-                num_heads,
-                head_dim,
-                num_key_value_heads,
-                hidden_states,
-                &self.rope_cache,
-                start_pos,
-            )?,
-            AttentionVariant::Grouped(ref attention) => attention.forward(
-                num_heads,
-                head_dim,
-                num_key_value_heads,
-                hidden_states,
-                &self.rope_cache,
-                start_pos,
-            )?,
-        };
+        let (query_states, key_states, value_states) = self.attention_variant.project(
+            num_heads,
+            head_dim,
+            num_key_value_heads,
+            hidden_states,
+        )?;
+        let (query_states, key_states) = self.attention_variant.apply_rope(
+            &self.rope_cache,
+            &query_states,
+            &key_states,
+            start_pos,
+        )?;
 
         let key_states = repeat_kv(key_states.clone(), num_key_value_groups)?;
         let value_states = repeat_kv(value_states, num_key_value_groups)?;
@@ -309,6 +318,111 @@ impl LlamaAttention {
             Some(cache) => cache.append(&key_states, &value_states)?,
         };
 
+        self.attend(
+            &query_states,
+            &key_states,
+            &value_states,
+            attention_mask,
+            bsz,
+            q_len,
+        )
+    }
+
+    /// Run attention for a batch of sequences that each have their own cache. The hidden states of each sequence are right padded to the longest sequence in the batch.
+    ///
+    /// The attention mask must hide the padding of both the new tokens and the cached tokens (see [`kalosm_common::MaskCache::get_batch_mask`]).
+    pub(crate) fn forward_batch(
+        &self,
+        hidden_states: &Tensor,
+        attention_mask: &AttentionMask,
+        seq_lens: &[usize],
+        start_positions: &[usize],
+        caches: &mut [&mut KvCache],
+    ) -> candle_core::Result<Tensor> {
+        let bsz = hidden_states.dims()[0];
+        let q_len = hidden_states.dims()[1];
+        let num_heads = self.n_head;
+        let head_dim = self.head_dim;
+        let num_key_value_heads = self.n_kv_head;
+        let num_key_value_groups = num_heads / num_key_value_heads;
+
+        // The projections are shared by the whole batch
+        let (query_states, key_states, value_states) = self.attention_variant.project(
+            num_heads,
+            head_dim,
+            num_key_value_heads,
+            hidden_states,
+        )?;
+
+        // Each sequence starts at a different position and has a different cache
+        let mut batch_queries = Vec::with_capacity(bsz);
+        let mut batch_keys = Vec::with_capacity(bsz);
+        let mut batch_values = Vec::with_capacity(bsz);
+        for (i, cache) in caches.iter_mut().enumerate() {
+            let seq_len = seq_lens[i];
+            let query_states = query_states.narrow(0, i, 1)?.narrow(2, 0, seq_len)?;
+            let key_states = key_states.narrow(0, i, 1)?.narrow(2, 0, seq_len)?;
+            let value_states = value_states.narrow(0, i, 1)?.narrow(2, 0, seq_len)?;
+            let (query_states, key_states) = self.attention_variant.apply_rope(
+                &self.rope_cache,
+                &query_states,
+                &key_states,
+                start_positions[i],
+            )?;
+
+            let key_states = repeat_kv(key_states, num_key_value_groups)?;
+            let value_states = repeat_kv(value_states, num_key_value_groups)?;
+            let (key_states, value_states) = cache.append(&key_states, &value_states)?;
+
+            batch_queries.push(query_states.pad_with_zeros(2, 0, q_len - seq_len)?);
+            batch_keys.push(key_states);
+            batch_values.push(value_states);
+        }
+
+        // Pad the keys and values to the longest sequence. The padding is hidden by the attention mask
+        let kv_len = batch_keys
+            .iter()
+            .map(|key_states| key_states.dim(2))
+            .collect::<candle_core::Result<Vec<_>>>()?
+            .into_iter()
+            .max()
+            .unwrap_or_default();
+        let pad = |states: Vec<Tensor>| -> candle_core::Result<Tensor> {
+            let states = states
+                .into_iter()
+                .map(|state| {
+                    let len = state.dim(2)?;
+                    state.pad_with_zeros(2, 0, kv_len - len)
+                })
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Tensor::cat(&states, 0)
+        };
+        let query_states = Tensor::cat(&batch_queries, 0)?;
+        let key_states = pad(batch_keys)?;
+        let value_states = pad(batch_values)?;
+
+        self.attend(
+            &query_states,
+            &key_states,
+            &value_states,
+            Some(attention_mask),
+            bsz,
+            q_len,
+        )
+    }
+
+    fn attend(
+        &self,
+        query_states: &Tensor,
+        key_states: &Tensor,
+        value_states: &Tensor,
+        attention_mask: Option<&AttentionMask>,
+        bsz: usize,
+        q_len: usize,
+    ) -> candle_core::Result<Tensor> {
+        let num_heads = self.n_head;
+        let head_dim = self.head_dim;
+
         let mut attn_weights = (query_states.matmul(&key_states.t()?)? / (head_dim as f64).sqrt())?;
 
         if let Some(attention_mask) = attention_mask {
@@ -317,7 +431,7 @@ impl LlamaAttention {
 
         attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
 
-        let mut attn_output = attn_weights.matmul(&value_states)?;
+        let mut attn_output = attn_weights.matmul(value_states)?;
 
         if attn_output.dims() != [bsz, num_heads, q_len, head_dim] {
             return Err(candle_core::Error::Msg(format!(
@@ -329,7 +443,7 @@ impl LlamaAttention {
 
         attn_output = attn_output.transpose(1, 2)?;
 
-        attn_output = attn_output.reshape(&[bsz, q_len, hidden_size])?;
+        attn_output = attn_output.reshape(&[bsz, q_len, self.hidden_size])?;
 
         attn_output = self.attention_wo.forward(&attn_output)?;
 
@@ -38,6 +38,44 @@ impl LlamaCache {
         }
     }
 
+    /// Remove every token after the first `len` tokens from the cache.
+    pub fn truncate(&mut self, len: usize) -> candle_core::Result<()> {
+        if len >= self.tokens.len() {
+            return Ok(());
+        }
+        self.tokens.truncate(len);
+        for block in &mut self.blocks {
+            block.truncate(len)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the cache into new memory. Unlike [`Clone::clone`], feeding tokens into the copy never changes the original cache.
+    pub fn deep_clone(&self) -> candle_core::Result<Self> {
+        Ok(Self {
+            max_seq_len: self.max_seq_len,
+            tokens: self.tokens.clone(),
+            blocks: self
+                .blocks
+                .iter()
+                .map(KvCache::deep_clone)
+                .collect::<candle_core::Result<_>>()?,
+        })
+    }
+
+    /// Get the number of bytes the keys and values in the cache use.
+    pub fn memory_usage(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter_map(|block| {
+                let (Ok(Some(k)), Ok(Some(v))) = (block.cache().k(), block.cache().v()) else {
+                    return None;
+                };
+                Some((k.elem_count() + v.elem_count()) * k.dtype().size_in_bytes())
+            })
+            .sum()
+    }
+
     /// Get the tensor map for this cache. This can be used to save the cache to disk.
     pub fn get_tensor_map(&self, device: &Device) -> HashMap<String, Tensor> {
         let mut map = HashMap::with_capacity(self.blocks.len());
@@ -122,3 +160,29 @@ impl LlamaCache {
         })
     }
 }
+
+#[test]
+fn truncate_cache() {
+    let config = LlamaConfig {
+        rope_freq_weight: None,
+        rope_theta: 10000.,
+        context_length: 16,
+        head_dimension: 2,
+        n_head: 1,
+        n_layer: 2,
+    };
+    let device = Device::Cpu;
+    let mut cache = LlamaCache::new(&config);
+    cache.tokens = vec![1, 2, 3, 4, 5];
+    let states = Tensor::zeros((1, 1, 5, 2), candle_core::DType::F32, &device).unwrap();
+    for block in &mut cache.blocks {
+        block.append(&states, &states).unwrap();
+    }
+
+    cache.truncate(2).unwrap();
+
+    assert_eq!(cache.tokens, [1, 2]);
+    for block in &cache.blocks {
+        assert_eq!(block.cache().current_seq_len(), 2);
+    }
+}
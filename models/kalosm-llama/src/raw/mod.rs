@@ -42,8 +42,16 @@ impl LlamaConfig {
     }
 }
 
+/// The chat template stored in the metadata of a GGUF file.
+pub(crate) struct GgufChatTemplate {
+    pub(crate) source: String,
+    pub(crate) bos_token_id: Option<u32>,
+    pub(crate) eos_token_id: Option<u32>,
+}
+
 pub struct Model {
     pub(crate) config: LlamaConfig,
+    pub(crate) chat_template: Option<GgufChatTemplate>,
     tok_embeddings: Embedding,
     layers: Vec<LlamaAttention>,
     norm: RmsNorm,
@@ -115,6 +123,7 @@ impl Model {
         }
         Ok(Self {
             config,
+            chat_template: None,
             tok_embeddings: Embedding::new(tok_embeddings, ct.hparams.n_embd as usize),
             layers,
             norm: decode_norm(ct.remove("norm.weight")?, 1e-5)?,
@@ -157,6 +166,19 @@ impl Model {
         let context_length = md_get(".context_length")?.to_u32()? as usize;
         let head_dim = embedding_length / head_count;
 
+        let chat_template = md_get("tokenizer.chat_template")
+            .and_then(|m| m.to_string().cloned())
+            .ok()
+            .map(|source| GgufChatTemplate {
+                source,
+                bos_token_id: md_get("tokenizer.ggml.bos_token_id")
+                    .and_then(|m| m.to_u32())
+                    .ok(),
+                eos_token_id: md_get("tokenizer.ggml.eos_token_id")
+                    .and_then(|m| m.to_u32())
+                    .ok(),
+            });
+
         let config = LlamaConfig {
             rope_freq_weight: match ct.tensor(reader, "rope_freqs.weight", device).ok() {
                 Some(rope_freq_weight) => Some(rope_freq_weight.dequantize(device)?),
@@ -264,6 +286,7 @@ impl Model {
         }
         Ok(Self {
             config,
+            chat_template,
             tok_embeddings: Embedding::new(tok_embeddings, embedding_length),
             layers,
             norm,
@@ -273,12 +296,26 @@ impl Model {
     }
 
     pub fn forward(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        cache: Option<&mut LlamaCache>,
+    ) -> Result<Tensor> {
+        self.forward_last_n(tokens, device, cache, 1)?.squeeze(1)
+    }
+
+    /// Run the model and return the logits for the last `logits_count` tokens with the shape `(1, logits_count, vocab)`.
+    pub fn forward_last_n(
         &self,
         tokens: &[u32],
         device: &Device,
         mut cache: Option<&mut LlamaCache>,
+        logits_count: usize,
     ) -> Result<Tensor> {
         let seq_len = tokens.len();
+        if logits_count == 0 || logits_count > seq_len {
+            candle_core::bail!("cannot return logits for {logits_count} of {seq_len} tokens");
+        }
         let cached_tokens = cache.as_ref().map(|c| c.tokens.len()).unwrap_or_default();
         // We use a lower cutoff than the context length to avoid recomputing the attention every single token
         let cutoff_len: usize = self.config.context_length - 32;
@@ -304,6 +341,8 @@ impl Model {
             }
             (Tensor::new(tokens, device)?.unsqueeze(0)?, index_pos)
         };
+        // If the context overflowed, the whole window is recomputed
+        let seq_len = x.dim(1)?;
         let mask = self.masks.get_mask(seq_len, index_pos, device)?;
 
         let mut layer_in = self.tok_embeddings.forward(&x)?;
@@ -326,7 +365,186 @@ impl Model {
             layer_in = (&layer.feed_forward_variant.forward(&x)? + residual)?;
         }
         let x = self.norm.forward(&layer_in)?;
-        let x = x.i((.., seq_len - 1, ..))?;
+        let x = x
+            .narrow(1, seq_len - logits_count, logits_count)?
+            .contiguous()?;
         self.output.forward(&x)
     }
+
+    /// Run the model on a batch of sequences that each have their own cache. Returns the logits of the last token of each sequence with the shape `(batch, vocab)`.
+    ///
+    /// Unlike [`Model::forward`], this does not handle sequences that overflow the context length.
+    pub fn forward_batch(
+        &self,
+        tokens: &[&[u32]],
+        device: &Device,
+        caches: &mut [&mut LlamaCache],
+    ) -> Result<Tensor> {
+        if tokens.len() != caches.len() {
+            candle_core::bail!(
+                "expected one cache per sequence, found {} sequences and {} caches",
+                tokens.len(),
+                caches.len()
+            );
+        }
+        let batch_size = tokens.len();
+        let seq_lens: Vec<_> = tokens.iter().map(|tokens| tokens.len()).collect();
+        let max_seq_len = seq_lens.iter().copied().max().unwrap_or_default();
+        let mut index_positions = Vec::with_capacity(batch_size);
+        for (cache, tokens) in caches.iter_mut().zip(tokens) {
+            index_positions.push(cache.tokens.len());
+            cache.tokens.extend_from_slice(tokens);
+        }
+
+        // Right pad every sequence to the longest sequence in the batch
+        let padded_tokens: Vec<u32> = tokens
+            .iter()
+            .flat_map(|tokens| {
+                tokens
+                    .iter()
+                    .copied()
+                    .chain(std::iter::repeat(0).take(max_seq_len - tokens.len()))
+            })
+            .collect();
+        let x = Tensor::from_vec(padded_tokens, (batch_size, max_seq_len), device)?;
+        let mask = self
+            .masks
+            .get_batch_mask(&seq_lens, &index_positions, device)?;
+
+        let mut layer_in = self.tok_embeddings.forward(&x)?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let x = layer_in;
+            let residual = &x;
+            let x = layer.attention_norm.forward(&x)?;
+            let mut layer_caches: Vec<_> = caches.iter_mut().map(|c| &mut c.blocks[i]).collect();
+            let attn =
+                layer.forward_batch(&x, &mask, &seq_lens, &index_positions, &mut layer_caches)?;
+            let x = (attn + residual)?;
+
+            // MLP
+            let residual = &x;
+            let x = layer.ffn_norm.forward(&x)?;
+
+            layer_in = (&layer.feed_forward_variant.forward(&x)? + residual)?;
+        }
+        let x = self.norm.forward(&layer_in)?;
+        // Only the last real token of each sequence is used to predict the next token
+        let last_tokens = seq_lens
+            .iter()
+            .enumerate()
+            .map(|(i, seq_len)| x.i((i, seq_len - 1, ..)))
+            .collect::<Result<Vec<_>>>()?;
+        let x = Tensor::stack(&last_tokens, 0)?;
+        self.output.forward(&x)
+    }
+}
+
+/// Create a small model with random weights.
+#[cfg(test)]
+fn random_model(device: &Device) -> Result<Model> {
+    const VOCAB: usize = 16;
+    const HIDDEN: usize = 8;
+    const FEED_FORWARD: usize = 16;
+
+    let config = LlamaConfig {
+        rope_freq_weight: None,
+        rope_theta: 10000.,
+        context_length: 64,
+        head_dimension: 4,
+        n_head: 2,
+        n_layer: 2,
+    };
+    let rope = RopeCache::new(&config, DType::F32, device)?;
+    let weight = |shape: (usize, usize)| -> Result<QMatMul> {
+        let tensor = (Tensor::randn(0f32, 1., shape, device)? * 0.2)?;
+        QMatMul::from_qtensor(QTensor::quantize(&tensor, GgmlDType::F32)?)
+    };
+    let norm = || -> Result<RmsNorm> {
+        let tensor = Tensor::ones(HIDDEN, DType::F32, device)?;
+        decode_norm(QTensor::quantize(&tensor, GgmlDType::F32)?, 1e-5)
+    };
+    let layers = (0..config.n_layer)
+        .map(|_| {
+            Ok(LlamaAttention {
+                attention_variant: AttentionVariant::Separate(SeparateAttention {
+                    attention_wq: weight((HIDDEN, HIDDEN))?,
+                    attention_wk: weight((HIDDEN, HIDDEN))?,
+                    attention_wv: weight((HIDDEN, HIDDEN))?,
+                    interleaved_rope: true,
+                    bias: None,
+                }),
+                attention_wo: weight((HIDDEN, HIDDEN))?,
+                attention_norm: norm()?,
+                feed_forward_variant: FeedForwardVariant::Llama(LlamaFeedForward {
+                    feed_forward_w1: weight((FEED_FORWARD, HIDDEN))?,
+                    feed_forward_w2: weight((HIDDEN, FEED_FORWARD))?,
+                    feed_forward_w3: weight((FEED_FORWARD, HIDDEN))?,
+                }),
+                ffn_norm: norm()?,
+                n_head: config.n_head,
+                n_kv_head: config.n_head,
+                head_dim: config.head_dimension,
+                hidden_size: config.hidden_size(),
+                rope_cache: rope.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Model {
+        chat_template: None,
+        tok_embeddings: Embedding::new(Tensor::randn(0f32, 1., (VOCAB, HIDDEN), device)?, HIDDEN),
+        layers,
+        norm: norm()?,
+        output: weight((VOCAB, HIDDEN))?,
+        masks: Default::default(),
+        config,
+    })
+}
+
+#[test]
+fn forward_batch_matches_forward() {
+    let device = Device::Cpu;
+    let model = random_model(&device).unwrap();
+    let sequences: [&[u32]; 3] = [&[1, 2, 3, 4, 5], &[6, 7], &[8, 9, 10]];
+    let next_tokens = [11, 12, 13];
+
+    // Run each sequence on its own
+    let mut expected = Vec::new();
+    for (tokens, next_token) in sequences.iter().zip(next_tokens) {
+        let mut cache = LlamaCache::new(&model.config);
+        let prompt = model.forward(tokens, &device, Some(&mut cache)).unwrap();
+        let next = model
+            .forward(&[next_token], &device, Some(&mut cache))
+            .unwrap();
+        expected.push((prompt.squeeze(0).unwrap(), next.squeeze(0).unwrap()));
+    }
+
+    // Run the sequences together. The second step has caches with different lengths
+    let mut caches: Vec<_> = sequences
+        .iter()
+        .map(|_| LlamaCache::new(&model.config))
+        .collect();
+    let mut caches: Vec<_> = caches.iter_mut().collect();
+    let prompt = model
+        .forward_batch(&sequences, &device, &mut caches)
+        .unwrap();
+    let next_tokens: Vec<_> = next_tokens.iter().map(std::slice::from_ref).collect();
+    let next = model
+        .forward_batch(&next_tokens, &device, &mut caches)
+        .unwrap();
+
+    let assert_close = |a: &Tensor, b: &Tensor| {
+        let difference = (a - b)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max(0)
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(difference < 1e-4, "logits differ by {difference}");
+    };
+    for (i, (expected_prompt, expected_next)) in expected.iter().enumerate() {
+        assert_close(&prompt.i(i).unwrap(), expected_prompt);
+        assert_close(&next.i(i).unwrap(), expected_next);
+    }
 }
@@ -8,6 +8,8 @@ use std::collections::HashMap;
 #[derive(Debug, Clone)]
 pub struct LlamaSession {
     pub(crate) cache: LlamaCache,
+    /// The cache of the draft model if speculative decoding is enabled. This is created lazily and synced with the main cache before each draft.
+    pub(crate) draft_cache: Option<LlamaCache>,
 }
 
 impl Session for LlamaSession {
@@ -21,6 +23,14 @@ impl Session for LlamaSession {
         &self.cache.tokens
     }
 
+    fn truncate(&mut self, len: usize) -> anyhow::Result<()> {
+        self.cache.truncate(len)?;
+        if let Some(draft_cache) = &mut self.draft_cache {
+            draft_cache.truncate(len)?;
+        }
+        Ok(())
+    }
+
     fn load_from(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self>
     where
         Self: std::marker::Sized,
@@ -35,7 +45,15 @@ impl Session for LlamaSession {
     where
         Self: std::marker::Sized,
     {
-        Ok(self.clone())
+        // Cloned caches share memory, so feeding one clone would change the other
+        Ok(Self {
+            cache: self.cache.deep_clone()?,
+            draft_cache: self
+                .draft_cache
+                .as_ref()
+                .map(LlamaCache::deep_clone)
+                .transpose()?,
+        })
     }
 }
 
@@ -48,6 +66,7 @@ impl LlamaSession {
     /// Import a cache tensor map.
     pub fn set_tensor_map(&mut self, map: HashMap<String, Tensor>) -> candle_core::Result<()> {
         self.cache = LlamaCache::from_tensor_map(map)?;
+        self.draft_cache = None;
         Ok(())
     }
 
@@ -55,6 +74,7 @@ impl LlamaSession {
     pub fn from_tensor_map(map: HashMap<String, Tensor>) -> candle_core::Result<Self> {
         Ok(Self {
             cache: LlamaCache::from_tensor_map(map)?,
+            draft_cache: None,
         })
     }
 }
@@ -62,7 +62,9 @@ impl Model for Phi {
             InferenceSettings::new(prompt)
                 .with_sample_len(max_length as usize)
                 .with_stop_on(generation_parameters.stop_on().map(|s| s.to_string())),
-            Arc::new(Mutex::new(generation_parameters.sampler())),
+            Arc::new(Mutex::new(
+                generation_parameters.sampler_with_tokenizer(&self.tokenizer())?,
+            )),
         )
         .map(Into::into)
     }